@@ -9,12 +9,17 @@
 //! new memory settings.
 //!
 //! The build script also sets the linker flags to tell it which link script to use.
+//!
+//! Settings of the hand-written `central`/`peripheral` binaries that RMK's
+//! `keyboard.toml` does not cover are read from the same keyboard TOML file and
+//! generated as constants into `OUT_DIR`.
 
 use const_gen::*;
 use std::fs::File;
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 use std::{env, fs};
+use toml::Table;
 use xz2::read::XzEncoder;
 
 fn main() {
@@ -28,6 +33,26 @@ fn main() {
     generate_vial_config(vial_config_path);
     // Generate vial config at the root of project
 
+    let keyboard_toml = read_keyboard_toml();
+    generate_conn_params(&keyboard_toml);
+    generate_tx_power(&keyboard_toml);
+    generate_ble_addresses(&keyboard_toml);
+    generate_bonds(&keyboard_toml);
+    generate_storage_layout(&keyboard_toml, include_str!("memory.x"));
+    generate_indicators(&keyboard_toml);
+    generate_underglow(&keyboard_toml);
+    generate_display(&keyboard_toml);
+    generate_split_uart(&keyboard_toml);
+    generate_combos(&keyboard_toml);
+    generate_behavior(&keyboard_toml);
+    generate_caps_word(&keyboard_toml);
+    generate_leader(&keyboard_toml);
+    generate_unicode(&keyboard_toml);
+    generate_keyboard_macros(&keyboard_toml);
+    generate_auto_shift(&keyboard_toml);
+    generate_key_overrides(&keyboard_toml);
+    generate_repeat(&keyboard_toml);
+
     // Put `memory.x` in our output directory and ensure it's
     // on the linker search path.
     let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
@@ -88,3 +113,1613 @@ fn generate_vial_config(vial_config_path: String) {
     .join("\n");
     fs::write(out_file, const_declarations).unwrap();
 }
+
+fn read_keyboard_toml() -> Table {
+    let toml_path = env::var("KEYBOARD_TOML_PATH").unwrap_or("keyboard_corne.toml".to_string());
+    println!("cargo:rerun-if-env-changed=KEYBOARD_TOML_PATH");
+    println!("cargo:rerun-if-changed={toml_path}");
+    let content = fs::read_to_string(&toml_path).expect("Cannot read keyboard toml");
+    content.parse::<Table>().expect("Cannot parse keyboard toml")
+}
+
+/// Parse a duration like "7.5ms" or "2s" into microseconds
+fn parse_duration_us(value: &str) -> u64 {
+    let value = value.trim();
+    let (number, scale) = if let Some(ms) = value.strip_suffix("ms") {
+        (ms, 1_000.0)
+    } else if let Some(us) = value.strip_suffix("us") {
+        (us, 1.0)
+    } else if let Some(s) = value.strip_suffix('s') {
+        (s, 1_000_000.0)
+    } else {
+        panic!("Invalid duration {value}, expected a value like \"30ms\"")
+    };
+    let number: f64 = number.trim().parse().expect("Invalid duration");
+    (number * scale) as u64
+}
+
+const CONN_PROFILE_NAMES: [(&str, &str); 3] = [
+    ("low_latency", "LowLatency"),
+    ("balanced", "Balanced"),
+    ("low_power", "LowPower"),
+];
+
+fn conn_profile_variant(name: &str) -> &'static str {
+    CONN_PROFILE_NAMES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .unwrap_or_else(|| panic!("Unknown connection profile {name}"))
+        .1
+}
+
+/// Generate the BLE connection parameter profiles from `[conn_params]`
+fn generate_conn_params(keyboard_toml: &Table) {
+    let out_file = Path::new(&env::var_os("OUT_DIR").unwrap()).join("conn_params_generated.rs");
+    let empty = Table::new();
+    let conn_params = keyboard_toml
+        .get("conn_params")
+        .and_then(|v| v.as_table())
+        .unwrap_or(&empty);
+    let profiles = conn_params
+        .get("profiles")
+        .and_then(|v| v.as_table())
+        .unwrap_or(&empty);
+
+    // Defaults used when the TOML does not override a profile
+    let defaults = [
+        ("low_latency", "7.5ms", 0, "2s", "2M"),
+        ("balanced", "15ms", 4, "4s", "2M"),
+        ("low_power", "30ms", 10, "6s", "1M"),
+    ];
+    let mut params = String::new();
+    for (name, interval, latency, timeout, phy) in defaults {
+        let profile = profiles.get(name).and_then(|v| v.as_table());
+        let get_str = |key: &str, default: &'static str| -> String {
+            profile
+                .and_then(|p| p.get(key))
+                .and_then(|v| v.as_str())
+                .unwrap_or(default)
+                .to_string()
+        };
+        let interval_us = parse_duration_us(&get_str("interval", interval));
+        let timeout_us = parse_duration_us(&get_str("timeout", timeout));
+        let latency = profile
+            .and_then(|p| p.get("latency"))
+            .and_then(|v| v.as_integer())
+            .unwrap_or(latency);
+        let phy = match get_str("phy", phy).as_str() {
+            "1M" => "Phy::Le1M",
+            "2M" => "Phy::Le2M",
+            other => panic!("Unknown PHY {other}, expected \"1M\" or \"2M\""),
+        };
+        assert!(
+            (7_500..=4_000_000).contains(&interval_us),
+            "Connection interval of {name} must be between 7.5ms and 4s"
+        );
+        params += &format!(
+            "    ConnParams {{ interval_us: {interval_us}, latency: {latency}, timeout_ms: {}, phy: {phy} }},\n",
+            timeout_us / 1_000
+        );
+    }
+
+    let select = |key: &str, default: &str| -> &'static str {
+        conn_profile_variant(
+            conn_params
+                .get(key)
+                .and_then(|v| v.as_str())
+                .unwrap_or(default),
+        )
+    };
+    let generated = format!(
+        "/// Connection parameters of each [`ConnProfile`], in declaration order\n\
+         pub(crate) const CONN_PROFILES: [ConnParams; 3] = [\n{params}];\n\
+         pub(crate) const SPLIT_USB_PROFILE: ConnProfile = ConnProfile::{};\n\
+         pub(crate) const SPLIT_BATTERY_PROFILE: ConnProfile = ConnProfile::{};\n\
+         pub(crate) const HOST_USB_PROFILE: ConnProfile = ConnProfile::{};\n\
+         pub(crate) const HOST_BATTERY_PROFILE: ConnProfile = ConnProfile::{};\n",
+        select("split_usb", "low_latency"),
+        select("split_battery", "balanced"),
+        select("host_usb", "low_latency"),
+        select("host_battery", "balanced"),
+    );
+    fs::write(out_file, generated).unwrap();
+}
+
+/// Generate the boot TX power from `default_tx_power` in `[ble]`
+fn generate_tx_power(keyboard_toml: &Table) {
+    let out_file = Path::new(&env::var_os("OUT_DIR").unwrap()).join("tx_power_generated.rs");
+    let tx_power = keyboard_toml
+        .get("ble")
+        .and_then(|v| v.get("default_tx_power"))
+        .and_then(|v| v.as_integer())
+        .unwrap_or(0);
+    assert!(
+        (-40..=8).contains(&tx_power),
+        "default_tx_power must be between -40 and 8 dBm on nRF52840"
+    );
+    fs::write(
+        out_file,
+        format!("/// TX power at boot, in dBm\npub(crate) const DEFAULT_TX_POWER: i8 = {tx_power};\n"),
+    )
+    .unwrap();
+}
+
+fn ble_addr_literal(value: Option<&toml::Value>) -> String {
+    let Some(addr) = value.and_then(|v| v.as_array()) else {
+        return "None".to_string();
+    };
+    assert!(addr.len() == 6, "ble_addr must have 6 bytes");
+    let bytes: Vec<String> = addr
+        .iter()
+        .map(|b| {
+            let b = b.as_integer().expect("ble_addr bytes must be integers");
+            assert!((0..=255).contains(&b), "ble_addr bytes must fit in u8");
+            format!("0x{b:02x}")
+        })
+        .collect();
+    format!("Some([{}])", bytes.join(", "))
+}
+
+/// Generate the `ble_addr` configured for each half in `[split]`
+fn generate_ble_addresses(keyboard_toml: &Table) {
+    let out_file = Path::new(&env::var_os("OUT_DIR").unwrap()).join("identity_generated.rs");
+    let split = keyboard_toml.get("split");
+    let central = split
+        .and_then(|s| s.get("central"))
+        .and_then(|c| c.get("ble_addr"));
+    let peripheral = split
+        .and_then(|s| s.get("peripheral"))
+        .and_then(|p| p.as_array())
+        .and_then(|p| p.first())
+        .and_then(|p| p.get("ble_addr"));
+    let generated = format!(
+        "/// `ble_addr` of `[split.central]`\n\
+         #[allow(dead_code)]\n\
+         pub(crate) const CENTRAL_BLE_ADDR: Option<[u8; 6]> = {};\n\
+         /// `ble_addr` of the first `[[split.peripheral]]`\n\
+         #[allow(dead_code)]\n\
+         pub(crate) const PERIPHERAL_BLE_ADDR: Option<[u8; 6]> = {};\n",
+        ble_addr_literal(central),
+        ble_addr_literal(peripheral),
+    );
+    fs::write(out_file, generated).unwrap();
+}
+
+/// `ble_profiles_num` in `[rmk]`, RMK's default of 3 when unset
+fn ble_profiles_num(keyboard_toml: &Table) -> i64 {
+    keyboard_toml
+        .get("rmk")
+        .and_then(|v| v.get("ble_profiles_num"))
+        .and_then(|v| v.as_integer())
+        .unwrap_or(3)
+}
+
+/// Generate the number of BLE profiles whose bonds are managed
+fn generate_bonds(keyboard_toml: &Table) {
+    let out_file = Path::new(&env::var_os("OUT_DIR").unwrap()).join("bonds_generated.rs");
+    let profiles = ble_profiles_num(keyboard_toml);
+    assert!(
+        (1..=u8::MAX as i64).contains(&profiles),
+        "[rmk] ble_profiles_num must be between 1 and 255"
+    );
+    let generated = format!(
+        "/// Number of BLE profiles, `ble_profiles_num` in `[rmk]`\n\
+         pub(crate) const NUM_BLE_PROFILES: usize = {profiles};\n"
+    );
+    fs::write(out_file, generated).unwrap();
+}
+
+/// Size of the nRF52840's flash
+const FLASH_SIZE: u64 = 0x10_0000;
+
+/// Size of an NVMC erase page
+const SECTOR_SIZE: u64 = 0x1000;
+
+/// Start of the Adafruit nRF52 bootloader
+const BOOTLOADER_START: u64 = 0xF4000;
+
+/// Sectors of the central's firmware-owned records, after RMK's storage
+const APP_STORAGE_SECTORS: u64 = 2;
+
+/// Parse a linker script number like "0x00026000", "488K" or "1M"
+fn parse_linker_number(value: &str) -> u64 {
+    let value = value.trim();
+    let (number, scale) = if let Some(k) = value.strip_suffix('K') {
+        (k, 1024)
+    } else if let Some(m) = value.strip_suffix('M') {
+        (m, 1024 * 1024)
+    } else {
+        (value, 1)
+    };
+    let number = match number
+        .strip_prefix("0x")
+        .or_else(|| number.strip_prefix("0X"))
+    {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => number.parse(),
+    };
+    number.unwrap_or_else(|_| panic!("Invalid number {value} in memory.x")) * scale
+}
+
+/// `ORIGIN` and `LENGTH` of the `FLASH` region in `memory.x`
+fn flash_region(memory_x: &str) -> (u64, u64) {
+    // Drop the comments, they hold the layouts of other SoftDevice versions
+    let mut script = String::new();
+    let mut rest = memory_x;
+    while let Some(start) = rest.find("/*") {
+        script += &rest[..start];
+        let end = rest[start..]
+            .find("*/")
+            .expect("Unterminated comment in memory.x");
+        rest = &rest[start + end + 2..];
+    }
+    script += rest;
+
+    let line = script
+        .lines()
+        .find(|l| l.trim_start().starts_with("FLASH"))
+        .expect("No FLASH region in memory.x");
+    let field = |name: &str| -> u64 {
+        let value = line
+            .split(',')
+            .find_map(|part| {
+                part.split_once('=')
+                    .filter(|(k, _)| k.trim().ends_with(name))
+            })
+            .unwrap_or_else(|| panic!("No {name} in the FLASH region of memory.x"))
+            .1;
+        parse_linker_number(value)
+    };
+    (field("ORIGIN"), field("LENGTH"))
+}
+
+/// Generate the storage regions from the application image in `memory.x`
+///
+/// RMK's storage starts right after the image unless `start_addr` in
+/// `[storage]` places it, and the central's own records follow it. Both have
+/// to fit between the image and the bootloader.
+fn generate_storage_layout(keyboard_toml: &Table, memory_x: &str) {
+    let out_file = Path::new(&env::var_os("OUT_DIR").unwrap()).join("storage_layout_generated.rs");
+    let (origin, length) = flash_region(memory_x);
+    let image_end = origin + length;
+
+    let storage = keyboard_toml.get("storage");
+    let configured_start = storage
+        .and_then(|s| s.get("start_addr"))
+        .and_then(|v| v.as_integer())
+        .unwrap_or(0) as u64;
+    let rmk_sectors = storage
+        .and_then(|s| s.get("num_sectors"))
+        .and_then(|v| v.as_integer())
+        .unwrap_or(6) as u64;
+    let rmk_start = if configured_start == 0 {
+        image_end.div_ceil(SECTOR_SIZE) * SECTOR_SIZE
+    } else {
+        configured_start
+    };
+    let app_start = rmk_start + rmk_sectors * SECTOR_SIZE;
+    let storage_end = app_start + APP_STORAGE_SECTORS * SECTOR_SIZE;
+
+    assert!(
+        rmk_start % SECTOR_SIZE == 0,
+        "Storage start 0x{rmk_start:x} is not aligned to a 0x{SECTOR_SIZE:x} sector"
+    );
+    assert!(
+        (1..=u8::MAX as u64).contains(&rmk_sectors),
+        "num_sectors in [storage] must be between 1 and 255"
+    );
+    assert!(
+        rmk_start >= image_end,
+        "Storage at 0x{rmk_start:x} overlaps the application image, which ends at 0x{image_end:x} \
+         (FLASH in memory.x)"
+    );
+    assert!(
+        storage_end <= BOOTLOADER_START,
+        "Storage ends at 0x{storage_end:x} and overlaps the bootloader at 0x{BOOTLOADER_START:x}, \
+         shorten FLASH in memory.x or reduce num_sectors"
+    );
+    assert!(storage_end <= FLASH_SIZE);
+
+    let generated = format!(
+        "/// Start of RMK's storage\n\
+         pub(crate) const RMK_STORAGE_START: u32 = 0x{rmk_start:x};\n\
+         /// Sectors used by RMK's storage\n\
+         pub(crate) const RMK_STORAGE_SECTORS: u8 = {rmk_sectors};\n\
+         /// Start of the central's firmware-owned records, right after RMK's storage\n\
+         #[allow(dead_code)] // The peripheral has no records of its own\n\
+         pub(crate) const APP_STORAGE_START: u32 = 0x{app_start:x};\n\
+         /// Sectors used by the central's firmware-owned records\n\
+         #[allow(dead_code)]\n\
+         pub(crate) const APP_STORAGE_SECTORS: u32 = {APP_STORAGE_SECTORS};\n"
+    );
+    fs::write(out_file, generated).unwrap();
+}
+
+/// Indicators of `src/indicators.rs` and their default patterns, `(on, off, pause)`
+///
+/// An `off` of 0 keeps the LED lit. Indicators with a number blink it that
+/// many times, then stay dark for `pause`.
+const INDICATORS: [(&str, &str, (&str, &str, &str)); 12] = [
+    ("caps_lock", "CapsLock", ("1s", "0ms", "0ms")),
+    ("caps_word", "CapsWord", ("300ms", "100ms", "0ms")),
+    ("num_lock", "NumLock", ("1s", "0ms", "0ms")),
+    ("scroll_lock", "ScrollLock", ("1s", "0ms", "0ms")),
+    ("layer", "Layer", ("150ms", "150ms", "1s")),
+    ("layer_lock", "LayerLock", ("400ms", "200ms", "1s")),
+    ("ble_profile", "BleProfile", ("150ms", "150ms", "1s")),
+    ("advertising", "Advertising", ("500ms", "500ms", "0ms")),
+    ("connected", "Connected", ("1s", "0ms", "0ms")),
+    ("split_down", "SplitDown", ("250ms", "250ms", "0ms")),
+    ("low_battery", "LowBattery", ("100ms", "1.9s", "0ms")),
+    ("charging", "Charging", ("1s", "1s", "0ms")),
+];
+
+/// Check that `pin` names a GPIO like "P0_15"
+fn assert_pin(pin: &str) {
+    let valid = pin.len() == 5
+        && (pin.starts_with("P0_") || pin.starts_with("P1_"))
+        && pin[3..].chars().all(|c| c.is_ascii_digit());
+    assert!(valid, "Invalid pin {pin}, expected a pin like \"P0_15\"");
+}
+
+fn duration_ms(value: &str, what: &str) -> u64 {
+    let ms = parse_duration_us(value) / 1_000;
+    assert!(
+        ms <= u16::MAX as u64,
+        "{what} is longer than {}ms",
+        u16::MAX
+    );
+    ms
+}
+
+/// Generate the indicator LEDs and patterns from `[indicators]`
+///
+/// A pattern is either `"on"` or a table with `on`, `off` and `pause`
+/// durations, missing ones keep their default.
+fn generate_indicators(keyboard_toml: &Table) {
+    let out_file = Path::new(&env::var_os("OUT_DIR").unwrap()).join("indicators_generated.rs");
+    let empty = Table::new();
+    let indicators = keyboard_toml
+        .get("indicators")
+        .and_then(|v| v.as_table())
+        .unwrap_or(&empty);
+    let patterns = indicators
+        .get("patterns")
+        .and_then(|v| v.as_table())
+        .unwrap_or(&empty);
+    for name in patterns.keys() {
+        assert!(
+            INDICATORS.iter().any(|(n, _, _)| n == name),
+            "Unknown indicator {name} in [indicators.patterns]"
+        );
+    }
+
+    let mut pattern_list = String::new();
+    for (name, _, (on, off, pause)) in INDICATORS {
+        let (on, off, pause) = match patterns.get(name) {
+            None => (on, off, pause),
+            Some(toml::Value::String(s)) if s == "on" => ("1s", "0ms", "0ms"),
+            Some(toml::Value::Table(t)) => {
+                let get = |key: &str, default: &'static str| -> &str {
+                    t.get(key).and_then(|v| v.as_str()).unwrap_or(default)
+                };
+                (get("on", on), get("off", off), get("pause", pause))
+            }
+            Some(_) => panic!("Pattern of {name} must be \"on\" or a table of durations"),
+        };
+        let on_ms = duration_ms(on, &format!("on of {name}"));
+        let off_ms = duration_ms(off, &format!("off of {name}"));
+        let pause_ms = duration_ms(pause, &format!("pause of {name}"));
+        assert!(on_ms > 0, "on of {name} must be longer than 0ms");
+        assert!(
+            off_ms > 0 || pause_ms == 0,
+            "pause of {name} needs an off time, a steady pattern never blinks"
+        );
+        pattern_list +=
+            &format!("    Pattern {{ on_ms: {on_ms}, off_ms: {off_ms}, pause_ms: {pause_ms} }},\n");
+    }
+
+    let leds = indicators
+        .get("led")
+        .and_then(|v| v.as_array())
+        .map(|a| a.as_slice())
+        .unwrap_or(&[]);
+    let mut led_indicators = String::new();
+    let mut led_active_low = Vec::new();
+    let mut led_outputs = String::new();
+    for led in leds {
+        let pin = led
+            .get("pin")
+            .and_then(|v| v.as_str())
+            .expect("Each [[indicators.led]] needs a pin like \"P0_15\"");
+        assert_pin(pin);
+        let active_low = led
+            .get("active_low")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        let show: Vec<String> = led
+            .get("show")
+            .and_then(|v| v.as_array())
+            .expect("Each [[indicators.led]] needs a show list")
+            .iter()
+            .map(|v| {
+                let name = v.as_str().expect("show lists indicator names");
+                let variant = INDICATORS
+                    .iter()
+                    .find(|(n, _, _)| *n == name)
+                    .unwrap_or_else(|| panic!("Unknown indicator {name} on {pin}"))
+                    .1;
+                format!("Indicator::{variant}")
+            })
+            .collect();
+        led_indicators += &format!("    &[{}],\n", show.join(", "));
+        led_active_low.push(active_low.to_string());
+        // Start dark
+        let level = if active_low { "High" } else { "Low" };
+        led_outputs += &format!(
+            "            embassy_nrf::gpio::Output::new(\n\
+             \x20               $p.{pin},\n\
+             \x20               embassy_nrf::gpio::Level::{level},\n\
+             \x20               embassy_nrf::gpio::OutputDrive::Standard,\n\
+             \x20           ),\n"
+        );
+    }
+
+    let low_battery = indicators
+        .get("low_battery_percent")
+        .and_then(|v| v.as_integer())
+        .unwrap_or(15);
+    assert!(
+        (0..=100).contains(&low_battery),
+        "low_battery_percent in [indicators] must be between 0 and 100"
+    );
+    let generated = format!(
+        "/// Battery level below which `low_battery` is shown, in percent\n\
+         pub(crate) const LOW_BATTERY_PERCENT: u8 = {low_battery};\n\
+         /// Pattern of each [`Indicator`], in declaration order\n\
+         pub(crate) const PATTERNS: [Pattern; {}] = [\n{pattern_list}];\n\
+         /// Number of indicator LEDs\n\
+         pub(crate) const LED_COUNT: usize = {};\n\
+         /// Indicators of each LED, highest priority first\n\
+         pub(crate) const LED_INDICATORS: [&[Indicator]; LED_COUNT] = [\n{led_indicators}];\n\
+         /// Whether each LED is lit by a low level\n\
+         pub(crate) const LED_ACTIVE_LOW: [bool; LED_COUNT] = [{}];\n\
+         \n\
+         /// Outputs of the indicator LEDs, dark\n\
+         macro_rules! indicator_leds {{\n\
+         \x20   ($p:ident) => {{\n\
+         \x20       [\n{led_outputs}        ]\n\
+         \x20   }};\n\
+         }}\n",
+        INDICATORS.len(),
+        leds.len(),
+        led_active_low.join(", "),
+    );
+    fs::write(out_file, generated).unwrap();
+}
+
+const UNDERGLOW_EFFECTS: [(&str, &str); 4] = [
+    ("static", "Static"),
+    ("breathing", "Breathing"),
+    ("layer", "Layer"),
+    ("battery", "Battery"),
+];
+
+fn rgb_literal(value: &toml::Value, what: &str) -> String {
+    let rgb = value
+        .as_array()
+        .filter(|a| a.len() == 3)
+        .unwrap_or_else(|| panic!("{what} must be [r, g, b]"));
+    let c: Vec<String> = rgb
+        .iter()
+        .map(|c| {
+            let c = c
+                .as_integer()
+                .filter(|c| (0..=255).contains(c))
+                .unwrap_or_else(|| panic!("{what} components must be between 0 and 255"));
+            format!("0x{c:02x}")
+        })
+        .collect();
+    format!("Rgb::new({})", c.join(", "))
+}
+
+/// Generate the WS2812 chain and its effect settings from `[underglow]`
+///
+/// Without the table there are no LEDs and `underglow_pins!` gives `None`.
+fn generate_underglow(keyboard_toml: &Table) {
+    let out_dir = env::var_os("OUT_DIR").unwrap();
+    let out_dir = Path::new(&out_dir);
+    let empty = Table::new();
+    let underglow = keyboard_toml.get("underglow").and_then(|v| v.as_table());
+    let config = underglow.unwrap_or(&empty);
+
+    let num_leds = config
+        .get("num_leds")
+        .and_then(|v| v.as_integer())
+        .unwrap_or(0);
+    assert!(
+        (0..=256).contains(&num_leds),
+        "num_leds in [underglow] must be between 0 and 256"
+    );
+    let brightness = config
+        .get("brightness")
+        .and_then(|v| v.as_integer())
+        .unwrap_or(128);
+    assert!(
+        (1..=255).contains(&brightness),
+        "brightness in [underglow] must be between 1 and 255"
+    );
+    let effect_name = config
+        .get("effect")
+        .and_then(|v| v.as_str())
+        .unwrap_or("static");
+    let effect = UNDERGLOW_EFFECTS
+        .iter()
+        .find(|(n, _)| *n == effect_name)
+        .unwrap_or_else(|| panic!("Unknown underglow effect {effect_name}"))
+        .1;
+    let color = config
+        .get("color")
+        .map(|v| rgb_literal(v, "color in [underglow]"))
+        .unwrap_or("Rgb::new(0xff, 0xff, 0xff)".to_string());
+    let period_ms = parse_duration_us(
+        config
+            .get("breathing_period")
+            .and_then(|v| v.as_str())
+            .unwrap_or("4s"),
+    ) / 1_000;
+    assert!(
+        period_ms >= 100,
+        "breathing_period in [underglow] must be at least 100ms"
+    );
+    let layer_colors: Vec<String> = config
+        .get("layer_colors")
+        .and_then(|v| v.as_array())
+        .map(|a| a.as_slice())
+        .unwrap_or(&[])
+        .iter()
+        .map(|v| rgb_literal(v, "layer_colors in [underglow]"))
+        .collect();
+    let power_active_low = config
+        .get("power_active_low")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(true);
+
+    let pins = match underglow {
+        None => "None".to_string(),
+        Some(config) => {
+            let data = config
+                .get("data_pin")
+                .and_then(|v| v.as_str())
+                .expect("[underglow] needs a data_pin like \"P0_06\"");
+            assert_pin(data);
+            let power = match config.get("power_pin").and_then(|v| v.as_str()) {
+                Some(pin) => {
+                    assert_pin(pin);
+                    assert!(
+                        pin != data,
+                        "power_pin and data_pin of [underglow] are the same"
+                    );
+                    format!("Some($p.{pin}.into())")
+                }
+                None => "None".to_string(),
+            };
+            format!(
+                "Some(crate::underglow::UnderglowPins {{\n\
+                 \x20           pwm: $p.PWM0,\n\
+                 \x20           data: $p.{data}.into(),\n\
+                 \x20           power: {power},\n\
+                 \x20       }})"
+            )
+        }
+    };
+
+    let render = format!(
+        "/// LEDs on the data line\n\
+         pub(crate) const NUM_LEDS: usize = {num_leds};\n\
+         /// Brightness the colors are scaled to, out of 255\n\
+         pub(crate) const BRIGHTNESS: u8 = {brightness};\n\
+         /// Effect at boot\n\
+         pub(crate) const DEFAULT_EFFECT: Effect = Effect::{effect};\n\
+         /// Color of the static and breathing effects, and of layers without a color\n\
+         pub(crate) const COLOR: Rgb = {color};\n\
+         /// Time of one breath\n\
+         pub(crate) const BREATHING_PERIOD_MS: u32 = {period_ms};\n\
+         /// Color of each layer for the layer effect\n\
+         pub(crate) const LAYER_COLORS: [Rgb; {}] = [{}];\n",
+        layer_colors.len(),
+        layer_colors.join(", "),
+    );
+    fs::write(out_dir.join("underglow_render_generated.rs"), render).unwrap();
+
+    let generated = format!(
+        "/// Whether a low level on the power pin turns the LEDs on\n\
+         pub(crate) const POWER_ACTIVE_LOW: bool = {power_active_low};\n\
+         \n\
+         /// Peripherals of the WS2812 chain, `None` without `[underglow]`\n\
+         macro_rules! underglow_pins {{\n\
+         \x20   ($p:ident) => {{\n\
+         \x20       {pins}\n\
+         \x20   }};\n\
+         }}\n"
+    );
+    fs::write(out_dir.join("underglow_generated.rs"), generated).unwrap();
+}
+
+/// Layer names from the `name` of each `[[layer]]`, indexed by layer
+fn layer_names(keyboard_toml: &Table) -> Vec<String> {
+    keyboard_toml
+        .get("layer")
+        .and_then(|v| v.as_array())
+        .map(|a| a.as_slice())
+        .unwrap_or(&[])
+        .iter()
+        .enumerate()
+        .map(|(i, layer)| {
+            layer
+                .get("name")
+                .and_then(|v| v.as_str())
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("layer {i}"))
+        })
+        .collect()
+}
+
+/// Generate the display of both halves from `[display]`
+///
+/// `driver` is `ssd1306` (128x32 OLED on I2C, `sda` and `scl`) or
+/// `nice_view` (160x68 Sharp memory LCD on SPI, `sck`, `mosi` and `cs`),
+/// `sleep_timeout` the time without activity until it sleeps, 60s by
+/// default. Without the table `display_pins!` gives `None`.
+fn generate_display(keyboard_toml: &Table) {
+    let out_file = Path::new(&env::var_os("OUT_DIR").unwrap()).join("display_generated.rs");
+    let pin = |config: &Table, key: &str| -> String {
+        let pin = config
+            .get(key)
+            .and_then(|v| v.as_str())
+            .unwrap_or_else(|| panic!("[display] needs {key}"));
+        assert_pin(pin);
+        pin.to_string()
+    };
+    let pins = match keyboard_toml.get("display").and_then(|v| v.as_table()) {
+        None => "None".to_string(),
+        Some(config) => match config.get("driver").and_then(|v| v.as_str()) {
+            Some("ssd1306") => format!(
+                "Some(crate::display::DisplayPins::Ssd1306 {{\n\
+                 \x20           twim: $p.TWISPI0,\n\
+                 \x20           sda: $p.{}.into(),\n\
+                 \x20           scl: $p.{}.into(),\n\
+                 \x20       }})",
+                pin(config, "sda"),
+                pin(config, "scl")
+            ),
+            Some("nice_view") => format!(
+                "Some(crate::display::DisplayPins::NiceView {{\n\
+                 \x20           spim: $p.SPI3,\n\
+                 \x20           sck: $p.{}.into(),\n\
+                 \x20           mosi: $p.{}.into(),\n\
+                 \x20           cs: $p.{}.into(),\n\
+                 \x20       }})",
+                pin(config, "sck"),
+                pin(config, "mosi"),
+                pin(config, "cs")
+            ),
+            other => {
+                panic!("Unknown display driver {other:?}, expected \"ssd1306\" or \"nice_view\"")
+            }
+        },
+    };
+    let sleep_timeout_ms = keyboard_toml
+        .get("display")
+        .and_then(|config| config.get("sleep_timeout"))
+        .and_then(|v| v.as_str())
+        .map_or(60_000, |value| parse_duration_us(value) / 1_000);
+    let names: Vec<String> = layer_names(keyboard_toml)
+        .iter()
+        .map(|name| format!("{name:?}"))
+        .collect();
+    let generated = format!(
+        "/// Name of each layer, `name` of its `[[layer]]`\n\
+         pub(crate) const LAYER_NAMES: [&str; {}] = [{}];\n\
+         /// Time without activity until the display sleeps\n\
+         pub(crate) const SLEEP_TIMEOUT: Duration = Duration::from_millis({sleep_timeout_ms});\n\
+         \n\
+         /// Peripherals of the display, `None` without `[display]`\n\
+         macro_rules! display_pins {{\n\
+         \x20   ($p:ident) => {{\n\
+         \x20       {pins}\n\
+         \x20   }};\n\
+         }}\n",
+        names.len(),
+        names.join(", "),
+    );
+    fs::write(out_file, generated).unwrap();
+}
+
+/// Generate the split transport from `connection` of `[split]`
+///
+/// `"serial"` selects the wired link on pin 0 (RX) and pin 1 (TX) of the
+/// nice!nano, `duplex` of `[split_uart]` its wiring. The WS2812 chain and the
+/// display must not use these pins then.
+fn generate_split_uart(keyboard_toml: &Table) {
+    let out_file = Path::new(&env::var_os("OUT_DIR").unwrap()).join("split_uart_generated.rs");
+    let connection = keyboard_toml
+        .get("split")
+        .and_then(|split| split.get("connection"))
+        .and_then(|v| v.as_str())
+        .unwrap_or("ble");
+    let duplex = match keyboard_toml
+        .get("split_uart")
+        .and_then(|config| config.get("duplex"))
+        .and_then(|v| v.as_str())
+        .unwrap_or("full")
+    {
+        "full" => "Duplex::Full",
+        "half" => "Duplex::Half",
+        other => panic!("Unknown duplex {other:?} in [split_uart], expected \"full\" or \"half\""),
+    };
+    let transport = match connection {
+        "ble" => "SplitTransport::Ble".to_string(),
+        "serial" => format!("SplitTransport::Uart({duplex})"),
+        other => panic!("Unknown connection {other:?} in [split], expected \"ble\" or \"serial\""),
+    };
+
+    let uart_pins = ["P0_08", "P0_06"];
+    let claimed = |table: &str, keys: &[&str]| -> Option<String> {
+        let config = keyboard_toml.get(table)?;
+        keys.iter().find_map(|key| {
+            config
+                .get(*key)
+                .and_then(|v| v.as_str())
+                .filter(|pin| uart_pins.contains(pin))
+                .map(|pin| format!("{key} = \"{pin}\" of [{table}]"))
+        })
+    };
+    let taken = claimed("underglow", &["data_pin", "power_pin"])
+        .or_else(|| claimed("display", &["sda", "scl", "sck", "mosi", "cs"]));
+    let pins = if connection == "serial" {
+        if let Some(taken) = taken {
+            panic!("{taken} is a pin of the wired split link, which connection = \"serial\" uses");
+        }
+        "Some(($p.P0_08, $p.P0_06))"
+    } else {
+        "None::<(\n\
+         \x20           embassy_nrf::Peri<'static, embassy_nrf::peripherals::P0_08>,\n\
+         \x20           embassy_nrf::Peri<'static, embassy_nrf::peripherals::P0_06>,\n\
+         \x20       )>"
+    };
+    let generated = format!(
+        "/// Selected split transport, shared by both halves so they always agree\n\
+         pub(crate) const SPLIT_TRANSPORT: SplitTransport = {transport};\n\
+         \n\
+         /// RX and TX of the wired split link, `None` unless `[split]` selects it\n\
+         macro_rules! split_uart_pins {{\n\
+         \x20   ($p:ident) => {{\n\
+         \x20       {pins}\n\
+         \x20   }};\n\
+         }}\n"
+    );
+    fs::write(out_file, generated).unwrap();
+}
+
+/// The name and arguments of an action like `WM(Kc1, LShift)`
+fn split_action(action: &str) -> (&str, Vec<&str>) {
+    match action.split_once('(') {
+        Some((name, args)) => (
+            name,
+            args.trim_end_matches(')')
+                .split(',')
+                .map(str::trim)
+                .collect(),
+        ),
+        None => (action, Vec::new()),
+    }
+}
+
+/// Rust expression of an action like `Backspace`, `MO(4)`, `Macro(0)` or `@Alias`
+fn action_literal(output: &str, aliases: &Table) -> String {
+    let output = output.trim();
+    let alias = aliases.get(output.trim_start_matches('@'));
+    if let Some(action) = alias.and_then(|v| v.as_str()) {
+        return action_literal(action, aliases);
+    }
+    let (kind, args) = split_action(output);
+    let number = || -> u8 {
+        args.first()
+            .and_then(|n| n.parse().ok())
+            .unwrap_or_else(|| panic!("Invalid action {output}, expected a number"))
+    };
+    match (kind, args.len()) {
+        ("MO", 1) => format!("rmk::mo!({})", number()),
+        ("TG", 1) => format!("rmk::tg!({})", number()),
+        ("TO", 1) => format!("rmk::to!({})", number()),
+        ("Macro", 1) => format!("rmk::k!(Macro{})", number()),
+        ("WM", 2) => format!("crate::wm!({}, {})", args[0], args[1]),
+        (key, 0) if is_key_name(key) => format!("rmk::k!({key})"),
+        _ => panic!(
+            "Unsupported action {output}, expected a key, @alias, MO(n), TG(n), TO(n), Macro(n) or WM(key, modifiers)"
+        ),
+    }
+}
+
+/// Check that `key` is a plain key name like `O` or `User15`
+fn is_key_name(key: &str) -> bool {
+    !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+/// Generate the combos of the central from `[[combo]]`
+///
+/// `keys` are matrix positions like `"2,0"` or key names like `"O"` or
+/// `"@UgNext"`, found in the keymap at boot. RMK has a single combo timeout,
+/// so the combos that set `timeout` have to agree. `host-tests` runs it as well.
+pub(crate) fn generate_combos(keyboard_toml: &Table) {
+    let out_file = Path::new(&env::var_os("OUT_DIR").unwrap()).join("combos_generated.rs");
+    let empty = Table::new();
+    let aliases = keyboard_toml
+        .get("aliases")
+        .and_then(|v| v.as_table())
+        .unwrap_or(&empty);
+    let rmk = keyboard_toml
+        .get("rmk")
+        .and_then(|v| v.as_table())
+        .unwrap_or(&empty);
+    let rmk_limit = |key: &str, default: i64| -> usize {
+        rmk.get(key).and_then(|v| v.as_integer()).unwrap_or(default) as usize
+    };
+    let (max_num, max_length) = (
+        rmk_limit("combo_max_num", 8),
+        rmk_limit("combo_max_length", 4),
+    );
+    let layout = keyboard_toml.get("layout");
+    let layout_size = |key: &str| -> i64 {
+        layout
+            .and_then(|v| v.get(key))
+            .and_then(|v| v.as_integer())
+            .unwrap_or_else(|| panic!("[layout] needs {key}"))
+    };
+    let (rows, cols, layers) = (
+        layout_size("rows") as u8,
+        layout_size("cols") as u8,
+        layout_size("layers"),
+    );
+
+    let combos = keyboard_toml
+        .get("combo")
+        .and_then(|v| v.as_array())
+        .map(|a| a.as_slice())
+        .unwrap_or(&[]);
+    assert!(
+        combos.len() <= max_num,
+        "{} combos in [[combo]], [rmk] combo_max_num allows {max_num}",
+        combos.len()
+    );
+    let mut timeout: Option<(u64, &str)> = None;
+    let mut literals = Vec::new();
+    for (i, combo) in combos.iter().enumerate() {
+        let what = format!("[[combo]] {i}");
+        let keys: Vec<String> = combo
+            .get("keys")
+            .and_then(|v| v.as_array())
+            .unwrap_or_else(|| panic!("{what} needs keys"))
+            .iter()
+            .map(|key| {
+                let key = key
+                    .as_str()
+                    .unwrap_or_else(|| panic!("{what}: keys are strings like \"2,0\" or \"O\""));
+                let position = key.split_once(',').and_then(|(row, col)| {
+                    Some((
+                        row.trim().parse::<u8>().ok()?,
+                        col.trim().parse::<u8>().ok()?,
+                    ))
+                });
+                if let Some((row, col)) = position {
+                    assert!(
+                        row < rows && col < cols,
+                        "{what}: {key} is outside the {rows}x{cols} matrix"
+                    );
+                    return format!("ComboKey::Position({row}, {col})");
+                }
+                let name = key.trim_start_matches('@');
+                let name = aliases.get(name).and_then(|v| v.as_str()).unwrap_or(name);
+                assert!(
+                    is_key_name(name),
+                    "{what}: {key} is neither \"row,col\" nor a key name"
+                );
+                format!("ComboKey::Key(KeyCode::{name})")
+            })
+            .collect();
+        assert!(
+            (2..=max_length).contains(&keys.len()),
+            "{what}: a combo has 2 to {max_length} keys ([rmk] combo_max_length)"
+        );
+        let output = combo
+            .get("output")
+            .and_then(|v| v.as_str())
+            .unwrap_or_else(|| panic!("{what} needs an output"));
+        let layer = match combo.get("layer").and_then(|v| v.as_integer()) {
+            Some(l) => {
+                assert!((0..layers).contains(&l), "{what}: layer {l} does not exist");
+                format!("Some({l})")
+            }
+            None => "None".to_string(),
+        };
+        if let Some(value) = combo.get("timeout").and_then(|v| v.as_str()) {
+            let ms = duration_ms(value, &what);
+            match timeout {
+                Some((other, other_value)) if other != ms => panic!(
+                    "{what}: timeout {value} differs from {other_value}, RMK has one timeout for all combos"
+                ),
+                _ => timeout = Some((ms, value)),
+            }
+        }
+        literals.push(format!(
+            "    ComboDef {{\n\
+             \x20       keys: &[{}],\n\
+             \x20       output: {},\n\
+             \x20       layer: {layer},\n\
+             \x20   }},\n",
+            keys.join(", "),
+            action_literal(output, aliases),
+        ));
+    }
+    let generated = format!(
+        "/// Longest time from the first to the last key of a combo\n\
+         pub(crate) const COMBO_TIMEOUT: embassy_time::Duration = embassy_time::Duration::from_millis({});\n\
+         \n\
+         /// Combos of `[[combo]]`\n\
+         pub(crate) const COMBOS: [ComboDef; {}] = [\n{}];\n",
+        timeout.map_or(50, |(ms, _)| ms),
+        literals.len(),
+        literals.concat(),
+    );
+    fs::write(out_file, generated).unwrap();
+}
+
+/// Generate the behaviors of the central from `[behavior]`
+///
+/// `[behavior.tri_layer]` activates `adjust` while `upper` and `lower` are
+/// both active. `host-tests` runs it as well.
+pub(crate) fn generate_behavior(keyboard_toml: &Table) {
+    let out_file = Path::new(&env::var_os("OUT_DIR").unwrap()).join("behavior_generated.rs");
+    let behavior = keyboard_toml.get("behavior");
+    let layers = keyboard_toml
+        .get("layout")
+        .and_then(|v| v.get("layers"))
+        .and_then(|v| v.as_integer())
+        .expect("[layout] needs layers");
+    let tri_layer = match behavior.and_then(|v| v.get("tri_layer")) {
+        None => "None".to_string(),
+        Some(config) => {
+            let layer = |key: &str| -> i64 {
+                let layer = config
+                    .get(key)
+                    .and_then(|v| v.as_integer())
+                    .unwrap_or_else(|| panic!("[behavior.tri_layer] needs {key}"));
+                assert!(
+                    (0..layers).contains(&layer),
+                    "[behavior.tri_layer] {key}: layer {layer} does not exist"
+                );
+                layer
+            };
+            let (upper, lower, adjust) = (layer("upper"), layer("lower"), layer("adjust"));
+            assert!(
+                upper != lower && adjust != upper && adjust != lower,
+                "[behavior.tri_layer] needs three different layers"
+            );
+            format!("Some([{upper}, {lower}, {adjust}])")
+        }
+    };
+    let generated = format!(
+        "/// Upper, lower and adjust layer of `[behavior.tri_layer]`\n\
+         pub(crate) const TRI_LAYER: Option<[u8; 3]> = {tri_layer};\n"
+    );
+    fs::write(out_file, generated).unwrap();
+}
+
+/// Generate the settings of Caps Word from `[caps_word]`
+///
+/// `continue_keys` keep Caps Word on besides letters and digits,
+/// `idle_timeout` turns it off when no key was pressed for that long.
+fn generate_caps_word(keyboard_toml: &Table) {
+    let out_file = Path::new(&env::var_os("OUT_DIR").unwrap()).join("caps_word_generated.rs");
+    let config = keyboard_toml.get("caps_word");
+    let continue_keys: Vec<String> = match config.and_then(|v| v.get("continue_keys")) {
+        None => vec![
+            "Minus".to_string(),
+            "Backspace".to_string(),
+            "Delete".to_string(),
+        ],
+        Some(keys) => keys
+            .as_array()
+            .expect("continue_keys in [caps_word] is a list of keys")
+            .iter()
+            .map(|key| {
+                let key = key.as_str().unwrap_or_default();
+                assert!(
+                    is_key_name(key),
+                    "Invalid key {key:?} in continue_keys of [caps_word]"
+                );
+                key.to_string()
+            })
+            .collect(),
+    };
+    let idle_timeout = config
+        .and_then(|v| v.get("idle_timeout"))
+        .map(|v| {
+            v.as_str()
+                .expect("idle_timeout in [caps_word] is a duration")
+        })
+        .unwrap_or("5s");
+    let idle_ms = parse_duration_us(idle_timeout) / 1_000;
+    let keys: Vec<String> = continue_keys
+        .iter()
+        .map(|key| format!("KeyCode::{key}"))
+        .collect();
+    let generated = format!(
+        "/// Keys besides letters and digits that keep Caps Word on\n\
+         pub(crate) const CONTINUE_KEYS: [KeyCode; {}] = [{}];\n\
+         /// Time without a key press after which Caps Word turns off\n\
+         pub(crate) const IDLE_TIMEOUT: embassy_time::Duration = embassy_time::Duration::from_millis({idle_ms});\n",
+        keys.len(),
+        keys.join(", "),
+    );
+    fs::write(out_file, generated).unwrap();
+}
+
+/// Generate the sequences of the leader key from `[leader]` as a trie
+///
+/// Each `[[leader.sequence]]` lists letter or digit `keys` typed after
+/// `Leader` and one action: `text` typed on the host, non-ASCII characters
+/// with the Unicode input method of `[unicode]`, `key` tapped, or the
+/// BLE profile `ble_profile` switched to. `timeout` is the longest time
+/// between two keys of a sequence, `layer` the layer of `No` keys that holds
+/// them back from the host, the last one by default. `host-tests` runs it as
+/// well.
+pub(crate) fn generate_leader(keyboard_toml: &Table) {
+    let out_file = Path::new(&env::var_os("OUT_DIR").unwrap()).join("leader_generated.rs");
+    let config = keyboard_toml.get("leader");
+    let timeout = config
+        .and_then(|v| v.get("timeout"))
+        .map(|v| v.as_str().expect("timeout in [leader] is a duration"))
+        .unwrap_or("1s");
+    let timeout_ms = duration_ms(timeout, "[leader] timeout");
+    let layers = keyboard_toml
+        .get("layout")
+        .and_then(|v| v.get("layers"))
+        .and_then(|v| v.as_integer())
+        .expect("[layout] needs layers");
+    let layer = match config.and_then(|v| v.get("layer")) {
+        Some(layer) => {
+            let layer = layer.as_integer().expect("layer in [leader] is a number");
+            assert!(
+                (1..layers).contains(&layer),
+                "[leader] layer: layer {layer} does not exist or is the base layer"
+            );
+            layer
+        }
+        None => layers - 1,
+    };
+    let profiles = ble_profiles_num(keyboard_toml);
+    let sequences = config
+        .and_then(|v| v.get("sequence"))
+        .and_then(|v| v.as_array())
+        .map(|a| a.as_slice())
+        .unwrap_or(&[]);
+
+    // Children and action of each node, the root first
+    let mut children: Vec<Vec<(String, usize)>> = vec![Vec::new()];
+    let mut actions: Vec<Option<String>> = vec![None];
+    for (i, sequence) in sequences.iter().enumerate() {
+        let what = format!("[[leader.sequence]] {i}");
+        let keys: Vec<String> = sequence
+            .get("keys")
+            .and_then(|v| v.as_array())
+            .unwrap_or_else(|| panic!("{what} needs keys"))
+            .iter()
+            .map(|key| {
+                let key = key.as_str().unwrap_or_default().to_uppercase();
+                match key.as_bytes() {
+                    [b'A'..=b'Z'] => key,
+                    [b'0'..=b'9'] => format!("Kc{key}"),
+                    _ => panic!("{what}: keys are single letters or digits"),
+                }
+            })
+            .collect();
+        assert!(!keys.is_empty(), "{what} needs keys");
+
+        let text = sequence.get("text").and_then(|v| v.as_str());
+        let key = sequence.get("key").and_then(|v| v.as_str());
+        let profile = sequence.get("ble_profile").and_then(|v| v.as_integer());
+        let action = match (text, key, profile) {
+            (Some(text), None, None) => {
+                assert!(
+                    !text
+                        .chars()
+                        .any(|c| c.is_control() && !matches!(c, '\n' | '\t')),
+                    "{what}: text has control characters"
+                );
+                format!("LeaderAction::Text({text:?})")
+            }
+            (None, Some(key), None) => {
+                assert!(is_key_name(key), "{what}: invalid key {key:?}");
+                format!("LeaderAction::Tap(KeyCode::{key})")
+            }
+            (None, None, Some(profile)) => {
+                assert!(
+                    (0..profiles).contains(&profile),
+                    "{what}: BLE profile {profile} does not exist, [rmk] ble_profiles_num is {profiles}"
+                );
+                format!("LeaderAction::BleProfile({profile})")
+            }
+            _ => panic!("{what} needs exactly one of text, key and ble_profile"),
+        };
+
+        let mut node = 0;
+        for key in keys {
+            node = match children[node].iter().find(|(k, _)| *k == key) {
+                Some(&(_, next)) => next,
+                None => {
+                    children.push(Vec::new());
+                    actions.push(None);
+                    let next = children.len() - 1;
+                    children[node].push((key, next));
+                    next
+                }
+            };
+        }
+        assert!(
+            actions[node].replace(action).is_none(),
+            "{what}: the same keys are already a sequence"
+        );
+    }
+
+    let nodes: Vec<String> = children
+        .iter()
+        .zip(&actions)
+        .map(|(children, action)| {
+            let children: Vec<String> = children
+                .iter()
+                .map(|(key, next)| format!("(KeyCode::{key}, {next})"))
+                .collect();
+            format!(
+                "    LeaderNode {{\n\
+                 \x20       children: &[{}],\n\
+                 \x20       action: {},\n\
+                 \x20   }},\n",
+                children.join(", "),
+                action
+                    .as_deref()
+                    .map_or("None".to_string(), |a| format!("Some({a})")),
+            )
+        })
+        .collect();
+    let generated = format!(
+        "/// Longest time between two keys of a leader sequence\n\
+         pub(crate) const LEADER_TIMEOUT: embassy_time::Duration = embassy_time::Duration::from_millis({timeout_ms});\n\
+         \n\
+         /// Layer of `No` keys active while a sequence runs\n\
+         pub(crate) const LEADER_LAYER: u8 = {layer};\n\
+         \n\
+         /// Trie of `[[leader.sequence]]`, the root first\n\
+         pub(crate) const LEADER_NODES: [LeaderNode; {}] = [\n{}];\n",
+        nodes.len(),
+        nodes.concat(),
+    );
+    fs::write(out_file, generated).unwrap();
+}
+
+/// Rust expression of a Unicode input method of `[unicode]`
+fn unicode_mode_literal(mode: &str) -> &'static str {
+    match mode {
+        "macos" => "UnicodeMode::MacOs",
+        "linux" => "UnicodeMode::Linux",
+        "wincompose" => "UnicodeMode::WinCompose",
+        "alt_codes" => "UnicodeMode::AltCodes",
+        _ => panic!(
+            "Unknown Unicode input method {mode:?}, expected macos, linux, wincompose or alt_codes"
+        ),
+    }
+}
+
+/// Generate the Unicode input of the central from `[unicode]`
+///
+/// `mode` is the host's input method, `windows_mode` the one while the
+/// `windows_layer` overlay is on. Each `[[unicode.text]]` binds a free `User`
+/// keycode, by name or `@alias`, to a `text` typed with them.
+fn generate_unicode(keyboard_toml: &Table) {
+    let out_file = Path::new(&env::var_os("OUT_DIR").unwrap()).join("unicode_generated.rs");
+    let config = keyboard_toml.get("unicode");
+    let empty = Table::new();
+    let aliases = keyboard_toml
+        .get("aliases")
+        .and_then(|v| v.as_table())
+        .unwrap_or(&empty);
+    let setting = |key: &str, default: &'static str| -> &'static str {
+        config
+            .and_then(|v| v.get(key))
+            .map(|v| {
+                unicode_mode_literal(
+                    v.as_str()
+                        .unwrap_or_else(|| panic!("{key} in [unicode] is a string")),
+                )
+            })
+            .unwrap_or(default)
+    };
+    let mode = setting("mode", "UnicodeMode::Linux");
+    let windows_mode = setting("windows_mode", "UnicodeMode::WinCompose");
+    let windows_layer = match config.and_then(|v| v.get("windows_layer")) {
+        None => "None".to_string(),
+        Some(layer) => {
+            let layer = layer
+                .as_integer()
+                .expect("windows_layer in [unicode] is a layer number");
+            assert!(
+                layer > 0,
+                "windows_layer in [unicode] is an overlay, not layer 0"
+            );
+            format!("Some({layer})")
+        }
+    };
+
+    let texts = config
+        .and_then(|v| v.get("text"))
+        .and_then(|v| v.as_array())
+        .map(|a| a.as_slice())
+        .unwrap_or(&[]);
+    let mut keys: Vec<String> = Vec::new();
+    let mut literals = Vec::new();
+    for (i, entry) in texts.iter().enumerate() {
+        let what = format!("[[unicode.text]] {i}");
+        let key = entry
+            .get("key")
+            .and_then(|v| v.as_str())
+            .unwrap_or_else(|| panic!("{what} needs a key"));
+        let name = key.trim_start_matches('@');
+        let name = aliases.get(name).and_then(|v| v.as_str()).unwrap_or(name);
+        let user = name
+            .strip_prefix("User")
+            .and_then(|n| n.parse::<u8>().ok())
+            .unwrap_or_else(|| panic!("{what}: {key} is not a User keycode"));
+        assert!(
+            (12..32).contains(&user),
+            "{what}: RMK handles User0..User11 itself"
+        );
+        assert!(
+            !keys.iter().any(|k| k == name),
+            "{what}: {key} already has a text"
+        );
+        keys.push(name.to_string());
+        let text = entry
+            .get("text")
+            .and_then(|v| v.as_str())
+            .unwrap_or_else(|| panic!("{what} needs a text"));
+        assert!(
+            !text
+                .chars()
+                .any(|c| c.is_control() && !matches!(c, '\n' | '\t')),
+            "{what}: text has control characters"
+        );
+        literals.push(format!("    (KeyCode::{name}, {text:?}),\n"));
+    }
+    let generated = format!(
+        "/// Input method of the host, `mode` in `[unicode]`\n\
+         pub(crate) const DEFAULT_MODE: UnicodeMode = {mode};\n\
+         /// Input method while the Windows overlay is on\n\
+         pub(crate) const WINDOWS_MODE: UnicodeMode = {windows_mode};\n\
+         /// The Windows overlay layer\n\
+         pub(crate) const WINDOWS_LAYER: Option<u8> = {windows_layer};\n\
+         \n\
+         /// Keys typing a text, `[[unicode.text]]`\n\
+         pub(crate) const UNICODE_TEXTS: [(KeyCode, &str); {}] = [\n{}];\n",
+        literals.len(),
+        literals.concat(),
+    );
+    fs::write(out_file, generated).unwrap();
+}
+
+/// Vial's macro encoding of `step` of a `[[macro]]`, one byte literal each
+fn macro_step_bytes(step: &toml::Value, what: &str) -> Vec<String> {
+    let step = step
+        .as_table()
+        .filter(|t| t.len() == 1)
+        .unwrap_or_else(|| panic!("{what}: a step is one of text, tap, press, release or delay"));
+    let (kind, value) = step.iter().next().unwrap();
+    let value = value
+        .as_str()
+        .unwrap_or_else(|| panic!("{what}: {kind} is a string"));
+    let key = |code: u8| {
+        assert!(is_key_name(value), "{what}: invalid key {value:?}");
+        vec![
+            "1".to_string(),
+            code.to_string(),
+            format!("hid(KeyCode::{value})"),
+        ]
+    };
+    match kind.as_str() {
+        "text" => {
+            assert!(
+                value
+                    .chars()
+                    .all(|c| c.is_ascii_graphic() || matches!(c, ' ' | '\n' | '\t')),
+                "{what}: macro text is printable ASCII, [[unicode.text]] types other characters"
+            );
+            value.chars().map(|c| format!("b{c:?}")).collect()
+        }
+        "tap" => key(1),
+        "press" => key(2),
+        "release" => key(3),
+        "delay" => {
+            let ms = duration_ms(value, what);
+            assert!(ms < 255 * 254, "{what}: a delay is shorter than 64s");
+            vec![
+                "1".to_string(),
+                "4".to_string(),
+                (ms % 255 + 1).to_string(),
+                (ms / 255 + 1).to_string(),
+            ]
+        }
+        _ => panic!("{what}: unknown step {kind}, expected text, tap, press, release or delay"),
+    }
+}
+
+/// Generate the default keyboard macros from `[[macro]]`
+///
+/// Each macro is a list of `steps`: `text` typed, `tap`, `press` or
+/// `release` of a key, or a `delay` like `"50ms"`. They are encoded the way
+/// Vial and RMK's macro storage keep them, and have to fit into
+/// `macro_space_size` of `[rmk]`. `Macro(n)` in the `[[layer]]` keys and
+/// combo outputs sends the n-th.
+fn generate_keyboard_macros(keyboard_toml: &Table) {
+    let out_file = Path::new(&env::var_os("OUT_DIR").unwrap()).join("keyboard_macros_generated.rs");
+    let space = keyboard_toml
+        .get("rmk")
+        .and_then(|v| v.get("macro_space_size"))
+        .and_then(|v| v.as_integer())
+        .unwrap_or(256) as usize;
+    let macros = keyboard_toml
+        .get("macro")
+        .and_then(|v| v.as_array())
+        .map(|a| a.as_slice())
+        .unwrap_or(&[]);
+    assert!(
+        macros.len() <= 32,
+        "RMK has 32 macro keycodes, Macro0..Macro31"
+    );
+
+    let mut lines = Vec::new();
+    let mut len = 0;
+    for (i, entry) in macros.iter().enumerate() {
+        let what = format!("[[macro]] {i}");
+        let steps = entry
+            .get("steps")
+            .and_then(|v| v.as_array())
+            .unwrap_or_else(|| panic!("{what} needs steps"));
+        let mut bytes: Vec<String> = steps
+            .iter()
+            .flat_map(|step| macro_step_bytes(step, &what))
+            .collect();
+        bytes.push("0".to_string());
+        len += bytes.len();
+        lines.push(format!("    // Macro({i})\n    {},\n", bytes.join(", ")));
+    }
+    assert!(
+        len <= space,
+        "[[macro]] takes {len} bytes, [rmk] macro_space_size is {space}"
+    );
+
+    // `Macro(n)` in the keys of the layers
+    let layers = keyboard_toml
+        .get("layer")
+        .and_then(|v| v.as_array())
+        .map(|a| a.as_slice())
+        .unwrap_or(&[]);
+    for (layer, keys) in layers
+        .iter()
+        .enumerate()
+        .filter_map(|(i, l)| Some((i, l.get("keys")?.as_str()?)))
+    {
+        for key in keys.split_whitespace() {
+            if let ("Macro", args) = split_action(key) {
+                let n: usize = args
+                    .first()
+                    .and_then(|n| n.parse().ok())
+                    .unwrap_or_else(|| panic!("Invalid {key} on layer {layer}"));
+                assert!(
+                    n < macros.len(),
+                    "{key} on layer {layer}, but [[macro]] has {} macros",
+                    macros.len()
+                );
+            }
+        }
+    }
+
+    let generated = format!(
+        "/// Macros of `[[macro]]` in Vial's encoding, each ended by a 0\n\
+         pub(crate) const MACRO_SEQUENCES: [u8; {len}] = [\n{}];\n",
+        lines.concat(),
+    );
+    fs::write(out_file, generated).unwrap();
+}
+
+/// Generate the auto shift settings from `[auto_shift]`
+///
+/// `alphas`, `numbers` and `symbols` pick the key classes sent shifted when
+/// held for `timeout`. Without the table auto shift is off.
+fn generate_auto_shift(keyboard_toml: &Table) {
+    let out_file = Path::new(&env::var_os("OUT_DIR").unwrap()).join("auto_shift_generated.rs");
+    let config = keyboard_toml.get("auto_shift");
+    let class = |key: &str| -> bool {
+        config
+            .and_then(|v| v.get(key))
+            .map(|v| {
+                v.as_bool()
+                    .unwrap_or_else(|| panic!("{key} in [auto_shift] is true or false"))
+            })
+            .unwrap_or(false)
+    };
+    let timeout = config
+        .and_then(|v| v.get("timeout"))
+        .map(|v| v.as_str().expect("timeout in [auto_shift] is a duration"))
+        .unwrap_or("200ms");
+    let timeout_ms = duration_ms(timeout, "[auto_shift] timeout");
+    let generated = format!(
+        "/// Key classes sent shifted when held, `[auto_shift]`\n\
+         pub(crate) const SHIFT_ALPHAS: bool = {};\n\
+         pub(crate) const SHIFT_NUMBERS: bool = {};\n\
+         pub(crate) const SHIFT_SYMBOLS: bool = {};\n\
+         /// Hold time in ms after which a key is sent shifted\n\
+         pub(crate) const AUTO_SHIFT_TIMEOUT_MS: u16 = {timeout_ms};\n",
+        class("alphas"),
+        class("numbers"),
+        class("symbols"),
+    );
+    fs::write(out_file, generated).unwrap();
+}
+
+/// HID modifier bits of a list of modifiers like `["LShift", "Ctrl"]`
+///
+/// A modifier without side means both.
+fn modifier_bits(value: Option<&toml::Value>, what: &str) -> u8 {
+    let Some(value) = value else {
+        return 0;
+    };
+    value
+        .as_array()
+        .unwrap_or_else(|| panic!("{what} is a list of modifiers"))
+        .iter()
+        .map(|m| match m.as_str().unwrap_or_default() {
+            "LCtrl" => 0x01,
+            "LShift" => 0x02,
+            "LAlt" => 0x04,
+            "LGui" => 0x08,
+            "RCtrl" => 0x10,
+            "RShift" => 0x20,
+            "RAlt" => 0x40,
+            "RGui" => 0x80,
+            "Ctrl" => 0x11,
+            "Shift" => 0x22,
+            "Alt" => 0x44,
+            "Gui" => 0x88,
+            other => panic!("{what}: unknown modifier {other:?}"),
+        })
+        .fold(0, |bits, bit| bits | bit)
+}
+
+/// Generate the key overrides of the central from `[[key_override]]`
+///
+/// `trigger` is a key sent as `replacement` while any of the `modifiers` and
+/// none of the `negative_modifiers` are held, on every layer. RMK runs them as
+/// forks, at most `fork_max_num` of `[rmk]`.
+///
+/// `host-tests` runs it as well.
+pub(crate) fn generate_key_overrides(keyboard_toml: &Table) {
+    let out_file = Path::new(&env::var_os("OUT_DIR").unwrap()).join("key_overrides_generated.rs");
+    let empty = Table::new();
+    let aliases = keyboard_toml
+        .get("aliases")
+        .and_then(|v| v.as_table())
+        .unwrap_or(&empty);
+    let rules = keyboard_toml
+        .get("key_override")
+        .and_then(|v| v.as_array())
+        .map(|a| a.as_slice())
+        .unwrap_or(&[]);
+
+    let mut literals = Vec::new();
+    for (i, rule) in rules.iter().enumerate() {
+        let what = format!("[[key_override]] {i}");
+        let trigger = rule
+            .get("trigger")
+            .and_then(|v| v.as_str())
+            .unwrap_or_else(|| panic!("{what} needs a trigger"));
+        let trigger = aliases
+            .get(trigger.trim_start_matches('@'))
+            .and_then(|v| v.as_str())
+            .unwrap_or(trigger);
+        assert!(is_key_name(trigger), "{what}: trigger is a key name");
+        let replacement = rule
+            .get("replacement")
+            .and_then(|v| v.as_str())
+            .unwrap_or_else(|| panic!("{what} needs a replacement"));
+        let match_any = modifier_bits(rule.get("modifiers"), &format!("{what} modifiers"));
+        assert!(match_any != 0, "{what} needs modifiers");
+        let match_none = modifier_bits(
+            rule.get("negative_modifiers"),
+            &format!("{what} negative_modifiers"),
+        );
+        assert!(
+            match_any & match_none == 0,
+            "{what}: a modifier is in both modifiers and negative_modifiers"
+        );
+        assert!(
+            rule.get("layers").is_none(),
+            "{what}: RMK's forks know no layers, key overrides work on all of them"
+        );
+        literals.push(format!(
+            "    KeyOverride {{\n\
+             \x20       trigger: KeyCode::{trigger},\n\
+             \x20       replacement: {},\n\
+             \x20       match_any: {match_any:#04x},\n\
+             \x20       match_none: {match_none:#04x},\n\
+             \x20   }},\n",
+            action_literal(replacement, aliases),
+        ));
+    }
+    let generated = format!(
+        "/// Key overrides of `[[key_override]]`\n\
+         pub(crate) const KEY_OVERRIDES: [KeyOverride; {}] = [\n{}];\n",
+        literals.len(),
+        literals.concat(),
+    );
+    fs::write(out_file, generated).unwrap();
+}
+
+/// Generate the alternates of the `AltRepeat` key from `[repeat]`
+///
+/// Each pair of `alternates`, e.g. `["Left", "Right"]`, works both ways.
+///
+/// `host-tests` runs it as well.
+pub(crate) fn generate_repeat(keyboard_toml: &Table) {
+    let out_file = Path::new(&env::var_os("OUT_DIR").unwrap()).join("repeat_generated.rs");
+    let pairs = keyboard_toml
+        .get("repeat")
+        .and_then(|v| v.get("alternates"))
+        .map(|v| {
+            v.as_array()
+                .expect("alternates in [repeat] is a list of key pairs")
+                .as_slice()
+        })
+        .unwrap_or(&[]);
+    let mut alternates: Vec<(String, String)> = Vec::new();
+    for pair in pairs {
+        let keys: Vec<&str> = pair
+            .as_array()
+            .map(|p| p.iter().filter_map(|k| k.as_str()).collect())
+            .unwrap_or_default();
+        let [key, alternate] = keys[..] else {
+            panic!("alternates in [repeat] are pairs of keys like [\"Left\", \"Right\"]");
+        };
+        for (from, to) in [(key, alternate), (alternate, key)] {
+            assert!(
+                is_key_name(from),
+                "Invalid key {from:?} in alternates of [repeat]"
+            );
+            assert!(
+                !alternates.iter().any(|(k, _)| k == from),
+                "{from} has two alternates in [repeat]"
+            );
+            alternates.push((from.to_string(), to.to_string()));
+        }
+    }
+    let literals: Vec<String> = alternates
+        .iter()
+        .map(|(from, to)| format!("    (KeyCode::{from}, KeyCode::{to}),\n"))
+        .collect();
+    let generated = format!(
+        "/// Keys and what `AltRepeat` sends after them, `[repeat]`\n\
+         pub(crate) const ALTERNATES: [(KeyCode, KeyCode); {}] = [\n{}];\n",
+        literals.len(),
+        literals.concat(),
+    );
+    fs::write(out_file, generated).unwrap();
+}
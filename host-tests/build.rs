@@ -0,0 +1,138 @@
+//! The files the firmware's build script generates from the keyboard TOML
+//!
+//! The ones the tests check against the Corne's keymap come from the
+//! firmware's generators run on `keyboard_corne.toml`, the others are stood in
+//! for.
+
+use std::path::Path;
+use std::{env, fs};
+
+use toml::Table;
+
+// Only the generators are run here
+#[allow(dead_code)]
+#[path = "../build.rs"]
+mod firmware;
+
+/// `keyboard_corne.toml` and entries of each kind it doesn't use
+fn keyboard_toml() -> Table {
+    let mut keyboard_toml: Table = fs::read_to_string("../keyboard_corne.toml")
+        .unwrap()
+        .parse()
+        .unwrap();
+    let extra: Table = r#"
+        [[combo]]
+        keys = ["2,4", "2,5"]
+        output = "Escape"
+
+        [[combo]]
+        keys = ["Kc1", "Kc2"]
+        output = "F12"
+        layer = 3
+
+        [[combo]]
+        keys = ["Z", "X"]
+        output = "Delete"
+
+        # F12 is not on the base layer
+        [[combo]]
+        keys = ["F12", "Q"]
+        output = "Tab"
+
+        # Escape is on two keys of the base layer
+        [[combo]]
+        keys = ["Escape", "Q"]
+        output = "Tab"
+
+        # A sequence with an action that another one continues
+        [[leader.sequence]]
+        keys = ["E"]
+        key = "Escape"
+
+        [[leader.sequence]]
+        keys = ["E", "M"]
+        text = "me@example.com"
+    "#
+    .parse()
+    .unwrap();
+    extend(&mut keyboard_toml, extra);
+    // Room for the extra combos
+    keyboard_toml["rmk"]
+        .as_table_mut()
+        .unwrap()
+        .insert("combo_max_num".into(), 16.into());
+    keyboard_toml
+}
+
+/// Add the arrays of `extra` to the ones of `table`
+fn extend(table: &mut Table, extra: Table) {
+    for (key, value) in extra {
+        match (table.get_mut(&key).unwrap(), value) {
+            (toml::Value::Table(table), toml::Value::Table(extra)) => extend(table, extra),
+            (toml::Value::Array(array), toml::Value::Array(extra)) => array.extend(extra),
+            _ => panic!("{key} is neither a table nor an array"),
+        }
+    }
+}
+
+fn main() {
+    let out_dir = env::var_os("OUT_DIR").unwrap();
+    let out_dir = Path::new(&out_dir);
+    let keyboard_toml = keyboard_toml();
+    firmware::generate_combos(&keyboard_toml);
+    firmware::generate_behavior(&keyboard_toml);
+    firmware::generate_leader(&keyboard_toml);
+    firmware::generate_key_overrides(&keyboard_toml);
+    firmware::generate_repeat(&keyboard_toml);
+    fs::write(
+        out_dir.join("identity_generated.rs"),
+        "pub(crate) const CENTRAL_BLE_ADDR: Option<[u8; 6]> = None;\n\
+         pub(crate) const PERIPHERAL_BLE_ADDR: Option<[u8; 6]> = None;\n",
+    )
+    .unwrap();
+    // The layout of keyboard_corne.toml and memory.x
+    fs::write(
+        out_dir.join("storage_layout_generated.rs"),
+        "pub(crate) const RMK_STORAGE_START: u32 = 0xa0000;\n\
+         pub(crate) const RMK_STORAGE_SECTORS: u8 = 6;\n\
+         pub(crate) const APP_STORAGE_START: u32 = 0xa6000;\n\
+         pub(crate) const APP_STORAGE_SECTORS: u32 = 2;\n",
+    )
+    .unwrap();
+    fs::write(
+        out_dir.join("bonds_generated.rs"),
+        "pub(crate) const NUM_BLE_PROFILES: usize = 3;\n",
+    )
+    .unwrap();
+    fs::write(
+        out_dir.join("underglow_render_generated.rs"),
+        "pub(crate) const NUM_LEDS: usize = 10;\n\
+         pub(crate) const BRIGHTNESS: u8 = 128;\n\
+         pub(crate) const DEFAULT_EFFECT: Effect = Effect::Static;\n\
+         pub(crate) const COLOR: Rgb = Rgb::new(0xff, 0x80, 0x00);\n\
+         pub(crate) const BREATHING_PERIOD_MS: u32 = 2000;\n\
+         pub(crate) const LAYER_COLORS: [Rgb; 3] = [\n\
+         \x20   Rgb::new(0xff, 0x00, 0x00),\n\
+         \x20   Rgb::new(0x00, 0xff, 0x00),\n\
+         \x20   Rgb::new(0x00, 0x00, 0xff),\n\
+         ];\n",
+    )
+    .unwrap();
+    fs::write(
+        out_dir.join("auto_shift_generated.rs"),
+        "pub(crate) const SHIFT_ALPHAS: bool = false;\n\
+         pub(crate) const SHIFT_NUMBERS: bool = true;\n\
+         pub(crate) const SHIFT_SYMBOLS: bool = true;\n\
+         pub(crate) const AUTO_SHIFT_TIMEOUT_MS: u16 = 200;\n",
+    )
+    .unwrap();
+    fs::write(
+        out_dir.join("caps_word_generated.rs"),
+        "pub(crate) const CONTINUE_KEYS: [KeyCode; 3] = [KeyCode::Minus, KeyCode::Backspace, KeyCode::Delete];\n\
+         pub(crate) const IDLE_TIMEOUT: embassy_time::Duration = embassy_time::Duration::from_millis(5000);\n",
+    )
+    .unwrap();
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=../build.rs");
+    println!("cargo:rerun-if-changed=../keyboard_corne.toml");
+}
@@ -0,0 +1,19 @@
+//! Stand-in for the system reset of `cortex-m`
+//!
+//! The firmware reboots with `SCB::sys_reset`. Here it unwinds with
+//! [`peripheral::Reset`], which the tests catch as the reboot.
+
+pub mod peripheral {
+    /// Payload of the unwind of [`SCB::sys_reset`]
+    pub struct Reset;
+
+    /// System control block
+    pub struct SCB;
+
+    impl SCB {
+        /// Unwinds with [`Reset`], without the panic hook's message
+        pub fn sys_reset() -> ! {
+            std::panic::resume_unwind(Box::new(Reset))
+        }
+    }
+}
@@ -0,0 +1,121 @@
+//! Stand-in for the MPSL flash driver of `nrf-mpsl`
+//!
+//! The firmware writes the NVMC through [`Flash`], which needs the SoftDevice
+//! Controller. Here it is a 1 MiB flash in RAM with the NVMC's sizes and rules:
+//! erased bytes read 0xFF and writes only clear bits, so a missing erase shows
+//! up as corrupt data. [`Flash::new`] replaces `Flash::take`, and
+//! [`Flash::memory`] stands in for reading the flash through its memory map.
+
+use core::marker::PhantomData;
+
+use embedded_storage_async::nor_flash::{
+    ErrorType, MultiwriteNorFlash, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+
+/// Size of the nRF52840's flash
+const CAPACITY: usize = 0x10_0000;
+
+/// Size of an NVMC page
+const ERASE_SIZE: u32 = 4096;
+
+/// Error of a flash operation
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum FlashError {
+    Failed,
+    Aborted,
+}
+
+impl NorFlashError for FlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        NorFlashErrorKind::Other
+    }
+}
+
+/// The flash, in RAM
+pub struct Flash<'d> {
+    data: Vec<u8>,
+    /// Pages whose writes fail
+    worn_out: Vec<u32>,
+    _mpsl: PhantomData<&'d ()>,
+}
+
+impl Flash<'_> {
+    /// An erased flash
+    pub fn new() -> Self {
+        Self {
+            data: vec![0xFF; CAPACITY],
+            worn_out: Vec::new(),
+            _mpsl: PhantomData,
+        }
+    }
+
+    /// The flash's memory, as the CPU reads it at address 0
+    ///
+    /// Stays where it is when the flash is moved. Reading it while a write
+    /// or erase is running is up to the caller.
+    pub fn memory(&self) -> *const u8 {
+        self.data.as_ptr()
+    }
+
+    /// Make writes to the page at `address` fail, as when it is worn out
+    pub fn wear_out(&mut self, address: u32) {
+        self.worn_out.push(address / ERASE_SIZE);
+    }
+
+    fn check(&self, offset: u32, len: usize, align: usize) -> Result<usize, FlashError> {
+        let offset = offset as usize;
+        if offset % align != 0 || len % align != 0 || offset + len > CAPACITY {
+            return Err(FlashError::Failed);
+        }
+        Ok(offset)
+    }
+}
+
+impl Default for Flash<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ErrorType for Flash<'_> {
+    type Error = FlashError;
+}
+
+impl ReadNorFlash for Flash<'_> {
+    const READ_SIZE: usize = 1;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let offset = self.check(offset, bytes.len(), Self::READ_SIZE)?;
+        bytes.copy_from_slice(&self.data[offset..offset + bytes.len()]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        CAPACITY
+    }
+}
+
+impl NorFlash for Flash<'_> {
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = ERASE_SIZE as usize;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        let from = self.check(from, 0, Self::ERASE_SIZE)?;
+        let to = self.check(to, 0, Self::ERASE_SIZE)?;
+        self.data[from..to].fill(0xFF);
+        Ok(())
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let offset = self.check(offset, bytes.len(), Self::WRITE_SIZE)?;
+        if self.worn_out.contains(&(offset as u32 / ERASE_SIZE)) {
+            return Err(FlashError::Failed);
+        }
+        for (cell, byte) in self.data[offset..].iter_mut().zip(bytes) {
+            *cell &= byte;
+        }
+        Ok(())
+    }
+}
+
+impl MultiwriteNorFlash for Flash<'_> {}
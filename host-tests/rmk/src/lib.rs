@@ -0,0 +1,691 @@
+//! Stand-in for RMK's key action types and controller channel
+//!
+//! RMK only builds for the target, so the host tests compile the firmware's
+//! modules against these: the parts of `rmk::types`, the controller events
+//! and channels, the keyboard report channel, the fork configuration and the
+//! keymap macros they use, with the same names and
+//! shapes as in RMK 0.8. Key codes of the
+//! HID keyboard page have their HID value, the others values of their own.
+
+#![no_std]
+// The controller traits are only used from the single-threaded tests
+#![allow(async_fn_in_trait)]
+
+pub mod types {
+    pub mod keycode {
+        use defmt::Format;
+
+        /// A key code, see the module doc for the values
+        #[repr(u16)]
+        #[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+        pub enum KeyCode {
+            No = 0x00,
+            A = 0x04,
+            B,
+            C,
+            D,
+            E,
+            F,
+            G,
+            H,
+            I,
+            J,
+            K,
+            L,
+            M,
+            N,
+            O,
+            P,
+            Q,
+            R,
+            S,
+            T,
+            U,
+            V,
+            W,
+            X,
+            Y,
+            Z,
+            Kc1,
+            Kc2,
+            Kc3,
+            Kc4,
+            Kc5,
+            Kc6,
+            Kc7,
+            Kc8,
+            Kc9,
+            Kc0,
+            Enter,
+            Escape,
+            Backspace,
+            Tab,
+            Space,
+            Minus,
+            Equal,
+            LeftBracket,
+            RightBracket,
+            Backslash,
+            NonusHash,
+            Semicolon,
+            Quote,
+            Grave,
+            Comma,
+            Dot,
+            Slash,
+            CapsLock,
+            F1,
+            F2,
+            F3,
+            F4,
+            F5,
+            F6,
+            F7,
+            F8,
+            F9,
+            F10,
+            F11,
+            F12,
+            PrintScreen,
+            ScrollLock,
+            Pause,
+            Insert,
+            Home,
+            PageUp,
+            Delete,
+            End,
+            PageDown,
+            Right,
+            Left,
+            Down,
+            Up,
+            NumLock,
+            KpSlash,
+            KpAsterisk,
+            KpMinus,
+            KpPlus,
+            KpEnter,
+            Kp1,
+            Kp2,
+            Kp3,
+            Kp4,
+            Kp5,
+            Kp6,
+            Kp7,
+            Kp8,
+            Kp9,
+            Kp0,
+            KpDot,
+            MouseUp = 0xCD,
+            MouseDown,
+            MouseLeft,
+            MouseRight,
+            MouseBtn1,
+            MouseBtn2,
+            MouseBtn3,
+            MouseWheelUp = 0xD9,
+            MouseWheelDown,
+            LCtrl = 0xE0,
+            LShift,
+            LAlt,
+            LGui,
+            RCtrl,
+            RShift,
+            RAlt,
+            RGui,
+            Macro0 = 0x500,
+            Macro1,
+            Macro2,
+            Macro3,
+            User0 = 0x840,
+            User1,
+            User2,
+            User3,
+            User4,
+            User5,
+            User6,
+            User7,
+            User8,
+            User9,
+            User10,
+            User11,
+            User12,
+            User13,
+            User14,
+            User15,
+            User16,
+            User17,
+            User18,
+            User19,
+            User20,
+            User21,
+            User22,
+            User23,
+            User24,
+            User25,
+            User26,
+            User27,
+            User28,
+            User29,
+            User30,
+            User31,
+            Bootloader = 0x7C00,
+        }
+    }
+
+    pub mod modifier {
+        use defmt::Format;
+
+        /// Modifiers of one side
+        #[derive(Clone, Copy, Default, PartialEq, Eq, Debug, Format)]
+        pub struct ModifierCombination {
+            right: bool,
+            gui: bool,
+            alt: bool,
+            shift: bool,
+            ctrl: bool,
+        }
+
+        impl ModifierCombination {
+            pub const LCTRL: Self = Self::new_from(false, false, false, false, true);
+            pub const LSHIFT: Self = Self::new_from(false, false, false, true, false);
+            pub const LALT: Self = Self::new_from(false, false, true, false, false);
+            pub const LGUI: Self = Self::new_from(false, true, false, false, false);
+            pub const RCTRL: Self = Self::new_from(true, false, false, false, true);
+            pub const RSHIFT: Self = Self::new_from(true, false, false, true, false);
+            pub const RALT: Self = Self::new_from(true, false, true, false, false);
+            pub const RGUI: Self = Self::new_from(true, true, false, false, false);
+
+            pub const fn new_from(
+                right: bool,
+                gui: bool,
+                alt: bool,
+                shift: bool,
+                ctrl: bool,
+            ) -> Self {
+                Self {
+                    right,
+                    gui,
+                    alt,
+                    shift,
+                    ctrl,
+                }
+            }
+
+            pub const fn right(self) -> bool {
+                self.right
+            }
+
+            pub const fn gui(self) -> bool {
+                self.gui
+            }
+
+            pub const fn alt(self) -> bool {
+                self.alt
+            }
+
+            pub const fn shift(self) -> bool {
+                self.shift
+            }
+
+            pub const fn ctrl(self) -> bool {
+                self.ctrl
+            }
+        }
+    }
+
+    pub mod action {
+        use defmt::Format;
+
+        use super::keycode::KeyCode;
+        use super::modifier::ModifierCombination;
+
+        /// How a tap-hold decides on other keys pressed while it is down
+        #[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+        pub enum MorseMode {
+            /// Only the hold timeout decides
+            Normal,
+            /// Another key pressed makes it a hold
+            HoldOnOtherPress,
+            /// Another key tapped makes it a hold
+            PermissiveHold,
+        }
+
+        /// Settings of a tap-hold, `None` for the default of the behavior config
+        #[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+        pub struct MorseProfile {
+            unilateral_tap: Option<bool>,
+            mode: Option<MorseMode>,
+            hold_timeout_ms: Option<u16>,
+            gap_timeout_ms: Option<u16>,
+        }
+
+        impl MorseProfile {
+            pub const fn new(
+                unilateral_tap: Option<bool>,
+                mode: Option<MorseMode>,
+                hold_timeout_ms: Option<u16>,
+                gap_timeout_ms: Option<u16>,
+            ) -> Self {
+                Self {
+                    unilateral_tap,
+                    mode,
+                    hold_timeout_ms,
+                    gap_timeout_ms,
+                }
+            }
+
+            pub const fn const_default() -> Self {
+                Self::new(None, None, None, None)
+            }
+
+            pub const fn unilateral_tap(self) -> Option<bool> {
+                self.unilateral_tap
+            }
+
+            pub const fn mode(self) -> Option<MorseMode> {
+                self.mode
+            }
+
+            pub const fn hold_timeout_ms(self) -> Option<u16> {
+                self.hold_timeout_ms
+            }
+
+            pub const fn gap_timeout_ms(self) -> Option<u16> {
+                self.gap_timeout_ms
+            }
+        }
+
+        /// What a key does
+        #[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+        pub enum Action {
+            No,
+            Transparent,
+            Key(KeyCode),
+            KeyWithModifier(KeyCode, ModifierCombination),
+            Modifier(ModifierCombination),
+            /// Hold the layer while the key is down
+            LayerOn(u8),
+            LayerOff(u8),
+            LayerToggle(u8),
+            /// Turn all layers but the default and this one off
+            LayerToggleOnly(u8),
+            DefaultLayer(u8),
+        }
+
+        /// The action of a key of the keymap
+        #[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+        pub enum KeyAction {
+            No,
+            Transparent,
+            Single(Action),
+            Tap(Action),
+            TapHold(Action, Action, MorseProfile),
+        }
+
+        /// The actions of a rotary encoder
+        #[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+        pub struct EncoderAction {
+            pub clockwise: KeyAction,
+            pub counter_clockwise: KeyAction,
+        }
+    }
+}
+
+pub mod ble {
+    use defmt::Format;
+
+    /// State of the connection of a BLE profile
+    #[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+    pub enum BleState {
+        None,
+        Advertising,
+        Connected,
+    }
+
+    pub mod profile {
+        use defmt::Format;
+
+        /// Request to the BLE stack's profile manager
+        #[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+        pub enum BleProfileAction {
+            SwitchProfile(u8),
+            PreviousProfile,
+            NextProfile,
+            ClearProfile,
+            ToggleConnection,
+        }
+    }
+}
+
+pub mod event {
+    use defmt::Format;
+
+    use crate::ble::BleState;
+    use crate::types::action::KeyAction;
+    use crate::types::modifier::ModifierCombination;
+
+    /// Matrix position of a key
+    #[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+    pub struct KeyPos {
+        pub row: u8,
+        pub col: u8,
+    }
+
+    /// Where a keyboard event comes from
+    #[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+    pub enum KeyboardEventPos {
+        Key(KeyPos),
+        RotaryEncoder(u8),
+    }
+
+    /// A key pressed or released
+    #[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+    pub struct KeyboardEvent {
+        pub pressed: bool,
+        pub pos: KeyboardEventPos,
+    }
+
+    impl KeyboardEvent {
+        pub const fn key(row: u8, col: u8, pressed: bool) -> Self {
+            Self {
+                pressed,
+                pos: KeyboardEventPos::Key(KeyPos { row, col }),
+            }
+        }
+    }
+
+    /// Event published to the controllers
+    #[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+    pub enum ControllerEvent {
+        Key(KeyboardEvent, KeyAction),
+        Battery(u8),
+        ChargingState(bool),
+        Layer(u8),
+        Modifier(ModifierCombination),
+        Wpm(u16),
+        BleState(u8, BleState),
+        BleProfile(u8),
+        SplitCentral(bool),
+        SplitPeripheral(usize, bool),
+    }
+}
+
+pub mod storage {
+    use defmt::Format;
+
+    /// Request to RMK's storage task
+    #[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+    pub enum FlashOperationMessage {
+        /// Erase the storage on the next boot
+        Reset,
+        /// Forget the bond of a BLE profile
+        ClearSlot(u8),
+    }
+}
+
+pub mod hid {
+    use usbd_hid::descriptor::KeyboardReport;
+
+    /// A report to the host
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    pub enum Report {
+        KeyboardReport(KeyboardReport),
+    }
+}
+
+pub mod channel {
+    use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+    use embassy_sync::channel::Channel;
+    use embassy_sync::pubsub::{PubSubChannel, Publisher, Subscriber};
+
+    use crate::ble::profile::BleProfileAction;
+    use crate::event::ControllerEvent;
+    use crate::hid::Report;
+    use crate::storage::FlashOperationMessage;
+
+    const REPORT_CHANNEL_SIZE: usize = 16;
+
+    const CONTROLLER_CHANNEL_SIZE: usize = 16;
+    const CONTROLLER_CHANNEL_SUBS: usize = 20;
+    const CONTROLLER_CHANNEL_PUBS: usize = 4;
+
+    pub type ControllerSub = Subscriber<
+        'static,
+        CriticalSectionRawMutex,
+        ControllerEvent,
+        CONTROLLER_CHANNEL_SIZE,
+        CONTROLLER_CHANNEL_SUBS,
+        CONTROLLER_CHANNEL_PUBS,
+    >;
+
+    pub type ControllerPub = Publisher<
+        'static,
+        CriticalSectionRawMutex,
+        ControllerEvent,
+        CONTROLLER_CHANNEL_SIZE,
+        CONTROLLER_CHANNEL_SUBS,
+        CONTROLLER_CHANNEL_PUBS,
+    >;
+
+    /// Events of the keyboard for the controllers
+    pub static CONTROLLER_CHANNEL: PubSubChannel<
+        CriticalSectionRawMutex,
+        ControllerEvent,
+        CONTROLLER_CHANNEL_SIZE,
+        CONTROLLER_CHANNEL_SUBS,
+        CONTROLLER_CHANNEL_PUBS,
+    > = PubSubChannel::new();
+
+    /// Requests to the BLE profile manager
+    pub static BLE_PROFILE_CHANNEL: Channel<CriticalSectionRawMutex, BleProfileAction, 1> =
+        Channel::new();
+
+    /// Reports to the host
+    pub static KEYBOARD_REPORT_CHANNEL: Channel<
+        CriticalSectionRawMutex,
+        Report,
+        REPORT_CHANNEL_SIZE,
+    > = Channel::new();
+
+    /// Requests to the storage task
+    pub static FLASH_CHANNEL: Channel<CriticalSectionRawMutex, FlashOperationMessage, 4> =
+        Channel::new();
+}
+
+pub mod hid_state {
+    /// HID modifier bits, left ones in the low nibble
+    #[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+    pub struct HidModifiers(u8);
+
+    impl HidModifiers {
+        pub const fn new() -> Self {
+            Self(0)
+        }
+
+        pub const fn from_bits(bits: u8) -> Self {
+            Self(bits)
+        }
+
+        pub const fn into_bits(self) -> u8 {
+            self.0
+        }
+    }
+
+    /// HID mouse button bits
+    #[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+    pub struct HidMouseButtons(u8);
+}
+
+pub mod light {
+    /// The LEDs the host reports
+    #[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+    pub struct LedIndicator(u8);
+}
+
+pub mod fork {
+    use crate::hid_state::{HidModifiers, HidMouseButtons};
+    use crate::light::LedIndicator;
+    use crate::types::action::KeyAction;
+
+    /// State of the keyboard a fork matches
+    #[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+    pub struct StateBits {
+        pub modifiers: HidModifiers,
+        pub leds: LedIndicator,
+        pub mouse: HidMouseButtons,
+    }
+
+    impl StateBits {
+        pub const fn new_from(
+            modifiers: HidModifiers,
+            leds: LedIndicator,
+            mouse: HidMouseButtons,
+        ) -> Self {
+            Self {
+                modifiers,
+                leds,
+                mouse,
+            }
+        }
+    }
+
+    /// A key sending `positive_output` instead of `negative_output` while
+    /// any of `match_any` and none of `match_none` are active
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    pub struct Fork {
+        pub trigger: KeyAction,
+        pub negative_output: KeyAction,
+        pub positive_output: KeyAction,
+        pub match_any: StateBits,
+        pub match_none: StateBits,
+        pub kept_modifiers: HidModifiers,
+        pub bindable: bool,
+    }
+
+    impl Fork {
+        pub fn new(
+            trigger: KeyAction,
+            negative_output: KeyAction,
+            positive_output: KeyAction,
+            match_any: StateBits,
+            match_none: StateBits,
+            kept_modifiers: HidModifiers,
+            bindable: bool,
+        ) -> Self {
+            Self {
+                trigger,
+                negative_output,
+                positive_output,
+                match_any,
+                match_none,
+                kept_modifiers,
+                bindable,
+            }
+        }
+    }
+}
+
+pub mod config {
+    use heapless::Vec;
+
+    use crate::fork::Fork;
+
+    /// `fork_max_num` of `[rmk]`
+    pub const FORK_MAX_NUM: usize = 8;
+
+    #[derive(Default)]
+    pub struct ForksConfig {
+        pub forks: Vec<Fork, FORK_MAX_NUM>,
+    }
+}
+
+pub mod controller {
+    use embassy_time::{Duration, Timer};
+
+    /// A task reacting to the events of the keyboard
+    pub trait Controller {
+        type Event;
+
+        async fn process_event(&mut self, event: Self::Event);
+
+        async fn next_message(&mut self) -> Self::Event;
+    }
+
+    /// A controller that also updates at a fixed interval
+    pub trait PollingController: Controller {
+        const INTERVAL: Duration;
+
+        async fn update(&mut self);
+
+        async fn polling_loop(&mut self) -> ! {
+            loop {
+                match embassy_futures::select::select(
+                    Timer::after(Self::INTERVAL),
+                    self.next_message(),
+                )
+                .await
+                {
+                    embassy_futures::select::Either::First(()) => self.update().await,
+                    embassy_futures::select::Either::Second(event) => {
+                        self.process_event(event).await
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A key tapping a key code
+#[macro_export]
+macro_rules! k {
+    ($x: ident) => {
+        $crate::types::action::KeyAction::Single($crate::types::action::Action::Key(
+            $crate::types::keycode::KeyCode::$x,
+        ))
+    };
+}
+
+/// A key action without an action, `a!(No)` or `a!(Transparent)`
+#[macro_export]
+macro_rules! a {
+    ($x: ident) => {
+        $crate::types::action::KeyAction::$x
+    };
+}
+
+/// Hold a layer
+#[macro_export]
+macro_rules! mo {
+    ($x: literal) => {
+        $crate::types::action::KeyAction::Single($crate::types::action::Action::LayerOn($x))
+    };
+}
+
+/// Toggle a layer
+#[macro_export]
+macro_rules! tg {
+    ($x: literal) => {
+        $crate::types::action::KeyAction::Single($crate::types::action::Action::LayerToggle($x))
+    };
+}
+
+/// Switch to a layer only
+#[macro_export]
+macro_rules! to {
+    ($x: literal) => {
+        $crate::types::action::KeyAction::Single($crate::types::action::Action::LayerToggleOnly($x))
+    };
+}
+
+/// Tap a key, hold a layer, with a morse profile
+#[macro_export]
+macro_rules! ltp {
+    ($x: literal, $k: ident, $p: expr) => {
+        $crate::types::action::KeyAction::TapHold(
+            $crate::types::action::Action::Key($crate::types::keycode::KeyCode::$k),
+            $crate::types::action::Action::LayerOn($x),
+            $p,
+        )
+    };
+}
@@ -0,0 +1,28 @@
+//! Stand-in for the firmware's `flash_map`
+//!
+//! There is no memory-mapped flash on the host. A test maps the stand-in
+//! [`Flash`] it hands to the firmware, and reads go to its memory.
+
+use std::cell::Cell;
+
+use nrf_mpsl::Flash;
+
+use crate::storage_layout::FLASH_SIZE;
+
+thread_local! {
+    static MAPPED: Cell<*const u8> = const { Cell::new(std::ptr::null()) };
+}
+
+/// Read `flash` through [`mapped`] on this thread
+pub(crate) fn map(flash: &Flash) {
+    MAPPED.set(flash.memory());
+}
+
+/// `len` bytes of the mapped flash from `address`
+pub(crate) fn mapped(address: u32, len: usize) -> &'static [u8] {
+    let memory = MAPPED.get();
+    assert!(!memory.is_null(), "no flash is mapped");
+    assert!(address as usize + len <= FLASH_SIZE as usize);
+    // SAFETY: in the flash's memory, which the tests leak
+    unsafe { std::slice::from_raw_parts(memory.add(address as usize), len) }
+}
@@ -0,0 +1,70 @@
+//! Host tests of the firmware
+//!
+//! The firmware only builds for the nRF52840. The modules below don't touch
+//! the hardware or RMK's tasks, so they are compiled here from `../src` as
+//! they are and tested on the host with `cargo test`. The files the
+//! firmware's build script generates are stood in for by `build.rs`, RMK's
+//! key action types and channels by the `rmk` crate next to it, the keyboard
+//! report by the `usbd-hid` one, the MPSL flash driver by the `nrf-mpsl` one,
+//! the system reset by the `cortex-m` one and the memory-mapped flash by
+//! [`flash_map`].
+
+// Each binary of the firmware uses a part of them
+#![allow(dead_code)]
+
+#[path = "../../src/app_storage.rs"]
+mod app_storage;
+#[path = "../../src/auto_shift.rs"]
+mod auto_shift;
+#[path = "../../src/backup.rs"]
+mod backup;
+#[path = "../../src/behavior.rs"]
+mod behavior;
+#[path = "../../src/blink.rs"]
+mod blink;
+#[path = "../../src/bonds.rs"]
+mod bonds;
+#[path = "../../src/caps_word_keys.rs"]
+mod caps_word_keys;
+#[path = "../../src/combo_keys.rs"]
+mod combo_keys;
+#[path = "../../src/custom_hid_desc.rs"]
+mod custom_hid_desc;
+#[path = "../../src/display_render.rs"]
+mod display_render;
+mod flash_map;
+#[path = "../../src/flash_wear.rs"]
+mod flash_wear;
+#[path = "../../src/host_keys.rs"]
+mod host_keys;
+#[path = "../../src/identity.rs"]
+mod identity;
+#[path = "../../src/key_overrides.rs"]
+mod key_overrides;
+#[path = "../../src/keymap.rs"]
+mod keymap;
+#[path = "../../src/leader_keys.rs"]
+mod leader_keys;
+#[path = "../../src/record_migration.rs"]
+mod record_migration;
+#[path = "../../src/repeat.rs"]
+mod repeat;
+#[path = "../../src/schema.rs"]
+mod schema;
+#[path = "../../src/split_frame.rs"]
+mod split_frame;
+#[path = "../../src/split_status.rs"]
+mod split_status;
+#[path = "../../src/storage_layout.rs"]
+mod storage_layout;
+#[path = "../../src/typing_stats.rs"]
+mod typing_stats;
+#[path = "../../src/underglow_render.rs"]
+mod underglow_render;
+#[path = "../../src/user_keys.rs"]
+mod user_keys;
+#[path = "../../src/vial_custom.rs"]
+mod vial_custom;
+
+#[cfg(test)]
+mod tests;
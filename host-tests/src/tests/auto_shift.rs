@@ -0,0 +1,119 @@
+use rmk::k;
+use rmk::types::action::{Action, KeyAction, MorseMode};
+use rmk::types::keycode::KeyCode;
+use rmk::types::modifier::ModifierCombination;
+
+use super::sim::Keyboard;
+use crate::auto_shift;
+use crate::keymap::get_default_keymap;
+
+// Positions on the central's matrix
+const A: (usize, usize) = (1, 1);
+const X: (usize, usize) = (2, 2);
+const DOT: (usize, usize) = (6, 3);
+/// `MO(3)`, the digits of the mix layer
+const MIX: (usize, usize) = (7, 4);
+/// `1` on the mix layer
+const KC1: (usize, usize) = (1, 5);
+
+fn key(code: KeyCode) -> Action {
+    Action::Key(code)
+}
+
+fn shifted(code: KeyCode) -> Action {
+    Action::KeyWithModifier(code, ModifierCombination::LSHIFT)
+}
+
+/// `(row, col)` pressed at `down` and released at `up`
+fn stroke((row, col): (usize, usize), down: u64, up: u64) -> (usize, usize, u64, u64) {
+    (row, col, down, up)
+}
+
+#[test]
+fn numbers_and_symbols_become_tap_holds() {
+    let plain = get_default_keymap();
+    let mut keymap = plain;
+    auto_shift::apply(&mut keymap);
+    let KeyAction::TapHold(tap, hold, profile) = keymap[0][DOT.0][DOT.1] else {
+        panic!("Dot is not a tap-hold");
+    };
+    assert_eq!((tap, hold), (key(KeyCode::Dot), shifted(KeyCode::Dot)));
+    // Only the timeout decides
+    assert_eq!(profile.hold_timeout_ms(), Some(200));
+    assert_eq!(profile.mode(), Some(MorseMode::Normal));
+    assert!(matches!(
+        keymap[3][KC1.0][KC1.1],
+        KeyAction::TapHold(Action::Key(KeyCode::Kc1), _, _)
+    ));
+
+    // Letters aren't shifted by default
+    assert_eq!(keymap[0][X.0][X.1], k!(X));
+    // Tap-holds and shifted keys keep what they do
+    assert_eq!(keymap[0][A.0][A.1], plain[0][A.0][A.1]);
+    assert_eq!(keymap[0][7][5], plain[0][7][5]);
+    assert_eq!(keymap[2][1][4], plain[2][1][4]);
+}
+
+#[test]
+fn held_past_the_timeout_is_shifted() {
+    let keyboard = Keyboard::new();
+    assert_eq!(
+        keyboard.type_keys(&[stroke(DOT, 0, 100)]),
+        [key(KeyCode::Dot)]
+    );
+    assert_eq!(
+        keyboard.type_keys(&[stroke(DOT, 0, 199)]),
+        [key(KeyCode::Dot)]
+    );
+    assert_eq!(
+        keyboard.type_keys(&[stroke(DOT, 0, 200)]),
+        [shifted(KeyCode::Dot)]
+    );
+}
+
+#[test]
+fn other_keys_dont_shift() {
+    let keyboard = Keyboard::new();
+    // X pressed and released while Dot is down
+    assert_eq!(
+        keyboard.type_keys(&[stroke(DOT, 0, 150), stroke(X, 50, 100)]),
+        [key(KeyCode::Dot), key(KeyCode::X)]
+    );
+    // The same timer holds a home row mod, which is a permissive hold
+    assert_eq!(
+        keyboard.type_keys(&[stroke(A, 0, 150), stroke(X, 50, 100)]),
+        [
+            Action::Modifier(ModifierCombination::LCTRL),
+            key(KeyCode::X)
+        ]
+    );
+}
+
+#[test]
+fn flow_tap_types_fast_keys_unshifted() {
+    let keyboard = Keyboard::new();
+    // Pressed 20ms after X, Dot taps however long it is held
+    assert_eq!(
+        keyboard.type_keys(&[stroke(X, 0, 10), stroke(DOT, 20, 300)]),
+        [key(KeyCode::X), key(KeyCode::Dot)]
+    );
+    assert_eq!(
+        keyboard.type_keys(&[stroke(X, 0, 10), stroke(DOT, 40, 300)]),
+        [key(KeyCode::X), shifted(KeyCode::Dot)]
+    );
+}
+
+#[test]
+fn digits_of_the_mix_layer_are_shifted() {
+    let mut keyboard = Keyboard::new();
+    keyboard.press(&[(MIX.0, MIX.1, 0)]);
+    assert_eq!(
+        keyboard.type_keys(&[stroke(KC1, 100, 150)]),
+        [key(KeyCode::Kc1)]
+    );
+    // `!`
+    assert_eq!(
+        keyboard.type_keys(&[stroke(KC1, 100, 350)]),
+        [shifted(KeyCode::Kc1)]
+    );
+}
@@ -0,0 +1,266 @@
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::sync::Mutex as StdMutex;
+use std::task::Poll;
+
+use embassy_futures::{block_on, poll_once};
+use embassy_sync::mutex::Mutex;
+use embedded_storage_async::nor_flash::NorFlash;
+use nrf_mpsl::Flash;
+use rmk::controller::Controller;
+
+use super::storage_backup::protocol::*;
+use super::storage_backup::transfer::{Device, backup, read_header, restore};
+use crate::app_storage::SharedFlash;
+use crate::backup::{BackupController, IMAGE_LEN, handle_custom_command};
+use crate::flash_map::{map, mapped};
+use crate::flash_wear::WearCountingFlash;
+use crate::storage_layout::{RMK_STORAGE_START, SECTOR_SIZE};
+use crate::vial_custom::{CustomChannel, VIA_REPORT_LEN, handle_custom_report};
+
+/// The restore state and request queue are global, one keyboard at a time
+static ONE_KEYBOARD: StdMutex<()> = StdMutex::new(());
+
+/// The central: its custom-channel reports go to the backup channel and the
+/// [`BackupController`] runs the queued requests before the next report
+struct Keyboard {
+    flash: &'static SharedFlash,
+    controller: BackupController,
+    rebooted: bool,
+}
+
+impl Keyboard {
+    /// A keyboard whose storage holds `image`
+    fn new(image: &[u8]) -> Self {
+        Self::with_flash(image, Flash::new())
+    }
+
+    fn with_flash(image: &[u8], memory: Flash<'static>) -> Self {
+        map(&memory);
+        let flash: &'static SharedFlash =
+            Box::leak(Box::new(Mutex::new(WearCountingFlash::new(memory))));
+        block_on(async {
+            let mut flash = flash.lock().await;
+            flash.write(RMK_STORAGE_START, image).await.unwrap();
+        });
+        Self {
+            flash,
+            controller: BackupController::new(flash),
+            rebooted: false,
+        }
+    }
+
+    /// The storage, e.g. after a restore
+    fn image(&self) -> Vec<u8> {
+        mapped(RMK_STORAGE_START, IMAGE_LEN as usize).to_vec()
+    }
+
+    /// Whether RMK's storage and the firmware's records can use the flash
+    fn flash_free(&self) -> bool {
+        self.flash.try_lock().is_ok()
+    }
+
+    fn run_controller(&mut self) {
+        while let Poll::Ready(request) = poll_once(self.controller.next_message()) {
+            let processed = catch_unwind(AssertUnwindSafe(|| {
+                block_on(self.controller.process_event(request))
+            }));
+            match processed {
+                Ok(()) => {}
+                Err(reset) if reset.is::<cortex_m::peripheral::Reset>() => self.rebooted = true,
+                Err(panic) => std::panic::resume_unwind(panic),
+            }
+        }
+    }
+}
+
+impl Device for Keyboard {
+    fn exchange(&mut self, report: &mut [u8; REPORT_LEN]) -> Result<(), String> {
+        let report: &mut [u8; VIA_REPORT_LEN] = report;
+        handle_custom_report(report, |channel, command, value_id, data| {
+            channel == CustomChannel::Backup && handle_custom_command(command, value_id, data)
+        });
+        self.run_controller();
+        Ok(())
+    }
+}
+
+/// A storage image with something different in every chunk
+fn image(seed: u8) -> Vec<u8> {
+    (0..IMAGE_LEN as usize)
+        .map(|i| (i / 7) as u8 ^ seed)
+        .collect()
+}
+
+/// Send a command on the backup channel, returns whether it was handled
+fn send(keyboard: &mut Keyboard, command: u8, value_id: u8, data: &[u8]) -> bool {
+    let mut report = [0; REPORT_LEN];
+    report[0] = command;
+    report[1] = BACKUP_CHANNEL;
+    report[2] = value_id;
+    report[DATA_OFFSET..DATA_OFFSET + data.len()].copy_from_slice(data);
+    keyboard.exchange(&mut report).unwrap();
+    report[0] != UNHANDLED
+}
+
+fn restore_state(keyboard: &mut Keyboard) -> Option<RestoreState> {
+    let mut report = [0; REPORT_LEN];
+    report[0] = CUSTOM_GET_VALUE;
+    report[1] = BACKUP_CHANNEL;
+    report[2] = VALUE_RESTORE_STATUS;
+    keyboard.exchange(&mut report).unwrap();
+    RestoreState::from_u8(report[DATA_OFFSET])
+}
+
+#[test]
+fn backup_is_the_header_and_the_image() {
+    let _one = ONE_KEYBOARD.lock().unwrap_or_else(|e| e.into_inner());
+    let mut keyboard = Keyboard::new(&image(1));
+    let file = backup(&mut keyboard).unwrap();
+    let header = Header::parse(&file).unwrap();
+    assert_eq!(header, read_header(&mut keyboard).unwrap());
+    assert_eq!(header.start, RMK_STORAGE_START);
+    assert_eq!(header.len, IMAGE_LEN);
+    assert_eq!(&file[Header::LEN..], &image(1)[..]);
+    assert_eq!(header.crc, crc32(&image(1)));
+}
+
+#[test]
+fn the_header_follows_changes_of_the_storage() {
+    let _one = ONE_KEYBOARD.lock().unwrap_or_else(|e| e.into_inner());
+    let mut keyboard = Keyboard::new(&image(1));
+    assert_eq!(read_header(&mut keyboard).unwrap().crc, crc32(&image(1)));
+    // RMK's storage task writes a record
+    block_on(async {
+        let mut flash = keyboard.flash.lock().await;
+        flash.write(RMK_STORAGE_START, &[0; 4]).await.unwrap();
+    });
+    assert_eq!(
+        read_header(&mut keyboard).unwrap().crc,
+        crc32(&keyboard.image())
+    );
+    assert_ne!(keyboard.image(), image(1));
+}
+
+#[test]
+fn backup_restores_on_another_keyboard() {
+    let _one = ONE_KEYBOARD.lock().unwrap_or_else(|e| e.into_inner());
+    let file = backup(&mut Keyboard::new(&image(1))).unwrap();
+    let mut replacement = Keyboard::new(&[]);
+    restore(&mut replacement, &file).unwrap();
+    assert!(replacement.rebooted);
+    assert_eq!(replacement.image(), image(1));
+    // And backs up the same again
+    assert_eq!(backup(&mut replacement).unwrap(), file);
+}
+
+#[test]
+fn restore_replaces_the_whole_storage() {
+    let _one = ONE_KEYBOARD.lock().unwrap_or_else(|e| e.into_inner());
+    let file = backup(&mut Keyboard::new(&image(1))).unwrap();
+    let mut keyboard = Keyboard::new(&image(2));
+    restore(&mut keyboard, &file).unwrap();
+    assert!(keyboard.rebooted);
+    assert_eq!(keyboard.image(), image(1));
+}
+
+#[test]
+fn damaged_backup_is_refused() {
+    let _one = ONE_KEYBOARD.lock().unwrap_or_else(|e| e.into_inner());
+    let mut file = backup(&mut Keyboard::new(&image(1))).unwrap();
+    file[Header::LEN + 100] ^= 0x01;
+    let mut keyboard = Keyboard::new(&image(2));
+    assert!(restore(&mut keyboard, &file).is_err());
+    assert_eq!(keyboard.image(), image(2));
+
+    let truncated = backup(&mut Keyboard::new(&image(1))).unwrap();
+    let mut keyboard = Keyboard::new(&image(2));
+    assert!(restore(&mut keyboard, &truncated[..truncated.len() - 1]).is_err());
+    assert_eq!(keyboard.image(), image(2));
+}
+
+#[test]
+fn other_layout_is_refused() {
+    let _one = ONE_KEYBOARD.lock().unwrap_or_else(|e| e.into_inner());
+    let file = backup(&mut Keyboard::new(&image(1))).unwrap();
+    let mut header = Header::parse(&file).unwrap();
+    header.rmk_sectors += 1;
+    let mut other = header.to_bytes().to_vec();
+    other.extend_from_slice(&file[Header::LEN..]);
+    let mut keyboard = Keyboard::new(&image(2));
+    assert!(restore(&mut keyboard, &other).is_err());
+    assert_eq!(keyboard.image(), image(2));
+}
+
+#[test]
+fn newer_schema_is_refused_by_the_keyboard() {
+    let _one = ONE_KEYBOARD.lock().unwrap_or_else(|e| e.into_inner());
+    let file = backup(&mut Keyboard::new(&image(1))).unwrap();
+    let mut header = Header::parse(&file).unwrap();
+    header.schema += 1;
+    let mut newer = header.to_bytes().to_vec();
+    newer.extend_from_slice(&file[Header::LEN..]);
+    let mut keyboard = Keyboard::new(&image(2));
+    let error = restore(&mut keyboard, &newer).unwrap_err();
+    assert!(error.contains("rejected"), "{error}");
+    assert_eq!(keyboard.image(), image(2));
+    assert!(keyboard.flash_free());
+}
+
+#[test]
+fn a_failed_write_gives_the_flash_back() {
+    let _one = ONE_KEYBOARD.lock().unwrap_or_else(|e| e.into_inner());
+    let file = backup(&mut Keyboard::new(&image(1))).unwrap();
+    let mut memory = Flash::new();
+    memory.wear_out(RMK_STORAGE_START + 2 * SECTOR_SIZE);
+    let mut keyboard = Keyboard::with_flash(&[], memory);
+    let error = restore(&mut keyboard, &file).unwrap_err();
+    assert!(error.contains("failed to write"), "{error}");
+    assert!(!keyboard.rebooted);
+    assert_eq!(restore_state(&mut keyboard), Some(RestoreState::Failed));
+    assert!(keyboard.flash_free());
+}
+
+#[test]
+fn a_crc_mismatch_gives_the_flash_back() {
+    let _one = ONE_KEYBOARD.lock().unwrap_or_else(|e| e.into_inner());
+    let mut keyboard = Keyboard::new(&image(1));
+    let mut header = read_header(&mut keyboard).unwrap();
+    header.crc ^= 1;
+    assert!(send(
+        &mut keyboard,
+        CUSTOM_SET_VALUE,
+        VALUE_BEGIN_RESTORE,
+        &header.to_bytes()
+    ));
+    assert_eq!(restore_state(&mut keyboard), Some(RestoreState::Receiving));
+    assert!(!keyboard.flash_free());
+    // Nothing written, the erased storage does not match
+    assert!(send(
+        &mut keyboard,
+        CUSTOM_SET_VALUE,
+        VALUE_FINISH_RESTORE,
+        &[]
+    ));
+    assert_eq!(restore_state(&mut keyboard), Some(RestoreState::Failed));
+    assert!(!keyboard.rebooted);
+    assert!(keyboard.flash_free());
+    // Chunks of the failed restore are refused
+    assert!(!send(
+        &mut keyboard,
+        CUSTOM_SET_VALUE,
+        VALUE_WRITE_CHUNK,
+        &[0, 0, 0, 0, 4, 1, 2, 3, 4]
+    ));
+}
+
+#[test]
+fn other_channels_are_unhandled() {
+    let _one = ONE_KEYBOARD.lock().unwrap_or_else(|e| e.into_inner());
+    let mut keyboard = Keyboard::new(&image(1));
+    let mut report = [0; REPORT_LEN];
+    report[0] = CUSTOM_GET_VALUE;
+    report[1] = BACKUP_CHANNEL + 1;
+    report[2] = VALUE_HEADER;
+    keyboard.exchange(&mut report).unwrap();
+    assert_eq!(report[0], UNHANDLED);
+}
@@ -0,0 +1,39 @@
+use rmk::{k, mo};
+
+use super::sim::Keyboard;
+use crate::behavior::TRI_LAYER;
+
+// Thumbs on the central's matrix
+const SYMBOL: (usize, usize) = (3, 4);
+const MIX: (usize, usize) = (7, 4);
+/// `UgToggle` on the adjust layer
+const UG_TOGGLE: (usize, usize) = (0, 2);
+
+#[test]
+fn tri_layer_is_generated_from_the_toml() {
+    assert_eq!(TRI_LAYER, Some([3, 2, 4]));
+}
+
+#[test]
+fn symbol_and_mix_activate_the_adjust_layer() {
+    let mut keyboard = Keyboard::new();
+    assert_eq!(keyboard.press(&[(SYMBOL.0, SYMBOL.1, 0)]), [mo!(2)]);
+    assert_eq!(keyboard.layer(), 2);
+    assert_eq!(keyboard.press(&[(MIX.0, MIX.1, 100)]), [mo!(3)]);
+    assert_eq!(keyboard.layer(), 4);
+    assert_eq!(keyboard.action(UG_TOGGLE.0, UG_TOGGLE.1), k!(User15));
+    // Either thumb up leaves it
+    keyboard.release(SYMBOL.0, SYMBOL.1);
+    assert_eq!(keyboard.layer(), 3);
+    assert_eq!(keyboard.action(UG_TOGGLE.0, UG_TOGGLE.1), k!(F2));
+}
+
+#[test]
+fn the_order_of_the_thumbs_does_not_matter() {
+    let mut keyboard = Keyboard::new();
+    assert_eq!(keyboard.press(&[(MIX.0, MIX.1, 0)]), [mo!(3)]);
+    assert_eq!(keyboard.press(&[(SYMBOL.0, SYMBOL.1, 100)]), [mo!(2)]);
+    assert_eq!(keyboard.layer(), 4);
+    keyboard.release(MIX.0, MIX.1);
+    assert_eq!(keyboard.layer(), 2);
+}
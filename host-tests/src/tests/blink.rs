@@ -0,0 +1,93 @@
+use crate::blink::Pattern;
+
+const fn pattern(on_ms: u16, off_ms: u16, pause_ms: u16) -> Pattern {
+    Pattern {
+        on_ms,
+        off_ms,
+        pause_ms,
+    }
+}
+
+/// The LED over time, one character per `step_ms`: `#` lit, `.` dark
+fn sequence(pattern: Pattern, count: u8, step_ms: u64, steps: u64) -> String {
+    (0..steps)
+        .map(|step| match pattern.lit(count, step * step_ms) {
+            true => '#',
+            false => '.',
+        })
+        .collect()
+}
+
+#[test]
+fn steady_stays_lit() {
+    // caps_lock, connected and "on" in [indicators.patterns]
+    let steady = pattern(1000, 0, 0);
+    assert_eq!(sequence(steady, 1, 250, 12), "############");
+    assert!(steady.lit(1, u64::MAX));
+    // The count doesn't matter
+    assert_eq!(sequence(steady, 3, 250, 12), "############");
+}
+
+#[test]
+fn caps_word_blinks() {
+    let caps_word = pattern(300, 100, 0);
+    assert_eq!(sequence(caps_word, 1, 100, 12), "###.###.###.");
+}
+
+#[test]
+fn advertising_blinks_evenly() {
+    let advertising = pattern(500, 500, 0);
+    assert_eq!(sequence(advertising, 1, 250, 12), "##..##..##..");
+}
+
+#[test]
+fn low_battery_flashes() {
+    let low_battery = pattern(100, 1900, 0);
+    assert_eq!(
+        sequence(low_battery, 1, 100, 42),
+        "#...................#...................#."
+    );
+}
+
+#[test]
+fn charging_blinks_slowly() {
+    let charging = pattern(1000, 1000, 0);
+    assert_eq!(sequence(charging, 1, 500, 8), "##..##..");
+}
+
+#[test]
+fn layer_blinks_its_number_then_pauses() {
+    // Also ble_profile
+    let layer = pattern(150, 150, 1000);
+    // Layer 3: three blinks in 900ms, 1s dark, again
+    let cycle = format!("{}{}", "###...".repeat(3), ".".repeat(20));
+    assert_eq!(sequence(layer, 3, 50, 76), cycle.repeat(2));
+    // Layer 1
+    let cycle = format!("###...{}", ".".repeat(20));
+    assert_eq!(sequence(layer, 1, 50, 52), cycle.repeat(2));
+}
+
+#[test]
+fn layer_lock_blinks_the_locked_layer() {
+    let layer_lock = pattern(400, 200, 1000);
+    assert_eq!(sequence(layer_lock, 2, 200, 22), "##.##......##.##......");
+}
+
+#[test]
+fn a_count_of_0_blinks_once() {
+    let layer = pattern(150, 150, 1000);
+    assert_eq!(sequence(layer, 0, 50, 52), sequence(layer, 1, 50, 52));
+}
+
+#[test]
+fn edges_fall_on_the_durations() {
+    let layer = pattern(150, 150, 1000);
+    assert!(layer.lit(2, 0));
+    assert!(layer.lit(2, 149));
+    assert!(!layer.lit(2, 150));
+    assert!(!layer.lit(2, 299));
+    assert!(layer.lit(2, 300));
+    assert!(!layer.lit(2, 600));
+    assert!(!layer.lit(2, 1599));
+    assert!(layer.lit(2, 1600));
+}
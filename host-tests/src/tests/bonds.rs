@@ -0,0 +1,158 @@
+use embassy_embedded_hal::flash::partition::Partition;
+use embassy_futures::block_on;
+use embassy_sync::mutex::Mutex;
+use nrf_mpsl::Flash;
+use sequential_storage::map::{SerializationError, Value};
+
+use crate::app_storage::{AppStorage, RecordKind, SharedFlash, record_key};
+use crate::bonds::{BondMeta, MAX_HOST_NAME};
+use crate::flash_wear::WearCountingFlash;
+use crate::storage_layout::{APP_STORAGE_SECTORS, APP_STORAGE_START, SECTOR_SIZE};
+
+/// Serialized size of a record
+const SIZE: usize = 22;
+
+/// Firmware-owned records on an erased flash
+fn app_storage() -> AppStorage {
+    let flash: &'static SharedFlash =
+        Box::leak(Box::new(Mutex::new(WearCountingFlash::new(Flash::new()))));
+    AppStorage::new(Partition::new(
+        flash,
+        APP_STORAGE_START,
+        APP_STORAGE_SECTORS * SECTOR_SIZE,
+    ))
+}
+
+fn named(name: &[u8], last_connected: u32) -> BondMeta {
+    let mut meta = BondMeta::EMPTY;
+    meta.bonded = true;
+    meta.last_connected = last_connected;
+    meta.set_name(name);
+    meta
+}
+
+/// Raw bytes stored at a record's key
+struct RawRecord<const N: usize>([u8; N]);
+
+impl<'a, const N: usize> Value<'a> for RawRecord<N> {
+    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+        buffer[..N].copy_from_slice(&self.0);
+        Ok(N)
+    }
+
+    fn deserialize_from(buffer: &'a [u8]) -> Result<Self, SerializationError> {
+        Ok(Self(
+            buffer
+                .try_into()
+                .map_err(|_| SerializationError::InvalidData)?,
+        ))
+    }
+}
+
+#[test]
+fn record_round_trips() {
+    let meta = named(b"laptop", 1_700_000_000);
+    let mut buf = [0; 32];
+    assert_eq!(meta.serialize_into(&mut buf), Ok(SIZE));
+    assert_eq!(&buf[..6], &[1, 0x00, 0xf1, 0x53, 0x65, 6]);
+    assert_eq!(&buf[6..12], b"laptop");
+    assert_eq!(&buf[12..SIZE], &[0; 10]);
+
+    let read = BondMeta::deserialize_from(&buf[..SIZE]).unwrap();
+    assert!(read == meta);
+    assert_eq!(read.name(), b"laptop");
+}
+
+#[test]
+fn empty_record_round_trips() {
+    let mut buf = [0xAA; SIZE];
+    BondMeta::EMPTY.serialize_into(&mut buf).unwrap();
+    assert_eq!(buf, [0; SIZE]);
+    assert!(BondMeta::deserialize_from(&buf).unwrap() == BondMeta::EMPTY);
+}
+
+#[test]
+fn name_is_truncated() {
+    let meta = named(b"a-very-long-host-name", 0);
+    assert_eq!(meta.name(), b"a-very-long-host");
+    assert_eq!(meta.name().len(), MAX_HOST_NAME);
+
+    // A shorter name leaves nothing of the longer one behind
+    let mut renamed = meta;
+    renamed.set_name(b"pc");
+    let mut buf = [0; SIZE];
+    renamed.serialize_into(&mut buf).unwrap();
+    assert_eq!(&buf[5..9], &[2, b'p', b'c', 0]);
+    assert_eq!(&buf[8..SIZE], &[0; 14]);
+}
+
+#[test]
+fn too_short_buffer_is_refused() {
+    let meta = named(b"phone", 42);
+    let mut buf = [0; SIZE - 1];
+    assert_eq!(
+        meta.serialize_into(&mut buf),
+        Err(SerializationError::BufferTooSmall)
+    );
+    assert!(matches!(
+        BondMeta::deserialize_from(&buf),
+        Err(SerializationError::BufferTooSmall)
+    ));
+    assert!(matches!(
+        BondMeta::deserialize_from(&[]),
+        Err(SerializationError::BufferTooSmall)
+    ));
+}
+
+#[test]
+fn oversized_name_is_invalid() {
+    let mut buf = [0; SIZE];
+    named(b"tablet", 7).serialize_into(&mut buf).unwrap();
+    buf[5] = MAX_HOST_NAME as u8 + 1;
+    assert!(matches!(
+        BondMeta::deserialize_from(&buf),
+        Err(SerializationError::InvalidFormat)
+    ));
+}
+
+#[test]
+fn longer_record_keeps_its_fields() {
+    // A later firmware appending fields
+    let meta = named(b"desktop", 99);
+    let mut buf = [0xEE; SIZE + 8];
+    meta.serialize_into(&mut buf).unwrap();
+    assert!(BondMeta::deserialize_from(&buf).unwrap() == meta);
+}
+
+#[test]
+fn stored_record_is_read_back() {
+    let mut storage = app_storage();
+    let key = record_key(RecordKind::BondMeta, 1);
+    let meta = named(b"work", 1234);
+    block_on(async {
+        assert!(storage.store(key, &meta).await);
+        assert!(storage.fetch::<BondMeta>(key).await == Some(meta));
+        assert!(
+            storage
+                .fetch::<BondMeta>(record_key(RecordKind::BondMeta, 2))
+                .await
+                .is_none()
+        );
+    });
+}
+
+#[test]
+fn old_length_record_reads_as_no_record() {
+    // bonded, last_connected and name_len without the name, as a record of an
+    // older layout would be
+    let mut storage = app_storage();
+    let key = record_key(RecordKind::BondMeta, 0);
+    block_on(async {
+        assert!(storage.store(key, &RawRecord([1, 1, 0, 0, 0, 3])).await);
+        assert!(storage.fetch::<BondMeta>(key).await.is_none());
+        // Saving the profile again replaces it
+        let meta = named(b"new", 5);
+        assert!(storage.store(key, &meta).await);
+        assert!(storage.fetch::<BondMeta>(key).await == Some(meta));
+    });
+}
@@ -0,0 +1,114 @@
+use rmk::types::action::{Action, KeyAction, MorseProfile};
+use rmk::types::keycode::KeyCode;
+use rmk::types::modifier::ModifierCombination;
+use rmk::{a, k, ltp, mo, tg};
+
+use super::sim::Keyboard;
+use crate::caps_word_keys::{Effect, effect};
+use crate::keymap::get_default_keymap;
+use crate::{mt, wm};
+
+#[test]
+fn letters_and_digits_continue() {
+    for action in [k!(A), k!(M), k!(Z), k!(Kc1), k!(Kc0)] {
+        assert_eq!(effect(&action), Effect::Word, "{action:?}");
+    }
+}
+
+#[test]
+fn continue_keys_continue() {
+    // The default continue_keys of [caps_word]
+    for action in [k!(Minus), k!(Backspace), k!(Delete)] {
+        assert_eq!(effect(&action), Effect::Word, "{action:?}");
+    }
+}
+
+#[test]
+fn other_keys_end() {
+    for action in [
+        k!(Space),
+        k!(Enter),
+        k!(Escape),
+        k!(Dot),
+        k!(Equal),
+        k!(F1),
+        k!(Left),
+    ] {
+        assert_eq!(effect(&action), Effect::End, "{action:?}");
+    }
+}
+
+#[test]
+fn modifiers_and_layers_are_ignored() {
+    let shift = KeyAction::Single(Action::Modifier(ModifierCombination::LSHIFT));
+    for action in [
+        k!(LShift),
+        k!(RGui),
+        shift,
+        mo!(2),
+        tg!(1),
+        a!(No),
+        a!(Transparent),
+    ] {
+        assert_eq!(effect(&action), Effect::Ignore, "{action:?}");
+    }
+    // The host's LED report ends it
+    assert_eq!(effect(&k!(CapsLock)), Effect::Ignore);
+}
+
+#[test]
+fn shifted_letters_continue_shifted_symbols_end() {
+    assert_eq!(effect(&wm!(A, LShift)), Effect::Word);
+    assert_eq!(effect(&wm!(Kc1, LShift)), Effect::End);
+    assert_eq!(effect(&wm!(Minus, LShift)), Effect::End);
+}
+
+#[test]
+fn tap_holds_tapping_a_word_key_continue() {
+    assert_eq!(effect(&mt!(A, LCtrl)), Effect::Word);
+    let thumb = MorseProfile::const_default();
+    assert_eq!(effect(&ltp!(2, Backspace, thumb)), Effect::Word);
+}
+
+#[test]
+fn letters_of_the_keymap_continue() {
+    let keymap = get_default_keymap();
+    let letters = keymap[0]
+        .iter()
+        .flatten()
+        .filter(|action| match action {
+            KeyAction::Single(Action::Key(code)) | KeyAction::TapHold(Action::Key(code), _, _) => {
+                (KeyCode::A as u16..=KeyCode::Z as u16).contains(&(*code as u16))
+            }
+            _ => false,
+        })
+        .inspect(|action| assert_eq!(effect(action), Effect::Word, "{action:?}"))
+        .count();
+    assert_eq!(letters, 26);
+}
+
+#[test]
+fn tap_holds_count_as_their_tap() {
+    let thumb = MorseProfile::const_default();
+    assert_eq!(effect(&ltp!(6, Escape, thumb)), Effect::End);
+    assert_eq!(effect(&mt!(Semicolon, RCtrl)), Effect::End);
+    assert_eq!(effect(&mt!(LShift, LCtrl)), Effect::Ignore);
+}
+
+#[test]
+fn auto_shifted_keys_count_as_their_tap() {
+    let mut keyboard = Keyboard::new();
+    // Dot and Comma on the base layer
+    for (row, col) in [(6, 2), (6, 3)] {
+        let action = keyboard.action(row, col);
+        assert!(matches!(action, KeyAction::TapHold(..)), "{action:?}");
+        assert_eq!(effect(&action), Effect::End, "{action:?}");
+    }
+    // Minus and the digits on the mix layer
+    keyboard.press(&[(7, 4, 0)]);
+    for (row, col) in [(5, 2), (1, 1), (2, 5)] {
+        let action = keyboard.action(row, col);
+        assert!(matches!(action, KeyAction::TapHold(..)), "{action:?}");
+        assert_eq!(effect(&action), Effect::Word, "{action:?}");
+    }
+}
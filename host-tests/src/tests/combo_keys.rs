@@ -0,0 +1,141 @@
+use embassy_time::Duration;
+use rmk::types::action::KeyAction;
+use rmk::{k, mo};
+
+use super::sim::Keyboard;
+use crate::combo_keys::{COMBO_TIMEOUT, COMBOS};
+use crate::keymap::get_default_keymap;
+use crate::mt;
+
+// Positions on the central's matrix
+const X: (usize, usize) = (2, 2);
+const C: (usize, usize) = (2, 3);
+const Z: (usize, usize) = (2, 1);
+const V: (usize, usize) = (2, 4);
+const B: (usize, usize) = (2, 5);
+const O: (usize, usize) = (4, 4);
+const P: (usize, usize) = (4, 5);
+const COMMA: (usize, usize) = (6, 2);
+const DOT: (usize, usize) = (6, 3);
+const KC2: (usize, usize) = (1, 4);
+const KC1: (usize, usize) = (1, 5);
+const MIX: (usize, usize) = (7, 4);
+
+fn at((row, col): (usize, usize), ms: u64) -> (usize, usize, u64) {
+    (row, col, ms)
+}
+
+#[test]
+fn combos_find_their_keys() {
+    let keymap = get_default_keymap();
+    let actions: Vec<Vec<KeyAction>> = COMBOS
+        .iter()
+        .map(|def| {
+            def.actions(&keymap)
+                .map(Iterator::collect)
+                .unwrap_or_default()
+        })
+        .collect();
+    assert_eq!(actions[0], [k!(O), k!(P)]);
+    assert_eq!(actions[1], [k!(X), k!(C)]);
+    assert_eq!(actions[2], [k!(Comma), k!(Dot)]);
+    // BtPre and BtNext on the adjust layer
+    assert_eq!(actions[3], [k!(User4), k!(User3)]);
+    // The entries build.rs adds
+    assert_eq!(actions[4], [k!(V), k!(B)]);
+    // On the combo's layer
+    assert_eq!(actions[5], [k!(Kc1), k!(Kc2)]);
+    // A tap-hold is found by its tap
+    assert_eq!(actions[6], [mt!(Z, LGui), k!(X)]);
+}
+
+#[test]
+fn combos_are_generated_from_the_toml() {
+    assert_eq!(COMBO_TIMEOUT, Duration::from_millis(50));
+    let outputs: Vec<_> = COMBOS.iter().map(|def| (def.output, def.layer)).collect();
+    assert_eq!(
+        outputs[..4],
+        [
+            (k!(Backspace), None),
+            (mo!(4), None),
+            (k!(User18), None),
+            (k!(User13), Some(4))
+        ]
+    );
+}
+
+#[test]
+fn combos_with_a_missing_key_are_dropped() {
+    let keymap = get_default_keymap();
+    // F12 is not on the base layer
+    assert!(COMBOS[7].actions(&keymap).is_none());
+    // Escape is on two keys of the base layer
+    assert!(COMBOS[8].actions(&keymap).is_none());
+    assert_eq!(Keyboard::new().combos(), 7);
+}
+
+#[test]
+fn backspace_is_a_combo() {
+    let mut keyboard = Keyboard::new();
+    assert_eq!(keyboard.press(&[at(O, 0), at(P, 20)]), [k!(Backspace)]);
+    keyboard.release(O.0, O.1);
+    // Either order
+    assert_eq!(keyboard.press(&[at(P, 100), at(O, 110)]), [k!(Backspace)]);
+}
+
+#[test]
+fn keys_apart_are_no_combo() {
+    let mut keyboard = Keyboard::new();
+    assert_eq!(keyboard.press(&[at(O, 0), at(P, 50)]), [k!(O), k!(P)]);
+}
+
+#[test]
+fn adjust_layer_is_held_by_a_combo() {
+    let mut keyboard = Keyboard::new();
+    assert_eq!(keyboard.press(&[at(X, 0), at(C, 10)]), [mo!(4)]);
+    assert_eq!(keyboard.layer(), 4);
+    // UgToggle
+    assert_eq!(keyboard.press(&[at((0, 2), 100)]), [k!(User15)]);
+    keyboard.release(C.0, C.1);
+    assert_eq!(keyboard.layer(), 0);
+    assert_eq!(keyboard.action(0, 2), k!(W));
+}
+
+#[test]
+fn leader_is_a_combo() {
+    let mut keyboard = Keyboard::new();
+    assert_eq!(keyboard.press(&[at(COMMA, 0), at(DOT, 5)]), [k!(User18)]);
+}
+
+#[test]
+fn combo_by_position() {
+    let mut keyboard = Keyboard::new();
+    assert_eq!(keyboard.press(&[at(V, 0), at(B, 5)]), [k!(Escape)]);
+}
+
+#[test]
+fn combo_with_a_tap_hold() {
+    let mut keyboard = Keyboard::new();
+    assert_eq!(keyboard.press(&[at(Z, 0), at(X, 5)]), [k!(Delete)]);
+}
+
+#[test]
+fn layer_combo_only_on_its_layer() {
+    let mut keyboard = Keyboard::new();
+    // G and F on the base layer
+    assert_eq!(
+        keyboard.press(&[at(KC1, 0), at(KC2, 5)]),
+        [keyboard.action(KC1.0, KC1.1), keyboard.action(KC2.0, KC2.1)]
+    );
+    keyboard.release(KC1.0, KC1.1);
+    keyboard.release(KC2.0, KC2.1);
+    assert_eq!(keyboard.press(&[at(MIX, 100)]), [mo!(3)]);
+    assert_eq!(keyboard.press(&[at(KC1, 200), at(KC2, 205)]), [k!(F12)]);
+}
+
+#[test]
+fn combos_leave_the_keys_they_stand_for() {
+    let keymap = get_default_keymap();
+    assert_eq!(keymap[0][2][0], mo!(4));
+    assert_eq!(keymap[0][6][5], k!(Backspace));
+}
@@ -0,0 +1,91 @@
+use crate::custom_hid_desc::{
+    HID_DESCRIPTOR, INTERFACE_LEN, REPORT_DESCRIPTOR, USAGE_PAGE, extend_config, interface,
+};
+
+const EP_IN: u8 = 0x83;
+const EP_OUT: u8 = 0x03;
+
+/// A configuration with one interface and nothing in it, as a device sends it
+fn config() -> Vec<u8> {
+    let mut desc = vec![9, 0x02, 18, 0, 1, 1, 0, 0xA0, 50];
+    desc.extend([9, 0x04, 0, 0, 0, 0x03, 0, 0, 0]);
+    desc
+}
+
+/// `sent` in a buffer with room for the interface
+fn buffer(sent: &[u8]) -> [u8; 64] {
+    let mut buf = [0; 64];
+    buf[..sent.len()].copy_from_slice(sent);
+    buf
+}
+
+#[test]
+fn interface_goes_last() {
+    let sent = config();
+    let mut buf = buffer(&sent);
+    let (len, number) = extend_config(&mut buf, sent.len(), 255, EP_IN, EP_OUT).unwrap();
+    assert_eq!(number, 1);
+    assert_eq!(len, sent.len() + INTERFACE_LEN);
+    // Two interfaces in all, the header counts the new one
+    assert_eq!(u16::from_le_bytes([buf[2], buf[3]]) as usize, len);
+    assert_eq!(buf[4], 2);
+    assert_eq!(&buf[5..sent.len()], &sent[5..]);
+    assert_eq!(&buf[sent.len()..len], &interface(1, EP_IN, EP_OUT));
+}
+
+#[test]
+fn header_request_gets_the_longer_length() {
+    // Hosts read the header first, then as much as it says
+    let sent = config();
+    let mut buf = buffer(&sent[..9]);
+    assert_eq!(extend_config(&mut buf, 9, 9, EP_IN, EP_OUT), Some((9, 1)));
+    assert_eq!(buf[2..5], [18 + INTERFACE_LEN as u8, 0, 2]);
+}
+
+#[test]
+fn reply_is_cut_to_the_request() {
+    let sent = config();
+    let mut buf = buffer(&sent);
+    assert_eq!(
+        extend_config(&mut buf, sent.len(), 20, EP_IN, EP_OUT),
+        Some((20, 1))
+    );
+}
+
+#[test]
+fn other_descriptors_are_left_alone() {
+    // A device descriptor
+    let sent = [18, 0x01, 0x00, 0x02, 0, 0, 0, 64];
+    let mut buf = buffer(&sent);
+    assert_eq!(
+        extend_config(&mut buf, sent.len(), 255, EP_IN, EP_OUT),
+        None
+    );
+    assert_eq!(buf, buffer(&sent));
+    // No room for the interface
+    let sent = config();
+    let mut buf = [0; 20];
+    buf[..sent.len()].copy_from_slice(&sent);
+    assert_eq!(
+        extend_config(&mut buf, sent.len(), 255, EP_IN, EP_OUT),
+        None
+    );
+}
+
+#[test]
+fn interface_layout() {
+    let desc = interface(3, EP_IN, EP_OUT);
+    // HID interface with two endpoints
+    assert_eq!(desc[..9], [9, 0x04, 3, 0, 2, 0x03, 0, 0, 0]);
+    assert_eq!(desc[9..18], HID_DESCRIPTOR);
+    assert_eq!(desc[18..25], [7, 0x05, EP_IN, 0x03, 32, 0, 1]);
+    assert_eq!(desc[25..], [7, 0x05, EP_OUT, 0x03, 32, 0, 1]);
+}
+
+#[test]
+fn report_descriptor_is_on_its_own_usage_page() {
+    assert_eq!(HID_DESCRIPTOR[7] as usize, REPORT_DESCRIPTOR.len());
+    assert_eq!(REPORT_DESCRIPTOR[..3], [0x06, 0x62, 0xFF]);
+    assert_eq!(USAGE_PAGE, 0xFF62);
+    assert_eq!(REPORT_DESCRIPTOR.last(), Some(&0xC0));
+}
@@ -0,0 +1,193 @@
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::mono_font::ascii::FONT_6X10;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::{PrimitiveStyle, Rectangle};
+use embedded_graphics::text::{Baseline, Text};
+
+use crate::display_render::{Frame, Status, render};
+use crate::split_status::SplitStatus;
+
+const LAYER_NAMES: [&str; 3] = ["base", "symbol", "adjust"];
+
+/// Both panels: the SSD1306 and the nice!view
+const PANELS: [(usize, usize); 2] = [(128, 32), (160, 68)];
+
+/// The frame as text, one character per pixel: `#` on, `.` off
+fn pixels(frame: &Frame, width: usize, height: usize) -> Vec<String> {
+    (0..height)
+        .map(|y| {
+            (0..width)
+                .map(|x| if frame.pixel(x, y) { '#' } else { '.' })
+                .collect()
+        })
+        .collect()
+}
+
+/// What `render` should draw: the lines in the display font, 11 pixels apart
+fn expected(lines: [&str; 3], width: usize, height: usize) -> Frame {
+    let mut frame = Frame::new(width, height);
+    let style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
+    for (i, line) in lines.iter().enumerate() {
+        let origin = Point::new(0, i as i32 * 11);
+        Text::with_baseline(line, origin, style, Baseline::Top)
+            .draw(&mut frame)
+            .unwrap();
+    }
+    frame
+}
+
+fn assert_shows(status: &Status, lines: [&str; 3]) {
+    for (width, height) in PANELS {
+        let mut frame = Frame::new(width, height);
+        // Whatever was shown before is cleared
+        frame.clear(BinaryColor::On).unwrap();
+        render(status, &LAYER_NAMES, &mut frame);
+        assert_eq!(
+            pixels(&frame, width, height),
+            pixels(&expected(lines, width, height), width, height),
+            "{lines:?} on {width}x{height}"
+        );
+    }
+}
+
+#[test]
+fn frame_packs_rows_msb_first() {
+    let mut frame = Frame::new(16, 2);
+    Pixel(Point::new(0, 0), BinaryColor::On)
+        .draw(&mut frame)
+        .unwrap();
+    Pixel(Point::new(9, 1), BinaryColor::On)
+        .draw(&mut frame)
+        .unwrap();
+    assert_eq!(frame.row(0), [0x80, 0x00]);
+    assert_eq!(frame.row(1), [0x00, 0x40]);
+    assert!(frame.pixel(0, 0) && frame.pixel(9, 1));
+    assert!(!frame.pixel(1, 0) && !frame.pixel(8, 1));
+
+    Pixel(Point::new(0, 0), BinaryColor::Off)
+        .draw(&mut frame)
+        .unwrap();
+    assert_eq!(frame.row(0), [0x00, 0x00]);
+}
+
+#[test]
+fn frame_clips_to_its_size() {
+    let mut frame = Frame::new(16, 2);
+    assert_eq!(frame.size(), Size::new(16, 2));
+    // Reaches past every edge
+    Rectangle::new(Point::new(-4, -4), Size::new(40, 40))
+        .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+        .draw(&mut frame)
+        .unwrap();
+    assert_eq!(pixels(&frame, 16, 2), ["################"; 2]);
+    frame.clear(BinaryColor::Off).unwrap();
+    assert_eq!(pixels(&frame, 16, 2), ["................"; 2]);
+}
+
+#[test]
+fn central_shows_its_status() {
+    let status = Status {
+        layer: Some(1),
+        profile: Some(0),
+        wpm: Some(42),
+        battery: Some(80),
+        charging: true,
+        linked: true,
+        other: SplitStatus {
+            battery: Some(75),
+            ..SplitStatus::UNKNOWN
+        },
+    };
+    assert_shows(&status, ["symbol", "BT1  WPM 42", "BAT 80%+ / 75%"]);
+}
+
+#[test]
+fn peripheral_shows_what_the_central_forwarded() {
+    let status = Status {
+        battery: Some(75),
+        linked: true,
+        other: SplitStatus {
+            layer: Some(2),
+            profile: Some(1),
+            wpm: Some(0),
+            battery: Some(80),
+            charging: true,
+            ..SplitStatus::UNKNOWN
+        },
+        ..Default::default()
+    };
+    assert_shows(&status, ["adjust", "BT2  WPM 0", "BAT 75% / 80%+"]);
+}
+
+#[test]
+fn unknown_status_shows_the_link() {
+    assert_shows(&Status::default(), ["no link", "", "BAT --"]);
+    let linked = Status {
+        linked: true,
+        ..Default::default()
+    };
+    assert_shows(&linked, ["linked", "", "BAT --"]);
+    // Charging before the other half links
+    let charging = Status {
+        battery: Some(100),
+        charging: true,
+        ..Default::default()
+    };
+    assert_shows(&charging, ["no link", "", "BAT 100%+"]);
+}
+
+#[test]
+fn layer_without_a_name() {
+    let status = Status {
+        layer: Some(LAYER_NAMES.len() as u8),
+        ..Default::default()
+    };
+    assert_shows(&status, ["?", "", "BAT --"]);
+}
+
+#[test]
+fn own_status_wins_over_the_other_half() {
+    let status = Status {
+        layer: Some(0),
+        wpm: Some(10),
+        other: SplitStatus {
+            layer: Some(2),
+            wpm: Some(99),
+            ..SplitStatus::UNKNOWN
+        },
+        ..Default::default()
+    };
+    assert_shows(&status, ["base", "WPM 10", "BAT --"]);
+}
+
+#[test]
+fn text_fits_the_ssd1306() {
+    let status = Status {
+        layer: Some(1),
+        profile: Some(2),
+        wpm: Some(250),
+        battery: Some(100),
+        charging: true,
+        linked: true,
+        other: SplitStatus {
+            battery: Some(100),
+            charging: true,
+            ..SplitStatus::UNKNOWN
+        },
+    };
+    let mut frame = Frame::new(128, 32);
+    render(&status, &LAYER_NAMES, &mut frame);
+    // The third line ends before the right edge and above the bottom one
+    let lines = pixels(&frame, 128, 32);
+    assert!(lines.iter().all(|line| line.ends_with("......")));
+    assert!(lines[31].chars().all(|c| c == '.'));
+    assert_eq!(
+        lines,
+        pixels(
+            &expected(["symbol", "BT3  WPM 250", "BAT 100%+ / 100%+"], 128, 32),
+            128,
+            32
+        )
+    );
+}
@@ -0,0 +1,69 @@
+use crate::identity::{ble_address, derive_address};
+
+const CONFIGURED: [u8; 6] = [0x18, 0xe2, 0x21, 0x80, 0xc0, 0xc7];
+
+const DEVICE_ID: u64 = 0x1234_5678_9abc_def0;
+
+fn is_static_random(addr: [u8; 6]) -> bool {
+    let random = u64::from_le_bytes([addr[0], addr[1], addr[2], addr[3], addr[4], addr[5], 0, 0])
+        & 0x3fff_ffff_ffff;
+    addr[5] & 0xc0 == 0xc0 && random != 0 && random != 0x3fff_ffff_ffff
+}
+
+#[test]
+fn profile_0_keeps_the_configured_address() {
+    assert_eq!(ble_address(Some(CONFIGURED), DEVICE_ID, 0), CONFIGURED);
+    // Even one that isn't a static random address
+    let public = [1, 2, 3, 4, 5, 6];
+    assert_eq!(ble_address(Some(public), DEVICE_ID, 0), public);
+}
+
+#[test]
+fn other_profiles_derive_from_the_configured_address() {
+    for profile in 1..8 {
+        let addr = ble_address(Some(CONFIGURED), DEVICE_ID, profile);
+        assert_ne!(addr, CONFIGURED);
+        assert!(is_static_random(addr), "profile {profile}: {addr:02x?}");
+        assert_eq!(addr, ble_address(Some(CONFIGURED), 0, profile));
+    }
+}
+
+#[test]
+fn device_id_is_used_without_a_configured_address() {
+    let addr = ble_address(None, DEVICE_ID, 0);
+    assert_eq!(addr, derive_address(DEVICE_ID, 0));
+    assert_eq!(addr, [0xf0, 0xde, 0xbc, 0x9a, 0x78, 0xd6]);
+    assert!(is_static_random(addr));
+}
+
+#[test]
+fn profiles_get_distinct_addresses() {
+    for base in [DEVICE_ID, 0, u64::MAX] {
+        let addrs: Vec<_> = (0..8)
+            .map(|profile| derive_address(base, profile))
+            .collect();
+        for (i, a) in addrs.iter().enumerate() {
+            assert!(is_static_random(*a), "{a:02x?}");
+            assert!(!addrs[i + 1..].contains(a), "{base:x}: {addrs:02x?}");
+        }
+    }
+}
+
+#[test]
+fn derivation_is_stable() {
+    // Hosts bond with these, they must not change between firmware versions
+    assert_eq!(
+        derive_address(DEVICE_ID, 1),
+        [0x51, 0x11, 0x23, 0x24, 0x4b, 0xfa]
+    );
+    assert_eq!(
+        derive_address(DEVICE_ID, 2),
+        [0x5c, 0x52, 0xd8, 0xb7, 0x5f, 0xca]
+    );
+}
+
+#[test]
+fn degenerate_random_parts_are_avoided() {
+    assert!(is_static_random(derive_address(0, 0)));
+    assert!(is_static_random(derive_address(0x3fff_ffff_ffff, 0)));
+}
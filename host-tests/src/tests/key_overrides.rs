@@ -0,0 +1,67 @@
+use rmk::types::action::{Action, KeyAction};
+use rmk::types::keycode::KeyCode;
+use rmk::{k, mo};
+
+use super::sim::Keyboard;
+
+// HID modifier bits
+const LCTRL: u8 = 0x01;
+const LSHIFT: u8 = 0x02;
+const LALT: u8 = 0x04;
+const RSHIFT: u8 = 0x20;
+
+/// `Backspace` of the base layer, right of `P`
+const BACKSPACE: (usize, usize) = (4, 0);
+/// `LT(2, Backspace)` of the right thumbs
+const THUMB: (usize, usize) = (7, 5);
+/// The mix thumb and `Backspace` of the mix layer
+const MIX: (usize, usize) = (7, 4);
+const MIX_BACKSPACE: (usize, usize) = (5, 1);
+
+#[test]
+fn shift_backspace_sends_delete() {
+    let keyboard = Keyboard::new();
+    let (row, col) = BACKSPACE;
+    assert_eq!(keyboard.tap(row, col, LSHIFT), (k!(Delete), 0));
+    assert_eq!(keyboard.tap(row, col, RSHIFT), (k!(Delete), 0));
+    // Other modifiers are sent along
+    assert_eq!(keyboard.tap(row, col, LSHIFT | LALT), (k!(Delete), LALT));
+}
+
+#[test]
+fn ctrl_keeps_backspace() {
+    let keyboard = Keyboard::new();
+    let (row, col) = BACKSPACE;
+    assert_eq!(
+        keyboard.tap(row, col, LSHIFT | LCTRL),
+        (k!(Backspace), LSHIFT | LCTRL)
+    );
+    assert_eq!(keyboard.tap(row, col, 0), (k!(Backspace), 0));
+    assert_eq!(keyboard.tap(row, col, LALT), (k!(Backspace), LALT));
+}
+
+#[test]
+fn the_backspace_thumb_is_overridden_as_a_whole() {
+    let keyboard = Keyboard::new();
+    let (row, col) = THUMB;
+    let thumb = keyboard.action(row, col);
+    assert!(matches!(
+        thumb,
+        KeyAction::TapHold(Action::Key(KeyCode::Backspace), Action::LayerOn(2), _)
+    ));
+    assert_eq!(keyboard.tap(row, col, LSHIFT), (k!(Delete), 0));
+    assert_eq!(
+        keyboard.tap(row, col, LSHIFT | LCTRL),
+        (thumb, LSHIFT | LCTRL)
+    );
+}
+
+#[test]
+fn overrides_leave_the_keymap_and_work_on_every_layer() {
+    let mut keyboard = Keyboard::new();
+    assert_eq!(keyboard.action(BACKSPACE.0, BACKSPACE.1), k!(Backspace));
+    assert_eq!(keyboard.press(&[(MIX.0, MIX.1, 0)]), [mo!(3)]);
+    let (row, col) = MIX_BACKSPACE;
+    assert_eq!(keyboard.action(row, col), k!(Backspace));
+    assert_eq!(keyboard.tap(row, col, LSHIFT), (k!(Delete), 0));
+}
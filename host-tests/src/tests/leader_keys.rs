@@ -0,0 +1,258 @@
+use rmk::types::action::KeyAction;
+use rmk::types::keycode::KeyCode;
+use rmk::{a, k};
+
+use super::sim::Keyboard;
+use crate::leader_keys::{LEADER_LAYER, LEADER_TIMEOUT, LeaderAction, Outcome, Sequence};
+
+// Positions on the central's matrix
+const B: (usize, usize) = (2, 5);
+const C: (usize, usize) = (2, 3);
+const E: (usize, usize) = (0, 3);
+/// `{` on the symbol layer, `Kc2` on the mix layer
+const F: (usize, usize) = (1, 4);
+const G: (usize, usize) = (1, 5);
+const M: (usize, usize) = (6, 1);
+const T: (usize, usize) = (0, 5);
+const X: (usize, usize) = (2, 2);
+const COMMA: (usize, usize) = (6, 2);
+const DOT: (usize, usize) = (6, 3);
+/// `LCtrl` on the mix layer
+const LCTRL: (usize, usize) = (1, 0);
+/// `MO(4)`, the pinky of the adjust layer
+const ADJUST: (usize, usize) = (2, 0);
+/// `MO(2)`, the symbol layer
+const SYMBOL: (usize, usize) = (3, 4);
+/// `LT(6, Escape)`, the number layer
+const NUMBER: (usize, usize) = (3, 3);
+/// `MO(3)`, the digits of the mix layer
+const MIX: (usize, usize) = (7, 4);
+/// `Leader` on the adjust layer
+const LEADER: (usize, usize) = (1, 3);
+
+/// Presses keys on the keyboard and hands what it does to a leader sequence,
+/// turning the leader layer on and off like the controller
+struct Typist {
+    keyboard: Keyboard,
+    sequence: Sequence,
+    /// What RMK did for the keys pressed
+    actions: Vec<KeyAction>,
+    ms: u64,
+}
+
+impl Typist {
+    fn new() -> Self {
+        Self {
+            keyboard: Keyboard::new(),
+            sequence: Sequence::new(),
+            actions: Vec::new(),
+            ms: 0,
+        }
+    }
+
+    fn follow(&mut self, outcome: Outcome) -> Outcome {
+        if outcome != Outcome::Ignore {
+            if self.sequence.running() {
+                self.keyboard.activate_layer(LEADER_LAYER);
+            } else {
+                self.keyboard.deactivate_layer(LEADER_LAYER);
+            }
+        }
+        outcome
+    }
+
+    /// Press `keys` together, return what they did to the sequence
+    fn press(&mut self, keys: &[(usize, usize)]) -> Vec<Outcome> {
+        let timed: Vec<_> = keys.iter().map(|&(row, col)| (row, col, self.ms)).collect();
+        self.ms += 100;
+        let actions = self.keyboard.press(&timed);
+        self.actions.extend(&actions);
+        // A combo's action comes with its first key
+        keys.iter()
+            .zip(actions)
+            .map(|(&(row, col), action)| {
+                let outcome = self.sequence.press(row as u8, col as u8, &action);
+                self.follow(outcome)
+            })
+            .collect()
+    }
+
+    fn release(&mut self, (row, col): (usize, usize)) {
+        self.keyboard.release(row, col);
+        self.sequence.release(row as u8, col as u8);
+    }
+
+    /// Tap `keys` one after the other
+    fn tap(&mut self, keys: &[(usize, usize)]) -> Vec<Outcome> {
+        keys.iter()
+            .flat_map(|&key| {
+                let outcomes = self.press(&[key]);
+                self.release(key);
+                outcomes
+            })
+            .collect()
+    }
+
+    /// `Comma` and `Dot` together
+    fn leader(&mut self) -> Vec<Outcome> {
+        let outcomes = self.press(&[COMMA, DOT]);
+        self.release(COMMA);
+        self.release(DOT);
+        outcomes
+    }
+
+    fn timeout(&mut self) -> Outcome {
+        let outcome = self.sequence.timeout();
+        self.follow(outcome)
+    }
+}
+
+const GIT: &LeaderAction = &LeaderAction::Text("git commit -m ");
+const EMAIL: &LeaderAction = &LeaderAction::Text("me@example.com");
+
+fn run(action: &'static LeaderAction) -> Outcome {
+    Outcome::Run(action)
+}
+
+#[test]
+fn sequences_are_generated_from_the_toml() {
+    assert_eq!(LEADER_TIMEOUT.as_millis(), 1000);
+    assert_eq!(LEADER_LAYER, 8);
+}
+
+#[test]
+fn sequence_after_the_leader_combo() {
+    let mut typist = Typist::new();
+    assert!(typist.leader() == [Outcome::Wait]);
+    assert!(typist.tap(&[G, C]) == [Outcome::Wait, run(GIT)]);
+    assert!(!typist.sequence.running());
+    // Without Leader, the keys are just typed
+    assert!(typist.tap(&[G, C]) == [Outcome::Ignore, Outcome::Ignore]);
+}
+
+#[test]
+fn keys_of_the_sequence_are_held_back() {
+    let mut typist = Typist::new();
+    typist.leader();
+    typist.tap(&[G, C]);
+    assert_eq!(typist.actions, [k!(User18), a!(No), a!(No)]);
+    // The leader layer is off again
+    typist.tap(&[G]);
+    assert_eq!(typist.actions.last(), Some(&k!(G)));
+}
+
+#[test]
+fn leader_layer_is_no_keys_but_the_layer_thumbs() {
+    let keyboard = Keyboard::new();
+    let mut keyboard_on = Keyboard::new();
+    keyboard_on.activate_layer(LEADER_LAYER);
+    for row in 0..8 {
+        for col in 0..6 {
+            let expected = match (row, col) {
+                SYMBOL | MIX => keyboard.action(row, col),
+                _ => a!(No),
+            };
+            assert_eq!(keyboard_on.action(row, col), expected);
+        }
+    }
+}
+
+#[test]
+fn leader_on_the_adjust_layer() {
+    let mut typist = Typist::new();
+    // X and C hold the adjust layer
+    assert!(typist.press(&[X, C]) == [Outcome::Ignore]);
+    assert!(typist.tap(&[LEADER]) == [Outcome::Wait]);
+    typist.release(X);
+    assert!(typist.tap(&[G, C]) == [Outcome::Wait, run(GIT)]);
+}
+
+#[test]
+fn digits_from_the_mix_layer() {
+    let mut typist = Typist::new();
+    typist.leader();
+    assert!(typist.tap(&[B, T]) == [Outcome::Wait, Outcome::Wait]);
+    // The layer key doesn't cancel the sequence, RMK still holds its layer
+    assert!(typist.press(&[MIX]) == [Outcome::Ignore]);
+    assert_eq!(typist.actions.last(), Some(&rmk::mo!(3)));
+    assert!(typist.tap(&[F]) == [run(&LeaderAction::BleProfile(1))]);
+}
+
+#[test]
+fn modifiers_and_layer_keys_are_ignored() {
+    let mut typist = Typist::new();
+    typist.leader();
+    assert!(typist.tap(&[G]) == [Outcome::Wait]);
+    assert!(typist.press(&[MIX]) == [Outcome::Ignore]);
+    assert!(typist.tap(&[LCTRL]) == [Outcome::Ignore]);
+    typist.release(MIX);
+    assert!(typist.tap(&[ADJUST]) == [Outcome::Ignore]);
+    assert!(typist.tap(&[C]) == [run(GIT)]);
+}
+
+#[test]
+fn other_keys_cancel() {
+    let mut typist = Typist::new();
+    typist.leader();
+    assert!(typist.tap(&[G, X, C]) == [Outcome::Wait, Outcome::Cancelled, Outcome::Ignore]);
+    // Held back as well, the keys after it are typed
+    assert_eq!(typist.actions[1..], [a!(No), a!(No), k!(C)]);
+    // A home row mod counts as its letter
+    typist.leader();
+    assert!(typist.tap(&[F]) == [Outcome::Cancelled]);
+}
+
+#[test]
+fn shifted_symbols_cancel() {
+    let mut typist = Typist::new();
+    typist.leader();
+    assert!(typist.press(&[SYMBOL]) == [Outcome::Ignore]);
+    assert!(typist.tap(&[F]) == [Outcome::Cancelled]);
+}
+
+#[test]
+fn tap_holds_count_as_their_tap() {
+    let mut typist = Typist::new();
+    typist.leader();
+    // Escape, whether or not the thumb is held for the number layer
+    assert!(typist.press(&[NUMBER]) == [Outcome::Cancelled]);
+    typist.release(NUMBER);
+    typist.leader();
+    assert!(typist.tap(&[G, DOT]) == [Outcome::Wait, Outcome::Cancelled]);
+}
+
+#[test]
+fn leader_combo_cancels_a_running_sequence() {
+    let mut typist = Typist::new();
+    typist.leader();
+    assert!(typist.tap(&[G]) == [Outcome::Wait]);
+    // Comma and Dot are held back, no combo
+    assert!(typist.leader() == [Outcome::Cancelled, Outcome::Ignore]);
+    assert!(typist.leader() == [Outcome::Wait]);
+    assert!(typist.tap(&[G, C]) == [Outcome::Wait, run(GIT)]);
+}
+
+#[test]
+fn start_of_a_longer_sequence_runs_on_timeout() {
+    let mut typist = Typist::new();
+    typist.leader();
+    assert!(typist.tap(&[E]) == [Outcome::Wait]);
+    assert!(typist.timeout() == run(&LeaderAction::Tap(KeyCode::Escape)));
+    assert!(!typist.sequence.running());
+
+    typist.leader();
+    assert!(typist.tap(&[E, M]) == [Outcome::Wait, run(EMAIL)]);
+}
+
+#[test]
+fn timeout_without_an_action_cancels() {
+    let mut typist = Typist::new();
+    typist.leader();
+    assert!(typist.timeout() == Outcome::Cancelled);
+    typist.leader();
+    assert!(typist.tap(&[B]) == [Outcome::Wait]);
+    assert!(typist.timeout() == Outcome::Cancelled);
+    // Nothing left to time out
+    assert!(typist.timeout() == Outcome::Ignore);
+    assert_eq!(typist.keyboard.layer(), 0);
+}
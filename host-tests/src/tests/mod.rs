@@ -0,0 +1,41 @@
+mod auto_shift;
+mod backup;
+mod behavior;
+mod blink;
+mod bonds;
+mod caps_word_keys;
+mod combo_keys;
+mod custom_hid_desc;
+mod display_render;
+mod identity;
+mod key_overrides;
+mod leader_keys;
+mod record_migration;
+mod repeat;
+mod schema;
+mod sim;
+mod split_frame;
+mod split_status;
+mod storage_backup;
+mod typing_stats;
+mod typing_stats_tool;
+mod underglow_render;
+
+// The firmware logs with defmt, the tests drop the logs
+
+#[defmt::global_logger]
+struct Logger;
+
+unsafe impl defmt::Logger for Logger {
+    fn acquire() {}
+    unsafe fn flush() {}
+    unsafe fn release() {}
+    unsafe fn write(_bytes: &[u8]) {}
+}
+
+#[defmt::panic_handler]
+fn panic() -> ! {
+    panic!("defmt panic")
+}
+
+defmt::timestamp!("{=u64}", 0);
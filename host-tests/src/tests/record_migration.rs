@@ -0,0 +1,385 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use embassy_futures::block_on;
+use embedded_storage_async::nor_flash::{
+    ErrorType, MultiwriteNorFlash, NorFlash, NorFlashErrorKind, ReadNorFlash,
+};
+use sequential_storage::cache::NoCache;
+use sequential_storage::map::{fetch_all_items, fetch_item, store_item};
+
+use crate::record_migration::{
+    Geometry, Migration, MigrationError, SchemaHeader, migrate_records, stored_geometry,
+};
+
+const SECTOR_SIZE: usize = 4096;
+
+/// RMK's storage, 6 sectors
+const RANGE: Range<u32> = 0..6 * SECTOR_SIZE as u32;
+
+/// The Corne's keymap, both halves stacked
+const LEGACY: Geometry = Geometry {
+    layers: 8,
+    rows: 8,
+    cols: 6,
+};
+
+/// Fewer layers, the halves side by side
+const SIDE_BY_SIDE: Geometry = Geometry {
+    layers: 6,
+    rows: 4,
+    cols: 12,
+};
+
+/// NVMC of the nRF52840: 4-byte words, 4 KiB pages, bits only cleared by writes
+///
+/// After `power_loss_after` writes and erases every further one fails, as
+/// when the power is cut.
+struct RamFlash {
+    data: Vec<u8>,
+    power_loss_after: Option<usize>,
+    operations: usize,
+}
+
+impl RamFlash {
+    fn new() -> Self {
+        Self {
+            data: vec![0xFF; RANGE.end as usize],
+            power_loss_after: None,
+            operations: 0,
+        }
+    }
+
+    fn power_lost(&mut self) -> bool {
+        self.operations += 1;
+        self.power_loss_after
+            .is_some_and(|after| self.operations > after)
+    }
+
+    fn restore_power(&mut self) {
+        self.power_loss_after = None;
+    }
+}
+
+impl ErrorType for RamFlash {
+    type Error = NorFlashErrorKind;
+}
+
+impl ReadNorFlash for RamFlash {
+    const READ_SIZE: usize = 1;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let start = offset as usize;
+        bytes.copy_from_slice(&self.data[start..start + bytes.len()]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl NorFlash for RamFlash {
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = SECTOR_SIZE;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        if self.power_lost() {
+            return Err(NorFlashErrorKind::Other);
+        }
+        self.data[from as usize..to as usize].fill(0xFF);
+        Ok(())
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        if self.power_lost() {
+            return Err(NorFlashErrorKind::Other);
+        }
+        let start = offset as usize;
+        for (cell, byte) in self.data[start..start + bytes.len()].iter_mut().zip(bytes) {
+            *cell &= byte;
+        }
+        Ok(())
+    }
+}
+
+impl MultiwriteNorFlash for RamFlash {}
+
+fn keymap_key(geometry: Geometry, (layer, row, col): (u8, u8, u8)) -> u32 {
+    0x1000 + (layer as u32 * geometry.rows as u32 + row as u32) * geometry.cols as u32 + col as u32
+}
+
+/// A keymap entry as RMK 0.8 stores it: tag, row, col, layer and the action
+fn keymap_value((layer, row, col): (u8, u8, u8)) -> Vec<u8> {
+    vec![0x01, row, col, layer, layer ^ row, col]
+}
+
+/// Every position of `geometry`
+fn positions(geometry: Geometry) -> Vec<(u8, u8, u8)> {
+    let mut all = Vec::new();
+    for layer in 0..geometry.layers {
+        for row in 0..geometry.rows {
+            for col in 0..geometry.cols {
+                all.push((layer, row, col));
+            }
+        }
+    }
+    all
+}
+
+/// Records other than the keymap: bonds, peripheral address, macros
+fn other_records() -> Vec<(u32, Vec<u8>)> {
+    vec![
+        (0x0001, vec![0xAA; 8]),
+        (0x0100, vec![1, 2, 3, 4, 5, 6]),
+        (0x0101, vec![7; 40]),
+        (0x3000, vec![0x42; 300]),
+    ]
+}
+
+fn store(flash: &mut RamFlash, key: u32, value: &[u8]) {
+    let mut buf = [0; 512];
+    block_on(store_item(
+        flash,
+        RANGE,
+        &mut NoCache::new(),
+        &mut buf,
+        &key,
+        &value,
+    ))
+    .unwrap();
+}
+
+/// Storage of a board whose whole keymap was remapped in `geometry`
+fn fixture(geometry: Geometry) -> RamFlash {
+    let mut flash = RamFlash::new();
+    for (key, value) in other_records() {
+        // A superseded value first, as after a change from Vial
+        store(&mut flash, key, &[0xEE]);
+        store(&mut flash, key, &value);
+    }
+    for position in positions(geometry) {
+        store(
+            &mut flash,
+            keymap_key(geometry, position),
+            &keymap_value(position),
+        );
+    }
+    flash
+}
+
+/// Latest value of every key
+fn records(flash: &mut RamFlash) -> BTreeMap<u32, Vec<u8>> {
+    let mut keys = Vec::new();
+    block_on(async {
+        let mut buf = [0; 512];
+        let mut cache = NoCache::new();
+        let mut iter = fetch_all_items::<u32, _, _>(flash, RANGE, &mut cache, &mut buf)
+            .await
+            .unwrap();
+        let mut item_buf = [0; 512];
+        while let Some((key, _)) = iter.next::<&[u8]>(&mut item_buf).await.unwrap() {
+            keys.push(key);
+        }
+    });
+    let mut all = BTreeMap::new();
+    for key in keys {
+        let mut buf = [0; 512];
+        let value = block_on(fetch_item::<u32, &[u8], _>(
+            flash,
+            RANGE,
+            &mut NoCache::new(),
+            &mut buf,
+            &key,
+        ))
+        .unwrap();
+        if let Some(value) = value {
+            all.insert(key, value.to_vec());
+        }
+    }
+    all
+}
+
+/// Records after moving the fixture of `from` to `to`
+fn expected(from: Geometry, to: Geometry) -> BTreeMap<u32, Vec<u8>> {
+    let mut all: BTreeMap<_, _> = other_records().into_iter().collect();
+    for position in positions(from) {
+        let (layer, row, col) = position;
+        if layer < to.layers && row < to.rows && col < to.cols {
+            all.insert(keymap_key(to, position), keymap_value(position));
+        }
+    }
+    all
+}
+
+fn header(version: u16, geometry: Geometry) -> SchemaHeader {
+    SchemaHeader { version, geometry }
+}
+
+fn migrate(
+    flash: &mut RamFlash,
+    from: SchemaHeader,
+    to: SchemaHeader,
+    migrations: &[Migration],
+) -> Result<usize, MigrationError> {
+    block_on(migrate_records(flash, RANGE, from, to, migrations))
+}
+
+#[test]
+fn same_schema_leaves_the_storage_untouched() {
+    let mut flash = fixture(SIDE_BY_SIDE);
+    let image = flash.data.clone();
+    assert_eq!(
+        migrate(
+            &mut flash,
+            header(1, SIDE_BY_SIDE),
+            header(1, SIDE_BY_SIDE),
+            &[]
+        ),
+        Ok(0)
+    );
+    assert_eq!(flash.data, image);
+}
+
+#[test]
+fn a_version_bump_without_changes_writes_nothing() {
+    let mut flash = fixture(LEGACY);
+    let image = flash.data.clone();
+    assert_eq!(
+        migrate(
+            &mut flash,
+            header(0, LEGACY),
+            header(1, LEGACY),
+            &[|_, _| true]
+        ),
+        Ok(0)
+    );
+    assert_eq!(flash.data, image);
+}
+
+#[test]
+fn version_migrations_rewrite_and_drop_records() {
+    let mut flash = fixture(SIDE_BY_SIDE);
+    // Bumps the first byte of 0x0100 to 0x10 and drops 0x0101
+    let migration: Migration = |key, value| {
+        if *key == 0x0100 {
+            value[0] = 0x10;
+        }
+        *key != 0x0101
+    };
+    let mut want = expected(SIDE_BY_SIDE, SIDE_BY_SIDE);
+    want.get_mut(&0x0100).unwrap()[0] = 0x10;
+    want.remove(&0x0101);
+
+    assert_eq!(
+        migrate(
+            &mut flash,
+            header(1, SIDE_BY_SIDE),
+            header(2, SIDE_BY_SIDE),
+            &[migration]
+        ),
+        Ok(2)
+    );
+    assert_eq!(records(&mut flash), want);
+    // Running it again changes nothing
+    assert_eq!(
+        migrate(
+            &mut flash,
+            header(1, SIDE_BY_SIDE),
+            header(2, SIDE_BY_SIDE),
+            &[migration]
+        ),
+        Ok(0)
+    );
+}
+
+#[test]
+fn keymap_entries_move_to_the_new_geometry() {
+    let mut flash = fixture(LEGACY);
+    migrate(
+        &mut flash,
+        header(0, LEGACY),
+        header(1, SIDE_BY_SIDE),
+        &[|_, _| true],
+    )
+    .unwrap();
+    assert_eq!(records(&mut flash), expected(LEGACY, SIDE_BY_SIDE));
+}
+
+#[test]
+fn keymap_entries_move_to_a_larger_geometry() {
+    let mut flash = fixture(SIDE_BY_SIDE);
+    migrate(&mut flash, header(1, SIDE_BY_SIDE), header(1, LEGACY), &[]).unwrap();
+    assert_eq!(records(&mut flash), expected(SIDE_BY_SIDE, LEGACY));
+}
+
+#[test]
+fn a_migration_cut_short_resumes_at_the_next_boot() {
+    // Small enough to cut the migration short after every single write
+    let (old, new) = (
+        Geometry {
+            layers: 2,
+            rows: 4,
+            cols: 3,
+        },
+        Geometry {
+            layers: 2,
+            rows: 3,
+            cols: 4,
+        },
+    );
+    let want = expected(old, new);
+    let mut cut_short = 0;
+    for power_loss_after in 0.. {
+        let mut flash = fixture(old);
+        flash.operations = 0;
+        flash.power_loss_after = Some(power_loss_after);
+        let first = migrate(&mut flash, header(0, old), header(1, new), &[|_, _| true]);
+        flash.restore_power();
+        if first.is_ok() {
+            assert_eq!(records(&mut flash), want);
+            break;
+        }
+        cut_short += 1;
+        // Every record RMK reads is still intact before the migration resumes
+        let before = records(&mut flash);
+        for (key, value) in other_records() {
+            assert_eq!(
+                before.get(&key),
+                Some(&value),
+                "{power_loss_after}: {key:x}"
+            );
+        }
+        // The next boot still reads the old geometry from the entries
+        assert_eq!(
+            block_on(stored_geometry(&mut flash, RANGE)),
+            Ok(Some(old)),
+            "{power_loss_after}"
+        );
+        migrate(&mut flash, header(0, old), header(1, new), &[|_, _| true]).unwrap();
+        assert_eq!(
+            records(&mut flash),
+            want,
+            "power lost after {power_loss_after}"
+        );
+    }
+    assert!(cut_short > 0);
+}
+
+#[test]
+fn short_values_in_the_keymap_range_are_kept() {
+    let mut flash = fixture(SIDE_BY_SIDE);
+    store(&mut flash, 0x1000, &[0x01, 0x00]);
+    let mut want = expected(SIDE_BY_SIDE, LEGACY);
+    want.remove(&0x1000);
+    migrate(&mut flash, header(1, SIDE_BY_SIDE), header(1, LEGACY), &[]).unwrap();
+    let got = records(&mut flash);
+    // Position (0, 0, 0) lost its value, the short one stays where it was
+    assert_eq!(got.get(&0x1000), Some(&vec![0x01, 0x00]));
+    assert_eq!(
+        got.into_iter()
+            .filter(|(key, _)| *key != 0x1000)
+            .collect::<BTreeMap<_, _>>(),
+        want
+    );
+}
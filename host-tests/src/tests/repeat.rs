@@ -0,0 +1,162 @@
+use std::sync::Mutex;
+
+use embassy_futures::block_on;
+use rmk::channel::KEYBOARD_REPORT_CHANNEL;
+use rmk::controller::Controller;
+use rmk::event::{ControllerEvent, KeyboardEvent};
+use rmk::hid::Report;
+use rmk::types::action::KeyAction;
+use rmk::types::keycode::KeyCode;
+use rmk::types::modifier::ModifierCombination;
+
+use super::sim::Keyboard;
+use crate::repeat::RepeatController;
+
+/// The report channel is global, one host at a time
+static ONE_HOST: Mutex<()> = Mutex::new(());
+
+const LSHIFT: u8 = 0x02;
+
+// Keys on the central's matrix
+const X: (usize, usize) = (2, 2);
+const A: (usize, usize) = (1, 1);
+/// `MT(F, LShift)`
+const F: (usize, usize) = (1, 4);
+/// `LT(5, Enter)`, holds the navigation layer
+const NAV: (usize, usize) = (7, 3);
+// On the navigation layer
+const REPEAT: (usize, usize) = (3, 4);
+const ALT_REPEAT: (usize, usize) = (3, 5);
+const LEFT: (usize, usize) = (5, 1);
+
+/// The central's keymap with the repeat controller on RMK's events
+struct Host {
+    keyboard: Keyboard,
+    repeat: RepeatController,
+    /// Keys down with the action RMK reports on their release
+    down: Vec<((usize, usize), KeyAction)>,
+}
+
+impl Host {
+    fn new() -> Self {
+        while KEYBOARD_REPORT_CHANNEL.try_receive().is_ok() {}
+        Self {
+            keyboard: Keyboard::new(),
+            repeat: RepeatController::new(),
+            down: Vec::new(),
+        }
+    }
+
+    fn event(&mut self, event: ControllerEvent) {
+        block_on(self.repeat.process_event(event));
+    }
+
+    fn press(&mut self, (row, col): (usize, usize)) {
+        let action = self.keyboard.press(&[(row, col, 0)])[0];
+        self.down.push(((row, col), action));
+        self.event(ControllerEvent::Key(
+            KeyboardEvent::key(row as u8, col as u8, true),
+            action,
+        ));
+    }
+
+    fn release(&mut self, (row, col): (usize, usize)) {
+        let i = self.down.iter().position(|&(key, _)| key == (row, col));
+        let (_, action) = self.down.remove(i.unwrap());
+        self.keyboard.release(row, col);
+        self.event(ControllerEvent::Key(
+            KeyboardEvent::key(row as u8, col as u8, false),
+            action,
+        ));
+    }
+
+    fn tap(&mut self, key: (usize, usize)) {
+        self.press(key);
+        self.release(key);
+    }
+
+    /// The modifiers RMK reports held
+    fn modifiers(&mut self, modifiers: ModifierCombination) {
+        self.event(ControllerEvent::Modifier(modifiers));
+    }
+
+    /// Hold the navigation thumb until RMK decides it is held
+    fn hold_nav(&mut self) {
+        self.press(NAV);
+        self.keyboard.activate_layer(5);
+        self.event(ControllerEvent::Layer(5));
+    }
+
+    /// The reports sent since the last call, modifiers and keys
+    fn reports(&mut self) -> Vec<(u8, [u8; 6])> {
+        let mut reports = Vec::new();
+        while let Ok(Report::KeyboardReport(report)) = KEYBOARD_REPORT_CHANNEL.try_receive() {
+            reports.push((report.modifier, report.keycodes));
+        }
+        reports
+    }
+}
+
+fn keys(codes: &[KeyCode]) -> [u8; 6] {
+    let mut keys = [0; 6];
+    for (slot, &code) in keys.iter_mut().zip(codes) {
+        *slot = code as u8;
+    }
+    keys
+}
+
+#[test]
+fn repeat_sends_the_last_key_across_the_layer_change() {
+    let _one = ONE_HOST.lock().unwrap_or_else(|e| e.into_inner());
+    let mut host = Host::new();
+    host.tap(X);
+    host.hold_nav();
+    host.tap(REPEAT);
+    assert_eq!(host.reports(), [(0, keys(&[KeyCode::X])), (0, keys(&[]))]);
+}
+
+#[test]
+fn alt_repeat_sends_the_alternate_and_keeps_the_held_key() {
+    let _one = ONE_HOST.lock().unwrap_or_else(|e| e.into_inner());
+    let mut host = Host::new();
+    host.hold_nav();
+    host.press(LEFT);
+    host.tap(ALT_REPEAT);
+    assert_eq!(
+        host.reports(),
+        [
+            (0, keys(&[KeyCode::Left, KeyCode::Right])),
+            (0, keys(&[KeyCode::Left])),
+        ]
+    );
+}
+
+#[test]
+fn a_tapped_home_row_mod_is_repeated() {
+    let _one = ONE_HOST.lock().unwrap_or_else(|e| e.into_inner());
+    let mut host = Host::new();
+    host.tap(A);
+    host.hold_nav();
+    host.tap(REPEAT);
+    assert_eq!(host.reports(), [(0, keys(&[KeyCode::A])), (0, keys(&[]))]);
+}
+
+#[test]
+fn a_held_home_row_mod_stays_held_and_shifts_the_repeat() {
+    let _one = ONE_HOST.lock().unwrap_or_else(|e| e.into_inner());
+    let mut host = Host::new();
+    host.tap(X);
+    host.press(F);
+    host.modifiers(ModifierCombination::LSHIFT);
+    host.hold_nav();
+    host.tap(REPEAT);
+    assert_eq!(
+        host.reports(),
+        [(LSHIFT, keys(&[KeyCode::X])), (LSHIFT, keys(&[]))]
+    );
+    // Held, the home row mod isn't the last key
+    host.modifiers(ModifierCombination::default());
+    host.release(F);
+    host.tap(REPEAT);
+    assert_eq!(host.reports(), [(0, keys(&[KeyCode::X])), (0, keys(&[]))]);
+}
@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use embassy_embedded_hal::flash::partition::Partition;
+use embassy_futures::block_on;
+use embassy_sync::mutex::Mutex;
+use nrf_mpsl::Flash;
+use sequential_storage::cache::NoCache;
+use sequential_storage::map::{fetch_all_items, fetch_item, store_item};
+
+use crate::app_storage::{AppStorage, FlashPartition, RecordKind, SharedFlash, record_key};
+use crate::flash_wear::WearCountingFlash;
+use crate::keymap::{COL, NUM_LAYER, ROW};
+use crate::record_migration::{Geometry, SchemaHeader, stored_geometry};
+use crate::schema::{SCHEMA_VERSION, migrate};
+use crate::storage_layout::{
+    APP_STORAGE_SECTORS, APP_STORAGE_START, RMK_STORAGE_SECTORS, RMK_STORAGE_START, SECTOR_SIZE,
+};
+
+/// RMK's storage of a Corne built with 5 layers
+const FIVE_LAYERS: &str = include_str!("../../fixtures/rmk-0.8-corne-5-layers.txt");
+
+const RANGE: Range<u32> = 0..RMK_STORAGE_SECTORS as u32 * SECTOR_SIZE;
+
+const CURRENT: Geometry = Geometry {
+    layers: NUM_LAYER as u8,
+    rows: ROW as u8,
+    cols: COL as u8,
+};
+
+/// Records of a fixture in the order they were stored
+fn fixture_records(fixture: &str) -> Vec<(u32, Vec<u8>)> {
+    fixture
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let (key, value) = line.split_once(' ').unwrap();
+            let value = (0..value.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&value[i..i + 2], 16).unwrap())
+                .collect();
+            (u32::from_str_radix(key, 16).unwrap(), value)
+        })
+        .collect()
+}
+
+/// RMK's storage and the firmware-owned records on an erased flash
+fn storages() -> (FlashPartition, AppStorage) {
+    let flash: &'static SharedFlash =
+        Box::leak(Box::new(Mutex::new(WearCountingFlash::new(Flash::new()))));
+    let rmk = Partition::new(flash, RMK_STORAGE_START, RANGE.end);
+    let app = Partition::new(flash, APP_STORAGE_START, APP_STORAGE_SECTORS * SECTOR_SIZE);
+    (rmk, AppStorage::new(app))
+}
+
+fn store(rmk: &mut FlashPartition, records: &[(u32, Vec<u8>)]) {
+    let mut buf = [0; 512];
+    for (key, value) in records {
+        block_on(store_item(
+            rmk,
+            RANGE,
+            &mut NoCache::new(),
+            &mut buf,
+            key,
+            &value.as_slice(),
+        ))
+        .unwrap();
+    }
+}
+
+/// Latest value of every key
+fn records(rmk: &mut FlashPartition) -> BTreeMap<u32, Vec<u8>> {
+    let mut keys = Vec::new();
+    block_on(async {
+        let mut buf = [0; 512];
+        let mut cache = NoCache::new();
+        let mut iter = fetch_all_items::<u32, _, _>(rmk, RANGE, &mut cache, &mut buf)
+            .await
+            .unwrap();
+        let mut item_buf = [0; 512];
+        while let Some((key, _)) = iter.next::<&[u8]>(&mut item_buf).await.unwrap() {
+            keys.push(key);
+        }
+    });
+    let mut all = BTreeMap::new();
+    for key in keys {
+        let mut buf = [0; 512];
+        let value = block_on(fetch_item::<u32, &[u8], _>(
+            rmk,
+            RANGE,
+            &mut NoCache::new(),
+            &mut buf,
+            &key,
+        ))
+        .unwrap();
+        if let Some(value) = value {
+            all.insert(key, value.to_vec());
+        }
+    }
+    all
+}
+
+fn header(app: &mut AppStorage) -> Option<SchemaHeader> {
+    block_on(app.fetch(record_key(RecordKind::Schema, 0)))
+}
+
+#[test]
+fn the_geometry_is_read_from_the_fixture() {
+    let (mut rmk, _) = storages();
+    store(&mut rmk, &fixture_records(FIVE_LAYERS));
+    let geometry = Geometry {
+        layers: 5,
+        rows: 8,
+        cols: 6,
+    };
+    assert_eq!(
+        block_on(stored_geometry(&mut rmk, RANGE)),
+        Ok(Some(geometry))
+    );
+}
+
+#[test]
+fn an_empty_storage_has_no_geometry() {
+    let (mut rmk, _) = storages();
+    assert_eq!(block_on(stored_geometry(&mut rmk, RANGE)), Ok(None));
+}
+
+#[test]
+fn the_fixture_migrates_to_the_current_keymap() {
+    let (mut rmk, mut app) = storages();
+    let fixture = fixture_records(FIVE_LAYERS);
+    store(&mut rmk, &fixture);
+    let before = records(&mut rmk);
+
+    block_on(migrate(&mut rmk, &mut app));
+    assert_eq!(
+        header(&mut app),
+        Some(SchemaHeader {
+            version: SCHEMA_VERSION,
+            geometry: CURRENT,
+        })
+    );
+    // More layers only add keys, every entry is back at its key
+    let after = records(&mut rmk);
+    assert_eq!(after, before);
+    // The remaps from Vial kept their last value
+    assert_eq!(after[&(0x1000 + (8 + 2) * 6 + 3)], [1, 2, 3, 1, 0, 0x2c]);
+    assert_eq!(
+        after[&(0x1000 + (4 * 8 + 7) * 6 + 5)],
+        [1, 7, 5, 4, 0, 0x39]
+    );
+    assert_eq!(after[&0x3000], fixture.last().unwrap().1);
+
+    // The next boot finds the header and leaves the storage alone
+    let image = records(&mut rmk);
+    block_on(migrate(&mut rmk, &mut app));
+    assert_eq!(records(&mut rmk), image);
+}
+
+#[test]
+fn an_empty_storage_only_gets_the_header() {
+    let (mut rmk, mut app) = storages();
+    block_on(migrate(&mut rmk, &mut app));
+    assert!(records(&mut rmk).is_empty());
+    assert_eq!(
+        header(&mut app),
+        Some(SchemaHeader {
+            version: SCHEMA_VERSION,
+            geometry: CURRENT,
+        })
+    );
+}
+
+#[test]
+fn a_newer_schema_is_left_alone() {
+    let (mut rmk, mut app) = storages();
+    store(&mut rmk, &fixture_records(FIVE_LAYERS));
+    let newer = SchemaHeader {
+        version: SCHEMA_VERSION + 1,
+        geometry: Geometry {
+            layers: 5,
+            rows: 8,
+            cols: 6,
+        },
+    };
+    block_on(app.store(record_key(RecordKind::Schema, 0), &newer));
+    let before = records(&mut rmk);
+    block_on(migrate(&mut rmk, &mut app));
+    assert_eq!(records(&mut rmk), before);
+    assert_eq!(header(&mut app), Some(newer));
+}
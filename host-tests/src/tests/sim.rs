@@ -0,0 +1,230 @@
+//! Keymap simulator
+//!
+//! Presses keys of the central's keymap the way RMK resolves them: a key does
+//! what the highest active layer that isn't transparent there says, `MO` keys
+//! hold their layer while down, the upper and lower layer of the tri-layer
+//! together activate its adjust layer, and keys pressed within the combo timeout
+//! whose actions are those of a combo on the active layer send its output
+//! instead. [`Keyboard::press`] gives the actions the controllers see,
+//! [`Keyboard::type_keys`] what the host gets once RMK's morse timer decided
+//! the tap-holds and [`Keyboard::tap`] what a key sends with modifiers held,
+//! its key overrides applied.
+
+use rmk::config::ForksConfig;
+use rmk::fork::Fork;
+use rmk::types::action::{Action, KeyAction, MorseMode};
+
+use crate::auto_shift;
+use crate::behavior::TRI_LAYER;
+use crate::combo_keys::{COMBO_TIMEOUT, COMBOS, Keymap};
+use crate::key_overrides;
+use crate::keymap::{MORSE_DEFAULT, PRIOR_IDLE_TIME_MS, get_default_keymap};
+
+/// A combo as RMK gets it from [`combos`](crate::combos)
+struct Combo {
+    actions: Vec<KeyAction>,
+    output: KeyAction,
+    layer: Option<u8>,
+}
+
+pub(crate) struct Keyboard {
+    keymap: Keymap,
+    combos: Vec<Combo>,
+    forks: Vec<Fork>,
+    /// Layers held by keys that are down, with the keys holding them
+    held: Vec<(Vec<(usize, usize)>, u8)>,
+    /// Layers a controller turned on
+    on: Vec<u8>,
+}
+
+impl Keyboard {
+    /// The keymap, the key overrides and the combos the central configures,
+    /// auto shift applied
+    pub(crate) fn new() -> Self {
+        let mut keymap = get_default_keymap();
+        auto_shift::apply(&mut keymap);
+        let mut forks = ForksConfig::default();
+        key_overrides::configure(&mut forks, &keymap);
+        let combos = COMBOS
+            .iter()
+            .filter_map(|def| {
+                Some(Combo {
+                    actions: def.actions(&keymap)?.collect(),
+                    output: def.output,
+                    layer: def.layer,
+                })
+            })
+            .collect();
+        Self {
+            keymap,
+            combos,
+            forks: forks.forks.into_iter().collect(),
+            held: Vec::new(),
+            on: Vec::new(),
+        }
+    }
+
+    /// Number of combos that found all of their keys
+    pub(crate) fn combos(&self) -> usize {
+        self.combos.len()
+    }
+
+    /// The active layers, highest first
+    fn layers(&self) -> Vec<u8> {
+        let mut layers: Vec<u8> = self.held.iter().map(|&(_, layer)| layer).collect();
+        layers.extend(&self.on);
+        if let Some([upper, lower, adjust]) = TRI_LAYER
+            && layers.contains(&upper)
+            && layers.contains(&lower)
+        {
+            layers.push(adjust);
+        }
+        layers.push(0);
+        layers.sort_unstable_by(|a, b| b.cmp(a));
+        layers
+    }
+
+    /// The active layer, the highest one
+    pub(crate) fn layer(&self) -> u8 {
+        self.layers()[0]
+    }
+
+    /// What the key at `row`, `col` does on the active layers
+    pub(crate) fn action(&self, row: usize, col: usize) -> KeyAction {
+        let layers = self.layers();
+        layers
+            .into_iter()
+            .map(|layer| self.keymap[layer as usize][row][col])
+            .find(|action| *action != KeyAction::Transparent)
+            .unwrap_or(KeyAction::No)
+    }
+
+    /// Hold the layer of an `MO` action for `keys`
+    fn hold(&mut self, keys: Vec<(usize, usize)>, action: KeyAction) {
+        if let KeyAction::Single(Action::LayerOn(layer)) = action {
+            self.held.push((keys, layer));
+        }
+    }
+
+    /// Press `keys`, each `(row, col, ms)`, and return what the keyboard does
+    ///
+    /// The keys stay down until [`release`](Self::release).
+    pub(crate) fn press(&mut self, keys: &[(usize, usize, u64)]) -> Vec<KeyAction> {
+        let actions: Vec<KeyAction> = keys
+            .iter()
+            .map(|&(row, col, _)| self.action(row, col))
+            .collect();
+        let first = keys.iter().map(|&(_, _, ms)| ms).min().unwrap_or(0);
+        let last = keys.iter().map(|&(_, _, ms)| ms).max().unwrap_or(0);
+        let together = keys.len() > 1 && last - first < COMBO_TIMEOUT.as_millis();
+        let layer = self.layer();
+        let combo = self.combos.iter().find(|combo| {
+            let mut wanted = combo.actions.clone();
+            together
+                && combo.layer.is_none_or(|l| l == layer)
+                && actions.len() == wanted.len()
+                && actions.iter().all(|action| {
+                    let found = wanted.iter().position(|a| a == action);
+                    found.map(|i| wanted.swap_remove(i)).is_some()
+                })
+        });
+        match combo.map(|combo| combo.output) {
+            Some(output) => {
+                let positions = keys.iter().map(|&(row, col, _)| (row, col)).collect();
+                self.hold(positions, output);
+                vec![output]
+            }
+            None => {
+                for (&(row, col, _), &action) in keys.iter().zip(&actions) {
+                    self.hold(vec![(row, col)], action);
+                }
+                actions
+            }
+        }
+    }
+
+    /// Type `keys`, each `(row, col, down_ms, up_ms)`, return what the host gets
+    ///
+    /// A tap-hold is decided like RMK's morse timer does, by its profile and
+    /// [`MORSE_DEFAULT`] for what the profile leaves out: pressed within
+    /// [`PRIOR_IDLE_TIME_MS`] of the key before it taps (flow tap), held for
+    /// the hold timeout it holds, and before that its mode decides on the keys
+    /// pressed meanwhile. Unilateral tap is not simulated. The layers stay as
+    /// they are.
+    pub(crate) fn type_keys(&self, keys: &[(usize, usize, u64, u64)]) -> Vec<Action> {
+        let mut keys = keys.to_vec();
+        keys.sort_by_key(|&(_, _, down, _)| down);
+        let mut previous: Option<u64> = None;
+        let mut typed = Vec::new();
+        for &(row, col, down, up) in &keys {
+            let action = match self.action(row, col) {
+                KeyAction::Single(action) | KeyAction::Tap(action) => action,
+                KeyAction::No | KeyAction::Transparent => Action::No,
+                KeyAction::TapHold(tap, hold, profile) => {
+                    let timeout = profile
+                        .hold_timeout_ms()
+                        .or(MORSE_DEFAULT.hold_timeout_ms())
+                        .unwrap() as u64;
+                    let mode = profile.mode().or(MORSE_DEFAULT.mode()).unwrap();
+                    // Keys pressed while it is down, with their release
+                    let mut others = keys
+                        .iter()
+                        .filter(|&&(_, _, other_down, _)| other_down > down && other_down < up)
+                        .map(|&(_, _, _, other_up)| other_up);
+                    let held = if previous.is_some_and(|last| down - last < PRIOR_IDLE_TIME_MS) {
+                        false
+                    } else if up - down >= timeout {
+                        true
+                    } else {
+                        match mode {
+                            MorseMode::Normal => false,
+                            MorseMode::HoldOnOtherPress => others.next().is_some(),
+                            MorseMode::PermissiveHold => others.any(|other_up| other_up < up),
+                        }
+                    };
+                    if held { hold } else { tap }
+                }
+            };
+            typed.push(action);
+            previous = Some(down);
+        }
+        typed
+    }
+
+    /// Tap the key at `row`, `col` with the HID `modifiers` held, return what
+    /// the host gets and the modifiers sent along
+    ///
+    /// A fork the key's action triggers sends its positive output while any
+    /// of its `match_any` and none of its `match_none` modifiers are held,
+    /// without the `match_any` ones it doesn't keep, like RMK's forks do.
+    pub(crate) fn tap(&self, row: usize, col: usize, modifiers: u8) -> (KeyAction, u8) {
+        let action = self.action(row, col);
+        let Some(fork) = self.forks.iter().find(|fork| fork.trigger == action) else {
+            return (action, modifiers);
+        };
+        let any = fork.match_any.modifiers.into_bits();
+        let none = fork.match_none.modifiers.into_bits();
+        if modifiers & any != 0 && modifiers & none == 0 {
+            let dropped = any & !fork.kept_modifiers.into_bits();
+            (fork.positive_output, modifiers & !dropped)
+        } else {
+            (fork.negative_output, modifiers)
+        }
+    }
+
+    /// Turn `layer` on, like a controller through RMK's keymap
+    pub(crate) fn activate_layer(&mut self, layer: u8) {
+        if !self.on.contains(&layer) {
+            self.on.push(layer);
+        }
+    }
+
+    pub(crate) fn deactivate_layer(&mut self, layer: u8) {
+        self.on.retain(|&l| l != layer);
+    }
+
+    /// Release the key at `row`, `col`, a layer it holds is left
+    pub(crate) fn release(&mut self, row: usize, col: usize) {
+        self.held.retain(|(keys, _)| !keys.contains(&(row, col)));
+    }
+}
@@ -0,0 +1,201 @@
+use crate::split_frame::{
+    Decoded, FRAME_OVERHEAD, FrameDecoder, MAX_PAYLOAD, SOF, crc16, encode_frame,
+};
+
+fn frame(seq: u8, payload: &[u8]) -> Vec<u8> {
+    let mut out = [0; MAX_PAYLOAD + FRAME_OVERHEAD];
+    let n = encode_frame(seq, payload, &mut out);
+    out[..n].to_vec()
+}
+
+/// Feed `bytes`, returns what the decoder completed with the payloads of the frames
+fn decode(decoder: &mut FrameDecoder, bytes: &[u8]) -> Vec<(Decoded, Vec<u8>)> {
+    let mut decoded = Vec::new();
+    for &byte in bytes {
+        decoder.push(byte);
+        loop {
+            match decoder.poll() {
+                Decoded::Pending => break,
+                Decoded::Frame => decoded.push((Decoded::Frame, decoder.payload().to_vec())),
+                Decoded::Corrupt => decoded.push((Decoded::Corrupt, Vec::new())),
+            }
+        }
+    }
+    decoded
+}
+
+/// Feed `bytes`, returns what the decoder completed
+fn feed(decoder: &mut FrameDecoder, bytes: &[u8]) -> Vec<Decoded> {
+    decode(decoder, bytes)
+        .into_iter()
+        .map(|(decoded, _)| decoded)
+        .collect()
+}
+
+#[test]
+fn crc16_check_value() {
+    // Check value of CRC-16/CCITT-FALSE
+    assert_eq!(crc16(b"123456789"), 0x29B1);
+    assert_eq!(crc16(&[]), 0xFFFF);
+}
+
+#[test]
+fn frame_layout() {
+    let encoded = frame(0x81, &[1, 2, 3]);
+    assert_eq!(encoded.len(), 3 + FRAME_OVERHEAD);
+    assert_eq!(&encoded[..6], &[SOF, 3, 0x81, 1, 2, 3]);
+    let crc = crc16(&[3, 0x81, 1, 2, 3]);
+    assert_eq!(&encoded[6..], &crc.to_le_bytes());
+}
+
+#[test]
+fn round_trip() {
+    let mut decoder = FrameDecoder::new();
+    let payload: Vec<u8> = (0..MAX_PAYLOAD as u8).collect();
+    assert_eq!(feed(&mut decoder, &frame(0x05, &payload)), [Decoded::Frame]);
+    assert_eq!(decoder.seq(), 0x05);
+    assert_eq!(decoder.payload(), &payload[..]);
+}
+
+#[test]
+fn heartbeat_has_no_payload() {
+    let mut decoder = FrameDecoder::new();
+    assert_eq!(feed(&mut decoder, &frame(0x80, &[])), [Decoded::Frame]);
+    assert_eq!(decoder.seq(), 0x80);
+    assert!(decoder.payload().is_empty());
+}
+
+#[test]
+fn frames_back_to_back() {
+    let mut decoder = FrameDecoder::new();
+    let mut stream = frame(1, b"ab");
+    stream.extend(frame(2, b"cde"));
+    assert_eq!(
+        decode(&mut decoder, &stream),
+        [
+            (Decoded::Frame, b"ab".to_vec()),
+            (Decoded::Frame, b"cde".to_vec())
+        ]
+    );
+    assert_eq!(decoder.seq(), 2);
+}
+
+#[test]
+fn noise_before_a_frame_is_skipped() {
+    let mut decoder = FrameDecoder::new();
+    let mut stream = vec![0x00, 0xFF, 0x13];
+    stream.extend(frame(7, b"x"));
+    assert_eq!(feed(&mut decoder, &stream), [Decoded::Frame]);
+    assert_eq!(decoder.payload(), b"x");
+}
+
+#[test]
+fn bad_crc_is_dropped_and_the_next_frame_decodes() {
+    let mut decoder = FrameDecoder::new();
+    let mut stream = frame(1, b"hello");
+    stream[4] ^= 0x01;
+    stream.extend(frame(2, b"world"));
+    assert_eq!(
+        feed(&mut decoder, &stream),
+        [Decoded::Corrupt, Decoded::Frame]
+    );
+    assert_eq!(decoder.seq(), 2);
+    assert_eq!(decoder.payload(), b"world");
+}
+
+#[test]
+fn every_flipped_bit_is_detected() {
+    let encoded = frame(0x42, b"split");
+    for byte in 1..encoded.len() {
+        for bit in 0..8 {
+            let mut stream = encoded.clone();
+            stream[byte] ^= 1 << bit;
+            let mut decoder = FrameDecoder::new();
+            let decoded = feed(&mut decoder, &stream);
+            assert!(
+                !decoded.contains(&Decoded::Frame),
+                "flipping bit {bit} of byte {byte} went unnoticed"
+            );
+        }
+    }
+}
+
+#[test]
+fn oversized_length_resyncs() {
+    let mut decoder = FrameDecoder::new();
+    let mut stream = vec![SOF, MAX_PAYLOAD as u8 + 1];
+    stream.extend(frame(3, b"ok"));
+    assert_eq!(
+        feed(&mut decoder, &stream),
+        [Decoded::Corrupt, Decoded::Frame]
+    );
+    assert_eq!(decoder.payload(), b"ok");
+}
+
+#[test]
+fn repeated_sof_is_taken_as_the_start() {
+    // A length of SOF is too long, the byte starts the next frame instead
+    let mut decoder = FrameDecoder::new();
+    let mut stream = vec![SOF];
+    stream.extend(frame(4, b"sync"));
+    assert_eq!(
+        feed(&mut decoder, &stream),
+        [Decoded::Corrupt, Decoded::Frame]
+    );
+    assert_eq!(decoder.seq(), 4);
+    assert_eq!(decoder.payload(), b"sync");
+}
+
+#[test]
+fn truncated_frame_is_dropped_and_the_next_frame_found() {
+    // The cable is pulled mid-frame, the next frame starts within its payload
+    let mut decoder = FrameDecoder::new();
+    let mut stream = frame(1, b"lost")[..4].to_vec();
+    stream.extend(frame(2, b"next"));
+    assert_eq!(
+        decode(&mut decoder, &stream),
+        [
+            (Decoded::Corrupt, Vec::new()),
+            (Decoded::Frame, b"next".to_vec())
+        ]
+    );
+    assert_eq!(decoder.seq(), 2);
+    assert_eq!(feed(&mut decoder, &frame(3, b"again")), [Decoded::Frame]);
+    assert_eq!(decoder.payload(), b"again");
+}
+
+#[test]
+fn frames_within_a_corrupt_one_are_found() {
+    // A long payload swallows two whole frames, both decode after its CRC fails
+    let mut decoder = FrameDecoder::new();
+    let mut stream = vec![SOF, 30, 9];
+    stream.extend(frame(2, b"one"));
+    stream.extend(frame(3, b"two"));
+    stream.extend([0; 40]);
+    assert_eq!(
+        decode(&mut decoder, &stream),
+        [
+            (Decoded::Corrupt, Vec::new()),
+            (Decoded::Frame, b"one".to_vec()),
+            (Decoded::Frame, b"two".to_vec())
+        ]
+    );
+}
+
+#[test]
+fn frame_starting_in_the_crc_is_found() {
+    let mut decoder = FrameDecoder::new();
+    let mut stream = frame(1, b"x");
+    let crc = stream.len() - 1;
+    stream[crc] = SOF;
+    stream.truncate(crc + 1);
+    // The SOF ending the corrupt frame starts the next
+    stream.extend(&frame(5, b"y")[1..]);
+    assert_eq!(
+        decode(&mut decoder, &stream),
+        [
+            (Decoded::Corrupt, Vec::new()),
+            (Decoded::Frame, b"y".to_vec())
+        ]
+    );
+}
@@ -0,0 +1,79 @@
+use crate::split_status::{STATUS_LEN, SplitStatus, UnderglowState};
+
+const CENTRAL: SplitStatus = SplitStatus {
+    layer: Some(3),
+    underglow: Some(UnderglowState {
+        enabled: true,
+        effect: 2,
+    }),
+    profile: Some(1),
+    wpm: Some(300),
+    battery: Some(80),
+    charging: false,
+};
+
+/// The peripheral only knows its battery
+const PERIPHERAL: SplitStatus = SplitStatus {
+    battery: Some(55),
+    charging: true,
+    ..SplitStatus::UNKNOWN
+};
+
+#[test]
+fn round_trip() {
+    let message = CENTRAL.encode();
+    assert_eq!(message.len(), STATUS_LEN);
+    assert_eq!(SplitStatus::decode(&message), Some(CENTRAL));
+
+    let off = SplitStatus {
+        underglow: Some(UnderglowState {
+            enabled: false,
+            effect: 0,
+        }),
+        ..CENTRAL
+    };
+    assert_eq!(SplitStatus::decode(&off.encode()), Some(off));
+    assert_eq!(SplitStatus::decode(&PERIPHERAL.encode()), Some(PERIPHERAL));
+}
+
+#[test]
+fn layout() {
+    assert_eq!(
+        CENTRAL.encode(),
+        [1, 0x3f, 3, 2, 1, 0x2c, 0x01, 80],
+        "300 WPM little endian"
+    );
+    assert_eq!(PERIPHERAL.encode(), [1, 0x60, 0, 0, 0, 0, 0, 55]);
+}
+
+#[test]
+fn unknown_fields_stay_unknown() {
+    let message = SplitStatus::UNKNOWN.encode();
+    assert_eq!(message[1], 0, "no flag set");
+    assert_eq!(SplitStatus::decode(&message), Some(SplitStatus::UNKNOWN));
+
+    // Layer 0 is known, not a missing layer
+    let base = SplitStatus {
+        layer: Some(0),
+        ..SplitStatus::UNKNOWN
+    };
+    assert_eq!(SplitStatus::decode(&base.encode()), Some(base));
+
+    // Only the flag tells a field is known, not its byte
+    let mut message = CENTRAL.encode();
+    message[1] = 0;
+    assert_eq!(SplitStatus::decode(&message), Some(SplitStatus::UNKNOWN));
+}
+
+#[test]
+fn other_firmware_dropped() {
+    let message = CENTRAL.encode();
+    let mut newer = message;
+    newer[0] += 1;
+    assert_eq!(SplitStatus::decode(&newer), None);
+    assert_eq!(SplitStatus::decode(&message[..STATUS_LEN - 1]), None);
+    let mut longer = message.to_vec();
+    longer.push(0);
+    assert_eq!(SplitStatus::decode(&longer), None);
+    assert_eq!(SplitStatus::decode(&[]), None);
+}
@@ -0,0 +1,6 @@
+//! The side of the backup channel of the host tool in `tools/storage-backup`
+
+#[path = "../../../tools/storage-backup/src/protocol.rs"]
+pub(super) mod protocol;
+#[path = "../../../tools/storage-backup/src/transfer.rs"]
+pub(super) mod transfer;
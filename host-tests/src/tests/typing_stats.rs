@@ -0,0 +1,155 @@
+use std::sync::Mutex as StdMutex;
+
+use embassy_embedded_hal::flash::partition::Partition;
+use embassy_futures::block_on;
+use embassy_sync::mutex::Mutex;
+use nrf_mpsl::Flash;
+use rmk::controller::Controller;
+use rmk::event::{ControllerEvent, KeyboardEvent};
+use rmk::k;
+
+use super::typing_stats_tool::protocol::*;
+use super::typing_stats_tool::transfer::{Device, command, read_counts, read_summary};
+use crate::app_storage::{AppStorage, SharedAppStorage, SharedFlash};
+use crate::flash_wear::WearCountingFlash;
+use crate::keymap::{COL, NUM_LAYER, ROW};
+use crate::storage_layout::{APP_STORAGE_SECTORS, APP_STORAGE_START, SECTOR_SIZE};
+use crate::typing_stats::{TypingStats, handle_custom_command};
+use crate::vial_custom::{CustomChannel, VIA_REPORT_LEN, handle_custom_report};
+
+/// The counts are global, one keyboard at a time
+static ONE_KEYBOARD: StdMutex<()> = StdMutex::new(());
+
+/// The central: its custom-channel reports go to the typing statistics channel
+/// and RMK's controller events to [`TypingStats`]
+struct Keyboard {
+    stats: TypingStats<'static>,
+}
+
+impl Keyboard {
+    fn new() -> Self {
+        let flash: &'static SharedFlash =
+            Box::leak(Box::new(Mutex::new(WearCountingFlash::new(Flash::new()))));
+        let storage: &'static SharedAppStorage = Box::leak(Box::new(Mutex::new(AppStorage::new(
+            Partition::new(flash, APP_STORAGE_START, APP_STORAGE_SECTORS * SECTOR_SIZE),
+        ))));
+        let mut keyboard = Self {
+            stats: block_on(TypingStats::new(storage)),
+        };
+        // The peak of the keyboard before
+        keyboard.event(ControllerEvent::Wpm(0));
+        reset(&mut keyboard);
+        keyboard
+    }
+
+    fn event(&mut self, event: ControllerEvent) {
+        block_on(self.stats.process_event(event));
+    }
+
+    fn tap(&mut self, row: u8, col: u8) {
+        self.event(ControllerEvent::Key(
+            KeyboardEvent::key(row, col, true),
+            k!(A),
+        ));
+        self.event(ControllerEvent::Key(
+            KeyboardEvent::key(row, col, false),
+            k!(A),
+        ));
+    }
+}
+
+impl Device for Keyboard {
+    fn exchange(&mut self, report: &mut [u8; REPORT_LEN]) -> Result<(), String> {
+        let report: &mut [u8; VIA_REPORT_LEN] = report;
+        handle_custom_report(report, |channel, command, value_id, data| {
+            channel == CustomChannel::TypingStats && handle_custom_command(command, value_id, data)
+        });
+        Ok(())
+    }
+}
+
+fn reset(keyboard: &mut Keyboard) {
+    assert!(
+        command(keyboard, CUSTOM_SET_VALUE, VALUE_RESET, &[])
+            .unwrap()
+            .is_some()
+    );
+}
+
+#[test]
+fn the_summary_has_the_keymap_size() {
+    let _one = ONE_KEYBOARD.lock().unwrap_or_else(|e| e.into_inner());
+    let mut keyboard = Keyboard::new();
+    let summary = read_summary(&mut keyboard).unwrap();
+    assert_eq!(
+        (summary.layers, summary.rows, summary.cols),
+        (NUM_LAYER as u8, ROW as u8, COL as u8)
+    );
+    assert_eq!(summary.total, 0);
+}
+
+#[test]
+fn the_wpm_is_the_one_rmk_publishes() {
+    let _one = ONE_KEYBOARD.lock().unwrap_or_else(|e| e.into_inner());
+    let mut keyboard = Keyboard::new();
+    for wpm in [40, 90, 30] {
+        keyboard.event(ControllerEvent::Wpm(wpm));
+    }
+    let summary = read_summary(&mut keyboard).unwrap();
+    assert_eq!((summary.wpm, summary.peak_wpm), (30, 90));
+    // Typing alone doesn't change it
+    keyboard.tap(0, 1);
+    assert_eq!(read_summary(&mut keyboard).unwrap().wpm, 30);
+}
+
+#[test]
+fn presses_count_on_the_active_layer() {
+    let _one = ONE_KEYBOARD.lock().unwrap_or_else(|e| e.into_inner());
+    let mut keyboard = Keyboard::new();
+    keyboard.tap(0, 1);
+    keyboard.tap(0, 1);
+    keyboard.event(ControllerEvent::Layer(2));
+    keyboard.tap(4, 3);
+    keyboard.event(ControllerEvent::Layer(0));
+    keyboard.tap(4, 3);
+
+    let summary = read_summary(&mut keyboard).unwrap();
+    assert_eq!(summary.total, 4);
+    let counts = read_counts(&mut keyboard, &summary).unwrap();
+    assert_eq!(counts.get(Some(0), 0, 1), Some(2));
+    assert_eq!(counts.get(Some(2), 0, 1), Some(0));
+    assert_eq!(counts.get(Some(2), 4, 3), Some(1));
+    assert_eq!(counts.get(None, 4, 3), Some(2));
+}
+
+#[test]
+fn reset_clears_the_counts_and_the_peak() {
+    let _one = ONE_KEYBOARD.lock().unwrap_or_else(|e| e.into_inner());
+    let mut keyboard = Keyboard::new();
+    keyboard.event(ControllerEvent::Wpm(80));
+    keyboard.event(ControllerEvent::Wpm(20));
+    keyboard.tap(1, 1);
+    reset(&mut keyboard);
+    let summary = read_summary(&mut keyboard).unwrap();
+    assert_eq!((summary.wpm, summary.peak_wpm, summary.total), (20, 20, 0));
+    let counts = read_counts(&mut keyboard, &summary).unwrap();
+    assert_eq!(counts.get(None, 1, 1), Some(0));
+}
+
+#[test]
+fn rows_outside_the_keymap_are_unhandled() {
+    let _one = ONE_KEYBOARD.lock().unwrap_or_else(|e| e.into_inner());
+    let mut keyboard = Keyboard::new();
+    let row = |keyboard: &mut Keyboard, layer: usize, row: usize| {
+        command(
+            keyboard,
+            CUSTOM_GET_VALUE,
+            VALUE_ROW,
+            &[layer as u8, row as u8],
+        )
+        .unwrap()
+    };
+    assert!(row(&mut keyboard, NUM_LAYER - 1, ROW - 1).is_some());
+    assert!(row(&mut keyboard, NUM_LAYER, 0).is_none());
+    assert!(row(&mut keyboard, 0, ROW).is_none());
+}
@@ -0,0 +1,6 @@
+//! The side of the typing statistics channel of the host tool in `tools/typing-stats`
+
+#[path = "../../../tools/typing-stats/src/protocol.rs"]
+pub(super) mod protocol;
+#[path = "../../../tools/typing-stats/src/transfer.rs"]
+pub(super) mod transfer;
@@ -0,0 +1,173 @@
+use crate::underglow_render::{
+    BRIGHTNESS, COLOR, Effect, EffectInputs, FRAME_WORDS, LAYER_COLORS, NUM_LEDS, Rgb,
+    breath_level, encode, render,
+};
+
+/// Duty words of a 1 and a 0 bit, 0.8 µs and 0.4 µs of 1.25 µs high
+const ONE: u16 = 0x8000 | 13;
+const ZERO: u16 = 0x8000 | 7;
+
+fn rendered(effect: Effect, inputs: EffectInputs, elapsed_ms: u64) -> [Rgb; NUM_LEDS] {
+    let mut frame = [Rgb::new(1, 2, 3); NUM_LEDS];
+    render(effect, inputs, elapsed_ms, &mut frame);
+    frame
+}
+
+fn battery(level: u8) -> [Rgb; NUM_LEDS] {
+    let inputs = EffectInputs {
+        battery: Some(level),
+        ..Default::default()
+    };
+    rendered(Effect::Battery, inputs, 0)
+}
+
+fn lit(frame: &[Rgb]) -> usize {
+    frame.iter().filter(|&&c| c != Rgb::BLACK).count()
+}
+
+#[test]
+fn scale_rounds_to_the_nearest_level() {
+    let color = Rgb::new(0xff, 0x80, 0x01);
+    assert_eq!(color.scale(255), color);
+    assert_eq!(color.scale(0), Rgb::BLACK);
+    assert_eq!(color.scale(128), Rgb::new(0x80, 0x40, 0x01));
+}
+
+#[test]
+fn effects_cycle() {
+    let mut effect = Effect::Static;
+    let mut seen = Vec::new();
+    for _ in 0..4 {
+        seen.push(effect);
+        effect = effect.next();
+    }
+    assert_eq!(effect, Effect::Static);
+    assert_eq!(
+        seen,
+        [
+            Effect::Static,
+            Effect::Breathing,
+            Effect::Layer,
+            Effect::Battery
+        ]
+    );
+}
+
+#[test]
+fn effects_forwarded_by_number() {
+    let mut effect = Effect::Static;
+    for index in 0..4 {
+        assert_eq!(Effect::from_index(index), Some(effect));
+        effect = effect.next();
+    }
+    assert_eq!(Effect::from_index(4), None);
+}
+
+#[test]
+fn static_fills_every_led() {
+    let frame = rendered(Effect::Static, EffectInputs::default(), 12_345);
+    assert_eq!(frame, [COLOR.scale(BRIGHTNESS); NUM_LEDS]);
+}
+
+#[test]
+fn breath_rises_and_falls_over_the_period() {
+    // 2 s period in the stand-in settings
+    assert_eq!(breath_level(0), 0);
+    assert_eq!(breath_level(1000), 255);
+    assert_eq!(breath_level(2000), 0);
+    // Squared, a quarter in it is a quarter of the way up
+    assert_eq!(breath_level(500), 63);
+    assert_eq!(breath_level(500), breath_level(1500));
+    for t in 0..1000 {
+        assert!(breath_level(t) <= breath_level(t + 1), "{t}");
+        assert!(breath_level(1000 + t) >= breath_level(1000 + t + 1), "{t}");
+        assert_eq!(breath_level(t), breath_level(t + 2000));
+    }
+}
+
+#[test]
+fn breathing_peaks_at_the_brightness() {
+    let inputs = EffectInputs::default();
+    assert_eq!(
+        rendered(Effect::Breathing, inputs, 0),
+        [Rgb::BLACK; NUM_LEDS]
+    );
+    assert_eq!(
+        rendered(Effect::Breathing, inputs, 1000),
+        rendered(Effect::Static, inputs, 0)
+    );
+    let half_way = rendered(Effect::Breathing, inputs, 500)[0];
+    assert!(half_way.r > 0 && half_way.r < COLOR.scale(BRIGHTNESS).r);
+}
+
+#[test]
+fn layer_shows_its_color() {
+    for (layer, color) in LAYER_COLORS.iter().enumerate() {
+        let inputs = EffectInputs {
+            layer: layer as u8,
+            ..Default::default()
+        };
+        assert_eq!(
+            rendered(Effect::Layer, inputs, 0),
+            [color.scale(BRIGHTNESS); NUM_LEDS]
+        );
+    }
+    // Layers without a color use the static one
+    let inputs = EffectInputs {
+        layer: LAYER_COLORS.len() as u8,
+        ..Default::default()
+    };
+    assert_eq!(
+        rendered(Effect::Layer, inputs, 0),
+        [COLOR.scale(BRIGHTNESS); NUM_LEDS]
+    );
+}
+
+#[test]
+fn battery_gauge_fills_with_the_level() {
+    // Dark until the first measurement
+    assert_eq!(
+        rendered(Effect::Battery, EffectInputs::default(), 0),
+        [Rgb::BLACK; NUM_LEDS]
+    );
+    assert_eq!(
+        battery(100),
+        [Rgb::new(0, 0xff, 0).scale(BRIGHTNESS); NUM_LEDS]
+    );
+    assert_eq!(lit(&battery(0)), 0);
+    assert_eq!(lit(&battery(1)), 1);
+    assert_eq!(lit(&battery(50)), 5);
+    assert_eq!(lit(&battery(51)), 6);
+    assert_eq!(battery(200), battery(100));
+    // From red to green
+    let half = battery(50)[0];
+    assert_eq!(half, Rgb::new(0x7f, 0x7f, 0).scale(BRIGHTNESS));
+    let low = battery(10)[0];
+    assert!(low.r > low.g);
+    // The rest is dark
+    assert!(battery(50)[5..].iter().all(|&c| c == Rgb::BLACK));
+}
+
+#[test]
+fn encode_sends_grb_most_significant_bit_first() {
+    let mut frame = [Rgb::BLACK; NUM_LEDS];
+    frame[0] = Rgb::new(0x0f, 0x81, 0xff);
+    frame[NUM_LEDS - 1] = Rgb::new(0x00, 0x00, 0x01);
+    let mut words = [0; FRAME_WORDS];
+    encode(&frame, &mut words);
+
+    let bits = |byte: u8| -> Vec<u16> {
+        (0..8)
+            .rev()
+            .map(|bit| if byte >> bit & 1 == 1 { ONE } else { ZERO })
+            .collect()
+    };
+    assert_eq!(words[0..8], bits(0x81)[..]);
+    assert_eq!(words[8..16], bits(0x0f)[..]);
+    assert_eq!(words[16..24], bits(0xff)[..]);
+    assert!(words[24..FRAME_WORDS - 2].iter().all(|&w| w == ZERO));
+    assert_eq!(words[FRAME_WORDS - 2], ONE);
+    // Low for a whole period at the end
+    assert_eq!(words[FRAME_WORDS - 1], 0x8000);
+    assert_eq!(FRAME_WORDS, NUM_LEDS * 24 + 1);
+}
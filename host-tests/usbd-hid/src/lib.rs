@@ -0,0 +1,17 @@
+//! Stand-in for the HID reports of `usbd-hid`
+//!
+//! The firmware only builds `usbd-hid` for the target with RMK, the host
+//! tests get the keyboard report with the same fields.
+
+#![no_std]
+
+pub mod descriptor {
+    /// Boot keyboard report
+    #[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+    pub struct KeyboardReport {
+        pub modifier: u8,
+        pub reserved: u8,
+        pub leds: u8,
+        pub keycodes: [u8; 6],
+    }
+}
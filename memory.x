@@ -4,11 +4,17 @@ MEMORY
   /* FLASH : ORIGIN = 0x00000000, LENGTH = 1024K */
   /* RAM : ORIGIN = 0x20000000, LENGTH = 256K */
 
+  /* The application image ends at 0xA0000, storage follows it up to the
+     bootloader at 0xF4000 (checked by build.rs). RMK's storage has always
+     been at 0xA0000, the old LENGTH of 824K ran the image over it up to the
+     bootloader. 0xA0000 - 0x26000 = 488K keeps the storage of upgraded
+     boards where it is, and still leaves the firmware room to grow. */
+
   /* These values correspond to the NRF52840 with Softdevices S140 6.1.1 */
-  FLASH : ORIGIN = 0x00026000, LENGTH = 824K
+  FLASH : ORIGIN = 0x00026000, LENGTH = 488K
 
   /* These values correspond to the NRF52840 with Softdevices S140 7.3.0 */
-  /*FLASH : ORIGIN = 0x00027000, LENGTH = 820K*/
+  /*FLASH : ORIGIN = 0x00027000, LENGTH = 484K*/
   
 RAM : ORIGIN = 0x20020000, LENGTH = 128K
 }
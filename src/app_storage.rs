@@ -0,0 +1,121 @@
+//! Firmware-owned records in flash
+//!
+//! RMK's storage only knows RMK's own data, so records added by this firmware
+//! (bond names, ...) live in a separate region right after it, see
+//! [`storage_layout`](crate::storage_layout). Both regions are partitions of
+//! the same NVMC, shared through a mutex.
+//!
+//! Records are kept in a `sequential-storage` map. Keys are `u16`: the high
+//! byte is the owning [`RecordKind`], the low byte an index within it.
+
+use defmt::{Format, warn};
+use embassy_embedded_hal::flash::partition::Partition;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::mutex::Mutex;
+use nrf_mpsl::Flash;
+use sequential_storage::cache::NoCache;
+use sequential_storage::map::{Value, fetch_item, store_item};
+
+use crate::flash_wear::WearCountingFlash;
+use crate::storage_layout::{APP_STORAGE_SECTORS, SECTOR_SIZE};
+
+/// The whole NVMC, shared between RMK's storage and [`AppStorage`]
+pub(crate) type SharedFlash = Mutex<NoopRawMutex, WearCountingFlash<Flash<'static>>>;
+
+/// A region of the shared NVMC
+pub(crate) type FlashPartition =
+    Partition<'static, NoopRawMutex, WearCountingFlash<Flash<'static>>>;
+
+/// [`AppStorage`] shared between the controllers that own records
+pub(crate) type SharedAppStorage = Mutex<NoopRawMutex, AppStorage>;
+
+/// Owner of a record, the high byte of its key
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+#[repr(u8)]
+pub(crate) enum RecordKind {
+    /// Host name and last connection of a BLE profile, indexed by profile
+    BondMeta = 0x01,
+    /// Schema of the storage, see [`schema`](crate::schema)
+    Schema = 0x02,
+    /// Erase counts of the storage sectors, see [`flash_wear`](crate::flash_wear)
+    Wear = 0x03,
+    /// Key presses of a row of a layer, indexed by `layer * ROW + row`, see
+    /// [`typing_stats`](crate::typing_stats)
+    TypingStats = 0x04,
+    /// BLE profile whose address the central advertises with, see
+    /// [`identity_controller`](crate::identity_controller)
+    ActiveProfile = 0x05,
+}
+
+/// Key of the `index`th record of `kind`
+pub(crate) const fn record_key(kind: RecordKind, index: u8) -> u16 {
+    (kind as u16) << 8 | index as u16
+}
+
+/// Largest serialized record
+const MAX_RECORD_SIZE: usize = 64;
+
+/// Map of firmware-owned records
+pub(crate) struct AppStorage {
+    flash: FlashPartition,
+    buf: [u8; MAX_RECORD_SIZE],
+}
+
+impl AppStorage {
+    /// `flash` is the partition starting at `APP_STORAGE_START`
+    pub(crate) fn new(flash: FlashPartition) -> Self {
+        Self {
+            flash,
+            buf: [0; MAX_RECORD_SIZE],
+        }
+    }
+
+    fn range() -> core::ops::Range<u32> {
+        0..APP_STORAGE_SECTORS * SECTOR_SIZE
+    }
+
+    /// Read the record at `key`
+    pub(crate) async fn fetch<V>(&mut self, key: u16) -> Option<V>
+    where
+        V: for<'a> Value<'a>,
+    {
+        match fetch_item::<u16, V, _>(
+            &mut self.flash,
+            Self::range(),
+            &mut NoCache::new(),
+            &mut self.buf,
+            &key,
+        )
+        .await
+        {
+            Ok(value) => value,
+            Err(e) => {
+                warn!("Failed to read record {:04x}: {:?}", key, e);
+                None
+            }
+        }
+    }
+
+    /// Write the record at `key`, returns `false` on a flash error
+    pub(crate) async fn store<V>(&mut self, key: u16, value: &V) -> bool
+    where
+        V: for<'a> Value<'a>,
+    {
+        match store_item(
+            &mut self.flash,
+            Self::range(),
+            &mut NoCache::new(),
+            &mut self.buf,
+            &key,
+            value,
+        )
+        .await
+        {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("Failed to write record {:04x}: {:?}", key, e);
+                false
+            }
+        }
+    }
+}
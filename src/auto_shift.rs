@@ -0,0 +1,70 @@
+//! Auto shift of the central
+//!
+//! Holding a key of the classes enabled in `[auto_shift]` of the keyboard
+//! TOML past `timeout` sends it shifted: alphas `A`..`Z`, numbers `1`..`0`
+//! and the symbols of the US layout (`-=[]\;'` `` ` `` `,./`). [`apply`] turns
+//! the plain keys of these classes in the default keymap into tap-holds
+//! holding their shifted form, so RMK's morse timer decides between the two
+//! like for any other tap-hold, flow tap included. Keys that are tap-holds
+//! already, the home row mods among them, keep their hold action.
+//!
+//! The keymap is the default one: RMK saves it to its storage on the first
+//! boot and Vial edits it there, a stored keymap keeps its keys.
+
+use rmk::types::action::{Action, KeyAction, MorseMode, MorseProfile};
+use rmk::types::keycode::KeyCode;
+use rmk::types::modifier::ModifierCombination;
+
+use crate::keymap::{COL, NUM_LAYER, ROW};
+
+type Keymap = [[[KeyAction; COL]; ROW]; NUM_LAYER];
+
+include!(concat!(env!("OUT_DIR"), "/auto_shift_generated.rs"));
+
+/// Shifted only by the timeout, other keys pressed meanwhile don't decide it
+const PROFILE: MorseProfile = MorseProfile::new(
+    Some(false), // unilateral_tap
+    Some(MorseMode::Normal),
+    Some(AUTO_SHIFT_TIMEOUT_MS), // hold_timeout
+    Some(AUTO_SHIFT_TIMEOUT_MS), // gap_timeout
+);
+
+const SYMBOLS: [KeyCode; 11] = [
+    KeyCode::Minus,
+    KeyCode::Equal,
+    KeyCode::LeftBracket,
+    KeyCode::RightBracket,
+    KeyCode::Backslash,
+    KeyCode::Semicolon,
+    KeyCode::Quote,
+    KeyCode::Grave,
+    KeyCode::Comma,
+    KeyCode::Dot,
+    KeyCode::Slash,
+];
+
+fn in_range(code: KeyCode, first: KeyCode, last: KeyCode) -> bool {
+    (first as u16..=last as u16).contains(&(code as u16))
+}
+
+/// Whether `code` is in a class sent shifted when held
+fn shifted(code: KeyCode) -> bool {
+    (SHIFT_ALPHAS && in_range(code, KeyCode::A, KeyCode::Z))
+        || (SHIFT_NUMBERS && in_range(code, KeyCode::Kc1, KeyCode::Kc0))
+        || (SHIFT_SYMBOLS && SYMBOLS.contains(&code))
+}
+
+/// Make the plain keys of the enabled classes in `keymap` auto shift
+pub(crate) fn apply(keymap: &mut Keymap) {
+    for action in keymap.iter_mut().flatten().flatten() {
+        if let KeyAction::Single(Action::Key(code)) = *action
+            && shifted(code)
+        {
+            *action = KeyAction::TapHold(
+                Action::Key(code),
+                Action::KeyWithModifier(code, ModifierCombination::LSHIFT),
+                PROFILE,
+            );
+        }
+    }
+}
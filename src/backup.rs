@@ -0,0 +1,406 @@
+//! Storage backup and restore over Vial
+//!
+//! The whole storage, RMK's region and the firmware-owned records, is
+//! exported as one image so a replacement controller can be set up
+//! identically. The host tool in `tools/storage-backup` reads it through the
+//! [`CustomChannel::Backup`] channel in chunks and saves it with a
+//! [`BackupHeader`] in front; restoring sends the header, then the chunks, and
+//! the central reboots once the image is written and its CRC checks out. The
+//! channel is answered on the USB custom-channel interface, see
+//! [`custom_hid`](crate::custom_hid).
+//!
+//! Reading is served from the memory-mapped flash straight from the Vial
+//! handler. The CRC of the storage is kept until the flash changes, so the
+//! handler only computes it again after a write. Erasing and writing wait
+//! for the flash, so they are queued to the [`BackupController`]. From the
+//! start of a restore it holds the shared flash until the reboot, which
+//! pauses RMK's storage task and the firmware's own records: nothing RMK
+//! still has in RAM can be written over the restored image. A failed restore
+//! gives the flash back with the storage as far as it got, the host tool
+//! starts over.
+//!
+//! [`CustomChannel::Backup`]: crate::vial_custom::CustomChannel::Backup
+
+use core::cell::{Cell, RefCell};
+
+use defmt::{Format, info, unwrap, warn};
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_sync::mutex::MutexGuard;
+use embassy_time::{Duration, Timer};
+use embedded_storage_async::nor_flash::NorFlash;
+use nrf_mpsl::Flash;
+use rmk::controller::Controller;
+
+use crate::app_storage::SharedFlash;
+use crate::flash_map::mapped;
+use crate::flash_wear::{WearCountingFlash, changes};
+use crate::schema::SCHEMA_VERSION;
+use crate::storage_layout::{
+    APP_STORAGE_SECTORS, RMK_STORAGE_SECTORS, RMK_STORAGE_START, SECTOR_SIZE,
+};
+use crate::vial_custom::CustomCommand;
+
+/// Size of the exported image, RMK's storage followed by the firmware-owned records
+pub(crate) const IMAGE_LEN: u32 = (RMK_STORAGE_SECTORS as u32 + APP_STORAGE_SECTORS) * SECTOR_SIZE;
+
+/// Image bytes per report
+const CHUNK_LEN: usize = 24;
+
+/// Magic at the start of a backup
+const MAGIC: [u8; 4] = *b"RMKB";
+
+/// Version of the backup format
+const FORMAT_VERSION: u8 = 1;
+
+/// How long the result of a restore stays readable before the reboot
+const REBOOT_DELAY: Duration = Duration::from_millis(500);
+
+/// Header of a backup, followed by [`IMAGE_LEN`] bytes of image
+///
+/// ```text
+/// | magic "RMKB" | format u8 | rmk sectors u8 | app sectors u8 | 0 u8 |
+/// | start u32 | length u32 | schema u16 | crc32 u32 |
+/// ```
+///
+/// All integers are little endian. The CRC is CRC-32/ISO-HDLC over the image.
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+pub(crate) struct BackupHeader {
+    rmk_sectors: u8,
+    app_sectors: u8,
+    start: u32,
+    len: u32,
+    schema: u16,
+    crc: u32,
+}
+
+impl BackupHeader {
+    const LEN: usize = 22;
+
+    /// Layout and schema of this firmware's storage
+    const LAYOUT: Self = Self {
+        rmk_sectors: RMK_STORAGE_SECTORS,
+        app_sectors: APP_STORAGE_SECTORS as u8,
+        start: RMK_STORAGE_START,
+        len: IMAGE_LEN,
+        schema: SCHEMA_VERSION,
+        crc: 0,
+    };
+
+    fn current() -> Self {
+        Self {
+            crc: image_crc(),
+            ..Self::LAYOUT
+        }
+    }
+
+    fn write(&self, buf: &mut [u8]) {
+        buf[0..4].copy_from_slice(&MAGIC);
+        buf[4] = FORMAT_VERSION;
+        buf[5] = self.rmk_sectors;
+        buf[6] = self.app_sectors;
+        buf[7] = 0;
+        buf[8..12].copy_from_slice(&self.start.to_le_bytes());
+        buf[12..16].copy_from_slice(&self.len.to_le_bytes());
+        buf[16..18].copy_from_slice(&self.schema.to_le_bytes());
+        buf[18..22].copy_from_slice(&self.crc.to_le_bytes());
+    }
+
+    fn read(buf: &[u8]) -> Option<Self> {
+        if buf.len() < Self::LEN || buf[0..4] != MAGIC || buf[4] != FORMAT_VERSION {
+            return None;
+        }
+        Some(Self {
+            rmk_sectors: buf[5],
+            app_sectors: buf[6],
+            start: u32::from_le_bytes(unwrap!(buf[8..12].try_into())),
+            len: u32::from_le_bytes(unwrap!(buf[12..16].try_into())),
+            schema: u16::from_le_bytes([buf[16], buf[17]]),
+            crc: u32::from_le_bytes(unwrap!(buf[18..22].try_into())),
+        })
+    }
+
+    /// Whether an image with this header fits this firmware's layout
+    ///
+    /// Older schemas are accepted, they are migrated at the next boot.
+    fn compatible(&self) -> bool {
+        let layout = Self::LAYOUT;
+        self.rmk_sectors == layout.rmk_sectors
+            && self.app_sectors == layout.app_sectors
+            && self.len == layout.len
+            && self.schema <= layout.schema
+    }
+}
+
+/// The storage, read through the memory-mapped flash
+fn image() -> &'static [u8] {
+    mapped(RMK_STORAGE_START, IMAGE_LEN as usize)
+}
+
+/// CRC of the storage and the [`changes`] of the flash it was computed at
+static IMAGE_CRC: BlockingMutex<CriticalSectionRawMutex, Cell<Option<(u32, u32)>>> =
+    BlockingMutex::new(Cell::new(None));
+
+/// CRC of the storage, computed again only when the flash changed
+fn image_crc() -> u32 {
+    // Read first, a change while the CRC is computed makes the next call redo it
+    let at = changes();
+    if let Some((computed_at, crc)) = IMAGE_CRC.lock(Cell::get)
+        && computed_at == at
+    {
+        return crc;
+    }
+    let crc = crc32(image());
+    IMAGE_CRC.lock(|c| c.set(Some((at, crc))));
+    crc
+}
+
+/// CRC-32/ISO-HDLC, as used by zip and the host tool
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Progress of a restore
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+#[repr(u8)]
+enum RestoreState {
+    Idle = 0,
+    Erasing = 1,
+    Receiving = 2,
+    Verifying = 3,
+    /// Image written and verified, rebooting
+    Done = 4,
+    Failed = 5,
+}
+
+struct RestoreStatus {
+    state: RestoreState,
+    header: Option<BackupHeader>,
+    written: u32,
+}
+
+static RESTORE: BlockingMutex<CriticalSectionRawMutex, RefCell<RestoreStatus>> =
+    BlockingMutex::new(RefCell::new(RestoreStatus {
+        state: RestoreState::Idle,
+        header: None,
+        written: 0,
+    }));
+
+fn set_state(state: RestoreState) {
+    RESTORE.lock(|r| r.borrow_mut().state = state);
+}
+
+fn receiving() -> bool {
+    RESTORE.lock(|r| r.borrow().state == RestoreState::Receiving)
+}
+
+/// Requests from the Vial handler, which cannot wait for flash
+#[derive(Clone, Copy, Format)]
+pub(crate) enum BackupRequest {
+    /// Erase the storage for a new image
+    Begin,
+    /// Write `len` bytes of `data` at `offset` of the image
+    Chunk {
+        offset: u32,
+        len: u8,
+        data: [u8; CHUNK_LEN],
+    },
+    /// Verify the image and reboot
+    Finish,
+}
+
+static BACKUP_REQUESTS: Channel<CriticalSectionRawMutex, BackupRequest, 8> = Channel::new();
+
+/// Value ids of the backup Vial channel
+const VALUE_HEADER: u8 = 0x01;
+const VALUE_CHUNK: u8 = 0x02;
+const VALUE_BEGIN_RESTORE: u8 = 0x03;
+const VALUE_WRITE_CHUNK: u8 = 0x04;
+const VALUE_FINISH_RESTORE: u8 = 0x05;
+const VALUE_RESTORE_STATUS: u8 = 0x06;
+
+/// Handle a custom command on the backup channel
+///
+/// - `get 0x01`: [`BackupHeader`] of the current storage
+/// - `get 0x02 <offset u32>`: `offset u32 | 24 bytes of image`
+/// - `set 0x03 <header>`: start a restore, erases the storage
+/// - `set 0x04 <offset u32> <len u8> <data>`: write a chunk, rejected while
+///   the queue is full
+/// - `set 0x05`: verify the CRC and reboot
+/// - `get 0x06`: `state u8 | bytes written u32`
+pub(crate) fn handle_custom_command(command: CustomCommand, value_id: u8, data: &mut [u8]) -> bool {
+    match (command, value_id) {
+        (CustomCommand::Get, VALUE_HEADER) => {
+            BackupHeader::current().write(data);
+            true
+        }
+        (CustomCommand::Get, VALUE_CHUNK) => {
+            let offset = u32::from_le_bytes(unwrap!(data[0..4].try_into())) as usize;
+            let Some(chunk) = image().get(offset..) else {
+                return false;
+            };
+            let len = chunk.len().min(CHUNK_LEN);
+            data[4..4 + len].copy_from_slice(&chunk[..len]);
+            true
+        }
+        (CustomCommand::Set, VALUE_BEGIN_RESTORE) => {
+            let Some(header) = BackupHeader::read(data).filter(|h| h.compatible()) else {
+                warn!("Rejected an incompatible backup");
+                return false;
+            };
+            let started = RESTORE.lock(|r| {
+                let mut r = r.borrow_mut();
+                if matches!(r.state, RestoreState::Erasing | RestoreState::Verifying) {
+                    return false;
+                }
+                r.state = RestoreState::Erasing;
+                r.header = Some(header);
+                r.written = 0;
+                true
+            });
+            started && BACKUP_REQUESTS.try_send(BackupRequest::Begin).is_ok()
+        }
+        (CustomCommand::Set, VALUE_WRITE_CHUNK) => {
+            let offset = u32::from_le_bytes(unwrap!(data[0..4].try_into()));
+            let len = data[4];
+            if len as usize > CHUNK_LEN || offset + len as u32 > IMAGE_LEN {
+                return false;
+            }
+            let mut chunk = [0; CHUNK_LEN];
+            chunk[..len as usize].copy_from_slice(&data[5..5 + len as usize]);
+            receiving()
+                && BACKUP_REQUESTS
+                    .try_send(BackupRequest::Chunk {
+                        offset,
+                        len,
+                        data: chunk,
+                    })
+                    .is_ok()
+        }
+        (CustomCommand::Set, VALUE_FINISH_RESTORE) => {
+            receiving() && BACKUP_REQUESTS.try_send(BackupRequest::Finish).is_ok()
+        }
+        (CustomCommand::Get, VALUE_RESTORE_STATUS) => {
+            let (state, written) = RESTORE.lock(|r| {
+                let r = r.borrow();
+                (r.state, r.written)
+            });
+            data[0] = state as u8;
+            data[1..5].copy_from_slice(&written.to_le_bytes());
+            true
+        }
+        _ => false,
+    }
+}
+
+/// The whole NVMC, held from the start of a restore
+type HeldFlash = MutexGuard<'static, NoopRawMutex, WearCountingFlash<Flash<'static>>>;
+
+/// Erases and writes the storage for a restore
+pub(crate) struct BackupController {
+    flash: &'static SharedFlash,
+    held: Option<HeldFlash>,
+}
+
+impl BackupController {
+    pub(crate) fn new(flash: &'static SharedFlash) -> Self {
+        Self { flash, held: None }
+    }
+
+    /// The NVMC, taken from RMK's storage and the firmware's records on first use
+    async fn hold(&mut self) -> &mut HeldFlash {
+        if self.held.is_none() {
+            info!("Pausing the storage for the restore");
+            self.held = Some(self.flash.lock().await);
+        }
+        unwrap!(self.held.as_mut())
+    }
+
+    /// Fail the restore and give the flash back to RMK and the firmware's records
+    fn fail(&mut self) {
+        set_state(RestoreState::Failed);
+        if self.held.take().is_some() {
+            info!("Resuming the storage");
+        }
+    }
+
+    async fn write_chunk(&mut self, offset: u32, data: &[u8]) -> bool {
+        // Erased flash already reads 0xFF
+        if data.iter().all(|&b| b == 0xFF) {
+            return true;
+        }
+        let flash = self.hold().await;
+        flash.write(RMK_STORAGE_START + offset, data).await.is_ok()
+    }
+}
+
+impl Controller for BackupController {
+    type Event = BackupRequest;
+
+    async fn process_event(&mut self, event: Self::Event) {
+        match event {
+            BackupRequest::Begin => {
+                info!("Restoring storage, erasing {} bytes", IMAGE_LEN);
+                let flash = self.hold().await;
+                let erased = flash
+                    .erase(RMK_STORAGE_START, RMK_STORAGE_START + IMAGE_LEN)
+                    .await
+                    .is_ok();
+                if erased {
+                    set_state(RestoreState::Receiving);
+                } else {
+                    warn!("Failed to erase the storage");
+                    self.fail();
+                }
+            }
+            BackupRequest::Chunk { offset, len, data } => {
+                // Chunks queued behind a failed one are dropped
+                if !receiving() {
+                    return;
+                }
+                if !self.write_chunk(offset, &data[..len as usize]).await {
+                    warn!("Failed to write the backup at {}", offset);
+                    self.fail();
+                    return;
+                }
+                RESTORE.lock(|r| {
+                    let mut r = r.borrow_mut();
+                    r.written = r.written.max(offset + len as u32);
+                });
+            }
+            BackupRequest::Finish => {
+                if !receiving() {
+                    return;
+                }
+                set_state(RestoreState::Verifying);
+                let expected = RESTORE.lock(|r| r.borrow().header.map(|h| h.crc));
+                if expected != Some(crc32(image())) {
+                    warn!("Restored storage does not match the backup CRC");
+                    self.fail();
+                    return;
+                }
+                info!("Storage restored, rebooting");
+                set_state(RestoreState::Done);
+                Timer::after(REBOOT_DELAY).await;
+                cortex_m::peripheral::SCB::sys_reset();
+            }
+        }
+    }
+
+    async fn next_message(&mut self) -> Self::Event {
+        BACKUP_REQUESTS.receive().await
+    }
+}
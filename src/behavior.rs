@@ -0,0 +1,6 @@
+//! Behaviors of the central from `[behavior]` of the keyboard TOML
+//!
+//! RMK's `*_config` binaries read these tables themselves, the hand-written
+//! central gets them from `build.rs`.
+
+include!(concat!(env!("OUT_DIR"), "/behavior_generated.rs"));
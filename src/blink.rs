@@ -0,0 +1,27 @@
+//! Blink patterns of the indicator LEDs, see [`indicators`](crate::indicators)
+
+use defmt::Format;
+
+/// Blink pattern of an indicator
+///
+/// The LED is lit for `on_ms` and dark for `off_ms`, `count` times, then stays
+/// dark for `pause_ms` before the cycle repeats. An `off_ms` of 0 keeps it lit.
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+pub(crate) struct Pattern {
+    pub(crate) on_ms: u16,
+    pub(crate) off_ms: u16,
+    pub(crate) pause_ms: u16,
+}
+
+impl Pattern {
+    /// Whether the LED is lit `elapsed_ms` after the indicator became active
+    pub(crate) fn lit(&self, count: u8, elapsed_ms: u64) -> bool {
+        if self.off_ms == 0 {
+            return true;
+        }
+        let blink = self.on_ms as u64 + self.off_ms as u64;
+        let blinks = blink * count.max(1) as u64;
+        let t = elapsed_ms % (blinks + self.pause_ms as u64);
+        t < blinks && t % blink < self.on_ms as u64
+    }
+}
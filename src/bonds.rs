@@ -0,0 +1,289 @@
+//! Bond management: names, last connection and forgetting single hosts
+//!
+//! RMK stores the bond of each BLE profile itself. Next to it this firmware
+//! keeps a [`BondMeta`] record per profile with a host name (set from the host
+//! tool) and the time of the last connection, and lets a single profile's bond
+//! be forgotten instead of clearing all of them:
+//!
+//! - from Vial, through the [`CustomChannel::Bonds`] channel
+//! - with `BtForget`, which forgets the active profile; the keymap sends it
+//!   from a combo of the adjust layer, `BtPre` and `BtNext` pressed together
+//!
+//! Times are unix seconds once the host tool has set the clock, otherwise
+//! seconds since boot.
+//!
+//! [`CustomChannel::Bonds`]: crate::vial_custom::CustomChannel::Bonds
+
+use core::cell::RefCell;
+
+use defmt::{Format, info, unwrap};
+use embassy_futures::select::{Either, select};
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::Instant;
+use rmk::ble::BleState;
+use rmk::ble::profile::BleProfileAction;
+use rmk::channel::{BLE_PROFILE_CHANNEL, CONTROLLER_CHANNEL, ControllerSub, FLASH_CHANNEL};
+use rmk::controller::Controller;
+use rmk::event::ControllerEvent;
+use rmk::storage::FlashOperationMessage;
+use sequential_storage::map::{SerializationError, Value};
+
+use crate::app_storage::{RecordKind, SharedAppStorage, record_key};
+use crate::user_keys::UserKey;
+use crate::vial_custom::CustomCommand;
+
+include!(concat!(env!("OUT_DIR"), "/bonds_generated.rs"));
+
+/// Longest host name, in bytes of UTF-8
+pub(crate) const MAX_HOST_NAME: usize = 16;
+
+/// Metadata kept for the bond of one BLE profile
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+pub(crate) struct BondMeta {
+    /// Whether a host connected on this profile since it was last forgotten
+    pub(crate) bonded: bool,
+    /// Time of the last connection
+    pub(crate) last_connected: u32,
+    name: [u8; MAX_HOST_NAME],
+    name_len: u8,
+}
+
+impl BondMeta {
+    pub(crate) const EMPTY: Self = Self {
+        bonded: false,
+        last_connected: 0,
+        name: [0; MAX_HOST_NAME],
+        name_len: 0,
+    };
+
+    /// Serialized size: bonded, last_connected, name_len, name
+    const SIZE: usize = 1 + 4 + 1 + MAX_HOST_NAME;
+
+    pub(crate) fn name(&self) -> &[u8] {
+        &self.name[..self.name_len as usize]
+    }
+
+    /// Set the host name, truncated to [`MAX_HOST_NAME`] bytes
+    pub(crate) fn set_name(&mut self, name: &[u8]) {
+        let len = name.len().min(MAX_HOST_NAME);
+        self.name = [0; MAX_HOST_NAME];
+        self.name[..len].copy_from_slice(&name[..len]);
+        self.name_len = len as u8;
+    }
+}
+
+impl<'a> Value<'a> for BondMeta {
+    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+        if buffer.len() < Self::SIZE {
+            return Err(SerializationError::BufferTooSmall);
+        }
+        buffer[0] = self.bonded as u8;
+        buffer[1..5].copy_from_slice(&self.last_connected.to_le_bytes());
+        buffer[5] = self.name_len;
+        buffer[6..Self::SIZE].copy_from_slice(&self.name);
+        Ok(Self::SIZE)
+    }
+
+    fn deserialize_from(buffer: &'a [u8]) -> Result<Self, SerializationError> {
+        if buffer.len() < Self::SIZE {
+            return Err(SerializationError::BufferTooSmall);
+        }
+        let name_len = buffer[5];
+        if name_len as usize > MAX_HOST_NAME {
+            return Err(SerializationError::InvalidFormat);
+        }
+        let mut name = [0; MAX_HOST_NAME];
+        name.copy_from_slice(&buffer[6..Self::SIZE]);
+        Ok(Self {
+            bonded: buffer[0] != 0,
+            last_connected: u32::from_le_bytes(unwrap!(buffer[1..5].try_into())),
+            name,
+            name_len,
+        })
+    }
+}
+
+/// In-RAM copy of the records, read by the Vial handler
+static BOND_META: BlockingMutex<CriticalSectionRawMutex, RefCell<[BondMeta; NUM_BLE_PROFILES]>> =
+    BlockingMutex::new(RefCell::new([BondMeta::EMPTY; NUM_BLE_PROFILES]));
+
+/// Unix time at boot, once the host tool has set the clock
+static UNIX_AT_BOOT: BlockingMutex<CriticalSectionRawMutex, RefCell<Option<u32>>> =
+    BlockingMutex::new(RefCell::new(None));
+
+/// Active BLE profile, as last reported by RMK
+static ACTIVE_PROFILE: BlockingMutex<CriticalSectionRawMutex, RefCell<u8>> =
+    BlockingMutex::new(RefCell::new(0));
+
+/// Current time, see the module docs
+pub(crate) fn now() -> u32 {
+    let uptime = Instant::now().as_secs() as u32;
+    UNIX_AT_BOOT.lock(|t| t.borrow().map_or(uptime, |boot| boot.wrapping_add(uptime)))
+}
+
+/// Requests from the Vial handler, which cannot wait for flash
+#[derive(Clone, Copy, Format)]
+pub(crate) enum BondRequest {
+    Save(u8),
+    Forget(u8),
+}
+
+static BOND_REQUESTS: Channel<CriticalSectionRawMutex, BondRequest, 4> = Channel::new();
+
+/// Value ids of the bonds Vial channel
+const VALUE_PROFILE_COUNT: u8 = 0x01;
+const VALUE_BOND: u8 = 0x02;
+const VALUE_HOST_NAME: u8 = 0x03;
+const VALUE_FORGET: u8 = 0x04;
+const VALUE_CLOCK: u8 = 0x05;
+
+/// Handle a custom command on the bonds channel
+///
+/// - `get 0x01`: `profiles u8 | active u8`
+/// - `get 0x02 <profile>`: `bonded u8 | last_connected u32 | name_len u8 | name`
+/// - `set 0x03 <profile> <len> <name>`: name the host of a profile
+/// - `set 0x04 <profile>`: forget the bond of a profile
+/// - `set 0x05 <unix time u32>`: set the clock used for `last_connected`
+pub(crate) fn handle_custom_command(command: CustomCommand, value_id: u8, data: &mut [u8]) -> bool {
+    match (command, value_id) {
+        (CustomCommand::Get, VALUE_PROFILE_COUNT) => {
+            data[0] = NUM_BLE_PROFILES as u8;
+            data[1] = ACTIVE_PROFILE.lock(|p| *p.borrow());
+            true
+        }
+        (CustomCommand::Get, VALUE_BOND) => {
+            let Some(meta) = BOND_META.lock(|m| m.borrow().get(data[0] as usize).copied()) else {
+                return false;
+            };
+            data[0] = meta.bonded as u8;
+            data[1..5].copy_from_slice(&meta.last_connected.to_le_bytes());
+            data[5] = meta.name_len;
+            data[6..6 + meta.name().len()].copy_from_slice(meta.name());
+            true
+        }
+        (CustomCommand::Set, VALUE_HOST_NAME) => {
+            let profile = data[0];
+            let len = (data[1] as usize).min(data.len() - 2);
+            let named = BOND_META.lock(|m| {
+                m.borrow_mut()
+                    .get_mut(profile as usize)
+                    .map(|meta| meta.set_name(&data[2..2 + len]))
+                    .is_some()
+            });
+            named && BOND_REQUESTS.try_send(BondRequest::Save(profile)).is_ok()
+        }
+        (CustomCommand::Set, VALUE_FORGET) if (data[0] as usize) < NUM_BLE_PROFILES => {
+            BOND_REQUESTS.try_send(BondRequest::Forget(data[0])).is_ok()
+        }
+        (CustomCommand::Set, VALUE_CLOCK) => {
+            let unix = u32::from_le_bytes(unwrap!(data[0..4].try_into()));
+            let uptime = Instant::now().as_secs() as u32;
+            UNIX_AT_BOOT.lock(|t| *t.borrow_mut() = Some(unix.wrapping_sub(uptime)));
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Event of the [`BondManager`]
+pub(crate) enum BondEvent {
+    Controller(ControllerEvent),
+    Request(BondRequest),
+}
+
+/// Keeps the bond records up to date and forgets single bonds
+pub(crate) struct BondManager<'a> {
+    storage: &'a SharedAppStorage,
+    sub: ControllerSub,
+    active_profile: u8,
+}
+
+impl<'a> BondManager<'a> {
+    /// Load the records from flash
+    pub(crate) async fn new(storage: &'a SharedAppStorage) -> Self {
+        {
+            let mut storage = storage.lock().await;
+            for profile in 0..NUM_BLE_PROFILES as u8 {
+                let key = record_key(RecordKind::BondMeta, profile);
+                if let Some(meta) = storage.fetch::<BondMeta>(key).await {
+                    BOND_META.lock(|m| m.borrow_mut()[profile as usize] = meta);
+                }
+            }
+        }
+        Self {
+            storage,
+            sub: unwrap!(CONTROLLER_CHANNEL.subscriber()),
+            active_profile: 0,
+        }
+    }
+
+    async fn save(&mut self, profile: u8) {
+        let Some(meta) = BOND_META.lock(|m| m.borrow().get(profile as usize).copied()) else {
+            return;
+        };
+        let key = record_key(RecordKind::BondMeta, profile);
+        self.storage.lock().await.store(key, &meta).await;
+    }
+
+    /// Forget the bond of `profile`
+    ///
+    /// RMK's profile action only clears the active profile, switching to
+    /// another one would drop the connected host. The bond of an inactive
+    /// profile is cleared from RMK's storage instead; the BLE stack has it
+    /// until it restarts, which switching to that profile does.
+    async fn forget(&mut self, profile: u8) {
+        info!("Forgetting bond of BLE profile {}", profile);
+        if profile == self.active_profile {
+            BLE_PROFILE_CHANNEL
+                .send(BleProfileAction::ClearProfile)
+                .await;
+        } else {
+            FLASH_CHANNEL
+                .send(FlashOperationMessage::ClearSlot(profile))
+                .await;
+        }
+        BOND_META.lock(|m| m.borrow_mut()[profile as usize] = BondMeta::EMPTY);
+        self.save(profile).await;
+    }
+}
+
+impl Controller for BondManager<'_> {
+    type Event = BondEvent;
+
+    async fn process_event(&mut self, event: Self::Event) {
+        match event {
+            BondEvent::Controller(ControllerEvent::BleProfile(profile)) => {
+                self.active_profile = profile;
+                ACTIVE_PROFILE.lock(|p| *p.borrow_mut() = profile);
+            }
+            BondEvent::Controller(ControllerEvent::BleState(profile, BleState::Connected)) => {
+                if (profile as usize) < NUM_BLE_PROFILES {
+                    BOND_META.lock(|m| {
+                        let meta = &mut m.borrow_mut()[profile as usize];
+                        meta.bonded = true;
+                        meta.last_connected = now();
+                    });
+                    self.save(profile).await;
+                }
+            }
+            BondEvent::Controller(ControllerEvent::Key(key_event, action))
+                if key_event.pressed
+                    && UserKey::from_action(&action) == Some(UserKey::ForgetBond) =>
+            {
+                self.forget(self.active_profile).await;
+            }
+            BondEvent::Controller(_) => {}
+            BondEvent::Request(BondRequest::Save(profile)) => self.save(profile).await,
+            BondEvent::Request(BondRequest::Forget(profile)) => self.forget(profile).await,
+        }
+    }
+
+    async fn next_message(&mut self) -> Self::Event {
+        match select(self.sub.next_message_pure(), BOND_REQUESTS.receive()).await {
+            Either::First(event) => BondEvent::Controller(event),
+            Either::Second(request) => BondEvent::Request(request),
+        }
+    }
+}
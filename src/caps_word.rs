@@ -0,0 +1,119 @@
+//! Caps Word of the central
+//!
+//! `CapsWord` turns the host's Caps Lock on for one word: the keys of
+//! [`caps_word_keys`](crate::caps_word_keys) keep it on, any other key or
+//! [`IDLE_TIMEOUT`] without a press turns it off again.
+//!
+//! RMK's keyboard has no hook to shift keys, so the word is written with the
+//! host's Caps Lock, tapped through [`host_keys`](crate::host_keys). It only
+//! shifts letters; `Minus` stays a minus.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use defmt::{info, unwrap};
+use embassy_time::{Duration, Instant};
+use rmk::channel::{CONTROLLER_CHANNEL, ControllerSub};
+use rmk::controller::{Controller, PollingController};
+use rmk::event::ControllerEvent;
+use rmk::types::keycode::KeyCode;
+
+use crate::caps_word_keys::{Effect, IDLE_TIMEOUT, effect};
+use crate::host_keys;
+use crate::user_keys::UserKey;
+
+/// Whether Caps Word is on, for the indicator LEDs
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Whether Caps Word is on
+pub(crate) fn active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Turns Caps Word on and off
+pub(crate) struct CapsWordController {
+    sub: ControllerSub,
+    /// Caps Lock of the host, as it reported its LEDs
+    caps_lock: bool,
+    /// Whether the host ever reported its LEDs
+    host_leds: bool,
+    last_press: Instant,
+}
+
+impl CapsWordController {
+    pub(crate) fn new() -> Self {
+        Self {
+            sub: unwrap!(CONTROLLER_CHANNEL.subscriber()),
+            caps_lock: false,
+            host_leds: false,
+            last_press: Instant::now(),
+        }
+    }
+
+    async fn start(&mut self) {
+        if !self.caps_lock {
+            host_keys::tap(KeyCode::CapsLock).await;
+        }
+        ACTIVE.store(true, Ordering::Relaxed);
+        self.last_press = Instant::now();
+        info!("Caps Word on");
+    }
+
+    async fn end(&mut self) {
+        ACTIVE.store(false, Ordering::Relaxed);
+        // Without LED reports, Caps Lock is still on from `start`
+        if self.caps_lock || !self.host_leds {
+            host_keys::tap(KeyCode::CapsLock).await;
+        }
+        info!("Caps Word off");
+    }
+}
+
+impl Controller for CapsWordController {
+    type Event = ControllerEvent;
+
+    async fn process_event(&mut self, event: Self::Event) {
+        match event {
+            ControllerEvent::KeyboardIndicator(leds) => {
+                self.caps_lock = leds.caps_lock();
+                self.host_leds = true;
+                // Caps Lock pressed, or turned off by the host
+                if active() && !self.caps_lock {
+                    ACTIVE.store(false, Ordering::Relaxed);
+                }
+            }
+            ControllerEvent::Key(key_event, action) if key_event.pressed => {
+                if UserKey::from_action(&action) == Some(UserKey::CapsWord) {
+                    if active() {
+                        self.end().await;
+                    } else {
+                        self.start().await;
+                    }
+                    return;
+                }
+                if !active() {
+                    return;
+                }
+                match effect(&action) {
+                    Effect::Word => self.last_press = Instant::now(),
+                    Effect::End => self.end().await,
+                    Effect::Ignore => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    async fn next_message(&mut self) -> Self::Event {
+        self.sub.next_message_pure().await
+    }
+}
+
+impl PollingController for CapsWordController {
+    const INTERVAL: Duration = Duration::from_millis(100);
+
+    async fn update(&mut self) {
+        if active() && self.last_press.elapsed() > IDLE_TIMEOUT {
+            self.end().await;
+        }
+    }
+}
@@ -0,0 +1,58 @@
+//! Keys of a Caps Word, see [`caps_word`](crate::caps_word)
+//!
+//! [`effect`] tells what pressing a key does to the word: letters, digits and
+//! the `continue_keys` of `[caps_word]` continue it, modifiers and layer keys
+//! are ignored, any other key ends it. A tap-hold counts as the key it taps,
+//! the auto shifted keys of [`auto_shift`](crate::auto_shift) and the `LT`
+//! thumbs among them.
+
+use rmk::types::action::{Action, KeyAction};
+use rmk::types::keycode::KeyCode;
+
+include!(concat!(env!("OUT_DIR"), "/caps_word_generated.rs"));
+
+/// What a key press does to Caps Word
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Effect {
+    /// Part of the word, keeps Caps Word on
+    Word,
+    /// Ends the word
+    End,
+    Ignore,
+}
+
+fn in_range(code: KeyCode, first: KeyCode, last: KeyCode) -> bool {
+    (first as u16..=last as u16).contains(&(code as u16))
+}
+
+fn key_effect(code: KeyCode) -> Effect {
+    if in_range(code, KeyCode::A, KeyCode::Z)
+        || in_range(code, KeyCode::Kc1, KeyCode::Kc0)
+        || CONTINUE_KEYS.contains(&code)
+    {
+        Effect::Word
+    } else if in_range(code, KeyCode::LCtrl, KeyCode::RGui) || code == KeyCode::CapsLock {
+        // Caps Lock ends it through the host's LED report
+        Effect::Ignore
+    } else {
+        Effect::End
+    }
+}
+
+/// What pressing a key with `action` does to Caps Word
+pub(crate) fn effect(action: &KeyAction) -> Effect {
+    match action {
+        // Whether it taps or holds is not known yet, it counts as the key it taps
+        KeyAction::Single(Action::Key(code)) | KeyAction::TapHold(Action::Key(code), _, _) => {
+            key_effect(*code)
+        }
+        // Shifted letters are part of the word, shifted symbols not
+        KeyAction::Single(Action::KeyWithModifier(code, _))
+            if in_range(*code, KeyCode::A, KeyCode::Z) =>
+        {
+            Effect::Word
+        }
+        KeyAction::Single(Action::KeyWithModifier(..)) => Effect::End,
+        _ => Effect::Ignore,
+    }
+}
@@ -4,43 +4,125 @@
 mod vial;
 #[macro_use]
 mod macros;
+mod app_storage;
+mod auto_shift;
+mod backup;
+mod behavior;
+mod blink;
+mod bonds;
+mod caps_word;
+mod caps_word_keys;
+mod combo_keys;
+mod combos;
+mod conn_handles;
+mod conn_params;
+mod custom_hid;
+mod custom_hid_desc;
+#[macro_use]
+mod display;
+mod display_render;
+mod ext_power;
+mod ficr;
+mod flash_map;
+mod flash_wear;
+mod host_keys;
+mod identity;
+mod identity_controller;
+#[macro_use]
+mod indicators;
+mod key_overrides;
 mod key_position;
+mod keyboard_macros;
 mod keymap;
+mod layer_lock;
+mod leader;
+mod leader_keys;
+mod pairing;
+mod pairing_controller;
+mod record_migration;
+mod repeat;
+mod schema;
+mod split_frame;
+mod split_status;
+mod split_telemetry;
+#[macro_use]
+mod split_uart;
+mod status_link;
+mod storage_layout;
+mod tx_power;
+mod tx_power_controller;
+mod typing_stats;
+#[macro_use]
+mod underglow;
+mod underglow_render;
+mod unicode;
+mod user_keys;
+mod vial_custom;
 
+use app_storage::{AppStorage, SharedAppStorage, SharedFlash};
+use backup::BackupController;
+use bonds::BondManager;
+use caps_word::CapsWordController;
+use conn_params::ConnParamsController;
+use custom_hid::{CustomEndpoints, CustomHidDriver};
 use defmt::{info, unwrap};
+use display::DisplayController;
+use embassy_embedded_hal::flash::partition::Partition;
 use embassy_executor::Spawner;
+use embassy_futures::select::{Either, select};
 use embassy_nrf::gpio::{Input, Output};
 use embassy_nrf::interrupt::{self, InterruptExt};
 use embassy_nrf::mode::Async;
-use embassy_nrf::peripherals::{RNG, SAADC, USBD};
+use embassy_nrf::peripherals::{RNG, SAADC, SPI3, TWISPI0, UARTE0, USBD};
 use embassy_nrf::saadc::{self, AnyInput, Input as _, Saadc};
 use embassy_nrf::usb::Driver;
 use embassy_nrf::usb::vbus_detect::HardwareVbusDetect;
-use embassy_nrf::{Peri, bind_interrupts, rng, usb};
+use embassy_nrf::{Peri, bind_interrupts, buffered_uarte, rng, spim, twim, uarte, usb};
+use embassy_sync::mutex::Mutex;
+use flash_wear::{WearCountingFlash, WearMonitor};
+use identity_controller::{IdentityController, identity_address};
+use indicators::IndicatorController;
+use layer_lock::LayerLockController;
+use leader::LeaderController;
 use nrf_mpsl::Flash;
 use nrf_sdc::mpsl::MultiprotocolServiceLayer;
 use nrf_sdc::{self as sdc, mpsl};
+use pairing::{PAIR_KEY, key_held};
+use pairing_controller::{PAIR_HALVES, PairingController, forget_peripherals};
 use rand_chacha::ChaCha12Rng;
 use rand_core::SeedableRng;
+use repeat::RepeatController;
 use rmk::ble::build_ble_stack;
 use rmk::channel::EVENT_CHANNEL;
 use rmk::config::{
     BehaviorConfig, BleBatteryConfig, DeviceConfig, PositionalConfig, RmkConfig, StorageConfig,
     VialConfig,
 };
-use rmk::controller::EventController as _;
-use rmk::controller::led_indicator::KeyboardIndicatorController;
+use rmk::controller::{EventController as _, PollingController as _};
 use rmk::debounce::default_debouncer::DefaultDebouncer;
-use rmk::futures::future::join4;
+use rmk::futures::future::{join, join3, join4};
 use rmk::input_device::Runnable;
 use rmk::input_device::battery::BatteryProcessor;
 use rmk::keyboard::Keyboard;
 use rmk::split::ble::central::{read_peripheral_addresses, scan_peripherals};
 use rmk::split::central::{CentralMatrix, run_peripheral_manager};
+use rmk::split::serial::run_serial_peripheral_manager;
 use rmk::{
     HostResources, initialize_encoder_keymap_and_storage, run_devices, run_processor_chain, run_rmk,
 };
+use split_telemetry::SplitLinkMonitor;
+use split_uart::{FramedUart, SPLIT_TRANSPORT, SPLIT_UART_DOWN, Side, SplitTransport};
 use static_cell::StaticCell;
+use status_link::StatusController;
+use storage_layout::{
+    APP_STORAGE_SECTORS, APP_STORAGE_START, FLASH_SIZE, RMK_STORAGE_SECTORS, RMK_STORAGE_START,
+    SECTOR_SIZE,
+};
+use tx_power::{DEFAULT_TX_POWER, TxPowerTarget, set_tx_power};
+use tx_power_controller::TxPowerController;
+use typing_stats::TypingStats;
+use underglow::UnderglowController;
+use unicode::UnicodeController;
 use vial::{VIAL_KEYBOARD_DEF, VIAL_KEYBOARD_ID};
 use {defmt_rtt as _, panic_probe as _};
 
@@ -53,6 +135,9 @@ bind_interrupts!(struct Irqs {
     RADIO => nrf_sdc::mpsl::HighPrioInterruptHandler;
     TIMER0 => nrf_sdc::mpsl::HighPrioInterruptHandler;
     RTC0 => nrf_sdc::mpsl::HighPrioInterruptHandler;
+    UARTE0 => buffered_uarte::InterruptHandler<UARTE0>;
+    TWISPI0 => twim::InterruptHandler<TWISPI0>;
+    SPIM3 => spim::InterruptHandler<SPI3>;
 });
 
 #[embassy_executor::task]
@@ -102,14 +187,6 @@ fn init_adc(adc_pin: AnyInput, adc: Peri<'static, SAADC>) -> Saadc<'static, 1> {
     saadc::Saadc::new(adc, Irqs, config, [channel_cfg])
 }
 
-fn ble_addr() -> [u8; 6] {
-    let ficr = embassy_nrf::pac::FICR;
-    let high = u64::from(ficr.deviceid(1).read());
-    let addr = high << 32 | u64::from(ficr.deviceid(0).read());
-    let addr = addr | 0x0000_c000_0000_0000;
-    unwrap!(addr.to_le_bytes()[..6].try_into())
-}
-
 #[embassy_executor::main]
 async fn main(spawner: Spawner) {
     info!("Hello RMK BLE in RUST!");
@@ -137,6 +214,19 @@ async fn main(spawner: Spawner) {
         SESSION_MEM.init(mpsl::SessionMem::new())
     )));
     spawner.must_spawn(mpsl_task(&*mpsl));
+
+    // Initialize flash, shared between RMK's storage and the firmware's own records
+    static FLASH: StaticCell<SharedFlash> = StaticCell::new();
+    let flash: &'static SharedFlash = FLASH.init(Mutex::new(WearCountingFlash::new(Flash::take(
+        mpsl, p.NVMC,
+    ))));
+    static APP_STORAGE: StaticCell<SharedAppStorage> = StaticCell::new();
+    let app_storage = APP_STORAGE.init(Mutex::new(AppStorage::new(Partition::new(
+        flash,
+        APP_STORAGE_START,
+        APP_STORAGE_SECTORS * SECTOR_SIZE,
+    ))));
+
     let sdc_p = sdc::Peripherals::new(
         p.PPI_CH17, p.PPI_CH18, p.PPI_CH20, p.PPI_CH21, p.PPI_CH22, p.PPI_CH23, p.PPI_CH24,
         p.PPI_CH25, p.PPI_CH26, p.PPI_CH27, p.PPI_CH28, p.PPI_CH29,
@@ -146,16 +236,52 @@ async fn main(spawner: Spawner) {
     let mut sdc_mem = sdc::Mem::<8192>::new();
     let sdc = unwrap!(build_sdc(sdc_p, &mut rng, mpsl, &mut sdc_mem));
     let mut host_resources = HostResources::new();
-    let stack = build_ble_stack(sdc, ble_addr(), &mut rng_gen, &mut host_resources).await;
-
-    // Initialize usb driver
-    let driver = Driver::new(p.USBD, Irqs, HardwareVbusDetect::new(Irqs));
+    let ble_addr = identity_address();
+    let stack = build_ble_stack(sdc, ble_addr, &mut rng_gen, &mut host_resources).await;
+    set_tx_power(&stack, TxPowerTarget::Advertising, DEFAULT_TX_POWER).await;
+    set_tx_power(&stack, TxPowerTarget::Initiator, DEFAULT_TX_POWER).await;
 
-    // Initialize flash
-    let flash = Flash::take(mpsl, p.NVMC);
+    // Initialize usb driver, the firmware adds its custom-channel interface to RMK's device
+    static CUSTOM_ENDPOINTS: CustomEndpoints<'static, Driver<'static, HardwareVbusDetect>> =
+        CustomEndpoints::new();
+    let driver = CustomHidDriver::new(
+        Driver::new(p.USBD, Irqs, HardwareVbusDetect::new(Irqs)),
+        &CUSTOM_ENDPOINTS,
+    );
 
     // Initialize IO Pins
-    let (row_pins, col_pins) = config_matrix_pins_nrf!(peripherals: p, input: [P0_22, P0_24, P1_00, P0_11], output:  [P0_31, P0_29, P0_02, P1_15, P1_13, P1_11]);
+    let (row_pins, mut col_pins) = config_matrix_pins_nrf!(peripherals: p, input: [P0_22, P0_24, P1_00, P0_11], output:  [P0_31, P0_29, P0_02, P1_15, P1_13, P1_11]);
+    let pair_at_boot = key_held(&row_pins, &mut col_pins, PAIR_KEY).await;
+
+    // Initialize the split UART, nice!nano pin 1 (TX) and pin 0 (RX)
+    let mut uart_config = uarte::Config::default();
+    uart_config.baudrate = split_uart::SPLIT_UART_BAUDRATE;
+    static UART_RX_BUF: StaticCell<[u8; 256]> = StaticCell::new();
+    static UART_TX_BUF: StaticCell<[u8; 256]> = StaticCell::new();
+    let split_duplex = match SPLIT_TRANSPORT {
+        SplitTransport::Uart(duplex) => duplex,
+        SplitTransport::Ble => split_uart::Duplex::Full,
+    };
+    // Only built when `[split]` selects the wired link
+    let mut split_uart = split_uart_pins!(p).map(|(rx, tx)| {
+        FramedUart::new(
+            buffered_uarte::BufferedUarte::new(
+                p.UARTE0,
+                p.TIMER1,
+                p.PPI_CH0,
+                p.PPI_CH1,
+                p.PPI_GROUP0,
+                rx,
+                tx,
+                Irqs,
+                uart_config,
+                UART_RX_BUF.init([0; 256]),
+                UART_TX_BUF.init([0; 256]),
+            ),
+            Side::Central,
+            split_duplex,
+        )
+    });
 
     // Initialize the ADC.
     // We are only using one channel for detecting battery level
@@ -176,8 +302,8 @@ async fn main(spawner: Spawner) {
     let vial_config = VialConfig::new(VIAL_KEYBOARD_ID, VIAL_KEYBOARD_DEF, &[(0, 0), (1, 1)]);
     let ble_battery_config = BleBatteryConfig::new(Some(is_charging_pin), true, None, false);
     let storage_config = StorageConfig {
-        start_addr: 0xA0000,
-        num_sectors: 6,
+        start_addr: RMK_STORAGE_START as usize,
+        num_sectors: RMK_STORAGE_SECTORS,
         ..Default::default()
     };
     let rmk_config = RmkConfig {
@@ -191,20 +317,33 @@ async fn main(spawner: Spawner) {
     let mut default_keymap = keymap::get_default_keymap();
     let mut behavior_config = BehaviorConfig::default();
     behavior_config.morse.enable_flow_tap = true;
-    behavior_config.morse.prior_idle_time = embassy_time::Duration::from_millis(30);
-    behavior_config.morse.default_profile = rmk::types::action::MorseProfile::new(
-        Some(true), // unilateral_tap
-        Some(rmk::types::action::MorseMode::PermissiveHold),
-        Some(240u16), // hold_timeout
-        Some(230u16), // gap_timeout
-    );
+    behavior_config.morse.prior_idle_time =
+        embassy_time::Duration::from_millis(keymap::PRIOR_IDLE_TIME_MS);
+    behavior_config.morse.default_profile = keymap::MORSE_DEFAULT;
+    behavior_config.tri_layer = behavior::TRI_LAYER;
+    // Before the combos, which look up keys in the keymap
+    auto_shift::apply(&mut default_keymap);
+    key_overrides::configure(&mut behavior_config.fork, &default_keymap);
+    combos::configure(&mut behavior_config.combo, &default_keymap);
+    keyboard_macros::configure(&mut behavior_config.keyboard_macros);
     // Create positional config based on real hand positions from matrix_map
     let mut key_config = key_position::create_corne_positional_config();
     let mut encoder_map = keymap::get_default_encoder_map();
+    // Bring the storage to this firmware's schema before RMK reads it
+    schema::migrate(
+        &mut Partition::new(
+            flash,
+            RMK_STORAGE_START,
+            RMK_STORAGE_SECTORS as u32 * SECTOR_SIZE,
+        ),
+        &mut *app_storage.lock().await,
+    )
+    .await;
     let (keymap, mut storage) = initialize_encoder_keymap_and_storage(
         &mut default_keymap,
         &mut encoder_map,
-        flash,
+        // RMK addresses the flash absolutely, so it gets all of it
+        Partition::new(flash, 0, FLASH_SIZE),
         &storage_config,
         &mut behavior_config,
         &mut key_config,
@@ -217,21 +356,74 @@ async fn main(spawner: Spawner) {
     let mut keyboard = Keyboard::new(&keymap);
 
     // Read peripheral address from storage
-    let peripheral_addrs = read_peripheral_addresses::<1, _, 8, 6, 8, 0>(&mut storage).await;
+    let mut peripheral_addrs = read_peripheral_addresses::<1, _, 8, 6, 9, 0>(&mut storage).await;
+    status_link::set_peer(peripheral_addrs.first().copied());
 
     // Initialize the encoder processor
     let mut batt_proc = BatteryProcessor::new(2000, 2806, &keymap);
 
     // Initialize the controllers
-    let mut capslock_led = KeyboardIndicatorController::new(
-        Output::new(
-            p.P0_15,
-            embassy_nrf::gpio::Level::Low,
-            embassy_nrf::gpio::OutputDrive::Standard,
-        ),
-        false,
-        rmk::types::led_indicator::LedIndicatorType::CapsLock,
-    );
+    let mut split_monitor = SplitLinkMonitor::new(&stack);
+    let mut indicators = IndicatorController::new(indicator_leds!(p));
+    let mut underglow = UnderglowController::new(underglow_pins!(p));
+    let mut display = DisplayController::new(display_pins!(p), Irqs);
+    let mut conn_params = ConnParamsController::new(&stack);
+    let mut tx_power = TxPowerController::new(&stack);
+    let mut identity = IdentityController::new(&stack, app_storage);
+    let mut bonds = BondManager::new(app_storage).await;
+    let mut wear = WearMonitor::new(app_storage).await;
+    let mut typing_stats = TypingStats::new(app_storage).await;
+    let mut pairing = PairingController::new(pair_at_boot);
+    let mut caps_word = CapsWordController::new();
+    let mut leader = LeaderController::new(&keymap);
+    let mut unicode = UnicodeController::new();
+    let mut repeat = RepeatController::new();
+    let mut layer_lock = LayerLockController::new(&keymap);
+    let mut backup = BackupController::new(flash);
+    let mut status = StatusController::new();
+
+    // Split link to the peripheral: BLE, or UART with BLE as fallback while the cable is unplugged.
+    // Restarted with the stored peripheral forgotten when the halves are re-paired.
+    let split_link = async {
+        let mut pair_halves = pair_at_boot;
+        loop {
+            if pair_halves {
+                forget_peripherals(&mut peripheral_addrs).await;
+            }
+            let link = async {
+                match (SPLIT_TRANSPORT, split_uart.as_mut()) {
+                    (SplitTransport::Uart(_), Some(split_uart)) => loop {
+                        if !split_uart.is_up() {
+                            select(
+                                split_uart.wait_link_up(),
+                                run_peripheral_manager::<4, 6, 4, 4, _>(
+                                    0,
+                                    &peripheral_addrs,
+                                    &stack,
+                                ),
+                            )
+                            .await;
+                        }
+                        SPLIT_UART_DOWN.reset();
+                        select(
+                            run_serial_peripheral_manager::<4, 6, 4, 4, _>(0, split_uart),
+                            SPLIT_UART_DOWN.wait(),
+                        )
+                        .await;
+                        info!("Split UART lost, falling back to BLE");
+                    },
+                    _ => {
+                        run_peripheral_manager::<4, 6, 4, 4, _>(0, &peripheral_addrs, &stack).await
+                    }
+                }
+            };
+            let scan = scan_peripherals(&stack, &peripheral_addrs);
+            pair_halves = matches!(
+                select(join(link, scan), PAIR_HALVES.wait()).await,
+                Either::Second(())
+            );
+        }
+    };
 
     // Start
     join4(
@@ -243,10 +435,42 @@ async fn main(spawner: Spawner) {
         },
         keyboard.run(),
         join4(
-            run_peripheral_manager::<4, 6, 4, 4, _>(0, &peripheral_addrs, &stack),
+            split_link,
             run_rmk(&keymap, driver, &stack, &mut storage, rmk_config),
-            scan_peripherals(&stack, &peripheral_addrs),
-            capslock_led.event_loop(),
+            join4(
+                pairing.event_loop(),
+                indicators.polling_loop(),
+                underglow.polling_loop(),
+                display.polling_loop(),
+            ),
+            join4(
+                split_monitor.polling_loop(),
+                conn_params.polling_loop(),
+                tx_power.polling_loop(),
+                join(
+                    join3(
+                        join4(
+                            identity.polling_loop(),
+                            bonds.event_loop(),
+                            backup.event_loop(),
+                            unicode.event_loop(),
+                        ),
+                        join4(
+                            repeat.event_loop(),
+                            layer_lock.event_loop(),
+                            status.event_loop(),
+                            status_link::run_central(&stack),
+                        ),
+                        custom_hid::run(&CUSTOM_ENDPOINTS),
+                    ),
+                    join4(
+                        wear.event_loop(),
+                        typing_stats.polling_loop(),
+                        caps_word.polling_loop(),
+                        leader.polling_loop(),
+                    ),
+                ),
+            ),
         ),
     )
     .await;
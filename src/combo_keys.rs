@@ -0,0 +1,95 @@
+//! Keys of the combos in `[[combo]]`, looked up in the keymap
+//!
+//! RMK matches combos by the actions of the pressed keys, so
+//! [`ComboDef::actions`] looks up the keys in the default keymap on the
+//! combo's layer: a matrix position gives its action, a key name the one key
+//! tapping it, tap-holds included. [`combos`](crate::combos) hands the
+//! combos to RMK.
+
+use defmt::warn;
+use rmk::types::action::{Action, KeyAction};
+use rmk::types::keycode::KeyCode;
+
+use crate::keymap::{COL, NUM_LAYER, ROW};
+
+pub(crate) type Keymap = [[[KeyAction; COL]; ROW]; NUM_LAYER];
+
+/// A key of a combo
+pub(crate) enum ComboKey {
+    /// The key at a row and column of the matrix
+    Position(u8, u8),
+    /// The key tapping a key code
+    Key(KeyCode),
+}
+
+/// A combo of `[[combo]]`
+pub(crate) struct ComboDef {
+    keys: &'static [ComboKey],
+    pub(crate) output: KeyAction,
+    /// The only layer the combo works on
+    pub(crate) layer: Option<u8>,
+}
+
+include!(concat!(env!("OUT_DIR"), "/combos_generated.rs"));
+
+/// The action at `row`, `col` on `layer`, transparent keys show the layers below
+pub(crate) fn resolve(keymap: &Keymap, layer: u8, row: usize, col: usize) -> KeyAction {
+    (0..=layer as usize)
+        .rev()
+        .map(|l| keymap[l][row][col])
+        .find(|action| *action != KeyAction::Transparent)
+        .unwrap_or(KeyAction::No)
+}
+
+/// The key code a key with `action` taps
+fn tapped(action: KeyAction) -> Option<KeyCode> {
+    match action {
+        KeyAction::Single(Action::Key(code)) | KeyAction::TapHold(Action::Key(code), _, _) => {
+            Some(code)
+        }
+        _ => None,
+    }
+}
+
+/// The action of `key` on `layer`, `None` unless exactly one key taps a key code
+fn key_action(keymap: &Keymap, layer: u8, key: &ComboKey) -> Option<KeyAction> {
+    match *key {
+        ComboKey::Position(row, col) => Some(resolve(keymap, layer, row as usize, col as usize)),
+        ComboKey::Key(code) => {
+            let mut found = (0..ROW)
+                .flat_map(|row| (0..COL).map(move |col| (row, col)))
+                .map(|(row, col)| resolve(keymap, layer, row, col))
+                .filter(|&action| tapped(action) == Some(code));
+            match (found.next(), found.next()) {
+                (Some(action), None) => Some(action),
+                (None, _) => {
+                    warn!("Combo key {:?} is not on layer {}", code, layer);
+                    None
+                }
+                (Some(_), Some(_)) => {
+                    warn!("Combo key {:?} is on several positions, give one", code);
+                    None
+                }
+            }
+        }
+    }
+}
+
+impl ComboDef {
+    /// The actions RMK matches for the keys in `keymap`, `None` if a key is not found
+    pub(crate) fn actions(&self, keymap: &Keymap) -> Option<impl Iterator<Item = KeyAction>> {
+        let layer = self.layer.unwrap_or(0);
+        if self
+            .keys
+            .iter()
+            .any(|key| key_action(keymap, layer, key).is_none())
+        {
+            return None;
+        }
+        Some(
+            self.keys
+                .iter()
+                .filter_map(move |key| key_action(keymap, layer, key)),
+        )
+    }
+}
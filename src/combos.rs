@@ -0,0 +1,29 @@
+//! Combos of the central
+//!
+//! `[[combo]]` in the keyboard TOML lists keys that send another action when
+//! pressed together. RMK matches combos by the actions of the pressed keys,
+//! [`combo_keys`](crate::combo_keys) finds them in the default keymap and
+//! [`configure`] hands them to RMK.
+//!
+//! The combos are defaults: RMK saves them to its storage on the first boot
+//! and Vial edits them there, later changes of `[[combo]]` only apply to a
+//! cleared storage.
+
+use rmk::combo::{Combo, ComboConfig};
+use rmk::config::CombosConfig;
+
+use crate::combo_keys::{COMBO_TIMEOUT, COMBOS, Keymap};
+
+/// Put the combos of `[[combo]]` and their timeout into `config`
+///
+/// A combo whose keys are not found is left out.
+pub(crate) fn configure(config: &mut CombosConfig, keymap: &Keymap) {
+    config.timeout = COMBO_TIMEOUT;
+    let combos = COMBOS.iter().filter_map(|def| {
+        let actions = def.actions(keymap)?;
+        Some(Combo::new(ComboConfig::new(actions, def.output, def.layer)))
+    });
+    for (slot, combo) in config.combos.iter_mut().zip(combos) {
+        *slot = Some(combo);
+    }
+}
@@ -0,0 +1,85 @@
+//! Connection handles of the split link and the host link
+//!
+//! The controller gives a new connection the lowest free handle, so which
+//! link has which handle depends on the order they connected in. When RMK
+//! reports a link connected, [`connected`] probes the controller's handles
+//! and takes the live one the other link doesn't have. Should both be new,
+//! the link reported first also connected first and got the lower handle.
+
+use core::cell::Cell;
+
+use bt_hci::cmd::status::ReadRssi;
+use bt_hci::controller::{Controller as HciController, ControllerCmdSync};
+use bt_hci::param::ConnHandle;
+use defmt::{Format, info, warn};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use trouble_host::prelude::{PacketPool, Stack};
+
+/// Connections the controller holds, `central_count` plus `peripheral_count` of the SDC
+const MAX_CONNECTIONS: u16 = 2;
+
+/// BLE links of a half
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+pub(crate) enum Link {
+    Split,
+    /// Only on the central
+    Host,
+}
+
+impl Link {
+    fn other(self) -> Self {
+        match self {
+            Link::Split => Link::Host,
+            Link::Host => Link::Split,
+        }
+    }
+}
+
+/// Handle of each link, `None` while it is disconnected
+static HANDLES: Mutex<CriticalSectionRawMutex, Cell<[Option<u16>; 2]>> =
+    Mutex::new(Cell::new([None; 2]));
+
+fn set(link: Link, handle: Option<u16>) {
+    HANDLES.lock(|handles| {
+        let mut all = handles.get();
+        all[link as usize] = handle;
+        handles.set(all);
+    });
+}
+
+/// Connection handle of `link`, `None` while it is disconnected
+pub(crate) fn handle(link: Link) -> Option<u16> {
+    HANDLES.lock(|handles| handles.get()[link as usize])
+}
+
+/// Find the handle of `link`, which RMK just reported connected
+pub(crate) async fn connected<C, P>(stack: &Stack<'_, C, P>, link: Link)
+where
+    C: HciController + ControllerCmdSync<ReadRssi>,
+    P: PacketPool,
+{
+    let taken = handle(link.other());
+    let mut found = None;
+    for candidate in (0..MAX_CONNECTIONS).filter(|h| Some(*h) != taken) {
+        // Only succeeds on a live connection
+        if stack
+            .command(ReadRssi::new(ConnHandle::new(candidate)))
+            .await
+            .is_ok()
+        {
+            found = Some(candidate);
+            break;
+        }
+    }
+    set(link, found);
+    match found {
+        Some(handle) => info!("{} link uses connection handle {}", link, handle),
+        None => warn!("No connection handle found for the {} link", link),
+    }
+}
+
+/// `link` was reported disconnected
+pub(crate) fn disconnected(link: Link) {
+    set(link, None);
+}
@@ -0,0 +1,203 @@
+//! BLE connection parameter profiles for the split link and the host link
+//!
+//! The profiles and which one each link uses are declared in `[conn_params]` of
+//! the keyboard TOML and generated by `build.rs`. [`ConnParamsController`]
+//! watches VBUS and switches both links between their USB and battery profiles.
+//! It also finds the links' connection handles, see
+//! [`conn_handles`](crate::conn_handles).
+
+use bt_hci::cmd::le::{LeConnUpdate, LeSetPhy};
+use bt_hci::cmd::status::ReadRssi;
+use bt_hci::controller::{Controller as HciController, ControllerCmdAsync, ControllerCmdSync};
+use bt_hci::param::{AllPhys, ConnHandle, PhyMask, PhyOptions};
+use defmt::{Format, info, unwrap, warn};
+use embassy_time::Duration;
+use rmk::ble::BleState;
+use rmk::channel::{CONTROLLER_CHANNEL, ControllerSub};
+use rmk::controller::{Controller, PollingController};
+use rmk::event::ControllerEvent;
+use trouble_host::prelude::{PacketPool, Stack};
+
+use crate::conn_handles::{self, Link};
+use crate::split_telemetry;
+
+/// Named connection parameter profiles
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+pub(crate) enum ConnProfile {
+    LowLatency = 0,
+    Balanced = 1,
+    LowPower = 2,
+}
+
+/// PHY used on a link
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+pub(crate) enum Phy {
+    Le1M,
+    Le2M,
+}
+
+/// Connection parameters of a profile
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+pub(crate) struct ConnParams {
+    /// Connection interval, min and max are both set to it
+    pub(crate) interval_us: u32,
+    /// Peripheral latency, in connection events
+    pub(crate) latency: u16,
+    /// Supervision timeout
+    pub(crate) timeout_ms: u32,
+    pub(crate) phy: Phy,
+}
+
+include!(concat!(env!("OUT_DIR"), "/conn_params_generated.rs"));
+
+impl ConnProfile {
+    pub(crate) const fn params(self) -> ConnParams {
+        CONN_PROFILES[self as usize]
+    }
+}
+
+impl Link {
+    fn profile(self, usb_powered: bool) -> ConnProfile {
+        match (self, usb_powered) {
+            (Link::Split, true) => SPLIT_USB_PROFILE,
+            (Link::Split, false) => SPLIT_BATTERY_PROFILE,
+            (Link::Host, true) => HOST_USB_PROFILE,
+            (Link::Host, false) => HOST_BATTERY_PROFILE,
+        }
+    }
+}
+
+/// Whether VBUS is present, i.e. the half is powered over USB
+pub(crate) fn usb_powered() -> bool {
+    embassy_nrf::pac::POWER.usbregstatus().read().vbusdetect()
+}
+
+/// Applies the connection profiles when a link connects or the power source changes
+pub(crate) struct ConnParamsController<'a, 'd, C: HciController, P: PacketPool> {
+    stack: &'a Stack<'d, C, P>,
+    sub: ControllerSub,
+    usb_powered: bool,
+    split_connected: bool,
+    host_connected: bool,
+}
+
+impl<'a, 'd, C, P> ConnParamsController<'a, 'd, C, P>
+where
+    C: HciController
+        + ControllerCmdAsync<LeConnUpdate>
+        + ControllerCmdAsync<LeSetPhy>
+        + ControllerCmdSync<ReadRssi>,
+    P: PacketPool,
+{
+    pub(crate) fn new(stack: &'a Stack<'d, C, P>) -> Self {
+        Self {
+            stack,
+            sub: unwrap!(CONTROLLER_CHANNEL.subscriber()),
+            usb_powered: usb_powered(),
+            split_connected: false,
+            host_connected: false,
+        }
+    }
+
+    async fn apply(&mut self, link: Link) {
+        let Some(handle) = conn_handles::handle(link) else {
+            return;
+        };
+        let handle = ConnHandle::new(handle);
+        let profile = link.profile(self.usb_powered);
+        let params = profile.params();
+        let interval = bt_hci::param::Duration::from_micros(params.interval_us as u64);
+        let update = LeConnUpdate::new(
+            handle,
+            interval,
+            interval,
+            params.latency,
+            bt_hci::param::Duration::from_millis(params.timeout_ms),
+            bt_hci::param::Duration::from_u16(0),
+            bt_hci::param::Duration::from_u16(0),
+        );
+        if self.stack.async_command(update).await.is_err() {
+            warn!("Failed to update connection parameters of {} link", link);
+            return;
+        }
+        let phy = match params.phy {
+            Phy::Le1M => PhyMask::new().set_le_1m_preferred(true),
+            Phy::Le2M => PhyMask::new().set_le_2m_preferred(true),
+        };
+        let set_phy = LeSetPhy::new(handle, AllPhys::default(), phy, phy, PhyOptions::default());
+        if self.stack.async_command(set_phy).await.is_err() {
+            warn!("Failed to set PHY of {} link", link);
+        }
+        if link == Link::Split {
+            split_telemetry::update(0, |s| s.conn_interval_us = Some(params.interval_us));
+        }
+        info!("{} link uses {} profile: {}", link, profile, params);
+    }
+}
+
+impl<C, P> Controller for ConnParamsController<'_, '_, C, P>
+where
+    C: HciController
+        + ControllerCmdAsync<LeConnUpdate>
+        + ControllerCmdAsync<LeSetPhy>
+        + ControllerCmdSync<ReadRssi>,
+    P: PacketPool,
+{
+    type Event = ControllerEvent;
+
+    async fn process_event(&mut self, event: Self::Event) {
+        match event {
+            ControllerEvent::SplitPeripheral(0, connected) => {
+                self.split_connected = connected;
+                if connected {
+                    conn_handles::connected(self.stack, Link::Split).await;
+                    self.apply(Link::Split).await;
+                } else {
+                    conn_handles::disconnected(Link::Split);
+                    split_telemetry::update(0, |s| s.conn_interval_us = None);
+                }
+            }
+            ControllerEvent::BleState(_, state) => {
+                let connected = state == BleState::Connected;
+                if connected && !self.host_connected {
+                    conn_handles::connected(self.stack, Link::Host).await;
+                    self.apply(Link::Host).await;
+                } else if !connected {
+                    conn_handles::disconnected(Link::Host);
+                }
+                self.host_connected = connected;
+            }
+            _ => {}
+        }
+    }
+
+    async fn next_message(&mut self) -> Self::Event {
+        self.sub.next_message_pure().await
+    }
+}
+
+impl<C, P> PollingController for ConnParamsController<'_, '_, C, P>
+where
+    C: HciController
+        + ControllerCmdAsync<LeConnUpdate>
+        + ControllerCmdAsync<LeSetPhy>
+        + ControllerCmdSync<ReadRssi>,
+    P: PacketPool,
+{
+    const INTERVAL: Duration = Duration::from_secs(1);
+
+    async fn update(&mut self) {
+        let usb_powered = usb_powered();
+        if usb_powered == self.usb_powered {
+            return;
+        }
+        self.usb_powered = usb_powered;
+        info!("Power source changed, USB: {}", usb_powered);
+        if self.split_connected {
+            self.apply(Link::Split).await;
+        }
+        if self.host_connected {
+            self.apply(Link::Host).await;
+        }
+    }
+}
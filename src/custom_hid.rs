@@ -0,0 +1,359 @@
+//! USB interface of the custom channels
+//!
+//! RMK 0.8's Vial service does not hand custom-channel reports to user code,
+//! and RMK builds the USB device itself. So the central hands RMK its USB
+//! driver wrapped in [`CustomHidDriver`], which adds a raw HID interface of
+//! its own next to RMK's, on the vendor usage page
+//! [`USAGE_PAGE`](crate::custom_hid_desc::USAGE_PAGE): it takes two more
+//! interrupt endpoints from the driver, appends the interface to the
+//! configuration descriptor and answers the HID requests for it, and
+//! enables its endpoints whenever RMK's device enables its own. [`run`]
+//! answers every report on the interface with
+//! [`handle_custom_report`], which hands it to the module owning its channel.
+//! RMK's interfaces and reports are left alone.
+//!
+//! Over BLE the host tools can't reach the channels, they need USB.
+
+use defmt::error;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_usb_driver::{
+    Bus, ControlPipe, Driver, Endpoint, EndpointAddress, EndpointAllocError, EndpointError,
+    EndpointIn, EndpointOut, EndpointType, Event, Unsupported,
+};
+
+use crate::custom_hid_desc::{
+    HID_DESCRIPTOR, INTERVAL_MS, REPORT_DESCRIPTOR, REPORT_LEN, extend_config,
+};
+use crate::vial_custom::{CustomChannel, CustomCommand, VIA_REPORT_LEN, handle_custom_report};
+use crate::{backup, bonds, flash_wear, split_telemetry, typing_stats};
+
+/// Endpoints of the interface, handed from the driver to [`run`]
+pub(crate) type CustomEndpoints<'a, D> = Signal<
+    CriticalSectionRawMutex,
+    (
+        <D as Driver<'a>>::EndpointIn,
+        <D as Driver<'a>>::EndpointOut,
+    ),
+>;
+
+/// Largest configuration descriptor with the interface added
+const CONFIG_DESCRIPTOR_MAX: usize = 512;
+
+// Standard and HID requests
+const GET_STATUS: u8 = 0x00;
+const GET_DESCRIPTOR: u8 = 0x06;
+const GET_INTERFACE: u8 = 0x0A;
+const SET_INTERFACE: u8 = 0x0B;
+const HID_SET_IDLE: u8 = 0x0A;
+const DESCRIPTOR_CONFIGURATION: u8 = 0x02;
+const DESCRIPTOR_HID: u8 = 0x21;
+const DESCRIPTOR_REPORT: u8 = 0x22;
+
+/// USB driver adding the custom-channel interface to RMK's device
+pub(crate) struct CustomHidDriver<'a, D: Driver<'a>> {
+    inner: D,
+    endpoints: &'a CustomEndpoints<'a, D>,
+}
+
+impl<'a, D: Driver<'a>> CustomHidDriver<'a, D> {
+    pub(crate) fn new(inner: D, endpoints: &'a CustomEndpoints<'a, D>) -> Self {
+        Self { inner, endpoints }
+    }
+}
+
+impl<'a, D: Driver<'a>> Driver<'a> for CustomHidDriver<'a, D> {
+    type EndpointOut = D::EndpointOut;
+    type EndpointIn = D::EndpointIn;
+    type ControlPipe = CustomHidControl<D::ControlPipe>;
+    type Bus = CustomHidBus<D::Bus>;
+
+    fn alloc_endpoint_out(
+        &mut self,
+        ep_type: EndpointType,
+        ep_addr: Option<EndpointAddress>,
+        max_packet_size: u16,
+        interval_ms: u8,
+    ) -> Result<Self::EndpointOut, EndpointAllocError> {
+        self.inner
+            .alloc_endpoint_out(ep_type, ep_addr, max_packet_size, interval_ms)
+    }
+
+    fn alloc_endpoint_in(
+        &mut self,
+        ep_type: EndpointType,
+        ep_addr: Option<EndpointAddress>,
+        max_packet_size: u16,
+        interval_ms: u8,
+    ) -> Result<Self::EndpointIn, EndpointAllocError> {
+        self.inner
+            .alloc_endpoint_in(ep_type, ep_addr, max_packet_size, interval_ms)
+    }
+
+    fn start(mut self, control_max_packet_size: u16) -> (Self::Bus, Self::ControlPipe) {
+        // RMK's device has taken its endpoints, the interface gets the next free ones
+        let endpoints = self
+            .inner
+            .alloc_endpoint_in(
+                EndpointType::Interrupt,
+                None,
+                REPORT_LEN as u16,
+                INTERVAL_MS,
+            )
+            .and_then(|ep_in| {
+                let ep_out = self.inner.alloc_endpoint_out(
+                    EndpointType::Interrupt,
+                    None,
+                    REPORT_LEN as u16,
+                    INTERVAL_MS,
+                )?;
+                Ok((ep_in, ep_out))
+            });
+        let addresses = match endpoints {
+            Ok((ep_in, ep_out)) => {
+                let addresses = Some((ep_in.info().addr, ep_out.info().addr));
+                self.endpoints.signal((ep_in, ep_out));
+                addresses
+            }
+            Err(_) => {
+                error!("No endpoints left for the custom channels");
+                None
+            }
+        };
+        let (bus, control) = self.inner.start(control_max_packet_size);
+        (
+            CustomHidBus {
+                inner: bus,
+                addresses,
+            },
+            CustomHidControl {
+                inner: control,
+                addresses,
+                interface: None,
+                config_request: None,
+                config: [0; CONFIG_DESCRIPTOR_MAX],
+                config_len: 0,
+            },
+        )
+    }
+}
+
+/// Bus of RMK's device, enabling the interface's endpoints along with RMK's
+pub(crate) struct CustomHidBus<B> {
+    inner: B,
+    addresses: Option<(EndpointAddress, EndpointAddress)>,
+}
+
+impl<B: Bus> Bus for CustomHidBus<B> {
+    async fn enable(&mut self) {
+        self.inner.enable().await
+    }
+
+    async fn disable(&mut self) {
+        self.inner.disable().await
+    }
+
+    async fn poll(&mut self) -> Event {
+        self.inner.poll().await
+    }
+
+    fn endpoint_set_enabled(&mut self, ep_addr: EndpointAddress, enabled: bool) {
+        self.inner.endpoint_set_enabled(ep_addr, enabled);
+        // RMK's device (de)configures its endpoints, ours share its configuration
+        if let Some((ep_in, ep_out)) = self.addresses {
+            if ep_addr != ep_in && ep_addr != ep_out {
+                self.inner.endpoint_set_enabled(ep_in, enabled);
+                self.inner.endpoint_set_enabled(ep_out, enabled);
+            }
+        }
+    }
+
+    fn endpoint_set_stalled(&mut self, ep_addr: EndpointAddress, stalled: bool) {
+        self.inner.endpoint_set_stalled(ep_addr, stalled)
+    }
+
+    fn endpoint_is_stalled(&mut self, ep_addr: EndpointAddress) -> bool {
+        self.inner.endpoint_is_stalled(ep_addr)
+    }
+
+    async fn remote_wakeup(&mut self) -> Result<(), Unsupported> {
+        self.inner.remote_wakeup().await
+    }
+}
+
+/// Control pipe of RMK's device, answering the requests for the interface
+pub(crate) struct CustomHidControl<C> {
+    inner: C,
+    addresses: Option<(EndpointAddress, EndpointAddress)>,
+    /// Number of the interface, known once the host read the configuration
+    interface: Option<u8>,
+    /// Length the host asked for while RMK's device sends the configuration
+    /// descriptor
+    config_request: Option<usize>,
+    /// The configuration descriptor collected from RMK's device
+    config: [u8; CONFIG_DESCRIPTOR_MAX],
+    config_len: usize,
+}
+
+impl<C: ControlPipe> CustomHidControl<C> {
+    /// Answer `setup` if it is for the interface
+    async fn handle(&mut self, setup: &[u8; 8]) -> bool {
+        let [
+            request_type,
+            request,
+            value_lo,
+            value_hi,
+            index,
+            _,
+            len_lo,
+            len_hi,
+        ] = *setup;
+        let requested = u16::from_le_bytes([len_lo, len_hi]) as usize;
+        // Requests to an interface, or its class
+        let recipient = request_type & 0x1F;
+        if recipient != 0x01 || self.interface != Some(index) {
+            return false;
+        }
+        let reply: &[u8] = match (request_type, request, value_hi) {
+            (0x81, GET_DESCRIPTOR, DESCRIPTOR_REPORT) => &REPORT_DESCRIPTOR,
+            (0x81, GET_DESCRIPTOR, DESCRIPTOR_HID) => &HID_DESCRIPTOR,
+            (0x81, GET_STATUS, _) => &[0, 0],
+            (0x81, GET_INTERFACE, _) => &[0],
+            (0x01, SET_INTERFACE, _) if value_lo == 0 => {
+                self.inner.accept().await;
+                return true;
+            }
+            (0x21, HID_SET_IDLE, _) => {
+                self.inner.accept().await;
+                return true;
+            }
+            _ => {
+                self.inner.reject().await;
+                return true;
+            }
+        };
+        let _ = send(&mut self.inner, reply, requested).await;
+        true
+    }
+}
+
+/// Send `data` cut to `requested` bytes on `pipe`, in packets
+async fn send<C: ControlPipe>(
+    pipe: &mut C,
+    data: &[u8],
+    requested: usize,
+) -> Result<(), EndpointError> {
+    let data = &data[..data.len().min(requested)];
+    let max = pipe.max_packet_size();
+    // A short reply ends with a short packet, a zero length one if need be
+    let zlp = data.len() < requested && data.len() % max == 0;
+    let packets = data.len().div_ceil(max);
+    for (i, packet) in data.chunks(max).enumerate() {
+        pipe.data_in(packet, i == 0, i + 1 == packets && !zlp)
+            .await?;
+    }
+    if zlp || data.is_empty() {
+        pipe.data_in(&[], data.is_empty(), true).await?;
+    }
+    Ok(())
+}
+
+impl<C: ControlPipe> ControlPipe for CustomHidControl<C> {
+    fn max_packet_size(&self) -> usize {
+        self.inner.max_packet_size()
+    }
+
+    async fn setup(&mut self) -> [u8; 8] {
+        loop {
+            let setup = self.inner.setup().await;
+            if self.handle(&setup).await {
+                continue;
+            }
+            let [request_type, request, _, value_hi, _, _, len_lo, len_hi] = setup;
+            self.config_request = (self.addresses.is_some()
+                && request_type == 0x80
+                && request == GET_DESCRIPTOR
+                && value_hi == DESCRIPTOR_CONFIGURATION)
+                .then(|| u16::from_le_bytes([len_lo, len_hi]) as usize);
+            self.config_len = 0;
+            return setup;
+        }
+    }
+
+    async fn data_out(
+        &mut self,
+        buf: &mut [u8],
+        first: bool,
+        last: bool,
+    ) -> Result<usize, EndpointError> {
+        self.inner.data_out(buf, first, last).await
+    }
+
+    async fn data_in(&mut self, data: &[u8], first: bool, last: bool) -> Result<(), EndpointError> {
+        let (Some(requested), Some((ep_in, ep_out))) = (self.config_request, self.addresses) else {
+            return self.inner.data_in(data, first, last).await;
+        };
+        // Collect the configuration descriptor, then send it with the interface
+        let end = self.config_len + data.len();
+        if end > self.config.len() {
+            self.config_request = None;
+            return self.inner.data_in(data, first, last).await;
+        }
+        self.config[self.config_len..end].copy_from_slice(data);
+        self.config_len = end;
+        if !last {
+            return Ok(());
+        }
+        self.config_request = None;
+        let mut len = self.config_len;
+        if let Some((extended, interface)) = extend_config(
+            &mut self.config,
+            len,
+            requested,
+            ep_in.into(),
+            ep_out.into(),
+        ) {
+            len = extended;
+            self.interface = Some(interface);
+        }
+        send(&mut self.inner, &self.config[..len], requested).await
+    }
+
+    async fn accept(&mut self) {
+        self.inner.accept().await
+    }
+
+    async fn reject(&mut self) {
+        self.inner.reject().await
+    }
+
+    async fn accept_set_address(&mut self, addr: u8) {
+        self.inner.accept_set_address(addr).await
+    }
+}
+
+/// Hand a custom command to the module owning its channel
+fn dispatch(channel: CustomChannel, command: CustomCommand, value_id: u8, data: &mut [u8]) -> bool {
+    match channel {
+        CustomChannel::SplitLink => split_telemetry::handle_custom_command(command, value_id, data),
+        CustomChannel::Bonds => bonds::handle_custom_command(command, value_id, data),
+        CustomChannel::Backup => backup::handle_custom_command(command, value_id, data),
+        CustomChannel::Wear => flash_wear::handle_custom_command(command, value_id, data),
+        CustomChannel::TypingStats => typing_stats::handle_custom_command(command, value_id, data),
+    }
+}
+
+/// Answer the reports on the interface
+pub(crate) async fn run<'a, D: Driver<'a>>(endpoints: &'a CustomEndpoints<'a, D>) {
+    let (mut ep_in, mut ep_out) = endpoints.wait().await;
+    loop {
+        ep_out.wait_enabled().await;
+        let mut report = [0; VIA_REPORT_LEN];
+        // Disabled by a reset or a report of the wrong size
+        if !matches!(ep_out.read(&mut report).await, Ok(REPORT_LEN)) {
+            continue;
+        }
+        handle_custom_report(&mut report, dispatch);
+        let _ = ep_in.write(&report).await;
+    }
+}
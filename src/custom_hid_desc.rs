@@ -0,0 +1,107 @@
+//! Descriptors of the custom-channel HID interface, see
+//! [`custom_hid`](crate::custom_hid)
+//!
+//! The interface is a raw HID interface like VIA's, 32-byte input and output
+//! reports without a report id, on its own vendor usage page. It goes last in
+//! the configuration descriptor RMK's USB device sends, which
+//! [`extend_config`] rewrites.
+
+/// Vendor usage page of the interface, VIA's is 0xFF60
+pub(crate) const USAGE_PAGE: u16 = 0xFF62;
+/// Usage of the interface within [`USAGE_PAGE`]
+pub(crate) const USAGE: u8 = 0x01;
+
+/// Size of the input and output reports
+pub(crate) const REPORT_LEN: usize = 32;
+
+/// Polling interval of the endpoints
+pub(crate) const INTERVAL_MS: u8 = 1;
+
+/// HID report descriptor
+#[rustfmt::skip]
+pub(crate) const REPORT_DESCRIPTOR: [u8; 34] = [
+    0x06, USAGE_PAGE as u8, (USAGE_PAGE >> 8) as u8, // Usage Page
+    0x09, USAGE, // Usage
+    0xA1, 0x01, // Collection (Application)
+    0x09, 0x02, //   Usage (Data In)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x26, 0xFF, 0x00, //   Logical Maximum (255)
+    0x95, REPORT_LEN as u8, //   Report Count
+    0x75, 0x08, //   Report Size (8)
+    0x81, 0x02, //   Input (Data, Variable, Absolute)
+    0x09, 0x03, //   Usage (Data Out)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x26, 0xFF, 0x00, //   Logical Maximum (255)
+    0x95, REPORT_LEN as u8, //   Report Count
+    0x75, 0x08, //   Report Size (8)
+    0x91, 0x02, //   Output (Data, Variable, Absolute)
+    0xC0, // End Collection
+];
+
+/// HID class descriptor of the interface
+pub(crate) const HID_DESCRIPTOR: [u8; 9] = [
+    9,
+    0x21, // HID
+    0x11,
+    0x01, // HID 1.11
+    0x00, // Not localized
+    1,    // One report descriptor
+    0x22, // Report
+    REPORT_DESCRIPTOR.len() as u8,
+    0,
+];
+
+/// Length of the interface, HID and endpoint descriptors
+pub(crate) const INTERFACE_LEN: usize = 9 + HID_DESCRIPTOR.len() + 2 * 7;
+
+/// Interface `number` with its HID descriptor and interrupt endpoints
+/// `ep_in`, `ep_out`
+pub(crate) fn interface(number: u8, ep_in: u8, ep_out: u8) -> [u8; INTERFACE_LEN] {
+    let mut desc = [0; INTERFACE_LEN];
+    // Alternate setting 0 with two endpoints, HID without subclass, boot protocol or name
+    desc[..9].copy_from_slice(&[9, 0x04, number, 0, 2, 0x03, 0, 0, 0]);
+    desc[9..18].copy_from_slice(&HID_DESCRIPTOR);
+    for (i, address) in [ep_in, ep_out].into_iter().enumerate() {
+        // Interrupt endpoint of one report
+        let endpoint = [7, 0x05, address, 0x03, REPORT_LEN as u8, 0, INTERVAL_MS];
+        desc[18 + 7 * i..25 + 7 * i].copy_from_slice(&endpoint);
+    }
+    desc
+}
+
+/// Total length of the configuration descriptor `desc` says it has
+fn total_len(desc: &[u8]) -> Option<usize> {
+    (desc.len() >= 4).then(|| u16::from_le_bytes([desc[2], desc[3]]) as usize)
+}
+
+/// Add the interface to the configuration descriptor in `buf[..len]`
+///
+/// `buf[..len]` is what RMK's device sends for a request of `requested`
+/// bytes, the descriptor cut to that length. The interface is appended once
+/// the whole descriptor is there, and the header counts it either way, so
+/// the host asks for the longer descriptor. Returns the length to send and
+/// the number of the interface, `None` if `buf` is too small or this is not
+/// a configuration descriptor.
+pub(crate) fn extend_config(
+    buf: &mut [u8],
+    len: usize,
+    requested: usize,
+    ep_in: u8,
+    ep_out: u8,
+) -> Option<(usize, u8)> {
+    let total = total_len(&buf[..len])?;
+    if len < 5 || buf[1] != 0x02 {
+        return None;
+    }
+    let number = buf[4];
+    let mut out = len;
+    if len == total {
+        let desc = interface(number, ep_in, ep_out);
+        buf.get_mut(len..len + INTERFACE_LEN)?
+            .copy_from_slice(&desc);
+        out += INTERFACE_LEN;
+    }
+    buf[2..4].copy_from_slice(&((total + INTERFACE_LEN) as u16).to_le_bytes());
+    buf[4] = number + 1;
+    Some((out.min(requested), number))
+}
@@ -0,0 +1,412 @@
+//! Display of both halves
+//!
+//! `[display]` in the keyboard TOML selects an SSD1306 OLED (128x32, I2C) or a
+//! nice!view (160x68 Sharp memory LCD, SPI); `build.rs` generates the
+//! [`display_pins!`] macro handing out its peripherals, the layer names and
+//! the sleep timeout.
+//!
+//! [`display_render`](crate::display_render) draws the status into a frame,
+//! the panel drivers convert the frame to their own memory layout on flush.
+//! [`DisplayController`] follows the controller events and what the other
+//! half forwards through [`status_link`](crate::status_link), and redraws
+//! when the status changes. Both halves show the central's layer, BLE
+//! profile and WPM and the batteries of both.
+//!
+//! After [`SLEEP_TIMEOUT`] without typing the display turns off and releases
+//! its supply in [`ext_power`](crate::ext_power), the next key or layer
+//! change wakes it.
+
+use defmt::{unwrap, warn};
+use embassy_nrf::gpio::{AnyPin, Level, Output, OutputDrive};
+use embassy_nrf::interrupt::typelevel::{self, Binding};
+use embassy_nrf::peripherals::{SPI3, TWISPI0};
+use embassy_nrf::{Peri, spim, twim};
+use embassy_time::{Duration, Instant, Timer};
+use embedded_hal_async::i2c::I2c;
+use embedded_hal_async::spi::SpiBus;
+use rmk::channel::{CONTROLLER_CHANNEL, ControllerSub};
+use rmk::controller::{Controller, PollingController};
+use rmk::event::ControllerEvent;
+use static_cell::ConstStaticCell;
+
+use crate::display_render::{Frame, Status, render};
+use crate::ext_power::{self, User};
+use crate::status_link;
+
+include!(concat!(env!("OUT_DIR"), "/display_generated.rs"));
+
+impl Status {
+    /// Apply a controller event, returns whether the status changed
+    pub(crate) fn update(&mut self, event: &ControllerEvent) -> bool {
+        let old = *self;
+        match *event {
+            ControllerEvent::Layer(layer) => self.layer = Some(layer),
+            ControllerEvent::BleProfile(profile) => self.profile = Some(profile),
+            ControllerEvent::Wpm(wpm) => self.wpm = Some(wpm),
+            ControllerEvent::Battery(level) => self.battery = Some(level),
+            ControllerEvent::ChargingState(charging) => self.charging = charging,
+            ControllerEvent::SplitPeripheral(_, connected)
+            | ControllerEvent::SplitCentral(connected) => self.linked = connected,
+            _ => {}
+        }
+        *self != old
+    }
+}
+
+/// Whether the change from `old` to `new` comes from typing, not from a battery
+fn typing_changed(old: &Status, new: &Status) -> bool {
+    let typing = |s: &Status| {
+        (
+            s.layer.or(s.other.layer),
+            s.profile.or(s.other.profile),
+            s.wpm.or(s.other.wpm),
+        )
+    };
+    typing(old) != typing(new)
+}
+
+/// Peripherals of the display, from [`display_pins!`]
+#[allow(dead_code)] // Only the driver in [display] is constructed
+pub(crate) enum DisplayPins {
+    Ssd1306 {
+        twim: Peri<'static, TWISPI0>,
+        sda: Peri<'static, AnyPin>,
+        scl: Peri<'static, AnyPin>,
+    },
+    NiceView {
+        spim: Peri<'static, SPI3>,
+        sck: Peri<'static, AnyPin>,
+        mosi: Peri<'static, AnyPin>,
+        cs: Peri<'static, AnyPin>,
+    },
+}
+
+/// I2C address of the SSD1306
+const SSD1306_ADDR: u8 = 0x3C;
+
+/// SSD1306 setup for a 128x32 panel with the charge pump on, horizontal addressing
+const SSD1306_INIT: [u8; 25] = [
+    0xAE, // display off
+    0xD5, 0x80, // clock divider
+    0xA8, 0x1F, // multiplex, 32 rows
+    0xD3, 0x00, // no display offset
+    0x40, // start line 0
+    0x8D, 0x14, // charge pump on
+    0x20, 0x00, // horizontal addressing
+    0xA1, // columns mirrored
+    0xC8, // rows scanned from the bottom
+    0xDA, 0x02, // COM pins for 32 rows
+    0x81, 0x8F, // contrast
+    0xD9, 0xF1, // precharge
+    0xDB, 0x40, // VCOMH level
+    0xA4, // show the RAM
+    0xA6, // not inverted
+    0xAF, // display on
+];
+
+/// SSD1306 OLED, 128x32 on I2C
+struct Ssd1306 {
+    i2c: twim::Twim<'static>,
+    /// Cleared on sleep, the supply may be cut until the next flush
+    initialized: bool,
+    /// Control byte followed by the RAM, 8 rows per byte
+    buf: [u8; 1 + 128 * 32 / 8],
+}
+
+impl Ssd1306 {
+    const WIDTH: usize = 128;
+    const HEIGHT: usize = 32;
+
+    async fn command(&mut self, commands: &[u8]) -> Result<(), twim::Error> {
+        let mut buf = [0; 1 + SSD1306_INIT.len()];
+        buf[1..1 + commands.len()].copy_from_slice(commands);
+        self.i2c
+            .write(SSD1306_ADDR, &buf[..1 + commands.len()])
+            .await
+    }
+
+    async fn flush(&mut self, frame: &Frame) -> Result<(), twim::Error> {
+        if !self.initialized {
+            self.command(&SSD1306_INIT).await?;
+            self.initialized = true;
+        }
+        // Whole RAM: columns 0..127, pages 0..3
+        self.command(&[0x21, 0, Self::WIDTH as u8 - 1, 0x22, 0, 3])
+            .await?;
+        self.buf[0] = 0x40;
+        for page in 0..Self::HEIGHT / 8 {
+            for x in 0..Self::WIDTH {
+                let column = (0..8).fold(0u8, |byte, bit| {
+                    byte | (frame.pixel(x, page * 8 + bit) as u8) << bit
+                });
+                self.buf[1 + page * Self::WIDTH + x] = column;
+            }
+        }
+        self.i2c.write(SSD1306_ADDR, &self.buf).await
+    }
+
+    async fn sleep(&mut self) -> Result<(), twim::Error> {
+        self.initialized = false;
+        // Display off, the charge pump stops with it
+        self.command(&[0xAE]).await
+    }
+}
+
+/// nice!view, a 160x68 Sharp LS011B7DH03 memory LCD on SPI
+///
+/// The panel keeps its image without refreshes, but its VCOM has to be
+/// toggled at least once a second.
+struct NiceView {
+    spi: spim::Spim<'static>,
+    cs: Output<'static>,
+    vcom: bool,
+    /// Command, then per row its address, its pixels and a dummy byte, then a trailer
+    buf: [u8; 2 + 68 * (160 / 8 + 2)],
+}
+
+impl NiceView {
+    const WIDTH: usize = 160;
+    const HEIGHT: usize = 68;
+    const WRITE: u8 = 0x01;
+    const VCOM: u8 = 0x02;
+    const CLEAR: u8 = 0x04;
+
+    async fn send(&mut self, len: usize) -> Result<(), spim::Error> {
+        // Chip select is active high
+        self.cs.set_high();
+        let result = self.spi.write(&self.buf[..len]).await;
+        self.cs.set_low();
+        result
+    }
+
+    fn command(&mut self, command: u8) -> u8 {
+        self.vcom = !self.vcom;
+        if self.vcom {
+            command | Self::VCOM
+        } else {
+            command
+        }
+    }
+
+    async fn flush(&mut self, frame: &Frame) -> Result<(), spim::Error> {
+        self.buf[0] = self.command(Self::WRITE);
+        let row_len = Self::WIDTH / 8 + 2;
+        for y in 0..Self::HEIGHT {
+            let row = &mut self.buf[1 + y * row_len..1 + (y + 1) * row_len];
+            row[0] = y as u8 + 1;
+            // A set bit is a white pixel
+            for (out, &pixels) in row[1..row_len - 1].iter_mut().zip(frame.row(y)) {
+                *out = !pixels;
+            }
+            row[row_len - 1] = 0;
+        }
+        let len = 1 + Self::HEIGHT * row_len + 1;
+        self.buf[len - 1] = 0;
+        self.send(len).await
+    }
+
+    /// Toggle VCOM without changing the image
+    async fn maintain(&mut self) -> Result<(), spim::Error> {
+        self.buf[0] = self.command(0);
+        self.buf[1] = 0;
+        self.send(2).await
+    }
+
+    /// Blank the panel, in case the underglow keeps it powered
+    async fn sleep(&mut self) -> Result<(), spim::Error> {
+        self.buf[0] = self.command(Self::CLEAR);
+        self.buf[1] = 0;
+        self.send(2).await
+    }
+}
+
+enum Panel {
+    Ssd1306(Ssd1306),
+    NiceView(NiceView),
+}
+
+impl Panel {
+    fn new<I>(pins: DisplayPins, irqs: I) -> Self
+    where
+        I: Binding<typelevel::TWISPI0, twim::InterruptHandler<TWISPI0>>
+            + Binding<typelevel::SPIM3, spim::InterruptHandler<SPI3>>,
+    {
+        match pins {
+            DisplayPins::Ssd1306 { twim, sda, scl } => {
+                // Only used for writes from flash, the frame is in RAM
+                static TWIM_RAM: ConstStaticCell<[u8; 32]> = ConstStaticCell::new([0; 32]);
+                let mut config = twim::Config::default();
+                config.frequency = twim::Frequency::K400;
+                Panel::Ssd1306(Ssd1306 {
+                    i2c: twim::Twim::new(twim, irqs, sda, scl, config, TWIM_RAM.take()),
+                    initialized: false,
+                    buf: [0; 1 + 128 * 32 / 8],
+                })
+            }
+            DisplayPins::NiceView {
+                spim,
+                sck,
+                mosi,
+                cs,
+            } => {
+                let mut config = spim::Config::default();
+                config.frequency = spim::Frequency::M1;
+                config.bit_order = spim::BitOrder::LSB_FIRST;
+                Panel::NiceView(NiceView {
+                    spi: spim::Spim::new_txonly(spim, irqs, sck, mosi, config),
+                    cs: Output::new(cs, Level::Low, OutputDrive::Standard),
+                    vcom: false,
+                    buf: [0; 2 + 68 * (160 / 8 + 2)],
+                })
+            }
+        }
+    }
+
+    fn frame(&self) -> Frame {
+        match self {
+            Panel::Ssd1306(_) => Frame::new(Ssd1306::WIDTH, Ssd1306::HEIGHT),
+            Panel::NiceView(_) => Frame::new(NiceView::WIDTH, NiceView::HEIGHT),
+        }
+    }
+
+    async fn flush(&mut self, frame: &Frame) -> bool {
+        match self {
+            Panel::Ssd1306(panel) => panel.flush(frame).await.is_ok(),
+            Panel::NiceView(panel) => panel.flush(frame).await.is_ok(),
+        }
+    }
+
+    async fn maintain(&mut self) -> bool {
+        match self {
+            Panel::Ssd1306(_) => true,
+            Panel::NiceView(panel) => panel.maintain().await.is_ok(),
+        }
+    }
+
+    async fn sleep(&mut self) -> bool {
+        match self {
+            Panel::Ssd1306(panel) => panel.sleep().await.is_ok(),
+            Panel::NiceView(panel) => panel.sleep().await.is_ok(),
+        }
+    }
+}
+
+/// Longest time between two transfers to the panel, for the nice!view's VCOM
+const MAINTAIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Time from powering the panel until it accepts commands
+const POWER_UP_TIME: Duration = Duration::from_millis(100);
+
+/// Redraws the display when the status changes
+pub(crate) struct DisplayController {
+    sub: ControllerSub,
+    panel: Option<Panel>,
+    frame: Frame,
+    status: Status,
+    dirty: bool,
+    last_transfer: Instant,
+    /// Last key or change from typing, the display sleeps [`SLEEP_TIMEOUT`] after it
+    last_activity: Instant,
+    /// Whether the panel is on and holds the supply
+    awake: bool,
+}
+
+impl DisplayController {
+    /// `pins` come from [`display_pins!`]; without them the controller does nothing
+    pub(crate) fn new<I>(pins: Option<DisplayPins>, irqs: I) -> Self
+    where
+        I: Binding<typelevel::TWISPI0, twim::InterruptHandler<TWISPI0>>
+            + Binding<typelevel::SPIM3, spim::InterruptHandler<SPI3>>,
+    {
+        let panel = pins.map(|pins| Panel::new(pins, irqs));
+        let frame = match &panel {
+            Some(panel) => panel.frame(),
+            None => Frame::new(0, 0),
+        };
+        Self {
+            sub: unwrap!(CONTROLLER_CHANNEL.subscriber()),
+            panel,
+            frame,
+            status: Status::default(),
+            dirty: true,
+            last_transfer: Instant::now(),
+            last_activity: Instant::now(),
+            awake: false,
+        }
+    }
+
+    /// Take what the other half forwarded last
+    fn follow_other_half(&mut self) {
+        let old = self.status;
+        self.status.other = status_link::remote();
+        if self.status != old {
+            self.dirty = true;
+            if typing_changed(&old, &self.status) {
+                self.last_activity = Instant::now();
+            }
+        }
+    }
+}
+
+impl Controller for DisplayController {
+    type Event = ControllerEvent;
+
+    async fn process_event(&mut self, event: Self::Event) {
+        let old = self.status;
+        if self.status.update(&event) {
+            self.dirty = true;
+            if typing_changed(&old, &self.status) {
+                self.last_activity = Instant::now();
+            }
+        }
+        if let ControllerEvent::Key(..) = event {
+            self.last_activity = Instant::now();
+        }
+    }
+
+    async fn next_message(&mut self) -> Self::Event {
+        self.sub.next_message_pure().await
+    }
+}
+
+impl PollingController for DisplayController {
+    /// Bounds the redraws while typing changes the WPM
+    const INTERVAL: Duration = Duration::from_millis(200);
+
+    async fn update(&mut self) {
+        self.follow_other_half();
+        let Some(panel) = self.panel.as_mut() else {
+            return;
+        };
+        if self.last_activity.elapsed() >= SLEEP_TIMEOUT {
+            if self.awake {
+                self.awake = false;
+                if !panel.sleep().await {
+                    warn!("Failed to turn the display off");
+                }
+                ext_power::release(User::Display);
+            }
+            return;
+        }
+        if !self.awake {
+            self.awake = true;
+            self.dirty = true;
+            if ext_power::claim(User::Display) {
+                Timer::after(POWER_UP_TIME).await;
+            }
+        }
+        let ok = if self.dirty {
+            self.dirty = false;
+            render(&self.status, &LAYER_NAMES, &mut self.frame);
+            panel.flush(&self.frame).await
+        } else if self.last_transfer.elapsed() >= MAINTAIN_INTERVAL {
+            panel.maintain().await
+        } else {
+            return;
+        };
+        self.last_transfer = Instant::now();
+        if !ok {
+            warn!("Failed to update the display");
+        }
+    }
+}
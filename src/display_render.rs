@@ -0,0 +1,162 @@
+//! What the displays show, drawn into a 1-bit frame
+//!
+//! [`render`] draws a [`Status`] into a [`Frame`] with embedded-graphics,
+//! the panel drivers in [`display`](crate::display) convert the frame to
+//! their own memory layout on flush.
+
+use core::fmt::Write as _;
+
+use defmt::Format;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::mono_font::ascii::FONT_6X10;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::*;
+use embedded_graphics::text::{Baseline, Text};
+
+use crate::split_status::SplitStatus;
+
+/// Largest panel, the nice!view
+const MAX_WIDTH: usize = 160;
+const MAX_HEIGHT: usize = 68;
+
+/// A 1-bit frame, rows of bytes with the leftmost pixel in the top bit
+pub(crate) struct Frame {
+    width: usize,
+    height: usize,
+    bits: [u8; MAX_WIDTH * MAX_HEIGHT / 8],
+}
+
+impl Frame {
+    /// A blank frame, `width` a multiple of 8
+    pub(crate) const fn new(width: usize, height: usize) -> Self {
+        assert!(width.is_multiple_of(8) && width <= MAX_WIDTH && height <= MAX_HEIGHT);
+        Self {
+            width,
+            height,
+            bits: [0; MAX_WIDTH * MAX_HEIGHT / 8],
+        }
+    }
+
+    fn stride(&self) -> usize {
+        self.width / 8
+    }
+
+    /// Whether the pixel at `x`, `y` is on
+    pub(crate) fn pixel(&self, x: usize, y: usize) -> bool {
+        self.bits[y * self.stride() + x / 8] & (0x80 >> (x % 8)) != 0
+    }
+
+    /// The pixels of row `y`, 8 per byte
+    pub(crate) fn row(&self, y: usize) -> &[u8] {
+        &self.bits[y * self.stride()..(y + 1) * self.stride()]
+    }
+}
+
+impl OriginDimensions for Frame {
+    fn size(&self) -> Size {
+        Size::new(self.width as u32, self.height as u32)
+    }
+}
+
+impl DrawTarget for Frame {
+    type Color = BinaryColor;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            let (Ok(x), Ok(y)) = (usize::try_from(point.x), usize::try_from(point.y)) else {
+                continue;
+            };
+            if x >= self.width || y >= self.height {
+                continue;
+            }
+            let index = y * self.stride() + x / 8;
+            let mask = 0x80 >> (x % 8);
+            if color.is_on() {
+                self.bits[index] |= mask;
+            } else {
+                self.bits[index] &= !mask;
+            }
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.bits.fill(if color.is_on() { 0xFF } else { 0 });
+        Ok(())
+    }
+}
+
+/// What the display shows, `None` until the half learns it
+#[derive(Clone, Copy, Default, PartialEq, Eq, Format)]
+pub(crate) struct Status {
+    pub(crate) layer: Option<u8>,
+    pub(crate) profile: Option<u8>,
+    pub(crate) wpm: Option<u16>,
+    pub(crate) battery: Option<u8>,
+    pub(crate) charging: bool,
+    /// Whether the other half is connected
+    pub(crate) linked: bool,
+    /// What the other half forwarded
+    pub(crate) other: SplitStatus,
+}
+
+/// Write a battery level, `+` while charging
+fn write_battery(line: &mut impl core::fmt::Write, battery: Option<u8>, charging: bool) {
+    let _ = match battery {
+        Some(level) => write!(line, "{level}%"),
+        None => write!(line, "--"),
+    };
+    if charging {
+        let _ = write!(line, "+");
+    }
+}
+
+/// Draw `status` into `frame`, three lines of text
+///
+/// ```text
+/// base_layer
+/// BT1  WPM 42
+/// BAT 80%+ / 75%
+/// ```
+///
+/// The layer, the profile and the WPM are the central's, the peripheral
+/// shows what it forwarded. The first battery is this half's, the second
+/// the other half's while it is connected. `layer_names` are indexed by
+/// layer.
+pub(crate) fn render(status: &Status, layer_names: &[&str], frame: &mut Frame) {
+    let _ = frame.clear(BinaryColor::Off);
+    let style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
+    let mut lines: [heapless::String<32>; 3] = Default::default();
+
+    // Without a layer, the link is all there is to show
+    let _ = match status.layer.or(status.other.layer) {
+        Some(layer) => {
+            let name = layer_names.get(layer as usize).copied().unwrap_or("?");
+            write!(lines[0], "{name}")
+        }
+        None if status.linked => write!(lines[0], "linked"),
+        None => write!(lines[0], "no link"),
+    };
+    if let Some(profile) = status.profile.or(status.other.profile) {
+        let _ = write!(lines[1], "BT{}  ", profile + 1);
+    }
+    if let Some(wpm) = status.wpm.or(status.other.wpm) {
+        let _ = write!(lines[1], "WPM {wpm}");
+    }
+    let _ = write!(lines[2], "BAT ");
+    write_battery(&mut lines[2], status.battery, status.charging);
+    if status.other.battery.is_some() {
+        let _ = write!(lines[2], " / ");
+        write_battery(&mut lines[2], status.other.battery, status.other.charging);
+    }
+
+    let line_height = FONT_6X10.character_size.height as i32 + 1;
+    for (i, line) in lines.iter().enumerate() {
+        let origin = Point::new(0, i as i32 * line_height);
+        let _ = Text::with_baseline(line, origin, style, Baseline::Top).draw(frame);
+    }
+}
@@ -0,0 +1,93 @@
+//! Switched supply of the WS2812 chain and the display
+//!
+//! On the nice!nano the `power_pin` of `[underglow]` (`P0_13`) switches the
+//! `VCC` pin of the header, which also supplies a display. The underglow and
+//! the display each claim the supply while they need it and it is cut once
+//! neither does: the LEDs while they are dark, the display while it sleeps.
+//! Without a power pin the supply stays on.
+
+use core::cell::RefCell;
+
+use embassy_nrf::Peri;
+use embassy_nrf::gpio::{AnyPin, Level, Output, OutputDrive};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+
+/// What needs the supply, one bit each
+#[derive(Clone, Copy)]
+pub(crate) enum User {
+    Underglow = 0x01,
+    Display = 0x02,
+}
+
+/// The power pin and whether a low level turns the supply on
+struct Switch {
+    pin: Output<'static>,
+    active_low: bool,
+}
+
+/// Level of a power pin that turns the supply `on`
+const fn level(on: bool, active_low: bool) -> Level {
+    if on != active_low {
+        Level::High
+    } else {
+        Level::Low
+    }
+}
+
+impl Switch {
+    fn set(&mut self, on: bool) {
+        self.pin.set_level(level(on, self.active_low));
+    }
+}
+
+/// Users holding the supply and the power pin switching it
+struct Supply {
+    claims: u8,
+    switch: Option<Switch>,
+}
+
+impl Supply {
+    fn update(&mut self) {
+        if let Some(switch) = self.switch.as_mut() {
+            switch.set(self.claims != 0);
+        }
+    }
+}
+
+static SUPPLY: Mutex<CriticalSectionRawMutex, RefCell<Supply>> = Mutex::new(RefCell::new(Supply {
+    claims: 0,
+    switch: None,
+}));
+
+/// Take the power pin, the supply follows the claims from now on
+pub(crate) fn init(pin: Peri<'static, AnyPin>, active_low: bool) {
+    SUPPLY.lock(|supply| {
+        let mut supply = supply.borrow_mut();
+        let initial = level(supply.claims != 0, active_low);
+        let pin = Output::new(pin, initial, OutputDrive::Standard);
+        supply.switch = Some(Switch { pin, active_low });
+    });
+}
+
+/// Claim the supply for `user`, returns whether this switched it on
+///
+/// The user has to give its devices time to power up then.
+pub(crate) fn claim(user: User) -> bool {
+    SUPPLY.lock(|supply| {
+        let mut supply = supply.borrow_mut();
+        let was_off = supply.claims == 0;
+        supply.claims |= user as u8;
+        supply.update();
+        was_off && supply.switch.is_some()
+    })
+}
+
+/// Release the claim of `user`, the supply is cut if it was the last one
+pub(crate) fn release(user: User) {
+    SUPPLY.lock(|supply| {
+        let mut supply = supply.borrow_mut();
+        supply.claims &= !(user as u8);
+        supply.update();
+    });
+}
@@ -0,0 +1,8 @@
+//! Factory information of the nRF52840
+
+/// 64-bit device id burned into FICR, unique per chip
+pub(crate) fn device_id() -> u64 {
+    let ficr = embassy_nrf::pac::FICR;
+    let high = u64::from(ficr.deviceid(1).read());
+    high << 32 | u64::from(ficr.deviceid(0).read())
+}
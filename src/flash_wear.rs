@@ -0,0 +1,281 @@
+//! Erase counters of the storage sectors
+//!
+//! Every Vial change ends up as a write to the storage, and sequential-storage
+//! erases a sector whenever it runs full. The NVMC only guarantees
+//! [`NVMC_ENDURANCE`] erase cycles per page, so the central counts them: the
+//! shared flash is wrapped in [`WearCountingFlash`], the totals are kept in the
+//! firmware-owned records and [`WearMonitor`] warns over defmt once a sector
+//! gets close to the limit. The host reads them through the
+//! [`CustomChannel::Wear`] Vial channel on the USB custom-channel interface,
+//! see [`custom_hid`](crate::custom_hid).
+//!
+//! The totals are saved right after every erase. Erases are rare, one per
+//! sector of records written, so this adds little wear of its own, and a
+//! power loss only loses the erases since the last save if it hits in
+//! between. Erases before the counters existed are not known, the counts
+//! start at 0.
+//!
+//! The wrapper also counts every change of the flash, so [`changes`] tells
+//! whether something computed from the storage is still current.
+//!
+//! [`CustomChannel::Wear`]: crate::vial_custom::CustomChannel::Wear
+
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use defmt::{Format, info, warn};
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embedded_storage_async::nor_flash::{ErrorType, MultiwriteNorFlash, NorFlash, ReadNorFlash};
+use rmk::controller::Controller;
+use sequential_storage::map::{SerializationError, Value};
+
+use crate::app_storage::{RecordKind, SharedAppStorage, record_key};
+use crate::storage_layout::{
+    APP_STORAGE_SECTORS, RMK_STORAGE_SECTORS, RMK_STORAGE_START, SECTOR_SIZE,
+};
+use crate::vial_custom::CustomCommand;
+
+/// Guaranteed erase cycles of an NVMC page on the nRF52840
+pub(crate) const NVMC_ENDURANCE: u32 = 10_000;
+
+/// Erase count from which a sector is reported as worn, 80% of the endurance
+const WARN_ERASES: u32 = NVMC_ENDURANCE / 5 * 4;
+
+/// Sectors of RMK's storage followed by the firmware-owned records
+pub(crate) const STORAGE_SECTORS: usize =
+    RMK_STORAGE_SECTORS as usize + APP_STORAGE_SECTORS as usize;
+
+/// Total erases of each storage sector, including the ones before this boot
+static ERASES: BlockingMutex<CriticalSectionRawMutex, RefCell<WearCounters>> =
+    BlockingMutex::new(RefCell::new(WearCounters {
+        erases: [0; STORAGE_SECTORS],
+        dirty: false,
+    }));
+
+/// Signaled when a storage sector was erased
+static ERASED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Writes and erases through [`WearCountingFlash`] since boot
+static CHANGES: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Clone, Copy, Format)]
+struct WearCounters {
+    erases: [u32; STORAGE_SECTORS],
+    /// Changed since the last save
+    dirty: bool,
+}
+
+/// Erase counts of the storage sectors
+pub(crate) fn erase_counts() -> [u32; STORAGE_SECTORS] {
+    ERASES.lock(|c| c.borrow().erases)
+}
+
+/// Number of writes and erases of the flash so far, wraps around
+pub(crate) fn changes() -> u32 {
+    CHANGES.load(Ordering::Acquire)
+}
+
+/// Count the erase of the sector at flash address `addr`, if it is a storage sector
+fn count_erase(addr: u32) {
+    let Some(offset) = addr.checked_sub(RMK_STORAGE_START) else {
+        return;
+    };
+    let sector = (offset / SECTOR_SIZE) as usize;
+    ERASES.lock(|c| {
+        let mut c = c.borrow_mut();
+        if let Some(erases) = c.erases.get_mut(sector) {
+            *erases = erases.saturating_add(1);
+            c.dirty = true;
+            ERASED.signal(());
+        }
+    });
+}
+
+/// A flash that counts the erases of the storage sectors
+///
+/// Addresses are absolute, so it wraps the whole NVMC below the partitions.
+pub(crate) struct WearCountingFlash<F> {
+    flash: F,
+}
+
+impl<F> WearCountingFlash<F> {
+    pub(crate) fn new(flash: F) -> Self {
+        Self { flash }
+    }
+}
+
+impl<F: ErrorType> ErrorType for WearCountingFlash<F> {
+    type Error = F::Error;
+}
+
+impl<F: ReadNorFlash> ReadNorFlash for WearCountingFlash<F> {
+    const READ_SIZE: usize = F::READ_SIZE;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.flash.read(offset, bytes).await
+    }
+
+    fn capacity(&self) -> usize {
+        self.flash.capacity()
+    }
+}
+
+impl<F: NorFlash> NorFlash for WearCountingFlash<F> {
+    const WRITE_SIZE: usize = F::WRITE_SIZE;
+    const ERASE_SIZE: usize = F::ERASE_SIZE;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        let erased = self.flash.erase(from, to).await;
+        // Once done, also when it failed half way
+        CHANGES.fetch_add(1, Ordering::AcqRel);
+        erased?;
+        for addr in (from..to).step_by(F::ERASE_SIZE) {
+            count_erase(addr);
+        }
+        Ok(())
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let written = self.flash.write(offset, bytes).await;
+        CHANGES.fetch_add(1, Ordering::AcqRel);
+        written
+    }
+}
+
+impl<F: MultiwriteNorFlash> MultiwriteNorFlash for WearCountingFlash<F> {}
+
+/// Saved erase counts, one `u32` per storage sector
+#[derive(Clone, Copy)]
+struct SavedCounts([u32; STORAGE_SECTORS]);
+
+impl<'a> Value<'a> for SavedCounts {
+    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+        let len = STORAGE_SECTORS * 4;
+        if buffer.len() < len {
+            return Err(SerializationError::BufferTooSmall);
+        }
+        for (chunk, count) in buffer.as_chunks_mut::<4>().0.iter_mut().zip(self.0) {
+            *chunk = count.to_le_bytes();
+        }
+        Ok(len)
+    }
+
+    fn deserialize_from(buffer: &'a [u8]) -> Result<Self, SerializationError> {
+        // Counts of sectors added since the save start at 0
+        let mut counts = [0; STORAGE_SECTORS];
+        for (count, chunk) in counts.iter_mut().zip(buffer.as_chunks::<4>().0) {
+            *count = u32::from_le_bytes(*chunk);
+        }
+        Ok(Self(counts))
+    }
+}
+
+/// Value ids of the wear Vial channel
+const VALUE_SUMMARY: u8 = 0x01;
+const VALUE_COUNTS: u8 = 0x02;
+
+/// Sector counts per report
+const COUNTS_PER_REPORT: usize = 7;
+
+/// Handle a custom command on the wear channel
+///
+/// - `get 0x01`: `sectors u8 | endurance u32 | highest count u32 | first sector address u32`
+/// - `get 0x02 <first sector>`: `first sector u8 | up to 7 counts u32`
+pub(crate) fn handle_custom_command(command: CustomCommand, value_id: u8, data: &mut [u8]) -> bool {
+    let counts = erase_counts();
+    match (command, value_id) {
+        (CustomCommand::Get, VALUE_SUMMARY) => {
+            let highest = counts.iter().copied().max().unwrap_or(0);
+            data[0] = STORAGE_SECTORS as u8;
+            data[1..5].copy_from_slice(&NVMC_ENDURANCE.to_le_bytes());
+            data[5..9].copy_from_slice(&highest.to_le_bytes());
+            data[9..13].copy_from_slice(&RMK_STORAGE_START.to_le_bytes());
+            true
+        }
+        (CustomCommand::Get, VALUE_COUNTS) => {
+            let Some(counts) = counts.get(data[0] as usize..) else {
+                return false;
+            };
+            for (chunk, count) in data[1..]
+                .as_chunks_mut::<4>()
+                .0
+                .iter_mut()
+                .zip(counts.iter().take(COUNTS_PER_REPORT))
+            {
+                *chunk = count.to_le_bytes();
+            }
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Saves the erase counts and warns about worn sectors
+pub(crate) struct WearMonitor<'a> {
+    storage: &'a SharedAppStorage,
+    warned: bool,
+}
+
+impl<'a> WearMonitor<'a> {
+    /// Add the saved counts to the ones counted since boot
+    pub(crate) async fn new(storage: &'a SharedAppStorage) -> Self {
+        let key = record_key(RecordKind::Wear, 0);
+        if let Some(SavedCounts(saved)) = storage.lock().await.fetch::<SavedCounts>(key).await {
+            ERASES.lock(|c| {
+                let mut c = c.borrow_mut();
+                for (erases, saved) in c.erases.iter_mut().zip(saved) {
+                    *erases = erases.saturating_add(saved);
+                }
+            });
+        }
+        Self {
+            storage,
+            warned: false,
+        }
+    }
+
+    fn log_summary(&mut self, counts: &[u32; STORAGE_SECTORS]) {
+        info!("Storage sector erases: {}", counts);
+        for (sector, &erases) in counts.iter().enumerate() {
+            if erases >= WARN_ERASES && !self.warned {
+                warn!(
+                    "Storage sector at {:#x} was erased {} times, the NVMC guarantees {}; consider moving the storage",
+                    RMK_STORAGE_START + sector as u32 * SECTOR_SIZE,
+                    erases,
+                    NVMC_ENDURANCE
+                );
+            }
+        }
+        self.warned |= counts.iter().any(|&e| e >= WARN_ERASES);
+    }
+}
+
+impl Controller for WearMonitor<'_> {
+    type Event = ();
+
+    /// Save the counts after an erase
+    ///
+    /// The save can erase a sector of the firmware's records in turn, which is
+    /// saved by the next round.
+    async fn process_event(&mut self, _: Self::Event) {
+        let Some(counts) = ERASES.lock(|c| {
+            let mut c = c.borrow_mut();
+            core::mem::take(&mut c.dirty).then_some(c.erases)
+        }) else {
+            return;
+        };
+        let key = record_key(RecordKind::Wear, 0);
+        self.storage
+            .lock()
+            .await
+            .store(key, &SavedCounts(counts))
+            .await;
+        self.log_summary(&counts);
+    }
+
+    async fn next_message(&mut self) -> Self::Event {
+        ERASED.wait().await
+    }
+}
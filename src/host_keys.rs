@@ -0,0 +1,139 @@
+//! Keys the firmware taps on the host by itself
+//!
+//! Actions that RMK's keyboard doesn't know send their own keyboard reports
+//! through RMK's report channel, in between the ones of the keyboard. Such a
+//! report replaces the keyboard's: unless it carries what the keyboard holds,
+//! see [`tap_holding`], a key held at that moment is released on the host
+//! until the keyboard sends its next report.
+//!
+//! Text is typed for a US layout on the host, characters outside ASCII
+//! through [`unicode`](crate::unicode).
+
+use embassy_time::{Duration, Timer};
+use rmk::channel::KEYBOARD_REPORT_CHANNEL;
+use rmk::hid::Report;
+use rmk::types::keycode::KeyCode;
+use usbd_hid::descriptor::KeyboardReport;
+
+/// HID modifier bits
+pub(crate) const LCTRL: u8 = 0x01;
+pub(crate) const LSHIFT: u8 = 0x02;
+pub(crate) const LALT: u8 = 0x04;
+pub(crate) const RALT: u8 = 0x40;
+
+/// How long a tapped Caps Lock stays down
+///
+/// macOS ignores shorter Caps Lock presses, QMK holds it as long
+/// (`TAP_HOLD_CAPS_DELAY`).
+const CAPS_LOCK_HOLD: Duration = Duration::from_millis(80);
+
+/// Press and release `key`
+pub(crate) async fn tap(key: KeyCode) {
+    tap_with(key, 0).await;
+}
+
+/// Press and release `key` with the HID `modifiers` held
+///
+/// Caps Lock is held for [`CAPS_LOCK_HOLD`].
+pub(crate) async fn tap_with(key: KeyCode, modifiers: u8) {
+    tap_holding(key, modifiers, 0, &[]).await;
+}
+
+/// Press and release `key` with the HID `modifiers` while the keyboard holds
+/// the HID modifiers `held_modifiers` and the keys `held_keys`
+///
+/// Both reports carry what the keyboard holds, so the host sees none of it
+/// released. With six keys held `key` has no room and is not sent. Caps Lock
+/// is held for [`CAPS_LOCK_HOLD`].
+pub(crate) async fn tap_holding(
+    key: KeyCode,
+    modifiers: u8,
+    held_modifiers: u8,
+    held_keys: &[KeyCode],
+) {
+    let mut held = [0; 6];
+    for (slot, &code) in held.iter_mut().zip(held_keys) {
+        *slot = code as u8;
+    }
+    let mut keycodes = held;
+    if let Some(slot) = keycodes.iter_mut().find(|slot| **slot == 0) {
+        *slot = key as u8;
+    }
+    send(modifiers | held_modifiers, keycodes).await;
+    if key == KeyCode::CapsLock {
+        Timer::after(CAPS_LOCK_HOLD).await;
+    }
+    send(held_modifiers, held).await;
+}
+
+/// Tap `keys` one after the other while the HID `modifiers` stay held
+pub(crate) async fn tap_all_with(keys: impl IntoIterator<Item = KeyCode>, modifiers: u8) {
+    for key in keys {
+        let mut keycodes = [0; 6];
+        keycodes[0] = key as u8;
+        send(modifiers, keycodes).await;
+        send(modifiers, [0; 6]).await;
+    }
+    send(0, [0; 6]).await;
+}
+
+/// The key and whether it is shifted that types `c` on a US layout
+pub(crate) fn ascii_key(c: char) -> Option<(KeyCode, bool)> {
+    use KeyCode::*;
+    const DIGITS: [KeyCode; 10] = [Kc0, Kc1, Kc2, Kc3, Kc4, Kc5, Kc6, Kc7, Kc8, Kc9];
+    const LETTERS: [KeyCode; 26] = [
+        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    ];
+    Some(match c {
+        'a'..='z' => (LETTERS[c as usize - 'a' as usize], false),
+        'A'..='Z' => (LETTERS[c as usize - 'A' as usize], true),
+        '0'..='9' => (DIGITS[c as usize - '0' as usize], false),
+        ')' => (Kc0, true),
+        '!' => (Kc1, true),
+        '@' => (Kc2, true),
+        '#' => (Kc3, true),
+        '$' => (Kc4, true),
+        '%' => (Kc5, true),
+        '^' => (Kc6, true),
+        '&' => (Kc7, true),
+        '*' => (Kc8, true),
+        '(' => (Kc9, true),
+        ' ' => (Space, false),
+        '\n' => (Enter, false),
+        '\t' => (Tab, false),
+        '-' => (Minus, false),
+        '_' => (Minus, true),
+        '=' => (Equal, false),
+        '+' => (Equal, true),
+        '[' => (LeftBracket, false),
+        '{' => (LeftBracket, true),
+        ']' => (RightBracket, false),
+        '}' => (RightBracket, true),
+        '\\' => (Backslash, false),
+        '|' => (Backslash, true),
+        ';' => (Semicolon, false),
+        ':' => (Semicolon, true),
+        '\'' => (Quote, false),
+        '"' => (Quote, true),
+        '`' => (Grave, false),
+        '~' => (Grave, true),
+        ',' => (Comma, false),
+        '<' => (Comma, true),
+        '.' => (Dot, false),
+        '>' => (Dot, true),
+        '/' => (Slash, false),
+        '?' => (Slash, true),
+        _ => return None,
+    })
+}
+
+async fn send(modifier: u8, keycodes: [u8; 6]) {
+    KEYBOARD_REPORT_CHANNEL
+        .send(Report::KeyboardReport(KeyboardReport {
+            modifier,
+            reserved: 0,
+            leds: 0,
+            keycodes,
+        }))
+        .await;
+}
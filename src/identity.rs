@@ -0,0 +1,72 @@
+//! BLE identity addresses
+//!
+//! Each half uses the `ble_addr` configured for it in the keyboard TOML, or
+//! derives one from the chip's FICR device id when none is set. The central
+//! also gets a distinct address per BLE profile, so each host pairs with what
+//! looks like a separate device. Profile 0 and the split link use the
+//! configured address as it is. The other profiles derive theirs from it, so
+//! their hosts have to pair again once.
+//!
+//! Without a configured address profile 0 gets the FICR address the firmware
+//! always used, which the peripheral and the hosts already know. Setting
+//! `ble_addr` on a keyboard in use changes it: the halves have to be paired
+//! again with the pairing key (see [`pairing`](crate::pairing)), and every
+//! host forgets the keyboard and pairs anew.
+//!
+//! The address of profile 0 is handed to the BLE stack when it is built, see
+//! [`identity_controller`](crate::identity_controller) for how the central
+//! advertises with the one of the active profile.
+//!
+//! Derived addresses are static random addresses: little endian, with the
+//! two most significant bits set.
+
+include!(concat!(env!("OUT_DIR"), "/identity_generated.rs"));
+
+/// The two most significant bits of a static random address
+const STATIC_RANDOM_BITS: u64 = 0x0000_c000_0000_0000;
+
+/// The 46 random bits of a static random address
+const RANDOM_PART: u64 = 0x0000_3fff_ffff_ffff;
+
+/// splitmix64 finalizer, spreads a profile index over all address bits
+const fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// Static random address of BLE `profile` for the given base
+pub(crate) const fn derive_address(base: u64, profile: u8) -> [u8; 6] {
+    let bits = if profile == 0 {
+        base
+    } else {
+        mix(base ^ ((profile as u64) << 48))
+    };
+    let mut random = bits & RANDOM_PART;
+    // The random part of a static address must not be all zeros or all ones
+    if random == 0 || random == RANDOM_PART {
+        random ^= 1;
+    }
+    let b = (random | STATIC_RANDOM_BITS).to_le_bytes();
+    [b[0], b[1], b[2], b[3], b[4], b[5]]
+}
+
+/// Address of this half for BLE `profile`
+///
+/// `configured` is the `ble_addr` from the TOML for this half, either
+/// [`CENTRAL_BLE_ADDR`] or [`PERIPHERAL_BLE_ADDR`], and `device_id` the FICR
+/// device id used in its place when none is configured.
+pub(crate) const fn ble_address(
+    configured: Option<[u8; 6]>,
+    device_id: u64,
+    profile: u8,
+) -> [u8; 6] {
+    match configured {
+        Some(a) if profile == 0 => a,
+        Some(a) => derive_address(
+            u64::from_le_bytes([a[0], a[1], a[2], a[3], a[4], a[5], 0, 0]),
+            profile,
+        ),
+        None => derive_address(device_id, profile),
+    }
+}
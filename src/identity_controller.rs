@@ -0,0 +1,136 @@
+//! Gives the central the address of the active BLE profile
+//!
+//! The BLE stack is built with the central's identity address, the one of
+//! profile 0, and the split link keeps it: the peripheral knows the central
+//! by it. Towards the hosts only the advertising address changes, set with
+//! `LE Set Random Address` when RMK switches profiles. The controller has one
+//! random address for advertising and for connecting to the peripheral, so
+//! the central takes the identity address back while the peripheral is
+//! disconnected and the profile's once it is connected again.
+//!
+//! The controller refuses the command while it is advertising or
+//! connecting, so [`IdentityController`] retries until it goes through. The
+//! active profile is kept in the firmware's records, so the central
+//! advertises with its address right from boot.
+
+use bt_hci::cmd::le::LeSetRandomAddr;
+use bt_hci::controller::{Controller as HciController, ControllerCmdSync};
+use bt_hci::param::BdAddr;
+use defmt::{info, unwrap};
+use embassy_time::Duration;
+use rmk::channel::{CONTROLLER_CHANNEL, ControllerSub};
+use rmk::controller::{Controller, PollingController};
+use rmk::event::ControllerEvent;
+use trouble_host::prelude::{PacketPool, Stack};
+
+use crate::app_storage::{RecordKind, SharedAppStorage, record_key};
+use crate::ficr::device_id;
+use crate::identity::{CENTRAL_BLE_ADDR, ble_address};
+
+const ACTIVE_PROFILE_KEY: u16 = record_key(RecordKind::ActiveProfile, 0);
+
+/// Address the central's BLE stack is built with, used on the split link
+pub(crate) fn identity_address() -> [u8; 6] {
+    ble_address(CENTRAL_BLE_ADDR, device_id(), 0)
+}
+
+/// Sets the advertising address of the active BLE profile
+pub(crate) struct IdentityController<'a, 'd, C: HciController, P: PacketPool> {
+    stack: &'a Stack<'d, C, P>,
+    storage: &'a SharedAppStorage,
+    sub: ControllerSub,
+    /// Active BLE profile, `None` until read from the records
+    profile: Option<u8>,
+    /// Whether the peripheral is connected
+    split_connected: bool,
+    /// Address set in the controller
+    address: [u8; 6],
+}
+
+impl<'a, 'd, C, P> IdentityController<'a, 'd, C, P>
+where
+    C: HciController + ControllerCmdSync<LeSetRandomAddr>,
+    P: PacketPool,
+{
+    pub(crate) fn new(stack: &'a Stack<'d, C, P>, storage: &'a SharedAppStorage) -> Self {
+        Self {
+            stack,
+            storage,
+            sub: unwrap!(CONTROLLER_CHANNEL.subscriber()),
+            profile: None,
+            split_connected: false,
+            address: identity_address(),
+        }
+    }
+
+    /// The address the controller should have now
+    fn wanted(&self) -> [u8; 6] {
+        match self.profile {
+            Some(profile) if self.split_connected => {
+                ble_address(CENTRAL_BLE_ADDR, device_id(), profile)
+            }
+            _ => identity_address(),
+        }
+    }
+}
+
+impl<C, P> Controller for IdentityController<'_, '_, C, P>
+where
+    C: HciController + ControllerCmdSync<LeSetRandomAddr>,
+    P: PacketPool,
+{
+    type Event = ControllerEvent;
+
+    async fn process_event(&mut self, event: Self::Event) {
+        match event {
+            ControllerEvent::BleProfile(profile) if self.profile != Some(profile) => {
+                self.profile = Some(profile);
+                self.storage
+                    .lock()
+                    .await
+                    .store(ACTIVE_PROFILE_KEY, &profile)
+                    .await;
+            }
+            ControllerEvent::SplitPeripheral(0, connected) => self.split_connected = connected,
+            _ => {}
+        }
+    }
+
+    async fn next_message(&mut self) -> Self::Event {
+        self.sub.next_message_pure().await
+    }
+}
+
+impl<C, P> PollingController for IdentityController<'_, '_, C, P>
+where
+    C: HciController + ControllerCmdSync<LeSetRandomAddr>,
+    P: PacketPool,
+{
+    const INTERVAL: Duration = Duration::from_millis(100);
+
+    async fn update(&mut self) {
+        if self.profile.is_none() {
+            let stored = self
+                .storage
+                .lock()
+                .await
+                .fetch::<u8>(ACTIVE_PROFILE_KEY)
+                .await;
+            self.profile = Some(stored.unwrap_or(0));
+        }
+        let wanted = self.wanted();
+        if wanted == self.address {
+            return;
+        }
+        // Refused while advertising or connecting, tried again on the next update
+        if self
+            .stack
+            .command(LeSetRandomAddr::new(BdAddr::new(wanted)))
+            .await
+            .is_ok()
+        {
+            info!("BLE address {:02x}", wanted);
+            self.address = wanted;
+        }
+    }
+}
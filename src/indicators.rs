@@ -0,0 +1,203 @@
+//! Indicator LEDs of the central
+//!
+//! Each LED in `[indicators]` of the keyboard TOML has a list of
+//! [`Indicator`]s, highest priority first, and shows the first active one with
+//! its [`Pattern`]. `build.rs` generates the LED list, the patterns and the
+//! [`indicator_leds!`] macro creating the outputs.
+//!
+//! The indicator state follows the controller events; [`IndicatorController`]
+//! samples the patterns every [`IndicatorController::INTERVAL`].
+
+use defmt::{Format, unwrap};
+use embassy_nrf::gpio::Output;
+use embassy_time::{Duration, Instant};
+use rmk::ble::BleState;
+use rmk::channel::{CONTROLLER_CHANNEL, ControllerSub};
+use rmk::controller::{Controller, PollingController};
+use rmk::event::ControllerEvent;
+
+use crate::blink::Pattern;
+use crate::caps_word;
+use crate::layer_lock;
+use crate::split_telemetry::SPLIT_PERIPHERALS_NUM;
+
+include!(concat!(env!("OUT_DIR"), "/indicators_generated.rs"));
+
+/// How long the BLE profile is shown after switching to it
+const PROFILE_SHOW_TIME: Duration = Duration::from_secs(3);
+
+/// Something an LED can show, `[indicators]` names them in snake case
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+#[allow(dead_code)] // Only the indicators listed in [indicators] are constructed
+pub(crate) enum Indicator {
+    CapsLock,
+    /// Caps Word is on, see [`caps_word`](crate::caps_word)
+    CapsWord,
+    NumLock,
+    ScrollLock,
+    /// Blinks the number of the active layer, while it is not the base layer
+    Layer,
+    /// Blinks the number of the locked layer, see [`layer_lock`](crate::layer_lock)
+    LayerLock,
+    /// Blinks the number of the BLE profile, counting from 1, after a switch
+    BleProfile,
+    /// The active BLE profile is advertising
+    Advertising,
+    /// The active BLE profile is connected to its host
+    Connected,
+    /// A split peripheral is disconnected
+    SplitDown,
+    /// The battery is below [`LOW_BATTERY_PERCENT`]
+    LowBattery,
+    Charging,
+}
+
+/// What the controller events told about the keyboard
+#[derive(Default)]
+struct IndicatorState {
+    caps_lock: bool,
+    num_lock: bool,
+    scroll_lock: bool,
+    layer: u8,
+    profile: u8,
+    profile_switched: Option<Instant>,
+    advertising: bool,
+    connected: bool,
+    split_connected: [bool; SPLIT_PERIPHERALS_NUM],
+    battery: Option<u8>,
+    charging: bool,
+}
+
+impl IndicatorState {
+    /// The blink count of `indicator` if it is active
+    fn active(&self, indicator: Indicator) -> Option<u8> {
+        let active = match indicator {
+            Indicator::CapsLock => self.caps_lock,
+            Indicator::CapsWord => caps_word::active(),
+            Indicator::NumLock => self.num_lock,
+            Indicator::ScrollLock => self.scroll_lock,
+            Indicator::Layer => return (self.layer > 0).then_some(self.layer),
+            Indicator::LayerLock => return layer_lock::locked(),
+            Indicator::BleProfile => {
+                let shown = self
+                    .profile_switched
+                    .is_some_and(|at| at.elapsed() < PROFILE_SHOW_TIME);
+                return shown.then_some(self.profile + 1);
+            }
+            Indicator::Advertising => self.advertising,
+            Indicator::Connected => self.connected,
+            Indicator::SplitDown => self.split_connected.iter().any(|c| !c),
+            // Charging tops the battery up, it is not low for long
+            Indicator::LowBattery => {
+                !self.charging && self.battery.is_some_and(|b| b < LOW_BATTERY_PERCENT)
+            }
+            Indicator::Charging => self.charging,
+        };
+        active.then_some(1)
+    }
+}
+
+/// The indicator an LED shows and since when
+#[derive(Clone, Copy)]
+struct Shown {
+    indicator: Indicator,
+    count: u8,
+    since: Instant,
+}
+
+/// Drives the indicator LEDs from the controller events
+pub(crate) struct IndicatorController<'d> {
+    sub: ControllerSub,
+    leds: [Output<'d>; LED_COUNT],
+    state: IndicatorState,
+    shown: [Option<Shown>; LED_COUNT],
+}
+
+impl<'d> IndicatorController<'d> {
+    /// `leds` come from [`indicator_leds!`], in `[indicators]` order
+    pub(crate) fn new(leds: [Output<'d>; LED_COUNT]) -> Self {
+        Self {
+            sub: unwrap!(CONTROLLER_CHANNEL.subscriber()),
+            leds,
+            state: IndicatorState::default(),
+            shown: [None; LED_COUNT],
+        }
+    }
+
+    fn refresh(&mut self) {
+        let now = Instant::now();
+        for (i, led) in self.leds.iter_mut().enumerate() {
+            let active = LED_INDICATORS[i]
+                .iter()
+                .find_map(|&ind| self.state.active(ind).map(|count| (ind, count)));
+            // A new indicator or count starts its pattern from the beginning
+            let shown = match (active, self.shown[i]) {
+                (None, _) => None,
+                (Some((indicator, count)), Some(s))
+                    if s.indicator == indicator && s.count == count =>
+                {
+                    Some(s)
+                }
+                (Some((indicator, count)), _) => Some(Shown {
+                    indicator,
+                    count,
+                    since: now,
+                }),
+            };
+            self.shown[i] = shown;
+            let lit = shown.is_some_and(|s| {
+                PATTERNS[s.indicator as usize].lit(s.count, (now - s.since).as_millis())
+            });
+            if lit != LED_ACTIVE_LOW[i] {
+                led.set_high()
+            } else {
+                led.set_low()
+            }
+        }
+    }
+}
+
+impl Controller for IndicatorController<'_> {
+    type Event = ControllerEvent;
+
+    async fn process_event(&mut self, event: Self::Event) {
+        let state = &mut self.state;
+        match event {
+            ControllerEvent::KeyboardIndicator(leds) => {
+                state.caps_lock = leds.caps_lock();
+                state.num_lock = leds.num_lock();
+                state.scroll_lock = leds.scroll_lock();
+            }
+            ControllerEvent::Layer(layer) => state.layer = layer,
+            ControllerEvent::BleProfile(profile) => {
+                state.profile = profile;
+                state.profile_switched = Some(Instant::now());
+            }
+            ControllerEvent::BleState(profile, ble_state) if profile == state.profile => {
+                state.advertising = ble_state == BleState::Advertising;
+                state.connected = ble_state == BleState::Connected;
+            }
+            ControllerEvent::SplitPeripheral(id, connected) => {
+                if let Some(c) = state.split_connected.get_mut(id) {
+                    *c = connected;
+                }
+            }
+            ControllerEvent::Battery(level) => state.battery = Some(level),
+            ControllerEvent::ChargingState(charging) => state.charging = charging,
+            _ => return,
+        }
+        self.refresh();
+    }
+
+    async fn next_message(&mut self) -> Self::Event {
+        self.sub.next_message_pure().await
+    }
+}
+
+impl PollingController for IndicatorController<'_> {
+    const INTERVAL: Duration = Duration::from_millis(50);
+
+    async fn update(&mut self) {
+        self.refresh();
+    }
+}
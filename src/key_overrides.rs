@@ -0,0 +1,97 @@
+//! Key overrides of the central
+//!
+//! `[[key_override]]` in the keyboard TOML replaces a key while modifiers are
+//! held, e.g. `Shift`+`Backspace` sends `Delete`: the override applies while
+//! any of its `modifiers` and none of its `negative_modifiers` are held, and
+//! the modifiers that triggered it are not sent along. RMK runs them as forks,
+//! which match the action of the pressed key, so [`configure`] adds a fork for
+//! each key of the default keymap tapping the trigger. A tap-hold tapping it
+//! is replaced as a whole, its hold included. Forks know no layers, so an
+//! override works on every layer and the keymap is left as it is.
+//!
+//! Like the combos, the overrides are defaults: RMK saves them to its storage
+//! on the first boot.
+
+use defmt::warn;
+use heapless::Vec;
+use rmk::config::ForksConfig;
+use rmk::fork::{Fork, StateBits};
+use rmk::hid_state::HidModifiers;
+use rmk::types::action::{Action, KeyAction};
+use rmk::types::keycode::KeyCode;
+
+use crate::keymap::{COL, NUM_LAYER, ROW};
+
+type Keymap = [[[KeyAction; COL]; ROW]; NUM_LAYER];
+
+/// An override of `[[key_override]]`
+pub(crate) struct KeyOverride {
+    trigger: KeyCode,
+    replacement: KeyAction,
+    /// HID modifiers of which one has to be held
+    match_any: u8,
+    /// HID modifiers of which none may be held
+    match_none: u8,
+}
+
+include!(concat!(env!("OUT_DIR"), "/key_overrides_generated.rs"));
+
+/// Most different keys tapping one trigger
+const MAX_TRIGGER_KEYS: usize = 4;
+
+/// The key code a key with `action` taps
+fn tapped(action: KeyAction) -> Option<KeyCode> {
+    match action {
+        KeyAction::Single(Action::Key(code)) | KeyAction::TapHold(Action::Key(code), _, _) => {
+            Some(code)
+        }
+        _ => None,
+    }
+}
+
+fn state(modifiers: u8) -> StateBits {
+    StateBits::new_from(
+        HidModifiers::from_bits(modifiers),
+        Default::default(),
+        Default::default(),
+    )
+}
+
+/// Put the overrides of `[[key_override]]` into `config` as forks
+pub(crate) fn configure(config: &mut ForksConfig, keymap: &Keymap) {
+    for rule in &KEY_OVERRIDES {
+        let mut triggers: Vec<KeyAction, MAX_TRIGGER_KEYS> = Vec::new();
+        for action in keymap.iter().flatten().flatten() {
+            if tapped(*action) != Some(rule.trigger) || triggers.contains(action) {
+                continue;
+            }
+            if triggers.push(*action).is_err() {
+                warn!(
+                    "{:?} is on more than {} kinds of keys, some are not overridden",
+                    rule.trigger, MAX_TRIGGER_KEYS
+                );
+            }
+        }
+        if triggers.is_empty() {
+            warn!(
+                "Key override trigger {:?} is not in the keymap",
+                rule.trigger
+            );
+        }
+        for trigger in triggers {
+            let fork = Fork::new(
+                trigger,
+                trigger,
+                rule.replacement,
+                state(rule.match_any),
+                state(rule.match_none),
+                HidModifiers::new(),
+                false,
+            );
+            if config.forks.push(fork).is_err() {
+                warn!("Too many key overrides, [rmk] fork_max_num is full");
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,26 @@
+//! Keyboard macros of the central from `[[macro]]` of the keyboard TOML
+//!
+//! `build.rs` encodes the macros the way Vial edits them: text as its ASCII
+//! bytes, `1, 1 | 2 | 3, <key>` for a tap, press or release and
+//! `1, 4, <ms % 255 + 1>, <ms / 255 + 1>` for a delay, each macro ended by a
+//! 0. `Macro0`..`Macro31` send them.
+//!
+//! Like the combos, the macros are defaults: RMK saves them to its storage on
+//! the first boot and Vial edits them there, later changes of `[[macro]]` only
+//! apply to a cleared storage.
+
+use rmk::config::KeyboardMacrosConfig;
+use rmk::types::keycode::KeyCode;
+
+/// The one-byte HID code of `code`, other keys don't fit a macro step
+const fn hid(code: KeyCode) -> u8 {
+    assert!((code as u16) < 0x100, "Macro steps only take HID keys");
+    code as u8
+}
+
+include!(concat!(env!("OUT_DIR"), "/keyboard_macros_generated.rs"));
+
+/// Put the macros of `[[macro]]` into `config`
+pub(crate) fn configure(config: &mut KeyboardMacrosConfig) {
+    config.macro_sequences[..MACRO_SEQUENCES.len()].copy_from_slice(&MACRO_SEQUENCES);
+}
@@ -33,6 +33,17 @@ macro_rules! mtp {
     };
 }
 
+/// Default of the tap-holds, `[behavior.morse]`
+pub(crate) const MORSE_DEFAULT: MorseProfile = MorseProfile::new(
+    Some(true), // unilateral_tap
+    Some(MorseMode::PermissiveHold),
+    Some(240u16), // hold_timeout
+    Some(230u16), // gap_timeout
+);
+
+/// Flow tap: a tap-hold pressed this soon after another key is a tap, `prior_idle_time`
+pub(crate) const PRIOR_IDLE_TIME_MS: u64 = 30;
+
 const HRM: MorseProfile = MorseProfile::new(
     Some(true), // unilateral_tap
     Some(MorseMode::PermissiveHold),
@@ -48,7 +59,7 @@ const THUMB_TAP: MorseProfile = MorseProfile::new(
 );
 pub(crate) const COL: usize = 6;
 pub(crate) const ROW: usize = 8;
-pub(crate) const NUM_LAYER: usize = 8;
+pub(crate) const NUM_LAYER: usize = 9;
 pub(crate) const NUM_ENCODER: usize = 0;
 #[rustfmt::skip]
 pub const fn get_default_keymap() -> [[[KeyAction; COL]; ROW]; NUM_LAYER] {
@@ -61,7 +72,7 @@ pub const fn get_default_keymap() -> [[[KeyAction; COL]; ROW]; NUM_LAYER] {
             [k!(No), k!(No), k!(No), ltp!(6, Escape, THUMB_TAP), mo!(2), k!(Space)],
             [k!(Backspace), k!(Y), k!(U), k!(I), k!(O), k!(P)],
             [k!(Enter), k!(H), mtp!(J, RShift, HRM), mtp!(K, RGui, HRM), mtp!(L, RAlt, HRM), mtp!(Semicolon, RCtrl, HRM)],
-            [k!(Slash), k!(M), k!(Comma), k!(Dot), k!(N), a!(No)],
+            [k!(Slash), k!(M), k!(Comma), k!(Dot), k!(N), k!(Backspace)],
             [k!(No), k!(No), k!(No), ltp!(5, Enter, THUMB_TAP), mo!(3), ltp!(2, Backspace, THUMB_TAP)]
         ],
         // Windows layer
@@ -78,20 +89,20 @@ pub const fn get_default_keymap() -> [[[KeyAction; COL]; ROW]; NUM_LAYER] {
         // Symbol layer
         [
             [a!(No), a!(No), a!(No), a!(No), a!(No), a!(No)],
-            [a!(No), a!(No), k!(LeftBracket), k!(RightBracket), wm!(LeftBracket, LShift), wm!(RightBracket, LShift)],
-            [a!(No), a!(No), k!(RightBracket), wm!(Kc9, LShift), wm!(Backslash, LShift), k!(Backslash)],
+            [a!(No), k!(User19), k!(LeftBracket), k!(RightBracket), wm!(LeftBracket, LShift), wm!(RightBracket, LShift)],
+            [a!(No), k!(User20), k!(RightBracket), wm!(Kc9, LShift), wm!(Backslash, LShift), k!(Backslash)],
             [a!(No), a!(No), a!(No), a!(No), a!(No), a!(No)],
             [a!(No), wm!(Kc6, LShift), wm!(Kc7, LShift), wm!(Kc8, LShift), wm!(Kc9, LShift), wm!(Kc0, LShift)],
             [a!(No), k!(Equal), wm!(Minus, LShift), k!(Minus), wm!(Equal, LShift), wm!(Quote, LShift)],
             [a!(No), k!(Slash), wm!(Slash, LShift), k!(Grave), wm!(Grave, LShift), k!(Quote)],
-            [a!(No), a!(No), a!(No), wm!(Grave, LShift), mo!(4), k!(Grave)]
+            [a!(No), a!(No), a!(No), wm!(Grave, LShift), a!(Transparent), k!(Grave)]
         ],
         // Mix layer
         [
             [k!(LShift), k!(F1), k!(F2), k!(F3), k!(F4), k!(F5)],
             [k!(LCtrl), k!(Kc5), k!(Kc4), k!(Kc3), k!(Kc2), k!(Kc1)],
             [k!(LGui), k!(Kc6), k!(Kc7), k!(Kc8), k!(Kc9), k!(Kc0)],
-            [k!(Tab), mo!(4), k!(Backspace), a!(No), a!(No), a!(No)],
+            [k!(Tab), a!(No), k!(Backspace), a!(No), a!(Transparent), a!(No)],
             [a!(No), k!(F6), k!(F7), k!(F8), k!(F9), k!(F10)],
             [a!(No), k!(Backspace), k!(Minus), k!(Equal), wm!(Equal, LShift), k!(Quote)],
             [a!(No), a!(No), k!(Insert), k!(Home), k!(End), a!(No)],
@@ -99,9 +110,9 @@ pub const fn get_default_keymap() -> [[[KeyAction; COL]; ROW]; NUM_LAYER] {
         ],
         // Adjust layer
         [
-            [k!(User0), k!(User3), a!(No), a!(No), a!(No), a!(No)],
-            [k!(User1), k!(User4), a!(No), a!(No), a!(No), a!(No)],
-            [k!(User2), k!(User5), k!(User6), k!(User5), a!(No), a!(No)],
+            [k!(User0), k!(User3), k!(User15), k!(User16), a!(No), a!(No)],
+            [k!(User1), k!(User4), k!(User17), k!(User18), a!(No), a!(No)],
+            [k!(User2), k!(User5), k!(User6), a!(No), k!(User12), a!(No)],
             [a!(No), a!(No), a!(No), a!(No), a!(No), a!(No)],
             [a!(No), a!(No), a!(No), a!(No), a!(No), a!(No)],
             [a!(No), tg!(1), to!(0), a!(No), a!(No), tg!(1)],
@@ -110,10 +121,10 @@ pub const fn get_default_keymap() -> [[[KeyAction; COL]; ROW]; NUM_LAYER] {
         ],
         // Navigation layer
         [
-            [a!(No), a!(No), a!(No), k!(MouseUp), k!(PageUp), k!(PageDown)],
+            [k!(User23), a!(No), a!(No), k!(MouseUp), k!(PageUp), k!(PageDown)],
             [a!(No), a!(No), k!(MouseLeft), k!(MouseDown), k!(MouseRight), k!(MouseWheelUp)],
-            [a!(No), a!(No), a!(No), a!(No), a!(No), k!(MouseWheelDown)],
-            [mo!(7), a!(No), a!(No), a!(No), a!(No), a!(No)],
+            [a!(No), k!(Macro0), k!(Macro1), a!(No), a!(No), k!(MouseWheelDown)],
+            [mo!(7), a!(No), a!(No), a!(No), k!(User21), k!(User22)],
             [a!(No), k!(MouseBtn1), k!(MouseBtn3), k!(MouseBtn2), k!(MouseWheelUp), k!(MouseWheelDown)],
             [a!(No), k!(Left), k!(Down), k!(Up), k!(Right), a!(No)],
             [a!(No), a!(No), a!(No), a!(No), a!(No), a!(No)],
@@ -125,7 +136,7 @@ pub const fn get_default_keymap() -> [[[KeyAction; COL]; ROW]; NUM_LAYER] {
             [a!(No), a!(No), a!(No), a!(No), a!(No), a!(No)],
             [a!(No), a!(No), a!(No), a!(No), a!(No), a!(No)],
             [a!(No), a!(No), a!(No), a!(No), a!(No), a!(No)],
-            [a!(No), k!(Kp7), k!(Kp8), k!(Kp9), k!(KpMinus), k!(KpAsterisk)],
+            [k!(User23), k!(Kp7), k!(Kp8), k!(Kp9), k!(KpMinus), k!(KpAsterisk)],
             [a!(No), k!(Kp4), k!(Kp5), k!(Kp6), k!(KpPlus), k!(KpSlash)],
             [a!(No), k!(Kp1), k!(Kp2), k!(Kp3), a!(No), a!(No)],
             [a!(No), a!(No), a!(No), k!(Kp0), k!(KpDot), k!(Kp0)]
@@ -134,13 +145,24 @@ pub const fn get_default_keymap() -> [[[KeyAction; COL]; ROW]; NUM_LAYER] {
         [
             [a!(No), a!(No), a!(No), a!(No), a!(No), a!(No)],
             [a!(No), a!(No), a!(No), a!(No), a!(No), a!(No)],
-            [a!(No), a!(No), a!(No), a!(No), a!(No), k!(Bootloader)],
+            [a!(No), a!(No), a!(No), a!(No), k!(User14), k!(Bootloader)],
             [a!(No), a!(No), a!(No), a!(No), a!(No), a!(No)],
             [a!(No), a!(No), a!(No), a!(No), a!(No), a!(No)],
             [a!(No), a!(No), a!(No), a!(No), a!(No), a!(No)],
             [a!(No), k!(Bootloader), a!(No), a!(No), a!(No), a!(No)],
             [a!(No), a!(No), a!(No), a!(No), a!(No), k!(Kc7)]
         ],
+        // Leader layer
+        [
+            [a!(No), a!(No), a!(No), a!(No), a!(No), a!(No)],
+            [a!(No), a!(No), a!(No), a!(No), a!(No), a!(No)],
+            [a!(No), a!(No), a!(No), a!(No), a!(No), a!(No)],
+            [a!(No), a!(No), a!(No), a!(No), a!(Transparent), a!(No)],
+            [a!(No), a!(No), a!(No), a!(No), a!(No), a!(No)],
+            [a!(No), a!(No), a!(No), a!(No), a!(No), a!(No)],
+            [a!(No), a!(No), a!(No), a!(No), a!(No), a!(No)],
+            [a!(No), a!(No), a!(No), a!(No), a!(Transparent), a!(No)]
+        ],
     ]
 }
 
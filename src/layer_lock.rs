@@ -0,0 +1,92 @@
+//! Layer lock of the central
+//!
+//! `LayerLock` latches the layer held at that moment, e.g. the navigation
+//! layer under its `LT` thumb, so it stays on after the thumb is released;
+//! pressing `LayerLock` again turns it off. The `layer_lock` indicator blinks
+//! the locked layer.
+//!
+//! RMK's momentary layer keys turn their layer off on release, whoever turned
+//! it on, so [`LayerLockController`] turns the locked layer back on whenever a
+//! lower layer is reported. `TO(0)` doesn't leave a locked layer either, only
+//! `LayerLock` does.
+
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use defmt::{info, unwrap};
+use rmk::channel::{CONTROLLER_CHANNEL, ControllerSub};
+use rmk::controller::Controller;
+use rmk::event::ControllerEvent;
+use rmk::keymap::KeyMap;
+
+use crate::keymap::{COL, NUM_ENCODER, NUM_LAYER, ROW};
+use crate::user_keys::UserKey;
+
+/// The locked layer, 0 while none is
+static LOCKED: AtomicU8 = AtomicU8::new(0);
+
+/// The locked layer, for the indicator LEDs
+pub(crate) fn locked() -> Option<u8> {
+    match LOCKED.load(Ordering::Relaxed) {
+        0 => None,
+        layer => Some(layer),
+    }
+}
+
+/// Locks and unlocks layers
+pub(crate) struct LayerLockController<'a> {
+    sub: ControllerSub,
+    keymap: &'a RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
+    /// Highest active layer, as RMK reported it
+    layer: u8,
+}
+
+impl<'a> LayerLockController<'a> {
+    pub(crate) fn new(keymap: &'a RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>>) -> Self {
+        Self {
+            sub: unwrap!(CONTROLLER_CHANNEL.subscriber()),
+            keymap,
+            layer: 0,
+        }
+    }
+
+    fn toggle(&mut self) {
+        if let Some(layer) = locked() {
+            LOCKED.store(0, Ordering::Relaxed);
+            self.keymap.borrow_mut().deactivate_layer(layer);
+            info!("Layer {} unlocked", layer);
+        } else if self.layer > 0 {
+            LOCKED.store(self.layer, Ordering::Relaxed);
+            info!("Layer {} locked", self.layer);
+        }
+    }
+}
+
+impl Controller for LayerLockController<'_> {
+    type Event = ControllerEvent;
+
+    async fn process_event(&mut self, event: Self::Event) {
+        match event {
+            ControllerEvent::Layer(layer) => {
+                self.layer = layer;
+                if let Some(locked) = locked()
+                    && layer < locked
+                {
+                    self.keymap.borrow_mut().activate_layer(locked);
+                    self.layer = locked;
+                }
+            }
+            ControllerEvent::Key(key_event, action)
+                if key_event.pressed
+                    && UserKey::from_action(&action) == Some(UserKey::LayerLock) =>
+            {
+                self.toggle();
+            }
+            _ => {}
+        }
+    }
+
+    async fn next_message(&mut self) -> Self::Event {
+        self.sub.next_message_pure().await
+    }
+}
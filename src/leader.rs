@@ -0,0 +1,112 @@
+//! Leader key of the central
+//!
+//! `Leader` starts a sequence: the letters and digits typed after it walk the
+//! trie of [`leader_keys`](crate::leader_keys), each within [`LEADER_TIMEOUT`]
+//! of the previous one. A sequence that no other one continues runs its
+//! action right away, one that is the start of a longer one runs it when the
+//! timeout passes. Any other key, or the timeout on a node without an
+//! action, cancels the sequence. Modifiers and layer keys are ignored, a
+//! tap-hold counts as the key it taps.
+//!
+//! RMK's keyboard has no hook to swallow keys and reports them before the
+//! controllers see them, so [`LeaderController`] turns [`LEADER_LAYER`] on
+//! while a sequence runs: its keys are `No`, nothing reaches the host, and
+//! the [`Sequence`] looks up what the keys would have been. The `MO` thumbs
+//! are transparent there, so RMK still holds their layers.
+//!
+//! [`LEADER_LAYER`]: crate::leader_keys::LEADER_LAYER
+
+use core::cell::RefCell;
+
+use defmt::{info, unwrap};
+use embassy_time::{Duration, Instant};
+use rmk::ble::profile::BleProfileAction;
+use rmk::channel::{BLE_PROFILE_CHANNEL, CONTROLLER_CHANNEL, ControllerSub};
+use rmk::controller::{Controller, PollingController};
+use rmk::event::{ControllerEvent, KeyboardEventPos};
+use rmk::keymap::KeyMap;
+
+use crate::host_keys;
+use crate::keymap::{COL, NUM_ENCODER, NUM_LAYER, ROW};
+use crate::leader_keys::{LEADER_LAYER, LEADER_TIMEOUT, LeaderAction, Outcome, Sequence};
+use crate::unicode;
+
+/// Follows the keys after `Leader` through the sequence trie
+pub(crate) struct LeaderController<'a> {
+    sub: ControllerSub,
+    keymap: &'a RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>>,
+    sequence: Sequence,
+    last_press: Instant,
+}
+
+impl<'a> LeaderController<'a> {
+    pub(crate) fn new(keymap: &'a RefCell<KeyMap<'a, ROW, COL, NUM_LAYER, NUM_ENCODER>>) -> Self {
+        Self {
+            sub: unwrap!(CONTROLLER_CHANNEL.subscriber()),
+            keymap,
+            sequence: Sequence::new(),
+            last_press: Instant::now(),
+        }
+    }
+
+    /// Do what the sequence asks for: hold its keys back and run the action
+    async fn handle(&mut self, outcome: Outcome) {
+        if outcome != Outcome::Ignore {
+            let mut keymap = self.keymap.borrow_mut();
+            if self.sequence.running() {
+                keymap.activate_layer(LEADER_LAYER);
+            } else {
+                keymap.deactivate_layer(LEADER_LAYER);
+            }
+        }
+        match outcome {
+            Outcome::Ignore => {}
+            Outcome::Wait => self.last_press = Instant::now(),
+            Outcome::Cancelled => info!("Leader sequence cancelled"),
+            Outcome::Run(action) => match *action {
+                LeaderAction::Text(text) => unicode::type_text(text).await,
+                LeaderAction::Tap(key) => host_keys::tap(key).await,
+                LeaderAction::BleProfile(profile) => {
+                    info!("Leader: switching to BLE profile {}", profile);
+                    BLE_PROFILE_CHANNEL
+                        .send(BleProfileAction::SwitchProfile(profile))
+                        .await;
+                }
+            },
+        }
+    }
+}
+
+impl Controller for LeaderController<'_> {
+    type Event = ControllerEvent;
+
+    async fn process_event(&mut self, event: Self::Event) {
+        let ControllerEvent::Key(key_event, action) = event else {
+            return;
+        };
+        let KeyboardEventPos::Key(pos) = key_event.pos else {
+            return;
+        };
+        if key_event.pressed {
+            let outcome = self.sequence.press(pos.row, pos.col, &action);
+            self.handle(outcome).await;
+        } else {
+            self.sequence.release(pos.row, pos.col);
+        }
+    }
+
+    async fn next_message(&mut self) -> Self::Event {
+        self.sub.next_message_pure().await
+    }
+}
+
+impl PollingController for LeaderController<'_> {
+    const INTERVAL: Duration = Duration::from_millis(50);
+
+    async fn update(&mut self) {
+        if self.sequence.running() && self.last_press.elapsed() > LEADER_TIMEOUT {
+            let outcome = self.sequence.timeout();
+            self.handle(outcome).await;
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//! Sequences of the leader key, see [`leader`](crate::leader)
+//!
+//! `build.rs` compiles `[[leader.sequence]]` of the keyboard TOML into a
+//! trie, [`LEADER_NODES`] with the root first. A [`Sequence`] follows the keys
+//! pressed after `Leader` through it and tells the controller when to run an
+//! action; the timing is up to the controller.
+//!
+//! While a sequence runs, [`LEADER_LAYER`] is on and RMK sees its `No` keys,
+//! so a [`Sequence`] looks the pressed positions up in the default keymap: on
+//! the base layer, or the layer of an `MO` key held meanwhile. Keys Vial
+//! moved are not followed.
+
+use heapless::Vec;
+use rmk::types::action::{Action, KeyAction};
+use rmk::types::keycode::KeyCode;
+
+use crate::combo_keys::{Keymap, resolve};
+use crate::keymap::get_default_keymap;
+use crate::user_keys::UserKey;
+
+static DEFAULT_KEYMAP: Keymap = get_default_keymap();
+
+/// What a leader sequence does
+#[derive(PartialEq, Eq)]
+pub(crate) enum LeaderAction {
+    /// Type the text on the host
+    Text(&'static str),
+    /// Tap a key
+    Tap(KeyCode),
+    /// Switch to a BLE profile
+    BleProfile(u8),
+}
+
+/// A node of the sequence trie
+pub(crate) struct LeaderNode {
+    /// The key continuing the sequence and its node
+    children: &'static [(KeyCode, u16)],
+    /// The action of the sequence ending here
+    action: Option<LeaderAction>,
+}
+
+include!(concat!(env!("OUT_DIR"), "/leader_generated.rs"));
+
+/// What a key press does to a running sequence
+enum Step {
+    /// A key that can be part of a sequence
+    Key(KeyCode),
+    Cancel,
+    Ignore,
+}
+
+fn in_range(code: KeyCode, first: KeyCode, last: KeyCode) -> bool {
+    (first as u16..=last as u16).contains(&(code as u16))
+}
+
+fn step(action: &KeyAction) -> Step {
+    match action {
+        // Whether it taps or holds is not known yet, it counts as the key it taps
+        KeyAction::Single(Action::Key(code)) | KeyAction::TapHold(Action::Key(code), _, _) => {
+            if in_range(*code, KeyCode::LCtrl, KeyCode::RGui) {
+                Step::Ignore
+            } else {
+                Step::Key(*code)
+            }
+        }
+        KeyAction::Single(Action::KeyWithModifier(..)) => Step::Cancel,
+        _ => Step::Ignore,
+    }
+}
+
+/// What the controller does after a key press or the timeout
+#[derive(PartialEq, Eq)]
+pub(crate) enum Outcome {
+    /// Nothing changed
+    Ignore,
+    /// The sequence started or went on, wait for the next key
+    Wait,
+    Cancelled,
+    /// The sequence ended, run `action`
+    Run(&'static LeaderAction),
+}
+
+/// The keys pressed after `Leader`
+pub(crate) struct Sequence {
+    /// Trie node of the keys typed so far, `None` without a running sequence
+    node: Option<u16>,
+    /// `MO` keys held during the sequence, with their row, column and layer
+    held: Vec<(u8, u8, u8), 4>,
+}
+
+impl Sequence {
+    pub(crate) const fn new() -> Self {
+        Self {
+            node: None,
+            held: Vec::new(),
+        }
+    }
+
+    /// Whether a sequence is running
+    pub(crate) fn running(&self) -> bool {
+        self.node.is_some()
+    }
+
+    /// End the sequence at `node`, running its action if it has one
+    fn finish(&mut self, node: u16) -> Outcome {
+        self.node = None;
+        match &LEADER_NODES[node as usize].action {
+            Some(action) => Outcome::Run(action),
+            None => Outcome::Cancelled,
+        }
+    }
+
+    /// The key at `row`, `col` was pressed, RMK did `action`
+    pub(crate) fn press(&mut self, row: u8, col: u8, action: &KeyAction) -> Outcome {
+        if UserKey::from_action(action) == Some(UserKey::Leader) {
+            self.node = Some(0);
+            self.held.clear();
+            return Outcome::Wait;
+        }
+        let Some(node) = self.node else {
+            return Outcome::Ignore;
+        };
+        // The key the leader layer held back
+        let layer = self.held.iter().map(|&(_, _, layer)| layer).max();
+        let action = resolve(
+            &DEFAULT_KEYMAP,
+            layer.unwrap_or(0),
+            row as usize,
+            col as usize,
+        );
+        if let KeyAction::Single(Action::LayerOn(layer)) = action {
+            // Without room the layer is left out
+            let _ = self.held.push((row, col, layer));
+        }
+        match step(&action) {
+            Step::Key(code) => {
+                let next = LEADER_NODES[node as usize]
+                    .children
+                    .iter()
+                    .find(|(key, _)| *key == code);
+                let Some(&(_, next)) = next else {
+                    self.node = None;
+                    return Outcome::Cancelled;
+                };
+                if LEADER_NODES[next as usize].children.is_empty() {
+                    self.finish(next)
+                } else {
+                    self.node = Some(next);
+                    Outcome::Wait
+                }
+            }
+            Step::Cancel => {
+                self.node = None;
+                Outcome::Cancelled
+            }
+            Step::Ignore => Outcome::Ignore,
+        }
+    }
+
+    /// The key at `row`, `col` was released
+    pub(crate) fn release(&mut self, row: u8, col: u8) {
+        self.held.retain(|&(r, c, _)| (r, c) != (row, col));
+    }
+
+    /// No key was pressed within [`LEADER_TIMEOUT`]
+    ///
+    /// A sequence that is the start of a longer one runs, any other is cancelled.
+    pub(crate) fn timeout(&mut self) -> Outcome {
+        match self.node {
+            Some(node) => self.finish(node),
+            None => Outcome::Ignore,
+        }
+    }
+}
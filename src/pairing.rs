@@ -0,0 +1,60 @@
+//! Re-pairing the halves without the reset firmware
+//!
+//! Each half remembers the address of the other one. To pair a new or
+//! reflashed half, hold [`PAIR_KEY`] on both halves while they boot (or reset
+//! them with it held): the central forgets its stored peripheral address and
+//! scans again, the peripheral wipes its storage and advertises to any
+//! central. On a running central the `PairHalves` key on the debug layer does
+//! the same for the central side.
+//!
+//! RMK stores the address of the other half once the halves are connected,
+//! but only hands it out at boot, and the status channel needs it, see
+//! [`status_link`](crate::status_link). A half that paired reboots once it is
+//! linked over BLE, after [`STORE_DELAY`] for the address to be written.
+
+use defmt::info;
+use embassy_nrf::gpio::{Input, Output};
+use embassy_time::{Duration, Timer};
+
+/// Matrix position of the pairing key on each half, (row, col) of the half's
+/// own matrix: the outermost key of the top row
+pub(crate) const PAIR_KEY: (usize, usize) = (0, 0);
+
+/// How many samples have to see the key pressed
+const SAMPLES: usize = 5;
+
+/// Delay between samples, longer than the switch bounce
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Time RMK's storage task gets to store the address of the other half
+const STORE_DELAY: Duration = Duration::from_secs(2);
+
+/// Whether the key at `pos` is held, scanning the matrix pins directly
+///
+/// Called before the pins are handed to the matrix. Rows are the inputs with
+/// pull-downs and columns the outputs, as set up by `config_matrix_pins_nrf!`.
+pub(crate) async fn key_held<const ROW: usize, const COL: usize>(
+    rows: &[Input<'_>; ROW],
+    cols: &mut [Output<'_>; COL],
+    (row, col): (usize, usize),
+) -> bool {
+    let mut held = true;
+    for _ in 0..SAMPLES {
+        cols[col].set_high();
+        Timer::after_micros(10).await;
+        held &= rows[row].is_high();
+        cols[col].set_low();
+        if !held {
+            break;
+        }
+        Timer::after(SAMPLE_INTERVAL).await;
+    }
+    held
+}
+
+/// Reboot a half that just paired, once it is linked over BLE
+pub(crate) async fn reboot_paired() -> ! {
+    Timer::after(STORE_DELAY).await;
+    info!("Halves paired, rebooting to read the stored address");
+    cortex_m::peripheral::SCB::sys_reset()
+}
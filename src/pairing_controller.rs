@@ -0,0 +1,85 @@
+//! Re-pairing the peripheral from a running central, see [`pairing`](crate::pairing)
+
+use core::sync::atomic::Ordering;
+
+use defmt::{info, unwrap};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use rmk::channel::{CONTROLLER_CHANNEL, ControllerSub, FLASH_CHANNEL};
+use rmk::controller::Controller;
+use rmk::event::ControllerEvent;
+use rmk::storage::{FlashOperationMessage, PeerAddress};
+
+use crate::pairing::reboot_paired;
+use crate::split_telemetry::SPLIT_PERIPHERALS_NUM;
+use crate::split_uart::SPLIT_UART_UP;
+use crate::status_link;
+use crate::user_keys::UserKey;
+
+/// Raised when the stored peripheral addresses should be forgotten and the
+/// split link restarted
+pub(crate) static PAIR_HALVES: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Forget the stored peripheral addresses
+///
+/// `addrs` is what `read_peripheral_addresses` returned at boot, cleared so
+/// that the restarted `scan_peripherals` looks for any peripheral. The stored
+/// addresses are invalidated through RMK's storage task, and the status
+/// channel stops looking for the old peripheral.
+pub(crate) async fn forget_peripherals<const N: usize>(addrs: &mut heapless::Vec<[u8; 6], N>) {
+    info!("Forgetting the peripheral addresses, scanning for halves to pair");
+    addrs.clear();
+    status_link::set_peer(None);
+    for id in 0..SPLIT_PERIPHERALS_NUM as u8 {
+        FLASH_CHANNEL
+            .send(FlashOperationMessage::PeerAddress(PeerAddress::new(
+                id, false, [0; 6],
+            )))
+            .await;
+    }
+}
+
+/// Raises [`PAIR_HALVES`] on the `PairHalves` key, reboots once a peripheral
+/// paired since boot is linked over BLE
+pub(crate) struct PairingController {
+    sub: ControllerSub,
+    /// Whether the stored peripheral was forgotten since boot
+    pairing: bool,
+}
+
+impl PairingController {
+    /// `pairing` if the peripheral is forgotten at boot
+    pub(crate) fn new(pairing: bool) -> Self {
+        Self {
+            sub: unwrap!(CONTROLLER_CHANNEL.subscriber()),
+            pairing,
+        }
+    }
+}
+
+impl Controller for PairingController {
+    type Event = ControllerEvent;
+
+    async fn process_event(&mut self, event: Self::Event) {
+        match event {
+            ControllerEvent::Key(key_event, action)
+                if key_event.pressed
+                    && UserKey::from_action(&action) == Some(UserKey::PairHalves) =>
+            {
+                self.pairing = true;
+                PAIR_HALVES.signal(());
+            }
+            // Over the cable there is no address to store
+            ControllerEvent::SplitPeripheral(0, true)
+                if self.pairing && !SPLIT_UART_UP.load(Ordering::Relaxed) =>
+            {
+                reboot_paired().await
+            }
+            _ => {}
+        }
+    }
+
+    async fn next_message(&mut self) -> Self::Event {
+        self.sub.next_message_pure().await
+    }
+}
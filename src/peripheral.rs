@@ -3,32 +3,74 @@
 
 #[macro_use]
 mod macros;
+mod conn_handles;
+#[macro_use]
+mod display;
+mod display_render;
+mod ext_power;
+mod ficr;
+mod identity;
 mod key_position;
+mod pairing;
+mod split_frame;
+mod split_status;
+#[macro_use]
+mod split_uart;
+mod status_link;
+mod storage_layout;
+mod tx_power;
+mod tx_power_controller;
+#[macro_use]
+mod underglow;
+mod underglow_render;
+mod user_keys;
 
-use defmt::{info, unwrap};
+use core::sync::atomic::Ordering;
+
+use defmt::{info, unwrap, warn};
+use display::DisplayController;
 use embassy_executor::Spawner;
+use embassy_futures::select::select;
 use embassy_nrf::gpio::{Input, Output};
 use embassy_nrf::interrupt::{self, InterruptExt};
 use embassy_nrf::mode::Async;
-use embassy_nrf::peripherals::{RNG, SAADC, USBD};
+use embassy_nrf::peripherals::{RNG, SAADC, SPI3, TWISPI0, UARTE0, USBD};
 use embassy_nrf::saadc::{self, AnyInput, Input as _, Saadc};
-use embassy_nrf::{Peri, bind_interrupts, rng, usb};
+use embassy_nrf::{Peri, bind_interrupts, buffered_uarte, rng, spim, twim, uarte, usb};
+use embassy_time::{Duration, Timer};
+use embedded_storage_async::nor_flash::NorFlash;
 use nrf_mpsl::Flash;
 use nrf_sdc::mpsl::MultiprotocolServiceLayer;
 use nrf_sdc::{self as sdc, mpsl};
 use rand_chacha::ChaCha12Rng;
 use rand_core::SeedableRng;
 use rmk::ble::build_ble_stack;
+use rmk::channel::CONTROLLER_CHANNEL;
 use rmk::channel::EVENT_CHANNEL;
 use rmk::config::StorageConfig;
+use rmk::controller::PollingController as _;
 use rmk::debounce::default_debouncer::DefaultDebouncer;
-use rmk::futures::future::join;
+use rmk::event::ControllerEvent;
+use rmk::futures::future::{join3, join4};
 
+use ficr::device_id;
+use identity::{PERIPHERAL_BLE_ADDR, ble_address};
+use pairing::{PAIR_KEY, key_held, reboot_paired};
 use rmk::matrix::Matrix;
-use rmk::split::peripheral::run_rmk_split_peripheral;
+use rmk::split::ble::central::read_peripheral_addresses;
+use rmk::split::peripheral::{SplitPeripheral, run_rmk_split_peripheral};
+use rmk::split::serial::SerialSplitDriver;
 use rmk::storage::new_storage_for_split_peripheral;
 use rmk::{HostResources, run_devices};
+use split_uart::{
+    FramedUart, SPLIT_TRANSPORT, SPLIT_UART_DOWN, SPLIT_UART_UP, Side, SplitTransport,
+};
 use static_cell::StaticCell;
+use status_link::StatusController;
+use storage_layout::{RMK_STORAGE_SECTORS, RMK_STORAGE_START, SECTOR_SIZE};
+use tx_power::{DEFAULT_TX_POWER, TxPowerTarget, set_tx_power};
+use tx_power_controller::TxPowerController;
+use underglow::UnderglowController;
 use {defmt_rtt as _, panic_probe as _};
 
 bind_interrupts!(struct Irqs {
@@ -40,6 +82,9 @@ bind_interrupts!(struct Irqs {
     RADIO => nrf_sdc::mpsl::HighPrioInterruptHandler;
     TIMER0 => nrf_sdc::mpsl::HighPrioInterruptHandler;
     RTC0 => nrf_sdc::mpsl::HighPrioInterruptHandler;
+    UARTE0 => buffered_uarte::InterruptHandler<UARTE0>;
+    TWISPI0 => twim::InterruptHandler<TWISPI0>;
+    SPIM3 => spim::InterruptHandler<SPI3>;
 });
 
 #[embassy_executor::task]
@@ -83,12 +128,27 @@ fn init_adc(adc_pin: AnyInput, adc: Peri<'static, SAADC>) -> Saadc<'static, 1> {
     saadc::Saadc::new(adc, Irqs, config, [channel_cfg])
 }
 
-fn ble_addr() -> [u8; 6] {
-    let ficr = embassy_nrf::pac::FICR;
-    let high = u64::from(ficr.deviceid(1).read());
-    let addr = high << 32 | u64::from(ficr.deviceid(0).read());
-    let addr = addr | 0x0000_c000_0000_0000;
-    unwrap!(addr.to_le_bytes()[..6].try_into())
+/// How often the peripheral measures its battery
+const BATTERY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Battery level from the voltage of VDDH, linear between 3.5 V and 4.2 V
+fn battery_percent(mv: u32) -> u8 {
+    (mv.clamp(3_500, 4_200) - 3_500).div_ceil(7) as u8
+}
+
+/// Publish the battery level for the display
+///
+/// RMK only measures the central's battery, the peripheral reads VDDH / 5 itself.
+async fn report_battery(saadc: &mut Saadc<'static, 1>) {
+    let publisher = CONTROLLER_CHANNEL.immediate_publisher();
+    loop {
+        let mut buf = [0; 1];
+        saadc.sample(&mut buf).await;
+        // 12 bits over 3.6 V
+        let mv = buf[0].max(0) as u32 * 3_600 * 5 / 4_096;
+        publisher.publish_immediate(ControllerEvent::Battery(battery_percent(mv)));
+        Timer::after(BATTERY_INTERVAL).await;
+    }
 }
 
 #[embassy_executor::main]
@@ -128,39 +188,144 @@ async fn main(spawner: Spawner) {
     let sdc = unwrap!(build_sdc(sdc_p, &mut rng, mpsl, &mut sdc_mem));
 
     let mut resources = HostResources::new();
-    let stack = build_ble_stack(sdc, ble_addr(), &mut rng_generator, &mut resources).await;
+    let stack = build_ble_stack(
+        sdc,
+        ble_address(PERIPHERAL_BLE_ADDR, device_id(), 0),
+        &mut rng_generator,
+        &mut resources,
+    )
+    .await;
+    // The split link is accepted from advertising and starts at the same power
+    set_tx_power(&stack, TxPowerTarget::Advertising, DEFAULT_TX_POWER).await;
 
     // Initialize the ADC. We are only using one channel for detecting battery level
     let adc_pin = p.P0_05.degrade_saadc();
-    let saadc = init_adc(adc_pin, p.SAADC);
+    let mut saadc = init_adc(adc_pin, p.SAADC);
     // Wait for ADC calibration.
     saadc.calibrate().await;
 
-    let (row_pins, col_pins) = config_matrix_pins_nrf!(peripherals: p, input: [P0_22, P0_24, P1_00, P0_11], output:  [P0_31, P0_29, P0_02, P1_15, P1_13, P1_11]);
+    let (row_pins, mut col_pins) = config_matrix_pins_nrf!(peripherals: p, input: [P0_22, P0_24, P1_00, P0_11], output:  [P0_31, P0_29, P0_02, P1_15, P1_13, P1_11]);
+    let pair_at_boot = key_held(&row_pins, &mut col_pins, PAIR_KEY).await;
 
-    // Initialize flash
-    // nRF52840's bootloader starts from 0xF4000(976K)
+    // Initialize the split UART, nice!nano pin 1 (TX) and pin 0 (RX)
+    let mut uart_config = uarte::Config::default();
+    uart_config.baudrate = split_uart::SPLIT_UART_BAUDRATE;
+    static UART_RX_BUF: StaticCell<[u8; 256]> = StaticCell::new();
+    static UART_TX_BUF: StaticCell<[u8; 256]> = StaticCell::new();
+    let split_duplex = match SPLIT_TRANSPORT {
+        SplitTransport::Uart(duplex) => duplex,
+        SplitTransport::Ble => split_uart::Duplex::Full,
+    };
+    // Only built when `[split]` selects the wired link
+    let mut split_uart = split_uart_pins!(p).map(|(rx, tx)| {
+        FramedUart::new(
+            buffered_uarte::BufferedUarte::new(
+                p.UARTE0,
+                p.TIMER1,
+                p.PPI_CH0,
+                p.PPI_CH1,
+                p.PPI_GROUP0,
+                rx,
+                tx,
+                Irqs,
+                uart_config,
+                UART_RX_BUF.init([0; 256]),
+                UART_TX_BUF.init([0; 256]),
+            ),
+            Side::Peripheral,
+            split_duplex,
+        )
+    });
+
+    // Initialize flash, the same region as RMK's storage on the central
     let storage_config = StorageConfig {
-        start_addr: 0xA0000, // 640K
-        num_sectors: 32,     // 128K
+        start_addr: RMK_STORAGE_START as usize,
+        num_sectors: RMK_STORAGE_SECTORS,
         ..Default::default()
     };
 
     // Create positional config - available for future use in peripheral if needed
     let _key_config = key_position::create_corne_positional_config();
-    let flash = Flash::take(mpsl, p.NVMC);
+    let mut flash = Flash::take(mpsl, p.NVMC);
+    if pair_at_boot {
+        // The peripheral only stores the central's address, wiping it all lets any central pair
+        info!("Pairing key held, wiping the stored central");
+        let end = RMK_STORAGE_START + RMK_STORAGE_SECTORS as u32 * SECTOR_SIZE;
+        if flash.erase(RMK_STORAGE_START, end).await.is_err() {
+            warn!("Failed to wipe the peripheral storage");
+        }
+    }
     let mut storage = new_storage_for_split_peripheral(flash, storage_config).await;
+    // RMK stores the central's address as the first peer on the peripheral
+    let central_addr = read_peripheral_addresses::<1, _, 8, 6, 8, 0>(&mut storage)
+        .await
+        .first()
+        .copied();
+    status_link::set_peer(central_addr);
 
     // Initialize the peripheral matrix
     let debouncer = DefaultDebouncer::new();
     let mut matrix = Matrix::<_, _, _, 4, 6, true>::new(row_pins, col_pins, debouncer);
     // let mut matrix = rmk::matrix::TestMatrix::<4, 7>::new();
+
+    // Split link to the central: BLE, or UART with BLE as fallback while the cable is unplugged
+    let split_link = async {
+        match (SPLIT_TRANSPORT, split_uart.as_mut()) {
+            (SplitTransport::Uart(_), Some(split_uart)) => loop {
+                if !split_uart.is_up() {
+                    select(
+                        split_uart.wait_link_up(),
+                        run_rmk_split_peripheral(0, &stack, &mut storage),
+                    )
+                    .await;
+                }
+                SPLIT_UART_DOWN.reset();
+                let mut peripheral = SplitPeripheral::new(SerialSplitDriver::new(&mut *split_uart));
+                select(peripheral.run(), SPLIT_UART_DOWN.wait()).await;
+                info!("Split UART lost, falling back to BLE");
+            },
+            _ => run_rmk_split_peripheral(0, &stack, &mut storage).await,
+        }
+    };
+
+    let mut display = DisplayController::new(display_pins!(p), Irqs);
+    let mut tx_power = TxPowerController::new(&stack);
+    let mut underglow = UnderglowController::new(underglow_pins!(p));
+    let mut status = StatusController::new();
+
+    // Once paired, reboot to read the central's address, see `pairing`
+    let paired = async {
+        if pair_at_boot {
+            let mut sub = unwrap!(CONTROLLER_CHANNEL.subscriber());
+            loop {
+                // Over the cable there is no address to store
+                if let ControllerEvent::SplitCentral(true) = sub.next_message_pure().await
+                    && !SPLIT_UART_UP.load(Ordering::Relaxed)
+                {
+                    reboot_paired().await;
+                }
+            }
+        }
+        core::future::pending::<()>().await
+    };
+
     // Start
-    join(
+    join4(
         run_devices! (
             (matrix) => EVENT_CHANNEL, // Peripheral uses EVENT_CHANNEL to send events to central
         ),
-        run_rmk_split_peripheral(0, &stack, &mut storage),
+        split_link,
+        join4(
+            display.polling_loop(),
+            tx_power.polling_loop(),
+            underglow.polling_loop(),
+            join3(
+                status.event_loop(),
+                status_link::run_peripheral(&stack),
+                paired,
+            ),
+        ),
+        report_battery(&mut saadc),
     )
     .await;
 }
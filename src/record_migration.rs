@@ -0,0 +1,327 @@
+//! Power-safe migration of RMK's records
+//!
+//! [`schema`](crate::schema) runs this at boot when the schema the storage
+//! was written with differs from the firmware's. Records are migrated in
+//! place, one at a time: a changed record is stored under its new key before
+//! the old one is removed, and `sequential-storage` makes each of those
+//! writes atomic. Nothing is erased, so a migration cut short by a power loss
+//! leaves every record readable and runs again at the next boot. A migration
+//! routine therefore has to leave a record it already migrated unchanged.
+//!
+//! Keymap entries move through a staging key, because the new key of one
+//! entry can be the old key of another: first every entry goes to the
+//! staging key of its position, then on to its key in the new geometry. The
+//! position is read from the entry's value, RMK 0.8 stores a tag byte
+//! followed by row, col and layer, so an entry a cut short migration left
+//! under either key is moved the same way.
+//!
+//! RMK 0.8 stores the keymap entry of (layer, row, col) under
+//! `0x1000 + (layer * ROW + row) * COL + col`, below its encoder entries at
+//! `0x2000`. There are no encoders, so encoder entries are not migrated.
+//!
+//! Storages written before the schema was recorded don't say which geometry
+//! they have, [`stored_geometry`] reads it from the positions in the entries.
+
+use core::ops::Range;
+
+use defmt::Format;
+use embedded_storage_async::nor_flash::MultiwriteNorFlash;
+use heapless::Vec;
+use sequential_storage::cache::NoCache;
+use sequential_storage::map::{
+    SerializationError, Value, fetch_all_items, fetch_item, remove_item, store_item,
+};
+
+/// First key of RMK's keymap entries
+const KEYMAP_KEY_BASE: u32 = 0x1000;
+
+/// Keys of RMK's keymap entries in any geometry
+const KEYMAP_KEYS: Range<u32> = KEYMAP_KEY_BASE..0x2000;
+
+/// Offset of row, col and layer in the value of a keymap entry
+const KEYMAP_POSITION_OFFSET: usize = 1;
+
+/// Staging key of keymap entries, followed by `layer << 16 | row << 8 | col`
+const KEYMAP_STAGING_BASE: u32 = 0xFF00_0000;
+
+/// Largest RMK record, the macro buffer
+const MAX_RECORD_SIZE: usize = 512;
+
+/// Keys looked up per pass over the storage
+const KEY_BATCH: usize = 32;
+
+/// Migration of one record from schema `n` to `n + 1`
+///
+/// Returns `false` to drop the record. Has to leave a record it already
+/// migrated unchanged.
+pub(crate) type Migration = fn(&mut u32, &mut [u8]) -> bool;
+
+/// Keymap geometry
+#[derive(Clone, Copy, PartialEq, Eq, Format, Debug)]
+pub(crate) struct Geometry {
+    pub(crate) layers: u8,
+    pub(crate) rows: u8,
+    pub(crate) cols: u8,
+}
+
+impl Geometry {
+    fn keymap_key(&self, (layer, row, col): (u8, u8, u8)) -> u32 {
+        let (rows, cols) = (self.rows as u32, self.cols as u32);
+        KEYMAP_KEY_BASE + (layer as u32 * rows + row as u32) * cols + col as u32
+    }
+
+    fn contains(&self, (layer, row, col): (u8, u8, u8)) -> bool {
+        layer < self.layers && row < self.rows && col < self.cols
+    }
+}
+
+/// Schema the storage was written with
+#[derive(Clone, Copy, PartialEq, Eq, Format, Debug)]
+pub(crate) struct SchemaHeader {
+    pub(crate) version: u16,
+    pub(crate) geometry: Geometry,
+}
+
+impl<'a> Value<'a> for SchemaHeader {
+    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+        if buffer.len() < 5 {
+            return Err(SerializationError::BufferTooSmall);
+        }
+        buffer[0..2].copy_from_slice(&self.version.to_le_bytes());
+        buffer[2] = self.geometry.layers;
+        buffer[3] = self.geometry.rows;
+        buffer[4] = self.geometry.cols;
+        Ok(5)
+    }
+
+    fn deserialize_from(buffer: &'a [u8]) -> Result<Self, SerializationError> {
+        if buffer.len() < 5 {
+            return Err(SerializationError::BufferTooSmall);
+        }
+        Ok(Self {
+            version: u16::from_le_bytes([buffer[0], buffer[1]]),
+            geometry: Geometry {
+                layers: buffer[2],
+                rows: buffer[3],
+                cols: buffer[4],
+            },
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Format, Debug)]
+pub(crate) enum MigrationError {
+    Flash,
+}
+
+impl<E> From<sequential_storage::Error<E>> for MigrationError {
+    fn from(_: sequential_storage::Error<E>) -> Self {
+        MigrationError::Flash
+    }
+}
+
+/// A pass over the records
+#[derive(Clone, Copy)]
+enum Pass<'m> {
+    /// Run the schema migrations on every record
+    Schema(&'m [Migration]),
+    /// Move the keymap entries to their staging keys
+    Stage,
+    /// Move the staged entries to their keys in the new geometry
+    Place(Geometry),
+}
+
+impl Pass<'_> {
+    /// Keys the pass visits
+    fn keys(&self) -> Range<u32> {
+        match self {
+            // Staged entries were migrated before they were staged
+            Pass::Schema(_) => 0..KEYMAP_STAGING_BASE,
+            // Entries a cut short migration already placed are staged again
+            Pass::Stage => KEYMAP_KEYS,
+            Pass::Place(_) => KEYMAP_STAGING_BASE..u32::MAX,
+        }
+    }
+
+    /// Migrate the record, returns `false` to drop it
+    fn apply(&self, key: &mut u32, value: &mut [u8]) -> bool {
+        match self {
+            Pass::Schema(migrations) => migrations.iter().all(|migrate| migrate(key, value)),
+            Pass::Stage => {
+                if let Some((layer, row, col)) = entry_position(value) {
+                    *key =
+                        KEYMAP_STAGING_BASE | (layer as u32) << 16 | (row as u32) << 8 | col as u32;
+                }
+                true
+            }
+            Pass::Place(to) => {
+                let position = staged_position(*key);
+                // Entries that don't fit fall back to the default keymap
+                *key = to.keymap_key(position);
+                to.contains(position)
+            }
+        }
+    }
+}
+
+/// (layer, row, col) of a keymap entry, from its value
+fn entry_position(value: &[u8]) -> Option<(u8, u8, u8)> {
+    match value.get(KEYMAP_POSITION_OFFSET..KEYMAP_POSITION_OFFSET + 3) {
+        Some(&[row, col, layer]) => Some((layer, row, col)),
+        _ => None,
+    }
+}
+
+/// (layer, row, col) of a keymap entry at its staging key
+fn staged_position(key: u32) -> (u8, u8, u8) {
+    let staged = key - KEYMAP_STAGING_BASE;
+    ((staged >> 16) as u8, (staged >> 8) as u8, staged as u8)
+}
+
+/// Geometry of the keymap entries in `range` of `flash`, `None` without any
+///
+/// RMK stores every position of the keymap, so the largest layer, row and
+/// col of the entries give the geometry. Entries a cut short migration
+/// staged count with their position, the ones it already placed still have
+/// theirs from the old geometry.
+pub(crate) async fn stored_geometry<F: MultiwriteNorFlash>(
+    flash: &mut F,
+    range: Range<u32>,
+) -> Result<Option<Geometry>, MigrationError> {
+    let mut largest: Option<(u8, u8, u8)> = None;
+    let mut buf = [0; MAX_RECORD_SIZE];
+    let mut cache = NoCache::new();
+    let mut iter = fetch_all_items::<u32, _, _>(flash, range, &mut cache, &mut buf).await?;
+    let mut item_buf = [0; MAX_RECORD_SIZE];
+    while let Some((key, value)) = iter.next::<&[u8]>(&mut item_buf).await? {
+        let position = if KEYMAP_KEYS.contains(&key) {
+            entry_position(value)
+        } else if key >= KEYMAP_STAGING_BASE {
+            Some(staged_position(key))
+        } else {
+            None
+        };
+        if let Some((layer, row, col)) = position {
+            let (layers, rows, cols) = largest.unwrap_or_default();
+            largest = Some((layers.max(layer), rows.max(row), cols.max(col)));
+        }
+    }
+    Ok(largest.map(|(layer, row, col)| Geometry {
+        layers: layer.saturating_add(1),
+        rows: row.saturating_add(1),
+        cols: col.saturating_add(1),
+    }))
+}
+
+/// Up to [`KEY_BATCH`] of the lowest keys in `keys` above `after`
+async fn next_keys<F: MultiwriteNorFlash>(
+    flash: &mut F,
+    range: Range<u32>,
+    keys: &Range<u32>,
+    after: Option<u32>,
+) -> Result<Vec<u32, KEY_BATCH>, MigrationError> {
+    let mut found: Vec<u32, KEY_BATCH> = Vec::new();
+    let mut buf = [0; MAX_RECORD_SIZE];
+    let mut cache = NoCache::new();
+    let mut iter = fetch_all_items::<u32, _, _>(flash, range, &mut cache, &mut buf).await?;
+    let mut item_buf = [0; MAX_RECORD_SIZE];
+    while let Some((key, _)) = iter.next::<&[u8]>(&mut item_buf).await? {
+        if !keys.contains(&key) || after.is_some_and(|after| key <= after) {
+            continue;
+        }
+        // The same key shows up once per stored value
+        let Err(index) = found.binary_search(&key) else {
+            continue;
+        };
+        if found.is_full() {
+            if index == found.len() {
+                continue;
+            }
+            found.pop();
+        }
+        let _ = found.insert(index, key);
+    }
+    Ok(found)
+}
+
+/// Run `pass` on every record it visits, returns how many were changed
+async fn run_pass<F: MultiwriteNorFlash>(
+    flash: &mut F,
+    range: Range<u32>,
+    pass: Pass<'_>,
+    keys: Range<u32>,
+) -> Result<usize, MigrationError> {
+    let mut changed = 0;
+    let mut after = None;
+    let mut buf = [0; MAX_RECORD_SIZE];
+    let mut value = [0; MAX_RECORD_SIZE];
+    loop {
+        let batch = next_keys(flash, range.clone(), &keys, after).await?;
+        let Some(&last) = batch.last() else {
+            return Ok(changed);
+        };
+        after = Some(last);
+        for key in batch {
+            let Some(stored) = fetch_item::<u32, &[u8], _>(
+                flash,
+                range.clone(),
+                &mut NoCache::new(),
+                &mut buf,
+                &key,
+            )
+            .await?
+            else {
+                continue;
+            };
+            let len = stored.len();
+            value[..len].copy_from_slice(stored);
+            let mut new_key = key;
+            let keep = pass.apply(&mut new_key, &mut value[..len]);
+            if keep && new_key == key && value[..len] == *stored {
+                continue;
+            }
+            if keep {
+                store_item(
+                    flash,
+                    range.clone(),
+                    &mut NoCache::new(),
+                    &mut buf,
+                    &new_key,
+                    &&value[..len],
+                )
+                .await?;
+            }
+            if !keep || new_key != key {
+                remove_item(flash, range.clone(), &mut NoCache::new(), &mut buf, &key).await?;
+            }
+            changed += 1;
+        }
+    }
+}
+
+/// Bring the records in `range` of `flash` from schema `from` to `to`
+///
+/// `migrations` are the routines from `from.version` to `to.version`.
+/// Returns how many records were changed.
+pub(crate) async fn migrate_records<F: MultiwriteNorFlash>(
+    flash: &mut F,
+    range: Range<u32>,
+    from: SchemaHeader,
+    to: SchemaHeader,
+    migrations: &[Migration],
+) -> Result<usize, MigrationError> {
+    let (old, new) = (from.geometry, to.geometry);
+    let mut passes: Vec<Pass, 3> = Vec::new();
+    if !migrations.is_empty() {
+        let _ = passes.push(Pass::Schema(migrations));
+    }
+    if old != new {
+        let _ = passes.push(Pass::Stage);
+        let _ = passes.push(Pass::Place(new));
+    }
+    let mut changed = 0;
+    for pass in passes {
+        changed += run_pass(flash, range.clone(), pass, pass.keys()).await?;
+    }
+    Ok(changed)
+}
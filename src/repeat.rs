@@ -0,0 +1,190 @@
+//! Repeat keys of the central
+//!
+//! `Repeat` sends the last key again with the modifiers it was sent with,
+//! `AltRepeat` its alternate from `[repeat]` of the keyboard TOML (`Right`
+//! after `Left`, ...), or nothing without one. Both tap on the host through
+//! [`host_keys`](crate::host_keys).
+//!
+//! The last key is the key code of the last action pressed, whatever layer it
+//! came from. A tap-hold only counts when it was tapped: RMK reports the
+//! actions, not how they resolved, so a tap-hold during which the modifiers or
+//! the layer changed counts as held. The modifiers of the last key are the
+//! ones of its action and those held while it was down; an auto shift key
+//! held for its shifted form repeats unshifted.
+//!
+//! The keys and modifiers held while `Repeat` or `AltRepeat` is pressed stay
+//! held on the host and are added to the repeated key, like a home row mod
+//! held while repeating.
+
+use defmt::{debug, unwrap};
+use heapless::Vec;
+use rmk::channel::{CONTROLLER_CHANNEL, ControllerSub};
+use rmk::controller::Controller;
+use rmk::event::ControllerEvent;
+use rmk::types::action::{Action, KeyAction};
+use rmk::types::keycode::KeyCode;
+use rmk::types::modifier::ModifierCombination;
+
+use crate::host_keys;
+use crate::user_keys::UserKey;
+
+include!(concat!(env!("OUT_DIR"), "/repeat_generated.rs"));
+
+/// HID modifier bits of `modifiers`
+fn hid_bits(modifiers: ModifierCombination) -> u8 {
+    let left = modifiers.ctrl() as u8
+        | (modifiers.shift() as u8) << 1
+        | (modifiers.alt() as u8) << 2
+        | (modifiers.gui() as u8) << 3;
+    if modifiers.right() { left << 4 } else { left }
+}
+
+/// Whether `code` is a key of the HID keyboard page, modifiers excluded
+fn is_keyboard_key(code: KeyCode) -> bool {
+    (KeyCode::A as u16..KeyCode::LCtrl as u16).contains(&(code as u16))
+}
+
+/// The key code and modifiers a key with `action` sends when tapped
+fn tapped(action: &KeyAction) -> Option<(KeyCode, u8)> {
+    let (code, modifiers) = match *action {
+        KeyAction::Single(Action::Key(code)) | KeyAction::TapHold(Action::Key(code), _, _) => {
+            (code, 0)
+        }
+        KeyAction::Single(Action::KeyWithModifier(code, modifiers))
+        | KeyAction::TapHold(Action::KeyWithModifier(code, modifiers), _, _) => {
+            (code, hid_bits(modifiers))
+        }
+        _ => return None,
+    };
+    is_keyboard_key(code).then_some((code, modifiers))
+}
+
+/// A key that is down
+struct Down {
+    code: KeyCode,
+    modifiers: u8,
+    tap_hold: bool,
+    /// Whether the modifiers or the layer changed since the press
+    changed: bool,
+}
+
+/// Remembers the last key and sends it again
+pub(crate) struct RepeatController {
+    sub: ControllerSub,
+    /// HID modifier bits held, as RMK reported them
+    held: u8,
+    /// Keys without hold held down, as the keyboard sends them
+    keys: Vec<KeyCode, 6>,
+    /// The last key pressed, until its release
+    down: Option<Down>,
+    /// The last key and its modifiers
+    last: Option<(KeyCode, u8)>,
+}
+
+impl RepeatController {
+    pub(crate) fn new() -> Self {
+        Self {
+            sub: unwrap!(CONTROLLER_CHANNEL.subscriber()),
+            held: 0,
+            keys: Vec::new(),
+            down: None,
+            last: None,
+        }
+    }
+
+    fn press(&mut self, action: &KeyAction) {
+        let Some((code, modifiers)) = tapped(action) else {
+            return;
+        };
+        let tap_hold = matches!(action, KeyAction::TapHold(..));
+        if !tap_hold {
+            self.last = Some((code, modifiers | self.held));
+            if !self.keys.contains(&code) {
+                // The keyboard doesn't send more than six either
+                let _ = self.keys.push(code);
+            }
+        }
+        self.down = Some(Down {
+            code,
+            modifiers: modifiers | self.held,
+            tap_hold,
+            changed: false,
+        });
+    }
+
+    fn release(&mut self, action: &KeyAction) {
+        if let KeyAction::Single(_) = action
+            && let Some((code, _)) = tapped(action)
+        {
+            self.keys.retain(|&key| key != code);
+        }
+        let Some(down) = self
+            .down
+            .take_if(|down| Some(down.code) == tapped(action).map(|t| t.0))
+        else {
+            return;
+        };
+        if !down.tap_hold || !down.changed {
+            self.last = Some((down.code, down.modifiers));
+        }
+    }
+
+    /// Send the last key again, or its alternate, with what is held
+    async fn repeat(&mut self, alternate: bool) {
+        let Some((code, modifiers)) = self.last else {
+            return;
+        };
+        let code = if alternate {
+            match ALTERNATES.iter().find(|(key, _)| *key == code) {
+                Some(&(_, alternate)) => alternate,
+                None => {
+                    debug!("No alternate of {:?}", code);
+                    return;
+                }
+            }
+        } else {
+            code
+        };
+        host_keys::tap_holding(code, modifiers, self.held, &self.keys).await;
+    }
+
+    /// The modifiers or the layer changed
+    fn changed(&mut self) {
+        if let Some(down) = &mut self.down {
+            down.changed = true;
+            // Modifiers held with a plain key belong to it
+            if !down.tap_hold {
+                down.modifiers |= self.held;
+                self.last = Some((down.code, down.modifiers));
+            }
+        }
+    }
+}
+
+impl Controller for RepeatController {
+    type Event = ControllerEvent;
+
+    async fn process_event(&mut self, event: Self::Event) {
+        match event {
+            ControllerEvent::Modifier(modifiers) => {
+                self.held = hid_bits(modifiers);
+                self.changed();
+            }
+            ControllerEvent::Layer(_) => self.changed(),
+            ControllerEvent::Key(key_event, action) if key_event.pressed => {
+                match UserKey::from_action(&action) {
+                    Some(UserKey::Repeat) => self.repeat(false).await,
+                    Some(UserKey::AltRepeat) => self.repeat(true).await,
+                    Some(_) => {}
+                    None => self.press(&action),
+                }
+            }
+            ControllerEvent::Key(_, action) => self.release(&action),
+            _ => {}
+        }
+    }
+
+    async fn next_message(&mut self) -> Self::Event {
+        self.sub.next_message_pure().await
+    }
+}
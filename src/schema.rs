@@ -0,0 +1,96 @@
+//! Versioned storage schema and migrations
+//!
+//! Instead of wiping the storage with `clear_storage` whenever the layout
+//! changes, the central records the schema its storage was written with and
+//! migrates RMK's records at boot, before RMK reads them:
+//!
+//! - each bump of [`SCHEMA_VERSION`] adds a routine to [`MIGRATIONS`]
+//! - a change of the keymap geometry (layers, rows, cols) moves the keymap
+//!   entries that still fit to their new keys, the others fall back to the
+//!   default keymap
+//!
+//! Schema 0 storages don't have a header, their geometry is read from the
+//! keymap entries RMK stored. The builds that wrote them had the keymap of
+//! the board they were flashed with, not necessarily this one's.
+//!
+//! Everything else RMK stores (bonds, peripheral addresses, morse profiles,
+//! macros, ...) is kept byte for byte. The records are migrated in place by
+//! [`record_migration`](crate::record_migration), which survives a power
+//! loss half way through.
+
+use defmt::{info, warn};
+
+use crate::app_storage::{AppStorage, FlashPartition, RecordKind, record_key};
+use crate::keymap::{COL, NUM_LAYER, ROW};
+use crate::record_migration::{
+    Geometry, Migration, SchemaHeader, migrate_records, stored_geometry,
+};
+use crate::storage_layout::{RMK_STORAGE_SECTORS, SECTOR_SIZE};
+
+/// Version of the storage schema
+///
+/// - 0: written before the schema was recorded
+/// - 1: schema header in the firmware-owned records
+pub(crate) const SCHEMA_VERSION: u16 = 1;
+
+/// Migration of each record from schema `n` to `n + 1`, `MIGRATIONS[n]`
+const MIGRATIONS: [Migration; SCHEMA_VERSION as usize] = [
+    // 1 only added the schema header
+    |_, _| true,
+];
+
+/// Geometry of this firmware
+const CURRENT_GEOMETRY: Geometry = Geometry {
+    layers: NUM_LAYER as u8,
+    rows: ROW as u8,
+    cols: COL as u8,
+};
+
+/// Bring RMK's storage to the firmware's schema, called before RMK reads it
+///
+/// `rmk_storage` is the partition of RMK's storage.
+pub(crate) async fn migrate(rmk_storage: &mut FlashPartition, app_storage: &mut AppStorage) {
+    let key = record_key(RecordKind::Schema, 0);
+    let range = 0..RMK_STORAGE_SECTORS as u32 * SECTOR_SIZE;
+    let stored = match app_storage.fetch::<SchemaHeader>(key).await {
+        Some(header) => header,
+        None => {
+            // An empty storage has nothing to move
+            let geometry = match stored_geometry(rmk_storage, range.clone()).await {
+                Ok(geometry) => geometry.unwrap_or(CURRENT_GEOMETRY),
+                Err(e) => {
+                    warn!("Reading the stored keymap failed: {}", e);
+                    return;
+                }
+            };
+            SchemaHeader {
+                version: 0,
+                geometry,
+            }
+        }
+    };
+    let current = SchemaHeader {
+        version: SCHEMA_VERSION,
+        geometry: CURRENT_GEOMETRY,
+    };
+    if stored == current {
+        return;
+    }
+    if stored.version > current.version {
+        warn!(
+            "Storage schema {} is newer than this firmware, not migrating",
+            stored.version
+        );
+        return;
+    }
+
+    info!("Migrating storage from {} to {}", stored, current);
+    let migrations = &MIGRATIONS[stored.version as usize..current.version as usize];
+    match migrate_records(rmk_storage, range, stored, current, migrations).await {
+        Ok(changed) => {
+            info!("Migrated {} records", changed);
+            app_storage.store(key, &current).await;
+        }
+        Err(e) => warn!("Storage migration failed: {}", e),
+    }
+}
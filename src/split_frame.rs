@@ -0,0 +1,189 @@
+//! Frames of the wired split link
+//!
+//! [`split_uart`](crate::split_uart) wraps every write of RMK's serial split
+//! driver into a frame, so that a glitch on the TRRS cable (plugging,
+//! unplugging, noise) can be detected and skipped:
+//!
+//! ```text
+//! | SOF (0xA5) | len | seq | payload (len bytes) | crc16 lo | crc16 hi |
+//! ```
+//!
+//! The CRC is CRC-16/CCITT-FALSE over `len`, `seq` and the payload. A frame
+//! with an empty payload is a heartbeat.
+
+/// Start of frame marker
+pub(crate) const SOF: u8 = 0xA5;
+
+/// Largest payload carried by one frame, longer writes are split
+pub(crate) const MAX_PAYLOAD: usize = 64;
+
+/// SOF, len, seq and two CRC bytes
+pub(crate) const FRAME_OVERHEAD: usize = 5;
+
+/// CRC-16/CCITT-FALSE
+pub(crate) fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Encode `payload` into `out`, returns the frame length
+pub(crate) fn encode_frame(seq: u8, payload: &[u8], out: &mut [u8]) -> usize {
+    let len = payload.len();
+    debug_assert!(len <= MAX_PAYLOAD && out.len() >= len + FRAME_OVERHEAD);
+    out[0] = SOF;
+    out[1] = len as u8;
+    out[2] = seq;
+    out[3..3 + len].copy_from_slice(payload);
+    let crc = crc16(&out[1..3 + len]);
+    out[3 + len..5 + len].copy_from_slice(&crc.to_le_bytes());
+    len + FRAME_OVERHEAD
+}
+
+/// Result of [`FrameDecoder::poll`]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Decoded {
+    /// More bytes needed
+    Pending,
+    /// A valid frame is complete
+    Frame,
+    /// A frame was dropped because of a bad length or CRC
+    Corrupt,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum DecodeState {
+    Sof,
+    Len,
+    Seq,
+    Payload,
+    CrcLo,
+    CrcHi,
+}
+
+/// Byte-wise frame decoder
+///
+/// A bad length or CRC drops the decoder back to hunting for the next SOF,
+/// which is how the link resyncs after a glitch. The hunt starts again from
+/// the byte after the SOF of the dropped frame, so a frame whose start was
+/// swallowed by a truncated one is still found.
+///
+/// [`push`](Self::push) a byte, then [`poll`](Self::poll) until it returns
+/// [`Decoded::Pending`]: after a drop the bytes kept are scanned again and can
+/// complete more than one frame.
+pub(crate) struct FrameDecoder {
+    state: DecodeState,
+    /// Bytes from the SOF of the frame being decoded on
+    window: [u8; MAX_PAYLOAD + FRAME_OVERHEAD],
+    /// Bytes held in `window`
+    held: usize,
+    /// Bytes of `window` decoded so far
+    pos: usize,
+    /// Sequence byte and payload length of the last complete frame
+    seq: u8,
+    len: usize,
+    payload: [u8; MAX_PAYLOAD],
+}
+
+impl FrameDecoder {
+    pub(crate) const fn new() -> Self {
+        Self {
+            state: DecodeState::Sof,
+            window: [0; MAX_PAYLOAD + FRAME_OVERHEAD],
+            held: 0,
+            pos: 0,
+            seq: 0,
+            len: 0,
+            payload: [0; MAX_PAYLOAD],
+        }
+    }
+
+    /// Sequence byte of the last complete frame
+    pub(crate) fn seq(&self) -> u8 {
+        self.seq
+    }
+
+    /// Payload of the last complete frame
+    pub(crate) fn payload(&self) -> &[u8] {
+        &self.payload[..self.len]
+    }
+
+    /// Feed one byte, [`poll`](Self::poll) must have returned
+    /// [`Decoded::Pending`] since the last one
+    pub(crate) fn push(&mut self, byte: u8) {
+        debug_assert!(self.pos == self.held);
+        if self.held < self.window.len() {
+            self.window[self.held] = byte;
+            self.held += 1;
+        }
+    }
+
+    /// Drop the first `n` bytes of the window and decode it from the start
+    fn drop_front(&mut self, n: usize) {
+        self.window.copy_within(n..self.held, 0);
+        self.held -= n;
+        self.pos = 0;
+        self.state = DecodeState::Sof;
+    }
+
+    /// Decode the bytes fed so far, up to the next complete or dropped frame
+    pub(crate) fn poll(&mut self) -> Decoded {
+        while self.pos < self.held {
+            let byte = self.window[self.pos];
+            self.pos += 1;
+            match self.state {
+                DecodeState::Sof => {
+                    if byte == SOF {
+                        self.state = DecodeState::Len;
+                    } else {
+                        self.drop_front(1);
+                    }
+                }
+                DecodeState::Len => {
+                    if byte as usize > MAX_PAYLOAD {
+                        self.drop_front(1);
+                        return Decoded::Corrupt;
+                    }
+                    self.state = DecodeState::Seq;
+                }
+                DecodeState::Seq => {
+                    self.state = if self.window[1] == 0 {
+                        DecodeState::CrcLo
+                    } else {
+                        DecodeState::Payload
+                    };
+                }
+                DecodeState::Payload => {
+                    if self.pos == 3 + self.window[1] as usize {
+                        self.state = DecodeState::CrcLo;
+                    }
+                }
+                DecodeState::CrcLo => self.state = DecodeState::CrcHi,
+                DecodeState::CrcHi => {
+                    let len = self.window[1] as usize;
+                    let crc = u16::from_le_bytes([self.window[3 + len], byte]);
+                    if crc != crc16(&self.window[1..3 + len]) {
+                        // Rescan from the byte after its SOF
+                        self.drop_front(1);
+                        return Decoded::Corrupt;
+                    }
+                    self.seq = self.window[2];
+                    self.len = len;
+                    self.payload[..len].copy_from_slice(&self.window[3..3 + len]);
+                    self.drop_front(self.pos);
+                    return Decoded::Frame;
+                }
+            }
+        }
+        Decoded::Pending
+    }
+}
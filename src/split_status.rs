@@ -0,0 +1,124 @@
+//! Status the halves forward to each other, see [`status_link`](crate::status_link)
+//!
+//! RMK's split link carries the peripheral's key events and little else, so
+//! what the peripheral shows of the central, and the central of the
+//! peripheral's battery, travels in messages of its own:
+//!
+//! ```text
+//! | version | flags | layer | effect | profile | wpm (LE, 2) | battery |
+//! ```
+//!
+//! A field whose flag is clear is not known to the sender, each half only
+//! fills in what it tracks.
+
+use defmt::Format;
+
+/// Length of an encoded [`SplitStatus`]
+pub(crate) const STATUS_LEN: usize = 8;
+
+/// Bumped when the layout changes, a message of another version is dropped
+const VERSION: u8 = 1;
+
+const LAYER: u8 = 0x01;
+const UNDERGLOW: u8 = 0x02;
+const UNDERGLOW_ON: u8 = 0x04;
+const PROFILE: u8 = 0x08;
+const WPM: u8 = 0x10;
+const BATTERY: u8 = 0x20;
+const CHARGING: u8 = 0x40;
+
+/// Underglow of the central, the peripheral's LEDs follow it
+#[derive(Clone, Copy, PartialEq, Eq, Format, Debug)]
+pub(crate) struct UnderglowState {
+    pub(crate) enabled: bool,
+    /// Number of the effect in declaration order
+    pub(crate) effect: u8,
+}
+
+/// What a half knows and the other one doesn't, all unknown by default
+#[derive(Clone, Copy, Default, PartialEq, Eq, Format, Debug)]
+pub(crate) struct SplitStatus {
+    /// Active layer of the central
+    pub(crate) layer: Option<u8>,
+    pub(crate) underglow: Option<UnderglowState>,
+    /// Active BLE profile of the central
+    pub(crate) profile: Option<u8>,
+    /// Typing speed, counted by the central
+    pub(crate) wpm: Option<u16>,
+    /// Battery level of the sender in percent
+    pub(crate) battery: Option<u8>,
+    pub(crate) charging: bool,
+}
+
+impl SplitStatus {
+    /// Nothing known
+    pub(crate) const UNKNOWN: Self = Self {
+        layer: None,
+        underglow: None,
+        profile: None,
+        wpm: None,
+        battery: None,
+        charging: false,
+    };
+
+    pub(crate) fn encode(&self) -> [u8; STATUS_LEN] {
+        let mut flags = 0;
+        let mut out = [VERSION, 0, 0, 0, 0, 0, 0, 0];
+        if let Some(layer) = self.layer {
+            flags |= LAYER;
+            out[2] = layer;
+        }
+        if let Some(underglow) = self.underglow {
+            flags |= UNDERGLOW;
+            if underglow.enabled {
+                flags |= UNDERGLOW_ON;
+            }
+            out[3] = underglow.effect;
+        }
+        if let Some(profile) = self.profile {
+            flags |= PROFILE;
+            out[4] = profile;
+        }
+        if let Some(wpm) = self.wpm {
+            flags |= WPM;
+            out[5..7].copy_from_slice(&wpm.to_le_bytes());
+        }
+        if let Some(battery) = self.battery {
+            flags |= BATTERY;
+            out[7] = battery;
+        }
+        if self.charging {
+            flags |= CHARGING;
+        }
+        out[1] = flags;
+        out
+    }
+
+    /// `None` for a message of another length or version
+    pub(crate) fn decode(message: &[u8]) -> Option<Self> {
+        let &[
+            VERSION,
+            flags,
+            layer,
+            effect,
+            profile,
+            wpm_lo,
+            wpm_hi,
+            battery,
+        ] = message
+        else {
+            return None;
+        };
+        Some(Self {
+            layer: (flags & LAYER != 0).then_some(layer),
+            underglow: (flags & UNDERGLOW != 0).then_some(UnderglowState {
+                enabled: flags & UNDERGLOW_ON != 0,
+                effect,
+            }),
+            profile: (flags & PROFILE != 0).then_some(profile),
+            wpm: (flags & WPM != 0).then_some(u16::from_le_bytes([wpm_lo, wpm_hi])),
+            battery: (flags & BATTERY != 0).then_some(battery),
+            charging: flags & CHARGING != 0,
+        })
+    }
+}
@@ -0,0 +1,253 @@
+//! Split link quality telemetry
+//!
+//! The central keeps one [`SplitLinkStats`] per peripheral: connection state,
+//! reconnect count, RSSI, connection interval and the UART frame counters.
+//! Over BLE the controller doesn't report lost packets or retransmissions, so
+//! the counters are only known while the halves talk over the cable.
+//! [`SplitLinkMonitor`] keeps them up to date, logs a defmt summary every
+//! [`SUMMARY_INTERVAL`]. The host reads them through the
+//! [`CustomChannel::SplitLink`] Vial channel.
+//!
+//! [`CustomChannel::SplitLink`]: crate::vial_custom::CustomChannel::SplitLink
+
+use core::cell::RefCell;
+use core::sync::atomic::Ordering;
+
+use bt_hci::cmd::status::ReadRssi;
+use bt_hci::controller::{Controller as HciController, ControllerCmdSync};
+use bt_hci::param::ConnHandle;
+use defmt::{Format, info, unwrap, warn};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{Duration, Instant};
+use rmk::channel::{CONTROLLER_CHANNEL, ControllerSub};
+use rmk::controller::{Controller, PollingController};
+use rmk::event::ControllerEvent;
+use trouble_host::prelude::{PacketPool, Stack};
+
+use crate::conn_handles::{self, Link};
+use crate::split_uart::{SPLIT_UART_FRAME_ERRORS, SPLIT_UART_RETRIES, SPLIT_UART_UP};
+use crate::vial_custom::CustomCommand;
+
+/// Number of peripherals connected to the central
+pub(crate) const SPLIT_PERIPHERALS_NUM: usize = 1;
+
+/// How often the defmt summary is printed
+const SUMMARY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often RSSI is read from the controller while connected
+const RSSI_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Quality counters of one split link
+#[derive(Clone, Copy, Default, Format)]
+pub(crate) struct SplitLinkStats {
+    /// Whether the peripheral is currently connected
+    pub(crate) connected: bool,
+    /// Connections after the first one
+    pub(crate) reconnects: u16,
+    /// Last RSSI read from the controller, in dBm
+    pub(crate) rssi: Option<i8>,
+    /// Connection interval of the link, in microseconds
+    pub(crate) conn_interval_us: Option<u32>,
+    /// Frames lost on the wired link because of a bad CRC or length, `None`
+    /// over BLE
+    pub(crate) lost_packets: Option<u32>,
+    /// Heartbeats resent on the wired link, `None` over BLE
+    pub(crate) retries: Option<u32>,
+    /// Seconds since boot of the last connect or disconnect
+    pub(crate) last_change_s: u32,
+}
+
+static SPLIT_LINK_STATS: Mutex<
+    CriticalSectionRawMutex,
+    RefCell<[SplitLinkStats; SPLIT_PERIPHERALS_NUM]>,
+> = Mutex::new(RefCell::new(
+    [SplitLinkStats {
+        connected: false,
+        reconnects: 0,
+        rssi: None,
+        conn_interval_us: None,
+        lost_packets: None,
+        retries: None,
+        last_change_s: 0,
+    }; SPLIT_PERIPHERALS_NUM],
+));
+
+/// Update the stats of peripheral `id`
+pub(crate) fn update(id: usize, f: impl FnOnce(&mut SplitLinkStats)) {
+    SPLIT_LINK_STATS.lock(|stats| {
+        if let Some(s) = stats.borrow_mut().get_mut(id) {
+            f(s);
+        }
+    });
+}
+
+/// Copy of the stats of peripheral `id`
+pub(crate) fn snapshot(id: usize) -> Option<SplitLinkStats> {
+    SPLIT_LINK_STATS.lock(|stats| stats.borrow().get(id).copied())
+}
+
+/// Value ids of the split link Vial channel
+const VALUE_LINK_STATS: u8 = 0x01;
+const VALUE_RESET_COUNTERS: u8 = 0x02;
+
+/// Handle a custom command on the split link channel
+///
+/// `get 0x01 <id>` returns:
+/// `connected u8 | reconnects u16 | rssi i8 (0x7F unknown) | interval_us u32 | lost u32 | retries u32 | last_change_s u32`,
+/// all little endian, starting at the first data byte. `lost` and `retries`
+/// are `0xFFFFFFFF` while the link is over BLE.
+/// `set 0x02 <id>` resets the counters of peripheral `id`.
+pub(crate) fn handle_custom_command(command: CustomCommand, value_id: u8, data: &mut [u8]) -> bool {
+    let id = data[0] as usize;
+    match (command, value_id) {
+        (CustomCommand::Get, VALUE_LINK_STATS) => {
+            let Some(s) = snapshot(id) else {
+                return false;
+            };
+            data[0] = s.connected as u8;
+            data[1..3].copy_from_slice(&s.reconnects.to_le_bytes());
+            data[3] = s.rssi.unwrap_or(i8::MAX) as u8;
+            data[4..8].copy_from_slice(&s.conn_interval_us.unwrap_or(0).to_le_bytes());
+            data[8..12].copy_from_slice(&s.lost_packets.unwrap_or(u32::MAX).to_le_bytes());
+            data[12..16].copy_from_slice(&s.retries.unwrap_or(u32::MAX).to_le_bytes());
+            data[16..20].copy_from_slice(&s.last_change_s.to_le_bytes());
+            true
+        }
+        (CustomCommand::Set, VALUE_RESET_COUNTERS) if id < SPLIT_PERIPHERALS_NUM => {
+            update(id, |s| {
+                s.reconnects = 0;
+            });
+            SPLIT_UART_FRAME_ERRORS.store(0, Ordering::Relaxed);
+            SPLIT_UART_RETRIES.store(0, Ordering::Relaxed);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Tracks split link events and polls RSSI
+pub(crate) struct SplitLinkMonitor<'a, 'd, C: HciController, P: PacketPool> {
+    stack: &'a Stack<'d, C, P>,
+    sub: ControllerSub,
+    /// Whether each peripheral has connected at least once since boot
+    seen: [bool; SPLIT_PERIPHERALS_NUM],
+    last_rssi: Instant,
+    last_summary: Instant,
+}
+
+impl<'a, 'd, C, P> SplitLinkMonitor<'a, 'd, C, P>
+where
+    C: HciController + ControllerCmdSync<ReadRssi>,
+    P: PacketPool,
+{
+    pub(crate) fn new(stack: &'a Stack<'d, C, P>) -> Self {
+        Self {
+            stack,
+            sub: unwrap!(CONTROLLER_CHANNEL.subscriber()),
+            seen: [false; SPLIT_PERIPHERALS_NUM],
+            last_rssi: Instant::now(),
+            last_summary: Instant::now(),
+        }
+    }
+
+    async fn poll_rssi(&mut self) {
+        if !snapshot(0).is_some_and(|s| s.connected) {
+            return;
+        }
+        let Some(handle) = conn_handles::handle(Link::Split) else {
+            return;
+        };
+        match self
+            .stack
+            .command(ReadRssi::new(ConnHandle::new(handle)))
+            .await
+        {
+            Ok(ret) => update(0, |s| s.rssi = Some(ret.rssi)),
+            Err(_) => warn!("Failed to read split link RSSI"),
+        }
+    }
+
+    fn log_summary(&self) {
+        for id in 0..SPLIT_PERIPHERALS_NUM {
+            if let Some(s) = snapshot(id) {
+                info!(
+                    "Split link {}: connected={}, reconnects={}, rssi={}, interval={}us, lost={}, retries={}",
+                    id,
+                    s.connected,
+                    s.reconnects,
+                    s.rssi,
+                    s.conn_interval_us,
+                    s.lost_packets,
+                    s.retries
+                );
+            }
+        }
+    }
+}
+
+impl<C, P> Controller for SplitLinkMonitor<'_, '_, C, P>
+where
+    C: HciController + ControllerCmdSync<ReadRssi>,
+    P: PacketPool,
+{
+    type Event = ControllerEvent;
+
+    async fn process_event(&mut self, event: Self::Event) {
+        match event {
+            ControllerEvent::SplitPeripheral(id, connected) => {
+                if id >= SPLIT_PERIPHERALS_NUM {
+                    return;
+                }
+                let reconnect = connected && self.seen[id];
+                self.seen[id] |= connected;
+                update(id, |s| {
+                    s.connected = connected;
+                    s.last_change_s = Instant::now().as_secs() as u32;
+                    if reconnect {
+                        s.reconnects = s.reconnects.saturating_add(1);
+                    }
+                    if !connected {
+                        s.rssi = None;
+                    }
+                });
+                if connected {
+                    info!("Split peripheral {} connected", id);
+                } else {
+                    warn!("Split peripheral {} disconnected", id);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    async fn next_message(&mut self) -> Self::Event {
+        self.sub.next_message_pure().await
+    }
+}
+
+impl<C, P> PollingController for SplitLinkMonitor<'_, '_, C, P>
+where
+    C: HciController + ControllerCmdSync<ReadRssi>,
+    P: PacketPool,
+{
+    const INTERVAL: Duration = Duration::from_millis(250);
+
+    async fn update(&mut self) {
+        // The wired link counters live in the UART driver, BLE has none
+        let wired = SPLIT_UART_UP.load(Ordering::Relaxed);
+        update(0, |s| {
+            s.lost_packets = wired.then(|| SPLIT_UART_FRAME_ERRORS.load(Ordering::Relaxed));
+            s.retries = wired.then(|| SPLIT_UART_RETRIES.load(Ordering::Relaxed));
+        });
+
+        if self.last_rssi.elapsed() >= RSSI_INTERVAL {
+            self.last_rssi = Instant::now();
+            self.poll_rssi().await;
+        }
+        if self.last_summary.elapsed() >= SUMMARY_INTERVAL {
+            self.last_summary = Instant::now();
+            self.log_summary();
+        }
+    }
+}
@@ -0,0 +1,299 @@
+//! Wired UART transport between the two halves
+//!
+//! Selected by `connection = "serial"` in `[split]` of the keyboard TOML. RMK's
+//! serial split driver writes its messages as a plain byte stream, this module
+//! wraps every write into a CRC-checked frame of
+//! [`split_frame`](crate::split_frame) so that a glitch on the TRRS cable is
+//! detected and skipped.
+//!
+//! The top bit of `seq` marks the sender (0 = central, 1 = peripheral), so a
+//! half that reads back its own bytes on a shared wire simply drops them. The
+//! next bit marks the frames of [`status_link`](crate::status_link), which are
+//! sent in place of a heartbeat or after one of RMK's writes. A
+//! heartbeat is sent whenever the line has been idle for
+//! [`HEARTBEAT_INTERVAL`]. When nothing valid arrives for [`LINK_TIMEOUT`] the
+//! link is reported down through [`SPLIT_UART_DOWN`] and the binaries fall back
+//! to BLE until the cable answers again.
+
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use defmt::{Format, info, warn};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer, with_timeout};
+use embedded_io_async::{ErrorKind, ErrorType, Read, Write};
+
+use crate::split_frame::{Decoded, FRAME_OVERHEAD, FrameDecoder, MAX_PAYLOAD, encode_frame};
+use crate::status_link::{self, WiredStatus};
+
+include!(concat!(env!("OUT_DIR"), "/split_uart_generated.rs"));
+
+/// Physical wiring of the split UART
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+pub(crate) enum Duplex {
+    /// Separate TX and RX wires between the halves
+    Full,
+    /// TX and RX of each half joined on a single wire (TX through a 1k resistor).
+    /// Every byte sent is read back, and a half only starts sending after the
+    /// line has been quiet for [`HALF_DUPLEX_GUARD`].
+    Half,
+}
+
+/// Transport used between the halves
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+pub(crate) enum SplitTransport {
+    /// BLE only, the default nice!nano setup
+    Ble,
+    /// UART over the TRRS cable, with BLE as fallback when the cable is unplugged
+    Uart(Duplex),
+}
+
+/// UART baudrate of the split link
+pub(crate) const SPLIT_UART_BAUDRATE: embassy_nrf::uarte::Baudrate =
+    embassy_nrf::uarte::Baudrate::BAUD115200;
+
+/// Sender of a frame, encoded in the top bit of `seq`
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+#[allow(dead_code)] // Each binary only uses its own side
+pub(crate) enum Side {
+    Central,
+    Peripheral,
+}
+
+impl Side {
+    const fn seq_bit(self) -> u8 {
+        match self {
+            Side::Central => 0x00,
+            Side::Peripheral => 0x80,
+        }
+    }
+}
+
+/// Bit of `seq` marking a status frame
+const STATUS_FRAME: u8 = 0x40;
+
+/// Send a heartbeat after the line has been idle this long
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The link is down after this long without a valid frame
+const LINK_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Quiet time required on a half-duplex line before sending
+const HALF_DUPLEX_GUARD: Duration = Duration::from_micros(500);
+
+/// Raised by [`FramedUart`] when no valid frame arrived for [`LINK_TIMEOUT`]
+pub(crate) static SPLIT_UART_DOWN: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Whether the halves are talking over the cable, see [`FramedUart::is_up`]
+pub(crate) static SPLIT_UART_UP: AtomicBool = AtomicBool::new(false);
+
+/// Frames dropped because of a bad length or CRC
+pub(crate) static SPLIT_UART_FRAME_ERRORS: AtomicU32 = AtomicU32::new(0);
+
+/// Heartbeats that went unanswered
+pub(crate) static SPLIT_UART_RETRIES: AtomicU32 = AtomicU32::new(0);
+
+/// Errors of the framed UART
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub(crate) enum LinkError {
+    /// The UART driver reported an error
+    Uart,
+    /// No valid frame within [`LINK_TIMEOUT`]
+    Timeout,
+}
+
+impl embedded_io_async::Error for LinkError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            LinkError::Uart => ErrorKind::Other,
+            LinkError::Timeout => ErrorKind::TimedOut,
+        }
+    }
+}
+
+/// A UART wrapped in CRC-checked frames with heartbeats and link supervision
+///
+/// Implements `embedded_io_async::{Read, Write}`, so it can be handed to RMK's
+/// serial split driver in place of the raw UART.
+pub(crate) struct FramedUart<U> {
+    uart: U,
+    side: Side,
+    duplex: Duplex,
+    decoder: FrameDecoder,
+    tx_seq: u8,
+    rx_pos: usize,
+    rx_len: usize,
+    rx_buf: [u8; MAX_PAYLOAD],
+    last_rx: Instant,
+    last_frame: Instant,
+    up: bool,
+    status: WiredStatus,
+}
+
+impl<U: Read + Write> FramedUart<U> {
+    pub(crate) fn new(uart: U, side: Side, duplex: Duplex) -> Self {
+        Self {
+            uart,
+            side,
+            duplex,
+            decoder: FrameDecoder::new(),
+            tx_seq: 0,
+            rx_pos: 0,
+            rx_len: 0,
+            rx_buf: [0; MAX_PAYLOAD],
+            last_rx: Instant::now(),
+            last_frame: Instant::now(),
+            up: false,
+            status: WiredStatus::new(),
+        }
+    }
+
+    /// Whether a valid frame from the other half arrived within [`LINK_TIMEOUT`]
+    pub(crate) fn is_up(&self) -> bool {
+        self.up
+    }
+
+    /// Send `payload`, a status message if `kind` is [`STATUS_FRAME`]
+    async fn send_frame(&mut self, kind: u8, payload: &[u8]) -> Result<(), LinkError> {
+        if self.duplex == Duplex::Half {
+            // Wait for the other half to finish talking
+            while self.last_rx.elapsed() < HALF_DUPLEX_GUARD {
+                Timer::after(HALF_DUPLEX_GUARD).await;
+            }
+        }
+        let mut frame = [0u8; MAX_PAYLOAD + FRAME_OVERHEAD];
+        let seq = self.side.seq_bit() | kind | (self.tx_seq & 0x3F);
+        self.tx_seq = self.tx_seq.wrapping_add(1);
+        let n = encode_frame(seq, payload, &mut frame);
+        self.uart
+            .write_all(&frame[..n])
+            .await
+            .map_err(|_| LinkError::Uart)?;
+        self.uart.flush().await.map_err(|_| LinkError::Uart)
+    }
+
+    /// Send the status if it is due, a heartbeat otherwise
+    async fn send_heartbeat(&mut self) -> Result<(), LinkError> {
+        match self.status.due() {
+            Some(status) => self.send_frame(STATUS_FRAME, &status).await,
+            None => self.send_frame(0, &[]).await,
+        }
+    }
+
+    /// Receive the next frame from the other half, sending heartbeats while idle.
+    ///
+    /// Heartbeats and our own echoed frames are consumed here; the payload of a
+    /// data frame is left in `rx_buf`.
+    async fn recv_frame(&mut self) -> Result<(), LinkError> {
+        loop {
+            match self.decoder.poll() {
+                Decoded::Pending => {}
+                Decoded::Corrupt => {
+                    SPLIT_UART_FRAME_ERRORS.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
+                Decoded::Frame => {
+                    if self.decoder.seq() & 0x80 == self.side.seq_bit() {
+                        // Our own frame echoed back on a half-duplex line
+                        continue;
+                    }
+                    self.last_frame = Instant::now();
+                    if !self.up {
+                        info!("Split UART link up");
+                        self.up = true;
+                        SPLIT_UART_UP.store(true, Ordering::Relaxed);
+                    }
+                    let payload = self.decoder.payload();
+                    if self.decoder.seq() & STATUS_FRAME != 0 {
+                        status_link::received(payload);
+                        continue;
+                    }
+                    if payload.is_empty() {
+                        continue;
+                    }
+                    self.rx_len = payload.len();
+                    self.rx_buf[..self.rx_len].copy_from_slice(payload);
+                    self.rx_pos = 0;
+                    return Ok(());
+                }
+            }
+            let mut byte = [0u8; 1];
+            match with_timeout(HEARTBEAT_INTERVAL, self.uart.read(&mut byte)).await {
+                Ok(Ok(0)) => continue,
+                Ok(Ok(_)) => {
+                    self.last_rx = Instant::now();
+                    self.decoder.push(byte[0]);
+                }
+                Ok(Err(_)) => return Err(LinkError::Uart),
+                Err(_) => {
+                    if self.up {
+                        SPLIT_UART_RETRIES.fetch_add(1, Ordering::Relaxed);
+                    }
+                    if self.last_frame.elapsed() > LINK_TIMEOUT {
+                        if self.up {
+                            warn!("Split UART link down");
+                            self.up = false;
+                            SPLIT_UART_UP.store(false, Ordering::Relaxed);
+                            SPLIT_UART_DOWN.signal(());
+                            status_link::lost();
+                        }
+                        // Keep the heartbeat going so the other half can find us again
+                        self.send_frame(0, &[]).await?;
+                        return Err(LinkError::Timeout);
+                    }
+                    self.send_heartbeat().await?;
+                }
+            }
+        }
+    }
+
+    /// Wait until the other half answers on the cable
+    ///
+    /// Runs while the halves talk over BLE, so that plugging the cable in
+    /// switches the split link over to UART.
+    pub(crate) async fn wait_link_up(&mut self) {
+        loop {
+            match self.recv_frame().await {
+                // A data frame while probing belongs to a session the other half
+                // already started, keep it for the reader
+                Ok(()) => return,
+                Err(_) if self.up => return,
+                Err(LinkError::Timeout) => {}
+                Err(LinkError::Uart) => Timer::after(LINK_TIMEOUT).await,
+            }
+        }
+    }
+}
+
+impl<U> ErrorType for FramedUart<U> {
+    type Error = LinkError;
+}
+
+impl<U: Read + Write> Read for FramedUart<U> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if self.rx_pos == self.rx_len {
+            self.recv_frame().await?;
+        }
+        let n = buf.len().min(self.rx_len - self.rx_pos);
+        buf[..n].copy_from_slice(&self.rx_buf[self.rx_pos..self.rx_pos + n]);
+        self.rx_pos += n;
+        Ok(n)
+    }
+}
+
+impl<U: Read + Write> Write for FramedUart<U> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let n = buf.len().min(MAX_PAYLOAD);
+        self.send_frame(0, &buf[..n]).await?;
+        // The line is rarely idle while typing fast
+        if let Some(status) = self.status.due() {
+            self.send_frame(STATUS_FRAME, &status).await?;
+        }
+        Ok(n)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        // Frames are flushed as they are sent
+        Ok(())
+    }
+}
@@ -0,0 +1,238 @@
+//! Forwards the [`SplitStatus`] of each half to the other
+//!
+//! Over BLE the status travels on an L2CAP channel of its own next to RMK's
+//! split connection: the central opens it on [`STATUS_PSM`] and sends its
+//! status whenever it changes, at least every [`KEEPALIVE`], and the
+//! peripheral answers each message with its own. Over the wired link,
+//! [`FramedUart`](crate::split_uart::FramedUart) sends it in frames of its
+//! own between RMK's.
+//!
+//! The halves find the split connection by the address of the other half,
+//! read again for every attempt to open the channel: RMK's stored address at
+//! boot, none once the halves are being paired again. RMK keeps the address
+//! of a newly paired half to itself until the next boot, so a half that
+//! paired reboots once the link is up, see [`pairing`](crate::pairing).
+
+use core::cell::Cell;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use bt_hci::controller::Controller as HciController;
+use defmt::{info, unwrap, warn};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer, with_timeout};
+use rmk::channel::{CONTROLLER_CHANNEL, ControllerSub};
+use rmk::controller::Controller;
+use rmk::event::ControllerEvent;
+use trouble_host::prelude::{Address, L2capChannel, L2capChannelConfig, PacketPool, Stack};
+
+use crate::split_status::{STATUS_LEN, SplitStatus};
+
+/// PSM of the status channel, from the LE dynamic range
+const STATUS_PSM: u16 = 0x00C5;
+
+/// Longest time between two status messages of the central
+const KEEPALIVE: Duration = Duration::from_secs(5);
+
+/// Time the central waits for the peripheral to answer
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Time between two attempts to open the status channel
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What this half forwards
+static LOCAL: Mutex<CriticalSectionRawMutex, Cell<SplitStatus>> =
+    Mutex::new(Cell::new(SplitStatus::UNKNOWN));
+
+/// Counts the changes of [`LOCAL`], for the wired link
+static LOCAL_VERSION: AtomicU32 = AtomicU32::new(0);
+
+/// Raised when [`LOCAL`] changes, for the BLE status channel
+static LOCAL_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// What the other half forwarded last, all unknown while the link is down
+static REMOTE: Mutex<CriticalSectionRawMutex, Cell<SplitStatus>> =
+    Mutex::new(Cell::new(SplitStatus::UNKNOWN));
+
+/// Address of the other half, `None` while there is none to connect to
+static PEER: Mutex<CriticalSectionRawMutex, Cell<Option<[u8; 6]>>> = Mutex::new(Cell::new(None));
+
+/// Set the address of the other half the BLE status channel goes to
+pub(crate) fn set_peer(peer: Option<[u8; 6]>) {
+    PEER.lock(|p| p.set(peer));
+}
+
+/// Change what this half forwards
+pub(crate) fn update_local(f: impl FnOnce(&mut SplitStatus)) {
+    let changed = LOCAL.lock(|local| {
+        let mut status = local.get();
+        f(&mut status);
+        let changed = status != local.get();
+        local.set(status);
+        changed
+    });
+    if changed {
+        LOCAL_VERSION.fetch_add(1, Ordering::Relaxed);
+        LOCAL_CHANGED.signal(());
+    }
+}
+
+/// What the other half forwarded last
+pub(crate) fn remote() -> SplitStatus {
+    REMOTE.lock(|remote| remote.get())
+}
+
+/// A status message from the other half
+pub(crate) fn received(message: &[u8]) {
+    match SplitStatus::decode(message) {
+        Some(status) => REMOTE.lock(|remote| remote.set(status)),
+        None => warn!("Dropped a status message of another firmware"),
+    }
+}
+
+/// The link to the other half is down, forget its status
+pub(crate) fn lost() {
+    REMOTE.lock(|remote| remote.set(SplitStatus::UNKNOWN));
+}
+
+/// Decides when the wired link sends the status
+pub(crate) struct WiredStatus {
+    sent_version: Option<u32>,
+    sent_at: Instant,
+}
+
+impl WiredStatus {
+    pub(crate) const fn new() -> Self {
+        Self {
+            sent_version: None,
+            sent_at: Instant::MIN,
+        }
+    }
+
+    /// The status to send, if it changed since the last one or [`KEEPALIVE`] passed
+    pub(crate) fn due(&mut self) -> Option<[u8; STATUS_LEN]> {
+        let version = LOCAL_VERSION.load(Ordering::Relaxed);
+        if self.sent_version == Some(version) && self.sent_at.elapsed() < KEEPALIVE {
+            return None;
+        }
+        self.sent_version = Some(version);
+        self.sent_at = Instant::now();
+        Some(LOCAL.lock(|local| local.get()).encode())
+    }
+}
+
+fn channel_config() -> L2capChannelConfig {
+    L2capChannelConfig {
+        mtu: Some(STATUS_LEN as u16),
+        ..Default::default()
+    }
+}
+
+/// Send the central's status to the peripheral at [`set_peer`]'s address and
+/// take its answers, never returns
+pub(crate) async fn run_central<C: HciController, P: PacketPool>(stack: &Stack<'_, C, P>) -> ! {
+    loop {
+        Timer::after(RETRY_INTERVAL).await;
+        let Some(peripheral) = PEER.lock(|p| p.get()) else {
+            continue;
+        };
+        let Some(conn) = stack.get_connection_by_peer_address(Address::random(peripheral)) else {
+            continue;
+        };
+        let Ok(mut channel) =
+            L2capChannel::create(stack, &conn, STATUS_PSM, &channel_config()).await
+        else {
+            continue;
+        };
+        info!("Status channel to the peripheral open");
+        let mut answer = [0; STATUS_LEN];
+        loop {
+            LOCAL_CHANGED.reset();
+            let status = LOCAL.lock(|local| local.get()).encode();
+            if channel.send(stack, &status).await.is_err() {
+                break;
+            }
+            match with_timeout(REPLY_TIMEOUT, channel.receive(stack, &mut answer)).await {
+                Ok(Ok(len)) => received(&answer[..len]),
+                _ => break,
+            }
+            let _ = with_timeout(KEEPALIVE, LOCAL_CHANGED.wait()).await;
+        }
+        warn!("Status channel to the peripheral lost");
+        lost();
+    }
+}
+
+/// Answer the status of the central at [`set_peer`]'s address with the
+/// peripheral's, never returns
+pub(crate) async fn run_peripheral<C: HciController, P: PacketPool>(stack: &Stack<'_, C, P>) -> ! {
+    loop {
+        Timer::after(RETRY_INTERVAL).await;
+        let Some(central) = PEER.lock(|p| p.get()) else {
+            continue;
+        };
+        let Some(conn) = stack.get_connection_by_peer_address(Address::random(central)) else {
+            continue;
+        };
+        let Ok(mut channel) =
+            L2capChannel::accept(stack, &conn, &[STATUS_PSM], &channel_config()).await
+        else {
+            continue;
+        };
+        info!("Status channel to the central open");
+        let mut message = [0; STATUS_LEN];
+        loop {
+            // The central sends at least every KEEPALIVE
+            match with_timeout(KEEPALIVE * 2, channel.receive(stack, &mut message)).await {
+                Ok(Ok(len)) => received(&message[..len]),
+                _ => break,
+            }
+            let status = LOCAL.lock(|local| local.get()).encode();
+            if channel.send(stack, &status).await.is_err() {
+                break;
+            }
+        }
+        warn!("Status channel to the central lost");
+        lost();
+    }
+}
+
+/// Keeps this half's part of the status up to date
+///
+/// Only the central hears of the layer, the profile and the WPM, both
+/// halves of their own battery.
+pub(crate) struct StatusController {
+    sub: ControllerSub,
+}
+
+impl StatusController {
+    pub(crate) fn new() -> Self {
+        Self {
+            sub: unwrap!(CONTROLLER_CHANNEL.subscriber()),
+        }
+    }
+}
+
+impl Controller for StatusController {
+    type Event = ControllerEvent;
+
+    async fn process_event(&mut self, event: Self::Event) {
+        match event {
+            ControllerEvent::Layer(layer) => update_local(|status| status.layer = Some(layer)),
+            ControllerEvent::BleProfile(profile) => {
+                update_local(|status| status.profile = Some(profile))
+            }
+            ControllerEvent::Wpm(wpm) => update_local(|status| status.wpm = Some(wpm)),
+            ControllerEvent::Battery(level) => update_local(|status| status.battery = Some(level)),
+            ControllerEvent::ChargingState(charging) => {
+                update_local(|status| status.charging = charging)
+            }
+            _ => {}
+        }
+    }
+
+    async fn next_message(&mut self) -> Self::Event {
+        self.sub.next_message_pure().await
+    }
+}
@@ -0,0 +1,21 @@
+//! Flash layout, computed by `build.rs` from `memory.x`
+//!
+//! ```text
+//! 0x00026000  application image (FLASH in memory.x)
+//! 0x000A0000  RMK storage: keymap, morse profiles, bonds, peripheral addresses
+//! 0x000A6000  firmware-owned records of the central, see `app_storage`
+//! 0x000F4000  bootloader
+//! ```
+//!
+//! RMK's storage starts where the image ends, unless `start_addr` in the
+//! TOML's `[storage]` moves it, and is `num_sectors` long on both halves. The
+//! build fails when a region overlaps the image or the bootloader.
+
+include!(concat!(env!("OUT_DIR"), "/storage_layout_generated.rs"));
+
+/// Size of the nRF52840's flash
+#[allow(dead_code)] // Only the central partitions the flash
+pub(crate) const FLASH_SIZE: u32 = 0x10_0000;
+
+/// Size of an NVMC erase page
+pub(crate) const SECTOR_SIZE: u32 = 0x1000;
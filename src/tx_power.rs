@@ -0,0 +1,49 @@
+//! Radio TX power
+//!
+//! Both halves start at `default_tx_power` from `[ble]` in the keyboard TOML,
+//! generated into [`DEFAULT_TX_POWER`] by `build.rs`.
+
+use bt_hci::controller::{Controller as HciController, ControllerCmdSync};
+use defmt::{Format, warn};
+use nrf_sdc::vendor::ZephyrWriteTxPower;
+use trouble_host::prelude::{PacketPool, Stack};
+
+include!(concat!(env!("OUT_DIR"), "/tx_power_generated.rs"));
+
+/// What a TX power setting applies to, the Zephyr vendor command handle types
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+pub(crate) enum TxPowerTarget {
+    /// Advertising, connections accepted from it start at the same power
+    Advertising,
+    /// Scanning and initiating, connections created from it start at the same power
+    Initiator,
+    /// An established connection
+    Connection(u16),
+}
+
+/// Set the TX power of `target`, returns the level selected by the controller
+pub(crate) async fn set_tx_power<C, P>(
+    stack: &Stack<'_, C, P>,
+    target: TxPowerTarget,
+    level: i8,
+) -> Option<i8>
+where
+    C: HciController + ControllerCmdSync<ZephyrWriteTxPower>,
+    P: PacketPool,
+{
+    let (handle_type, handle) = match target {
+        TxPowerTarget::Advertising => (0, 0),
+        TxPowerTarget::Initiator => (1, 0),
+        TxPowerTarget::Connection(handle) => (2, handle),
+    };
+    match stack
+        .command(ZephyrWriteTxPower::new(handle_type, handle, level))
+        .await
+    {
+        Ok(ret) => Some(ret.selected_tx_power),
+        Err(_) => {
+            warn!("Failed to set TX power of {}", target);
+            None
+        }
+    }
+}
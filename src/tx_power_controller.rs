@@ -0,0 +1,181 @@
+//! Runtime TX power control
+//!
+//! The `TxPower` key cycles the central through [`TX_POWER_LEVELS`]. Both
+//! halves lower the power of the split link while its RSSI is strong, which is
+//! most of the time with both halves on one desk; each half measures the RSSI
+//! of what it receives and sets its own transmitter.
+
+use bt_hci::cmd::status::ReadRssi;
+use bt_hci::controller::{Controller as HciController, ControllerCmdSync};
+use bt_hci::param::ConnHandle;
+use defmt::{info, unwrap, warn};
+use embassy_time::Duration;
+use nrf_sdc::vendor::ZephyrWriteTxPower;
+use rmk::channel::{CONTROLLER_CHANNEL, ControllerSub};
+use rmk::controller::{Controller, PollingController};
+use rmk::event::ControllerEvent;
+use trouble_host::prelude::{PacketPool, Stack};
+
+use crate::conn_handles::{self, Link};
+use crate::tx_power::{DEFAULT_TX_POWER, TxPowerTarget, set_tx_power};
+use crate::user_keys::UserKey;
+
+/// TX power levels supported by the nRF52840 radio, cycled from the highest
+pub(crate) const TX_POWER_LEVELS: [i8; 8] = [8, 4, 0, -4, -8, -12, -16, -20];
+
+/// Split link TX power while its RSSI is strong
+const REDUCED_TX_POWER: i8 = -8;
+
+/// RSSI above which the split link power is reduced, in dBm
+const STRONG_RSSI: i8 = -50;
+
+/// RSSI below which the split link power is restored, in dBm
+const WEAK_RSSI: i8 = -70;
+
+/// Cycles the TX power on the `TxPower` key and reduces it on a strong split link
+pub(crate) struct TxPowerController<'a, 'd, C: HciController, P: PacketPool> {
+    stack: &'a Stack<'d, C, P>,
+    sub: ControllerSub,
+    level: i8,
+    split_reduced: bool,
+    /// Connection handle each link's power was last set on
+    applied: [Option<u16>; 2],
+}
+
+impl<'a, 'd, C, P> TxPowerController<'a, 'd, C, P>
+where
+    C: HciController + ControllerCmdSync<ZephyrWriteTxPower> + ControllerCmdSync<ReadRssi>,
+    P: PacketPool,
+{
+    pub(crate) fn new(stack: &'a Stack<'d, C, P>) -> Self {
+        Self {
+            stack,
+            sub: unwrap!(CONTROLLER_CHANNEL.subscriber()),
+            level: DEFAULT_TX_POWER,
+            split_reduced: false,
+            applied: [None; 2],
+        }
+    }
+
+    /// Apply the current level to advertising, scanning and the open connections
+    async fn apply(&mut self) {
+        set_tx_power(self.stack, TxPowerTarget::Advertising, self.level).await;
+        set_tx_power(self.stack, TxPowerTarget::Initiator, self.level).await;
+        self.applied = [None; 2];
+        self.apply_links().await;
+    }
+
+    /// Set the power of the links connected since it was last set
+    ///
+    /// The handles are found when RMK reports a link connected, which may
+    /// come after this controller saw the same report.
+    async fn apply_links(&mut self) {
+        for link in [Link::Split, Link::Host] {
+            let handle = conn_handles::handle(link);
+            if handle == self.applied[link as usize] {
+                continue;
+            }
+            self.applied[link as usize] = handle;
+            let Some(handle) = handle else {
+                continue;
+            };
+            let level = if link == Link::Split && self.split_reduced {
+                self.level.min(REDUCED_TX_POWER)
+            } else {
+                self.level
+            };
+            set_tx_power(self.stack, TxPowerTarget::Connection(handle), level).await;
+        }
+    }
+
+    /// RSSI of the split link, `None` while it is disconnected
+    async fn split_rssi(&self) -> Option<i8> {
+        let handle = conn_handles::handle(Link::Split)?;
+        match self
+            .stack
+            .command(ReadRssi::new(ConnHandle::new(handle)))
+            .await
+        {
+            Ok(ret) => Some(ret.rssi),
+            Err(_) => {
+                warn!("Failed to read split link RSSI");
+                None
+            }
+        }
+    }
+
+    fn next_level(&self) -> i8 {
+        let current = TX_POWER_LEVELS
+            .iter()
+            .position(|l| *l == self.level)
+            .unwrap_or(0);
+        TX_POWER_LEVELS[(current + 1) % TX_POWER_LEVELS.len()]
+    }
+}
+
+impl<C, P> Controller for TxPowerController<'_, '_, C, P>
+where
+    C: HciController + ControllerCmdSync<ZephyrWriteTxPower> + ControllerCmdSync<ReadRssi>,
+    P: PacketPool,
+{
+    type Event = ControllerEvent;
+
+    async fn process_event(&mut self, event: Self::Event) {
+        match event {
+            ControllerEvent::Key(key_event, action) => {
+                if key_event.pressed && UserKey::from_action(&action) == Some(UserKey::CycleTxPower)
+                {
+                    self.level = self.next_level();
+                    info!("TX power set to {} dBm", self.level);
+                    self.apply().await;
+                }
+            }
+            ControllerEvent::SplitPeripheral(0, _) => {
+                self.split_reduced = false;
+                self.apply_links().await;
+            }
+            // The peripheral's only link, the central's are found by its connection parameters
+            ControllerEvent::SplitCentral(connected) => {
+                self.split_reduced = false;
+                if connected {
+                    conn_handles::connected(self.stack, Link::Split).await;
+                } else {
+                    conn_handles::disconnected(Link::Split);
+                }
+                self.apply_links().await;
+            }
+            ControllerEvent::BleState(..) => self.apply_links().await,
+            _ => {}
+        }
+    }
+
+    async fn next_message(&mut self) -> Self::Event {
+        self.sub.next_message_pure().await
+    }
+}
+
+impl<C, P> PollingController for TxPowerController<'_, '_, C, P>
+where
+    C: HciController + ControllerCmdSync<ZephyrWriteTxPower> + ControllerCmdSync<ReadRssi>,
+    P: PacketPool,
+{
+    const INTERVAL: Duration = Duration::from_secs(5);
+
+    async fn update(&mut self) {
+        self.apply_links().await;
+        let Some(rssi) = self.split_rssi().await else {
+            return;
+        };
+        let reduce = if self.split_reduced {
+            rssi > WEAK_RSSI
+        } else {
+            rssi > STRONG_RSSI
+        };
+        if reduce != self.split_reduced {
+            self.split_reduced = reduce;
+            info!("Split link RSSI {} dBm, reduced TX power: {}", rssi, reduce);
+            self.applied[Link::Split as usize] = None;
+            self.apply_links().await;
+        }
+    }
+}
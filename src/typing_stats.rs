@@ -0,0 +1,244 @@
+//! Typing statistics of the central
+//!
+//! [`TypingStats`] counts the presses of every matrix position on every layer
+//! and keeps the WPM RMK publishes, with its peak since boot. The counts add
+//! up across reboots: they are kept in the
+//! firmware-owned records, one per layer and row, and only the rows typed on
+//! since the last save are written, at most every [`SAVE_INTERVAL`]. The host
+//! reads them through the [`CustomChannel::TypingStats`] Vial channel,
+//! `tools/typing-stats` draws them as a heatmap.
+//!
+//! The layer of a press is the highest active layer when it happened. The
+//! peripheral's keys are counted at their rows in the central's matrix.
+//!
+//! [`CustomChannel::TypingStats`]: crate::vial_custom::CustomChannel::TypingStats
+
+use core::cell::RefCell;
+
+use defmt::{info, unwrap};
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{Duration, Instant};
+use rmk::channel::{CONTROLLER_CHANNEL, ControllerSub};
+use rmk::controller::{Controller, PollingController};
+use rmk::event::{ControllerEvent, KeyboardEventPos};
+use sequential_storage::map::{SerializationError, Value};
+
+use crate::app_storage::{RecordKind, SharedAppStorage, record_key};
+use crate::keymap::{COL, NUM_LAYER, ROW};
+use crate::vial_custom::CustomCommand;
+
+/// Shortest time between two saves of the counts
+///
+/// A save writes about 32 bytes per row typed on, so a day of typing costs a
+/// few sector erases.
+const SAVE_INTERVAL: Duration = Duration::from_secs(30 * 60);
+
+/// Presses per layer, row and column, including the ones before this boot
+static COUNTS: BlockingMutex<CriticalSectionRawMutex, RefCell<PressCounts>> =
+    BlockingMutex::new(RefCell::new(PressCounts {
+        presses: [[[0; COL]; ROW]; NUM_LAYER],
+        dirty: [[false; ROW]; NUM_LAYER],
+        wpm: 0,
+        peak_wpm: 0,
+    }));
+
+struct PressCounts {
+    presses: [[[u32; COL]; ROW]; NUM_LAYER],
+    /// Rows changed since the last save
+    dirty: [[bool; ROW]; NUM_LAYER],
+    wpm: u16,
+    /// Highest WPM since boot
+    peak_wpm: u16,
+}
+
+impl PressCounts {
+    fn total(&self) -> u32 {
+        self.presses
+            .iter()
+            .flatten()
+            .flatten()
+            .fold(0u32, |total, &n| total.saturating_add(n))
+    }
+}
+
+/// Saved presses of one row of one layer
+#[derive(Clone, Copy)]
+struct SavedRow([u32; COL]);
+
+impl<'a> Value<'a> for SavedRow {
+    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+        let len = COL * 4;
+        if buffer.len() < len {
+            return Err(SerializationError::BufferTooSmall);
+        }
+        for (chunk, count) in buffer.as_chunks_mut::<4>().0.iter_mut().zip(self.0) {
+            *chunk = count.to_le_bytes();
+        }
+        Ok(len)
+    }
+
+    fn deserialize_from(buffer: &'a [u8]) -> Result<Self, SerializationError> {
+        let mut counts = [0; COL];
+        for (count, chunk) in counts.iter_mut().zip(buffer.as_chunks::<4>().0) {
+            *count = u32::from_le_bytes(*chunk);
+        }
+        Ok(Self(counts))
+    }
+}
+
+/// Record of `row` on `layer`
+fn row_key(layer: usize, row: usize) -> u16 {
+    record_key(RecordKind::TypingStats, (layer * ROW + row) as u8)
+}
+
+/// Value ids of the typing statistics Vial channel
+const VALUE_SUMMARY: u8 = 0x01;
+const VALUE_ROW: u8 = 0x02;
+const VALUE_RESET: u8 = 0x03;
+
+/// Handle a custom command on the typing statistics channel
+///
+/// - `get 0x01`: `layers u8 | rows u8 | cols u8 | wpm u16 | peak wpm u16 | total presses u32`
+/// - `get 0x02 <layer> <row>`: `layer u8 | row u8 | presses u32 per column`
+/// - `set 0x03`: reset all counts, saved with the next save
+pub(crate) fn handle_custom_command(command: CustomCommand, value_id: u8, data: &mut [u8]) -> bool {
+    COUNTS.lock(|c| {
+        let mut c = c.borrow_mut();
+        match (command, value_id) {
+            (CustomCommand::Get, VALUE_SUMMARY) => {
+                data[0] = NUM_LAYER as u8;
+                data[1] = ROW as u8;
+                data[2] = COL as u8;
+                data[3..5].copy_from_slice(&c.wpm.to_le_bytes());
+                data[5..7].copy_from_slice(&c.peak_wpm.to_le_bytes());
+                data[7..11].copy_from_slice(&c.total().to_le_bytes());
+                true
+            }
+            (CustomCommand::Get, VALUE_ROW) => {
+                let (layer, row) = (data[0] as usize, data[1] as usize);
+                let Some(presses) = c.presses.get(layer).and_then(|l| l.get(row)) else {
+                    return false;
+                };
+                for (chunk, count) in data[2..].as_chunks_mut::<4>().0.iter_mut().zip(presses) {
+                    *chunk = count.to_le_bytes();
+                }
+                true
+            }
+            (CustomCommand::Set, VALUE_RESET) => {
+                c.presses = [[[0; COL]; ROW]; NUM_LAYER];
+                c.dirty = [[true; ROW]; NUM_LAYER];
+                c.peak_wpm = c.wpm;
+                info!("Typing statistics reset");
+                true
+            }
+            _ => false,
+        }
+    })
+}
+
+/// Counts the presses and saves them
+pub(crate) struct TypingStats<'a> {
+    sub: ControllerSub,
+    storage: &'a SharedAppStorage,
+    layer: u8,
+    last_save: Instant,
+}
+
+impl<'a> TypingStats<'a> {
+    /// Load the saved counts
+    pub(crate) async fn new(storage: &'a SharedAppStorage) -> Self {
+        let mut saved = [[[0; COL]; ROW]; NUM_LAYER];
+        {
+            let mut storage = storage.lock().await;
+            for (layer, rows) in saved.iter_mut().enumerate() {
+                for (row, presses) in rows.iter_mut().enumerate() {
+                    if let Some(SavedRow(counts)) =
+                        storage.fetch::<SavedRow>(row_key(layer, row)).await
+                    {
+                        *presses = counts;
+                    }
+                }
+            }
+        }
+        COUNTS.lock(|c| c.borrow_mut().presses = saved);
+        Self {
+            sub: unwrap!(CONTROLLER_CHANNEL.subscriber()),
+            storage,
+            layer: 0,
+            last_save: Instant::now(),
+        }
+    }
+
+    fn count(&mut self, row: u8, col: u8) {
+        let (layer, row, col) = (self.layer as usize, row as usize, col as usize);
+        COUNTS.lock(|c| {
+            let mut c = c.borrow_mut();
+            let Some(presses) = c
+                .presses
+                .get_mut(layer)
+                .and_then(|l| l.get_mut(row))
+                .and_then(|r| r.get_mut(col))
+            else {
+                return;
+            };
+            *presses = presses.saturating_add(1);
+            c.dirty[layer][row] = true;
+        });
+    }
+
+    /// Save the rows changed since the last save
+    async fn save(&mut self) {
+        let mut storage = self.storage.lock().await;
+        for layer in 0..NUM_LAYER {
+            for row in 0..ROW {
+                let Some(presses) = COUNTS.lock(|c| {
+                    let mut c = c.borrow_mut();
+                    core::mem::take(&mut c.dirty[layer][row]).then_some(c.presses[layer][row])
+                }) else {
+                    continue;
+                };
+                if !storage.store(row_key(layer, row), &SavedRow(presses)).await {
+                    // Retried with the next save
+                    COUNTS.lock(|c| c.borrow_mut().dirty[layer][row] = true);
+                }
+            }
+        }
+    }
+}
+
+impl Controller for TypingStats<'_> {
+    type Event = ControllerEvent;
+
+    async fn process_event(&mut self, event: Self::Event) {
+        match event {
+            ControllerEvent::Key(key_event, _) if key_event.pressed => {
+                if let KeyboardEventPos::Key(pos) = key_event.pos {
+                    self.count(pos.row, pos.col);
+                }
+            }
+            ControllerEvent::Layer(layer) => self.layer = layer,
+            ControllerEvent::Wpm(wpm) => COUNTS.lock(|c| {
+                let mut c = c.borrow_mut();
+                c.wpm = wpm;
+                c.peak_wpm = c.peak_wpm.max(wpm);
+            }),
+            _ => {}
+        }
+    }
+
+    async fn next_message(&mut self) -> Self::Event {
+        self.sub.next_message_pure().await
+    }
+}
+
+impl PollingController for TypingStats<'_> {
+    const INTERVAL: Duration = Duration::from_secs(60);
+
+    async fn update(&mut self) {
+        if self.last_save.elapsed() >= SAVE_INTERVAL {
+            self.last_save = Instant::now();
+            self.save().await;
+        }
+    }
+}
@@ -0,0 +1,228 @@
+//! WS2812 underglow of both halves
+//!
+//! The chain in `[underglow]` of the keyboard TOML is driven by PWM0: each
+//! bit is one 1.25 µs PWM period whose duty encodes it, streamed by the PWM's
+//! sequence DMA. `build.rs` generates the settings and the
+//! [`underglow_pins!`] macro handing out the peripherals.
+//!
+//! [`underglow_render`](crate::underglow_render) draws the effect into a
+//! frame, [`UnderglowController`] sends the frames and releases the LED
+//! supply in [`ext_power`](crate::ext_power) while they are dark. `UgToggle`
+//! turns the LEDs on and off, `UgNext` switches to the next effect.
+//!
+//! The keys and the layer only reach the central. The peripheral follows the
+//! on/off state, the effect and the layer the central forwards through
+//! [`status_link`](crate::status_link), its battery gauge shows its own
+//! battery.
+
+use defmt::{info, unwrap, warn};
+use embassy_nrf::Peri;
+use embassy_nrf::gpio::AnyPin;
+use embassy_nrf::peripherals::PWM0;
+use embassy_nrf::pwm::{
+    Config, Prescaler, SequenceConfig, SequenceLoad, SequencePwm, SingleSequenceMode,
+    SingleSequencer,
+};
+use embassy_time::{Duration, Instant, Timer};
+use rmk::channel::{CONTROLLER_CHANNEL, ControllerSub};
+use rmk::controller::{Controller, PollingController};
+use rmk::event::ControllerEvent;
+
+use crate::ext_power::{self, User};
+use crate::split_status::UnderglowState;
+use crate::status_link;
+use crate::underglow_render::{
+    BIT_TICKS, DEFAULT_EFFECT, Effect, EffectInputs, FRAME_WORDS, NUM_LEDS, RES, Rgb, encode,
+    render,
+};
+use crate::user_keys::UserKey;
+
+include!(concat!(env!("OUT_DIR"), "/underglow_generated.rs"));
+
+/// Low periods after a frame for the 50 µs latch, minus the trailing one
+const LATCH_TICKS: u32 = 40 * BIT_TICKS as u32 - 1;
+
+/// Time to send a frame including the latch
+const FRAME_TIME: Duration =
+    Duration::from_micros(FRAME_WORDS as u64 * 5 / 4 + LATCH_TICKS as u64 / 16 + 1);
+
+/// Time from powering the LEDs until they accept data
+const POWER_UP_TIME: Duration = Duration::from_millis(1);
+
+/// Peripherals of the WS2812 chain, from [`underglow_pins!`]
+pub(crate) struct UnderglowPins {
+    pub(crate) pwm: Peri<'static, PWM0>,
+    pub(crate) data: Peri<'static, AnyPin>,
+    pub(crate) power: Option<Peri<'static, AnyPin>>,
+}
+
+/// A WS2812 chain on PWM0
+struct Ws2812 {
+    pwm: SequencePwm<'static>,
+    words: [u16; FRAME_WORDS],
+}
+
+impl Ws2812 {
+    fn new(pwm: Peri<'static, PWM0>, data: Peri<'static, AnyPin>) -> Self {
+        let mut config = Config::default();
+        config.sequence_load = SequenceLoad::Common;
+        config.prescaler = Prescaler::Div1;
+        config.max_duty = BIT_TICKS;
+        Self {
+            pwm: unwrap!(SequencePwm::new_1ch(pwm, data, config)),
+            words: [RES; FRAME_WORDS],
+        }
+    }
+
+    async fn write(&mut self, frame: &[Rgb; NUM_LEDS]) {
+        encode(frame, &mut self.words);
+        let mut seq_config = SequenceConfig::default();
+        seq_config.end_delay = LATCH_TICKS;
+        let sequencer = SingleSequencer::new(&mut self.pwm, &self.words, seq_config);
+        if sequencer.start(SingleSequenceMode::Times(1)).is_err() {
+            warn!("Failed to start the WS2812 sequence");
+            return;
+        }
+        // Dropping the sequencer stops the PWM
+        Timer::after(FRAME_TIME).await;
+    }
+}
+
+/// Renders the effect and drives the LEDs
+pub(crate) struct UnderglowController {
+    sub: ControllerSub,
+    leds: Option<Ws2812>,
+    enabled: bool,
+    effect: Effect,
+    inputs: EffectInputs,
+    started: Instant,
+    frame: [Rgb; NUM_LEDS],
+    /// Frame on the LEDs, `None` while they don't claim the supply
+    shown: Option<[Rgb; NUM_LEDS]>,
+}
+
+impl UnderglowController {
+    /// `pins` come from [`underglow_pins!`]; without them the controller does nothing
+    pub(crate) fn new(pins: Option<UnderglowPins>) -> Self {
+        let leds = pins.map(|pins| {
+            if let Some(power) = pins.power {
+                ext_power::init(power, POWER_ACTIVE_LOW);
+            }
+            Ws2812::new(pins.pwm, pins.data)
+        });
+        Self {
+            sub: unwrap!(CONTROLLER_CHANNEL.subscriber()),
+            leds,
+            enabled: true,
+            effect: DEFAULT_EFFECT,
+            inputs: EffectInputs::default(),
+            started: Instant::now(),
+            frame: [Rgb::BLACK; NUM_LEDS],
+            shown: None,
+        }
+    }
+
+    /// Forward the on/off state and the effect to the peripheral
+    fn forward(&self) {
+        let state = UnderglowState {
+            enabled: self.enabled,
+            effect: self.effect as u8,
+        };
+        status_link::update_local(|status| status.underglow = Some(state));
+    }
+
+    /// Follow what the central forwarded, only the peripheral hears of it
+    fn follow_central(&mut self) {
+        let central = status_link::remote();
+        if let Some(layer) = central.layer {
+            self.inputs.layer = layer;
+        }
+        if let Some(state) = central.underglow {
+            self.enabled = state.enabled;
+            if let Some(effect) = Effect::from_index(state.effect)
+                && effect != self.effect
+            {
+                self.effect = effect;
+                self.started = Instant::now();
+            }
+        }
+    }
+
+    /// Render the effect and send the frame if it changed
+    async fn refresh(&mut self) {
+        let Some(leds) = self.leds.as_mut() else {
+            return;
+        };
+        if self.enabled {
+            let elapsed = self.started.elapsed().as_millis();
+            render(self.effect, self.inputs, elapsed, &mut self.frame);
+        } else {
+            self.frame.fill(Rgb::BLACK);
+        }
+
+        let dark = self.frame.iter().all(|&c| c == Rgb::BLACK);
+        match self.shown {
+            Some(shown) if shown == self.frame => {}
+            // Nothing to show on unpowered LEDs
+            None if dark => {}
+            None => {
+                if ext_power::claim(User::Underglow) {
+                    Timer::after(POWER_UP_TIME).await;
+                }
+                leds.write(&self.frame).await;
+                self.shown = Some(self.frame);
+            }
+            Some(_) => {
+                leds.write(&self.frame).await;
+                self.shown = Some(self.frame);
+            }
+        }
+        // Dark LEDs still draw about 1 mA each, cut their supply
+        if dark && self.shown.is_some() {
+            ext_power::release(User::Underglow);
+            self.shown = None;
+        }
+    }
+}
+
+impl Controller for UnderglowController {
+    type Event = ControllerEvent;
+
+    async fn process_event(&mut self, event: Self::Event) {
+        match event {
+            ControllerEvent::Key(key_event, action) if key_event.pressed => {
+                match UserKey::from_action(&action) {
+                    Some(UserKey::UnderglowToggle) => {
+                        self.enabled = !self.enabled;
+                        info!("Underglow {}", if self.enabled { "on" } else { "off" });
+                    }
+                    Some(UserKey::UnderglowNext) => {
+                        self.effect = self.effect.next();
+                        self.started = Instant::now();
+                        info!("Underglow effect {}", self.effect);
+                    }
+                    _ => return,
+                }
+                self.forward();
+            }
+            ControllerEvent::Layer(layer) => self.inputs.layer = layer,
+            ControllerEvent::Battery(level) => self.inputs.battery = Some(level),
+            _ => return,
+        }
+        self.refresh().await;
+    }
+
+    async fn next_message(&mut self) -> Self::Event {
+        self.sub.next_message_pure().await
+    }
+}
+
+impl PollingController for UnderglowController {
+    const INTERVAL: Duration = Duration::from_millis(33);
+
+    async fn update(&mut self) {
+        self.follow_central();
+        // Frames are only sent when they change
+        self.refresh().await;
+    }
+}
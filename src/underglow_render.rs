@@ -0,0 +1,140 @@
+//! Rendering of the underglow, see [`underglow`](crate::underglow)
+//!
+//! [`render`] draws an [`Effect`] into a frame of colors and [`encode`] turns
+//! the frame into the PWM duty words the WS2812 chain is streamed from.
+//! `build.rs` generates the effect settings from `[underglow]`.
+
+use defmt::Format;
+
+include!(concat!(env!("OUT_DIR"), "/underglow_render_generated.rs"));
+
+/// PWM ticks of one bit at 16 MHz, 1.25 µs
+pub(crate) const BIT_TICKS: u16 = 20;
+/// Duty of a 1 bit, 0.8 µs high. The top bit makes the output start high.
+const T1H: u16 = 0x8000 | 13;
+/// Duty of a 0 bit, 0.4 µs high
+const T0H: u16 = 0x8000 | 7;
+/// Low for a whole period, ends the frame
+pub(crate) const RES: u16 = 0x8000;
+/// Words of a frame, one per bit and a trailing low period
+pub(crate) const FRAME_WORDS: usize = NUM_LEDS * 24 + 1;
+
+/// An RGB color
+#[derive(Clone, Copy, Default, PartialEq, Eq, Format, Debug)]
+pub(crate) struct Rgb {
+    pub(crate) r: u8,
+    pub(crate) g: u8,
+    pub(crate) b: u8,
+}
+
+impl Rgb {
+    pub(crate) const BLACK: Rgb = Rgb::new(0, 0, 0);
+
+    pub(crate) const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    /// The color at `level` out of 255
+    pub(crate) const fn scale(self, level: u8) -> Self {
+        const fn s(c: u8, level: u8) -> u8 {
+            ((c as u16 * level as u16 + 127) / 255) as u8
+        }
+        Self::new(s(self.r, level), s(self.g, level), s(self.b, level))
+    }
+}
+
+/// What the LEDs show
+#[derive(Clone, Copy, PartialEq, Eq, Format, Debug)]
+pub(crate) enum Effect {
+    /// [`COLOR`] on every LED
+    Static,
+    /// [`COLOR`] fading in and out over [`BREATHING_PERIOD_MS`]
+    Breathing,
+    /// The color of the active layer from [`LAYER_COLORS`]
+    Layer,
+    /// A gauge of the battery level, from red to green
+    Battery,
+}
+
+impl Effect {
+    const ALL: [Effect; 4] = [
+        Effect::Static,
+        Effect::Breathing,
+        Effect::Layer,
+        Effect::Battery,
+    ];
+
+    pub(crate) fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+
+    /// The effect numbered `index` in declaration order
+    pub(crate) fn from_index(index: u8) -> Option<Self> {
+        Self::ALL.get(index as usize).copied()
+    }
+}
+
+/// What effects are drawn from
+#[derive(Clone, Copy, Default)]
+pub(crate) struct EffectInputs {
+    pub(crate) layer: u8,
+    /// Battery level in percent, unknown until the first measurement
+    pub(crate) battery: Option<u8>,
+}
+
+/// Level of the breathing effect `elapsed_ms` into it, out of 255
+///
+/// A triangle wave squared, so the fade looks even to the eye.
+pub(crate) fn breath_level(elapsed_ms: u64) -> u8 {
+    let period = BREATHING_PERIOD_MS as u64;
+    let half = period / 2;
+    let t = elapsed_ms % period;
+    let linear = (if t < half { t } else { period - t }) * 255 / half;
+    (linear * linear / 255) as u8
+}
+
+/// Draw `effect` into `frame` at [`BRIGHTNESS`], `elapsed_ms` after it started
+pub(crate) fn render(effect: Effect, inputs: EffectInputs, elapsed_ms: u64, frame: &mut [Rgb]) {
+    match effect {
+        Effect::Static => frame.fill(COLOR.scale(BRIGHTNESS)),
+        Effect::Breathing => {
+            let level = (breath_level(elapsed_ms) as u16 * BRIGHTNESS as u16 / 255) as u8;
+            frame.fill(COLOR.scale(level));
+        }
+        Effect::Layer => {
+            let color = LAYER_COLORS
+                .get(inputs.layer as usize)
+                .copied()
+                .unwrap_or(COLOR);
+            frame.fill(color.scale(BRIGHTNESS));
+        }
+        Effect::Battery => {
+            frame.fill(Rgb::BLACK);
+            let Some(level) = inputs.battery else {
+                return;
+            };
+            let level = level.min(100) as usize;
+            let color = Rgb::new(
+                (255 * (100 - level) / 100) as u8,
+                (255 * level / 100) as u8,
+                0,
+            );
+            // At least one LED while there is charge left
+            let lit = (frame.len() * level).div_ceil(100);
+            frame[..lit].fill(color.scale(BRIGHTNESS));
+        }
+    }
+}
+
+/// Encode `frame` into PWM duty words, GRB and most significant bit first
+pub(crate) fn encode(frame: &[Rgb; NUM_LEDS], words: &mut [u16; FRAME_WORDS]) {
+    let bits = frame.iter().flat_map(|c| [c.g, c.r, c.b]).flat_map(|byte| {
+        (0..8)
+            .rev()
+            .map(move |bit| if (byte >> bit) & 1 == 1 { T1H } else { T0H })
+    });
+    for (word, bit) in words.iter_mut().zip(bits) {
+        *word = bit;
+    }
+    words[FRAME_WORDS - 1] = RES;
+}
@@ -0,0 +1,170 @@
+//! Unicode input of the central
+//!
+//! Characters outside ASCII are typed with an input method of the host, as
+//! their code point in hex:
+//!
+//! - `macos`: "Unicode Hex Input" source, Option held over the four hex digits
+//!   of each UTF-16 unit
+//! - `linux`: IBus and GTK, `Ctrl+Shift+U`, the hex digits and `Space`
+//! - `wincompose`: WinCompose with its default `RAlt` compose key, `U`, the
+//!   hex digits and `Enter`
+//! - `alt_codes`: Windows with `EnableHexNumpad` set in the registry, `Alt`
+//!   held over `KpPlus` and the hex digits, only the Basic Multilingual Plane
+//!
+//! `mode` of `[unicode]` in the keyboard TOML is the host's method, and
+//! `windows_mode` the one while the Windows overlay `windows_layer` is on. RMK
+//! only reports the highest active layer, so the overlay counts as on from a
+//! report of its layer until one of layer 0; a momentary layer above it leaves
+//! the mode as it was. ASCII is typed with plain keys in every mode.
+//!
+//! `[[unicode.text]]` binds free `User` keycodes to texts typed this way, the
+//! `text` of leader sequences is typed the same.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use defmt::{Format, info, unwrap, warn};
+use rmk::channel::{CONTROLLER_CHANNEL, ControllerSub};
+use rmk::controller::Controller;
+use rmk::event::ControllerEvent;
+use rmk::types::action::{Action, KeyAction};
+use rmk::types::keycode::KeyCode;
+
+use crate::host_keys::{self, LALT, LCTRL, LSHIFT, RALT};
+
+/// Input method of the host
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+pub(crate) enum UnicodeMode {
+    MacOs,
+    Linux,
+    WinCompose,
+    AltCodes,
+}
+
+include!(concat!(env!("OUT_DIR"), "/unicode_generated.rs"));
+
+/// Whether the Windows overlay is on
+static WINDOWS: AtomicBool = AtomicBool::new(false);
+
+/// The input method in use
+pub(crate) fn mode() -> UnicodeMode {
+    if WINDOWS.load(Ordering::Relaxed) {
+        WINDOWS_MODE
+    } else {
+        DEFAULT_MODE
+    }
+}
+
+/// Hex digits of `value`, most significant first and at least four
+fn hex_digits(value: u32) -> impl Iterator<Item = u8> {
+    let len = (32 - value.leading_zeros()).div_ceil(4).max(4);
+    (0..len)
+        .rev()
+        .map(move |i| ((value >> (i * 4)) & 0xf) as u8)
+}
+
+/// The key typing a hex digit, from the keypad for Alt codes
+fn hex_key(digit: u8, keypad: bool) -> KeyCode {
+    use KeyCode::*;
+    const DIGITS: [KeyCode; 10] = [Kc0, Kc1, Kc2, Kc3, Kc4, Kc5, Kc6, Kc7, Kc8, Kc9];
+    const KEYPAD: [KeyCode; 10] = [Kp0, Kp1, Kp2, Kp3, Kp4, Kp5, Kp6, Kp7, Kp8, Kp9];
+    const LETTERS: [KeyCode; 6] = [A, B, C, D, E, F];
+    match digit {
+        0..=9 if keypad => KEYPAD[digit as usize],
+        0..=9 => DIGITS[digit as usize],
+        _ => LETTERS[digit as usize - 10],
+    }
+}
+
+fn hex_keys(value: u32) -> impl Iterator<Item = KeyCode> {
+    hex_digits(value).map(|digit| hex_key(digit, false))
+}
+
+/// Type `c` with the input method `mode`
+async fn type_char(c: char, mode: UnicodeMode) {
+    if let Some((key, shifted)) = host_keys::ascii_key(c) {
+        host_keys::tap_with(key, if shifted { LSHIFT } else { 0 }).await;
+        return;
+    }
+    let code_point = c as u32;
+    match mode {
+        UnicodeMode::MacOs => {
+            let mut units = [0; 2];
+            for unit in c.encode_utf16(&mut units) {
+                host_keys::tap_all_with(hex_keys(*unit as u32), LALT).await;
+            }
+        }
+        UnicodeMode::Linux => {
+            host_keys::tap_with(KeyCode::U, LCTRL | LSHIFT).await;
+            host_keys::tap_all_with(hex_keys(code_point), 0).await;
+            host_keys::tap(KeyCode::Space).await;
+        }
+        UnicodeMode::WinCompose => {
+            // The compose key alone, as a modifier
+            host_keys::tap_with(KeyCode::No, RALT).await;
+            host_keys::tap(KeyCode::U).await;
+            host_keys::tap_all_with(hex_keys(code_point), 0).await;
+            host_keys::tap(KeyCode::Enter).await;
+        }
+        UnicodeMode::AltCodes if code_point > 0xffff => {
+            warn!("U+{:x} is outside the range of Alt codes", code_point);
+        }
+        UnicodeMode::AltCodes => {
+            let digits = hex_digits(code_point).map(|digit| hex_key(digit, true));
+            host_keys::tap_all_with(core::iter::once(KeyCode::KpPlus).chain(digits), LALT).await;
+        }
+    }
+}
+
+/// Type `text` on the host, characters outside ASCII with the input method in use
+pub(crate) async fn type_text(text: &str) {
+    let mode = mode();
+    for c in text.chars() {
+        type_char(c, mode).await;
+    }
+}
+
+/// Follows the Windows overlay and types the texts of `[[unicode.text]]`
+pub(crate) struct UnicodeController {
+    sub: ControllerSub,
+}
+
+impl UnicodeController {
+    pub(crate) fn new() -> Self {
+        Self {
+            sub: unwrap!(CONTROLLER_CHANNEL.subscriber()),
+        }
+    }
+}
+
+impl Controller for UnicodeController {
+    type Event = ControllerEvent;
+
+    async fn process_event(&mut self, event: Self::Event) {
+        match event {
+            ControllerEvent::Layer(layer) => {
+                let windows = if layer == 0 {
+                    false
+                } else if Some(layer) == WINDOWS_LAYER {
+                    true
+                } else {
+                    return;
+                };
+                if WINDOWS.swap(windows, Ordering::Relaxed) != windows {
+                    info!("Unicode input: {}", mode());
+                }
+            }
+            ControllerEvent::Key(key_event, KeyAction::Single(Action::Key(code)))
+                if key_event.pressed =>
+            {
+                if let Some((_, text)) = UNICODE_TEXTS.iter().find(|(key, _)| *key == code) {
+                    type_text(text).await;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    async fn next_message(&mut self) -> Self::Event {
+        self.sub.next_message_pure().await
+    }
+}
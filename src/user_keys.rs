@@ -0,0 +1,78 @@
+//! Custom actions bound to RMK's `User` keycodes
+//!
+//! RMK handles `User0`..`User11` itself (BLE profiles and output switching),
+//! the remaining `User` keycodes are free for the firmware's own actions. Each
+//! one gets an alias in the keyboard TOML, e.g. `TxPower = "User12"`, and the
+//! controller implementing the action matches on [`UserKey`]. Those left over
+//! can type texts, see [`unicode`](crate::unicode).
+
+use defmt::Format;
+use rmk::types::action::{Action, KeyAction};
+use rmk::types::keycode::KeyCode;
+
+/// Firmware actions behind the free `User` keycodes
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+pub(crate) enum UserKey {
+    /// Cycle the radio TX power, `TxPower` in the TOML
+    ///
+    /// See [`TX_POWER_LEVELS`](crate::tx_power_controller::TX_POWER_LEVELS)
+    CycleTxPower,
+    /// Forget the bond of the active BLE profile, `BtForget` in the TOML
+    ///
+    /// See [`bonds`](crate::bonds)
+    ForgetBond,
+    /// Forget the peripheral's address and pair the halves again, `PairHalves` in the TOML
+    ///
+    /// See [`pairing`](crate::pairing)
+    PairHalves,
+    /// Turn the WS2812 LEDs on or off, `UgToggle` in the TOML
+    ///
+    /// See [`underglow`](crate::underglow)
+    UnderglowToggle,
+    /// Switch to the next underglow effect, `UgNext` in the TOML
+    UnderglowNext,
+    /// Shift the next word, `CapsWord` in the TOML
+    ///
+    /// See [`caps_word`](crate::caps_word)
+    CapsWord,
+    /// Start a leader sequence, `Leader` in the TOML
+    ///
+    /// See [`leader`](crate::leader)
+    Leader,
+    /// Send the last key again, `Repeat` in the TOML
+    ///
+    /// See [`repeat`](crate::repeat)
+    Repeat,
+    /// Send the alternate of the last key, `AltRepeat` in the TOML
+    AltRepeat,
+    /// Keep the held layer on until pressed again, `LayerLock` in the TOML
+    ///
+    /// See [`layer_lock`](crate::layer_lock)
+    LayerLock,
+}
+
+impl UserKey {
+    pub(crate) fn from_keycode(keycode: KeyCode) -> Option<Self> {
+        match keycode {
+            KeyCode::User12 => Some(UserKey::CycleTxPower),
+            KeyCode::User13 => Some(UserKey::ForgetBond),
+            KeyCode::User14 => Some(UserKey::PairHalves),
+            KeyCode::User15 => Some(UserKey::UnderglowToggle),
+            KeyCode::User16 => Some(UserKey::UnderglowNext),
+            KeyCode::User17 => Some(UserKey::CapsWord),
+            KeyCode::User18 => Some(UserKey::Leader),
+            KeyCode::User21 => Some(UserKey::Repeat),
+            KeyCode::User22 => Some(UserKey::AltRepeat),
+            KeyCode::User23 => Some(UserKey::LayerLock),
+            _ => None,
+        }
+    }
+
+    /// The action of a key, if it is a plain tap of one of the custom `User` keycodes
+    pub(crate) fn from_action(action: &KeyAction) -> Option<Self> {
+        match action {
+            KeyAction::Single(Action::Key(keycode)) => Self::from_keycode(*keycode),
+            _ => None,
+        }
+    }
+}
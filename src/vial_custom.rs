@@ -0,0 +1,99 @@
+//! Vial custom-channel commands
+//!
+//! VIA reports are 32 bytes. The custom commands use the standard
+//! `id_custom_set_value` / `id_custom_get_value` / `id_custom_save` layout:
+//!
+//! ```text
+//! | command id | channel id | value id | data ... |
+//! ```
+//!
+//! Every feature that exposes data to the host owns one channel in
+//! [`CustomChannel`]. RMK 0.8's Vial service does not dispatch custom-channel
+//! reports to user code, so they go to an interface of the firmware's own,
+//! [`custom_hid`](crate::custom_hid), which calls [`handle_custom_report`].
+
+use defmt::Format;
+
+/// Size of a VIA report
+pub(crate) const VIA_REPORT_LEN: usize = 32;
+
+/// Offset of the first data byte in a custom command report
+pub(crate) const DATA_OFFSET: usize = 3;
+
+/// `id_custom_set_value`
+const CUSTOM_SET_VALUE: u8 = 0x07;
+/// `id_custom_get_value`
+const CUSTOM_GET_VALUE: u8 = 0x08;
+/// `id_custom_save`
+const CUSTOM_SAVE: u8 = 0x09;
+/// `id_unhandled`, written to byte 0 when a command is not understood
+pub(crate) const UNHANDLED: u8 = 0xFF;
+
+/// Custom channels, one per feature. VIA reserves 0..=4 for lighting and audio.
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+#[repr(u8)]
+pub(crate) enum CustomChannel {
+    /// Split link quality, see [`split_telemetry`](crate::split_telemetry)
+    SplitLink = 0x10,
+    /// Bond names and forgetting single bonds, see [`bonds`](crate::bonds)
+    Bonds = 0x11,
+    /// Storage backup and restore, see [`backup`](crate::backup)
+    Backup = 0x12,
+    /// Erase counts of the storage sectors, see [`flash_wear`](crate::flash_wear)
+    Wear = 0x13,
+    /// Key press counts and WPM, see [`typing_stats`](crate::typing_stats)
+    TypingStats = 0x14,
+}
+
+impl CustomChannel {
+    fn from_u8(id: u8) -> Option<Self> {
+        match id {
+            0x10 => Some(CustomChannel::SplitLink),
+            0x11 => Some(CustomChannel::Bonds),
+            0x12 => Some(CustomChannel::Backup),
+            0x13 => Some(CustomChannel::Wear),
+            0x14 => Some(CustomChannel::TypingStats),
+            _ => None,
+        }
+    }
+}
+
+/// Kind of custom command
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+pub(crate) enum CustomCommand {
+    Get,
+    Set,
+    Save,
+}
+
+/// Handle a custom-channel VIA report in place.
+///
+/// `dispatch` hands the command, value id and data of the report to the
+/// channel's handler, see [`custom_hid`](crate::custom_hid). Returns `false`
+/// and marks the report as unhandled when the command or channel is unknown,
+/// or when the channel rejects the value id.
+pub(crate) fn handle_custom_report(
+    report: &mut [u8; VIA_REPORT_LEN],
+    dispatch: impl FnOnce(CustomChannel, CustomCommand, u8, &mut [u8]) -> bool,
+) -> bool {
+    let command = match report[0] {
+        CUSTOM_GET_VALUE => CustomCommand::Get,
+        CUSTOM_SET_VALUE => CustomCommand::Set,
+        CUSTOM_SAVE => CustomCommand::Save,
+        _ => return unhandled(report),
+    };
+    let Some(channel) = CustomChannel::from_u8(report[1]) else {
+        return unhandled(report);
+    };
+    let value_id = report[2];
+    let data = &mut report[DATA_OFFSET..];
+    if !dispatch(channel, command, value_id, data) {
+        return unhandled(report);
+    }
+    true
+}
+
+fn unhandled(report: &mut [u8; VIA_REPORT_LEN]) -> bool {
+    report[0] = UNHANDLED;
+    false
+}
@@ -0,0 +1,56 @@
+//! The keyboard over HID
+
+use std::time::Duration;
+
+use crate::protocol::REPORT_LEN;
+use crate::transfer::Device;
+
+/// Vendor id of the keyboard, `vendor_id` in the keyboard TOML
+const VENDOR_ID: u16 = 0x4653;
+/// Product id of the keyboard, `product_id` in the keyboard TOML
+const PRODUCT_ID: u16 = 0x0001;
+/// Usage page and usage of the firmware's custom-channel interface on USB,
+/// next to the Vial one (`src/custom_hid_desc.rs`)
+const RAW_USAGE_PAGE: u16 = 0xFF62;
+const RAW_USAGE: u16 = 0x01;
+
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The keyboard's custom-channel interface on USB
+pub struct HidDevice {
+    device: hidapi::HidDevice,
+}
+
+impl HidDevice {
+    pub fn open() -> Result<Self, String> {
+        let api = hidapi::HidApi::new().map_err(|e| e.to_string())?;
+        let info = api
+            .device_list()
+            .find(|d| {
+                d.vendor_id() == VENDOR_ID
+                    && d.product_id() == PRODUCT_ID
+                    && d.usage_page() == RAW_USAGE_PAGE
+                    && d.usage() == RAW_USAGE
+            })
+            .ok_or("keyboard not found, is it connected over USB?")?;
+        let device = info.open_device(&api).map_err(|e| e.to_string())?;
+        Ok(Self { device })
+    }
+}
+
+impl Device for HidDevice {
+    fn exchange(&mut self, report: &mut [u8; REPORT_LEN]) -> Result<(), String> {
+        // Report id 0 in front
+        let mut out = [0; REPORT_LEN + 1];
+        out[1..].copy_from_slice(report);
+        self.device.write(&out).map_err(|e| e.to_string())?;
+        let read = self
+            .device
+            .read_timeout(report, READ_TIMEOUT.as_millis() as i32)
+            .map_err(|e| e.to_string())?;
+        if read != REPORT_LEN {
+            return Err("no answer from the keyboard".to_string());
+        }
+        Ok(())
+    }
+}
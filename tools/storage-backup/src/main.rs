@@ -0,0 +1,79 @@
+//! Back up and restore the keyboard's storage over Vial
+//!
+//! ```text
+//! storage-backup info
+//! storage-backup backup <file>
+//! storage-backup restore <file>
+//! ```
+//!
+//! A backup holds the keymap, morse profiles, bonds, peripheral addresses
+//! and the firmware's own records of the central. The keyboard has to be
+//! connected over USB, the firmware answers the backup channel on its Vial
+//! interface only.
+
+mod device;
+mod protocol;
+#[cfg(test)]
+mod tests;
+mod transfer;
+
+use std::process::ExitCode;
+
+use device::HidDevice;
+use transfer::{backup, read_header, restore};
+
+fn usage() -> ExitCode {
+    eprintln!("usage: storage-backup info | backup <file> | restore <file>");
+    ExitCode::FAILURE
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let known = match args {
+        [cmd] => cmd == "info",
+        [cmd, _] => cmd == "backup" || cmd == "restore",
+        _ => false,
+    };
+    if !known {
+        return Err(String::new());
+    }
+    let device = &mut HidDevice::open()?;
+
+    match args {
+        [cmd] if cmd == "info" => {
+            let header = read_header(device)?;
+            println!(
+                "storage at 0x{:x}: {} bytes ({} RMK + {} firmware sectors), schema {}, crc32 {:08x}",
+                header.start,
+                header.len,
+                header.rmk_sectors,
+                header.app_sectors,
+                header.schema,
+                header.crc
+            );
+        }
+        [cmd, file] if cmd == "backup" => {
+            let backup = backup(device)?;
+            std::fs::write(file, &backup).map_err(|e| format!("{file}: {e}"))?;
+            println!("saved {} bytes to {file}", backup.len());
+        }
+        [cmd, file] if cmd == "restore" => {
+            let backup = std::fs::read(file).map_err(|e| format!("{file}: {e}"))?;
+            restore(device, &backup)?;
+            println!("restored {file}, the keyboard reboots");
+        }
+        _ => return Err(String::new()),
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) if e.is_empty() => usage(),
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,120 @@
+//! The backup channel of the firmware, see `src/backup.rs` and `src/vial_custom.rs`
+
+/// Size of a VIA report
+pub const REPORT_LEN: usize = 32;
+
+/// Offset of the first data byte in a custom command report
+pub const DATA_OFFSET: usize = 3;
+
+/// `id_custom_set_value`
+pub const CUSTOM_SET_VALUE: u8 = 0x07;
+/// `id_custom_get_value`
+pub const CUSTOM_GET_VALUE: u8 = 0x08;
+/// `id_unhandled`, byte 0 of the answer when the firmware rejected a command
+pub const UNHANDLED: u8 = 0xFF;
+
+/// Custom channel of the backup commands
+pub const BACKUP_CHANNEL: u8 = 0x12;
+
+pub const VALUE_HEADER: u8 = 0x01;
+pub const VALUE_CHUNK: u8 = 0x02;
+pub const VALUE_BEGIN_RESTORE: u8 = 0x03;
+pub const VALUE_WRITE_CHUNK: u8 = 0x04;
+pub const VALUE_FINISH_RESTORE: u8 = 0x05;
+pub const VALUE_RESTORE_STATUS: u8 = 0x06;
+
+/// Image bytes per report
+pub const CHUNK_LEN: usize = 24;
+
+const MAGIC: [u8; 4] = *b"RMKB";
+const FORMAT_VERSION: u8 = 1;
+
+/// Header in front of the image in a backup file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub rmk_sectors: u8,
+    pub app_sectors: u8,
+    /// Flash address of the storage
+    pub start: u32,
+    pub len: u32,
+    /// Storage schema version
+    pub schema: u16,
+    /// CRC-32/ISO-HDLC of the image
+    pub crc: u32,
+}
+
+impl Header {
+    pub const LEN: usize = 22;
+
+    pub fn parse(buf: &[u8]) -> Result<Self, String> {
+        if buf.len() < Self::LEN || buf[0..4] != MAGIC {
+            return Err("not a storage backup".to_string());
+        }
+        if buf[4] != FORMAT_VERSION {
+            return Err(format!("unsupported backup format {}", buf[4]));
+        }
+        let u32_at = |i: usize| u32::from_le_bytes(buf[i..i + 4].try_into().unwrap());
+        Ok(Self {
+            rmk_sectors: buf[5],
+            app_sectors: buf[6],
+            start: u32_at(8),
+            len: u32_at(12),
+            schema: u16::from_le_bytes([buf[16], buf[17]]),
+            crc: u32_at(18),
+        })
+    }
+
+    pub fn to_bytes(self) -> [u8; Self::LEN] {
+        let mut buf = [0; Self::LEN];
+        buf[0..4].copy_from_slice(&MAGIC);
+        buf[4] = FORMAT_VERSION;
+        buf[5] = self.rmk_sectors;
+        buf[6] = self.app_sectors;
+        buf[8..12].copy_from_slice(&self.start.to_le_bytes());
+        buf[12..16].copy_from_slice(&self.len.to_le_bytes());
+        buf[16..18].copy_from_slice(&self.schema.to_le_bytes());
+        buf[18..22].copy_from_slice(&self.crc.to_le_bytes());
+        buf
+    }
+}
+
+/// Progress of a restore on the keyboard
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RestoreState {
+    Idle,
+    Erasing,
+    Receiving,
+    Verifying,
+    Done,
+    Failed,
+}
+
+impl RestoreState {
+    pub fn from_u8(state: u8) -> Option<Self> {
+        Some(match state {
+            0 => RestoreState::Idle,
+            1 => RestoreState::Erasing,
+            2 => RestoreState::Receiving,
+            3 => RestoreState::Verifying,
+            4 => RestoreState::Done,
+            5 => RestoreState::Failed,
+            _ => return None,
+        })
+    }
+}
+
+/// CRC-32/ISO-HDLC, as computed by the firmware
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
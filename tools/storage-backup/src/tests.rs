@@ -0,0 +1,22 @@
+//! Backups and restores against the firmware are tested in `host-tests`
+
+use crate::protocol::*;
+
+#[test]
+fn crc32_check_value() {
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+}
+
+#[test]
+fn header_round_trips() {
+    let header = Header {
+        rmk_sectors: 6,
+        app_sectors: 2,
+        start: 0xA0000,
+        len: 0x8000,
+        schema: 1,
+        crc: 0x1234_5678,
+    };
+    assert_eq!(Header::parse(&header.to_bytes()), Ok(header));
+    assert!(Header::parse(b"not a backup at all, really").is_err());
+}
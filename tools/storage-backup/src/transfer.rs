@@ -0,0 +1,140 @@
+//! Backups and restores over the backup channel of a [`Device`]
+//!
+//! Kept apart from the HID and command line code so the host tests of the
+//! firmware (`host-tests`) run them against its backup channel.
+
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use super::protocol::*;
+
+/// Something that answers VIA reports
+pub trait Device {
+    /// Send `report` and replace it with the answer
+    fn exchange(&mut self, report: &mut [u8; REPORT_LEN]) -> Result<(), String>;
+}
+
+/// How long the keyboard may take to erase the storage or verify the image
+const RESTORE_STEP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Pause before resending a chunk the keyboard had no room for
+const RETRY_DELAY: Duration = Duration::from_millis(5);
+
+/// Send a custom command on the backup channel, returns the data of the answer
+fn command(
+    device: &mut dyn Device,
+    command: u8,
+    value_id: u8,
+    data: &[u8],
+) -> Result<Option<[u8; REPORT_LEN - DATA_OFFSET]>, String> {
+    let mut report = [0; REPORT_LEN];
+    report[0] = command;
+    report[1] = BACKUP_CHANNEL;
+    report[2] = value_id;
+    report[DATA_OFFSET..DATA_OFFSET + data.len()].copy_from_slice(data);
+    device.exchange(&mut report)?;
+    if report[0] == UNHANDLED {
+        return Ok(None);
+    }
+    Ok(Some(report[DATA_OFFSET..].try_into().unwrap()))
+}
+
+pub fn read_header(device: &mut dyn Device) -> Result<Header, String> {
+    let data = command(device, CUSTOM_GET_VALUE, VALUE_HEADER, &[])?
+        .ok_or("the firmware does not support backups")?;
+    Header::parse(&data)
+}
+
+/// Read the storage, returns the backup file contents
+pub fn backup(device: &mut dyn Device) -> Result<Vec<u8>, String> {
+    let header = read_header(device)?;
+    let mut image = Vec::with_capacity(header.len as usize);
+    while image.len() < header.len as usize {
+        let offset = image.len() as u32;
+        let data = command(device, CUSTOM_GET_VALUE, VALUE_CHUNK, &offset.to_le_bytes())?
+            .ok_or_else(|| format!("failed to read the storage at {offset}"))?;
+        let len = CHUNK_LEN.min(header.len as usize - image.len());
+        image.extend_from_slice(&data[4..4 + len]);
+    }
+    if crc32(&image) != header.crc {
+        return Err("the storage changed while it was read, try again".to_string());
+    }
+    let mut file = header.to_bytes().to_vec();
+    file.extend_from_slice(&image);
+    Ok(file)
+}
+
+fn restore_status(device: &mut dyn Device) -> Result<(RestoreState, u32), String> {
+    let data = command(device, CUSTOM_GET_VALUE, VALUE_RESTORE_STATUS, &[])?
+        .ok_or("the firmware does not support restores")?;
+    let state = RestoreState::from_u8(data[0]).ok_or("unknown restore state")?;
+    Ok((state, u32::from_le_bytes(data[1..5].try_into().unwrap())))
+}
+
+/// Wait while the keyboard is in `busy`
+fn wait_while(device: &mut dyn Device, busy: RestoreState) -> Result<RestoreState, String> {
+    let start = Instant::now();
+    loop {
+        let (state, _) = restore_status(device)?;
+        if state != busy {
+            return Ok(state);
+        }
+        if start.elapsed() > RESTORE_STEP_TIMEOUT {
+            return Err(format!("the keyboard is stuck in {busy:?}"));
+        }
+        sleep(RETRY_DELAY);
+    }
+}
+
+/// Write a backup file to the storage, the keyboard reboots afterwards
+pub fn restore(device: &mut dyn Device, file: &[u8]) -> Result<(), String> {
+    let header = Header::parse(file)?;
+    let image = &file[Header::LEN..];
+    if image.len() != header.len as usize || crc32(image) != header.crc {
+        return Err("the backup is damaged".to_string());
+    }
+    let current = read_header(device)?;
+    if (current.rmk_sectors, current.app_sectors) != (header.rmk_sectors, header.app_sectors) {
+        return Err(format!(
+            "the backup has a different storage layout ({}+{} sectors, the keyboard has {}+{})",
+            header.rmk_sectors, header.app_sectors, current.rmk_sectors, current.app_sectors
+        ));
+    }
+
+    command(
+        device,
+        CUSTOM_SET_VALUE,
+        VALUE_BEGIN_RESTORE,
+        &header.to_bytes(),
+    )?
+    .ok_or("the keyboard rejected the backup, is its firmware older than the backup?")?;
+    if wait_while(device, RestoreState::Erasing)? != RestoreState::Receiving {
+        return Err("the keyboard failed to erase its storage".to_string());
+    }
+
+    for (i, chunk) in image.chunks(CHUNK_LEN).enumerate() {
+        let offset = (i * CHUNK_LEN) as u32;
+        let mut data = offset.to_le_bytes().to_vec();
+        data.push(chunk.len() as u8);
+        data.extend_from_slice(chunk);
+        // Rejected while the keyboard's write queue is full
+        while command(device, CUSTOM_SET_VALUE, VALUE_WRITE_CHUNK, &data)?.is_none() {
+            if restore_status(device)?.0 != RestoreState::Receiving {
+                return Err(format!("the keyboard failed to write at {offset}"));
+            }
+            sleep(RETRY_DELAY);
+        }
+    }
+
+    // The last chunks may still be queued
+    while command(device, CUSTOM_SET_VALUE, VALUE_FINISH_RESTORE, &[])?.is_none() {
+        if restore_status(device)?.0 != RestoreState::Receiving {
+            return Err("the keyboard failed to write the backup".to_string());
+        }
+        sleep(RETRY_DELAY);
+    }
+    match wait_while(device, RestoreState::Verifying)? {
+        RestoreState::Done => Ok(()),
+        _ => Err("the restored storage does not match the backup".to_string()),
+    }
+}
@@ -0,0 +1,130 @@
+//! Keyboards to talk to: the real one over HID, or a mock
+//!
+//! [`MockDevice`] answers the typing statistics commands like the firmware
+//! does, with made-up counts, so the heatmap can be tried without a keyboard.
+
+use std::time::Duration;
+
+use crate::protocol::*;
+use crate::transfer::Device;
+
+/// Vendor id of the keyboard, `vendor_id` in the keyboard TOML
+const VENDOR_ID: u16 = 0x4653;
+/// Product id of the keyboard, `product_id` in the keyboard TOML
+const PRODUCT_ID: u16 = 0x0001;
+/// Usage page and usage of the firmware's custom-channel interface on USB,
+/// next to the Vial one (`src/custom_hid_desc.rs`)
+const RAW_USAGE_PAGE: u16 = 0xFF62;
+const RAW_USAGE: u16 = 0x01;
+
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The keyboard's custom-channel interface on USB
+pub struct HidDevice {
+    device: hidapi::HidDevice,
+}
+
+impl HidDevice {
+    pub fn open() -> Result<Self, String> {
+        let api = hidapi::HidApi::new().map_err(|e| e.to_string())?;
+        let info = api
+            .device_list()
+            .find(|d| {
+                d.vendor_id() == VENDOR_ID
+                    && d.product_id() == PRODUCT_ID
+                    && d.usage_page() == RAW_USAGE_PAGE
+                    && d.usage() == RAW_USAGE
+            })
+            .ok_or("keyboard not found, is it connected over USB?")?;
+        let device = info.open_device(&api).map_err(|e| e.to_string())?;
+        Ok(Self { device })
+    }
+}
+
+impl Device for HidDevice {
+    fn exchange(&mut self, report: &mut [u8; REPORT_LEN]) -> Result<(), String> {
+        // Report id 0 in front
+        let mut out = [0; REPORT_LEN + 1];
+        out[1..].copy_from_slice(report);
+        self.device.write(&out).map_err(|e| e.to_string())?;
+        let read = self
+            .device
+            .read_timeout(report, READ_TIMEOUT.as_millis() as i32)
+            .map_err(|e| e.to_string())?;
+        if read != REPORT_LEN {
+            return Err("no answer from the keyboard".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Emulates the firmware's typing statistics channel
+pub struct MockDevice {
+    counts: Counts,
+}
+
+impl MockDevice {
+    /// Matrix of the Corne's central, see `src/keymap.rs`
+    pub const LAYERS: usize = 8;
+    pub const ROWS: usize = 8;
+    pub const COLS: usize = 6;
+
+    /// A keyboard that was typed on mostly on the home row of the base layer
+    pub fn new() -> Self {
+        let mut counts = Counts::new(Self::LAYERS, Self::ROWS, Self::COLS);
+        for (layer, presses) in counts.layers.iter_mut().take(3).enumerate() {
+            for (i, count) in presses.iter_mut().enumerate() {
+                let (row, col) = (i / Self::COLS % 4, i % Self::COLS);
+                let home = if row == 1 { 4 } else { 1 };
+                let reach = if col == 0 { 1 } else { 3 };
+                *count = (home * reach * (37 + i as u32 * 53 % 41)) >> (layer * 2);
+            }
+        }
+        Self { counts }
+    }
+
+    fn handle(&mut self, command: u8, value_id: u8, data: &mut [u8]) -> bool {
+        match (command, value_id) {
+            (CUSTOM_GET_VALUE, VALUE_SUMMARY) => {
+                let summary = Summary {
+                    layers: Self::LAYERS as u8,
+                    rows: Self::ROWS as u8,
+                    cols: Self::COLS as u8,
+                    wpm: 0,
+                    peak_wpm: 64,
+                    total: self.counts.layers.iter().flatten().sum(),
+                };
+                data[..Summary::LEN].copy_from_slice(&summary.to_bytes());
+                true
+            }
+            (CUSTOM_GET_VALUE, VALUE_ROW) => {
+                let (layer, row) = (data[0] as usize, data[1] as usize);
+                if layer >= Self::LAYERS || row >= Self::ROWS {
+                    return false;
+                }
+                let presses = &self.counts.layers[layer][row * Self::COLS..][..Self::COLS];
+                for (bytes, count) in data[2..].as_chunks_mut::<4>().0.iter_mut().zip(presses) {
+                    *bytes = count.to_le_bytes();
+                }
+                true
+            }
+            (CUSTOM_SET_VALUE, VALUE_RESET) => {
+                self.counts = Counts::new(Self::LAYERS, Self::ROWS, Self::COLS);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Device for MockDevice {
+    fn exchange(&mut self, report: &mut [u8; REPORT_LEN]) -> Result<(), String> {
+        let (command, channel, value_id) = (report[0], report[1], report[2]);
+        let handled = channel == TYPING_STATS_CHANNEL
+            && self.handle(command, value_id, &mut report[DATA_OFFSET..]);
+        if !handled {
+            report[0] = UNHANDLED;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+//! SVG heatmap of the press counts over a KLE layout
+
+use std::fmt::Write;
+
+use crate::kle::Key;
+use crate::protocol::Counts;
+
+/// Pixels of a key unit
+const UNIT: f64 = 60.0;
+/// Gap between neighbouring keys
+const GAP: f64 = 4.0;
+const MARGIN: f64 = 20.0;
+
+/// Fill of a key pressed `share` as often as the most pressed one, from white to red
+fn color(share: f64) -> String {
+    // The square root keeps the rarely pressed keys apart
+    let t = share.clamp(0.0, 1.0).sqrt();
+    let (r, g, b) = if t < 0.5 {
+        // White to yellow
+        (255.0, 255.0, 255.0 * (1.0 - t * 2.0))
+    } else {
+        // Yellow to red
+        (255.0, 255.0 * (2.0 - t * 2.0), 0.0)
+    };
+    format!("#{:02x}{:02x}{:02x}", r as u8, g as u8, b as u8)
+}
+
+/// Draw `keys` colored by their presses on `layer`, or on all layers
+///
+/// Keys outside the keyboard's matrix are drawn grey.
+pub fn render(keys: &[Key], counts: &Counts, layer: Option<usize>, title: &str) -> String {
+    let max = keys
+        .iter()
+        .filter_map(|k| counts.get(layer, k.row, k.col))
+        .max()
+        .unwrap_or(0);
+    let total: u64 = keys
+        .iter()
+        .filter_map(|k| counts.get(layer, k.row, k.col))
+        .map(u64::from)
+        .sum();
+
+    // Rotated keys may stick out of the rows by up to half a unit
+    let width = (keys.iter().map(|k| k.x + k.w).fold(0.0, f64::max) + 0.5) * UNIT + MARGIN * 2.0;
+    let height =
+        (keys.iter().map(|k| k.y + k.h).fold(0.0, f64::max) + 0.5) * UNIT + MARGIN * 3.0 + 20.0;
+
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width:.0}" height="{height:.0}" font-family="sans-serif" text-anchor="middle">"#
+    );
+    let _ = writeln!(
+        svg,
+        r#"<text x="{:.0}" y="{:.0}" font-size="16">{title}, {total} presses</text>"#,
+        width / 2.0,
+        MARGIN + 6.0
+    );
+    let top = MARGIN * 2.0 + 20.0;
+    for key in keys {
+        let count = counts.get(layer, key.row, key.col);
+        let fill = match count {
+            Some(n) if max > 0 => color(n as f64 / max as f64),
+            Some(_) => color(0.0),
+            None => "#c8c8c8".to_string(),
+        };
+        let (x, y) = (MARGIN + key.x * UNIT, top + key.y * UNIT);
+        let (w, h) = (key.w * UNIT - GAP, key.h * UNIT - GAP);
+        let _ = writeln!(
+            svg,
+            r#"<g transform="rotate({} {:.1} {:.1})">"#,
+            key.r,
+            MARGIN + key.rx * UNIT,
+            top + key.ry * UNIT
+        );
+        let _ = writeln!(
+            svg,
+            r##"<rect x="{x:.1}" y="{y:.1}" width="{w:.1}" height="{h:.1}" rx="6" fill="{fill}" stroke="#555"/>"##
+        );
+        let _ = writeln!(
+            svg,
+            r##"<text x="{:.1}" y="{:.1}" font-size="10" fill="#555">{},{}</text>"##,
+            x + w / 2.0,
+            y + 14.0,
+            key.row,
+            key.col
+        );
+        if let Some(n) = count {
+            let percent = if total > 0 {
+                n as f64 * 100.0 / total as f64
+            } else {
+                0.0
+            };
+            let _ = writeln!(
+                svg,
+                r#"<text x="{:.1}" y="{:.1}" font-size="12">{n}</text>"#,
+                x + w / 2.0,
+                y + h / 2.0 + 6.0
+            );
+            let _ = writeln!(
+                svg,
+                r#"<text x="{:.1}" y="{:.1}" font-size="10">{percent:.1}%</text>"#,
+                x + w / 2.0,
+                y + h - 6.0
+            );
+        }
+        svg.push_str("</g>\n");
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
@@ -0,0 +1,109 @@
+//! Key positions of a KLE layout
+//!
+//! Reads the raw data of keyboard-layout-editor.com, as used by VIA and Vial:
+//! the legend of each key is its `row,col` matrix position. Keys with an `e`
+//! in their last legend are encoder directions and skipped.
+
+use serde_json::Value;
+
+/// A key of the layout, in key units
+#[derive(Clone, Debug, PartialEq)]
+pub struct Key {
+    pub row: usize,
+    pub col: usize,
+    pub x: f64,
+    pub y: f64,
+    pub w: f64,
+    pub h: f64,
+    /// Clockwise rotation in degrees around `rx`, `ry`
+    pub r: f64,
+    pub rx: f64,
+    pub ry: f64,
+}
+
+/// Position of the next key while walking the rows
+struct Cursor {
+    x: f64,
+    y: f64,
+    w: f64,
+    h: f64,
+    r: f64,
+    rx: f64,
+    ry: f64,
+}
+
+/// Parse the keys of a KLE JSON file
+pub fn parse(json: &str) -> Result<Vec<Key>, String> {
+    let layout: Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+    let rows = layout
+        .as_array()
+        .ok_or("a KLE layout is an array of rows")?;
+    let mut keys = Vec::new();
+    let mut at = Cursor {
+        x: 0.0,
+        y: 0.0,
+        w: 1.0,
+        h: 1.0,
+        r: 0.0,
+        rx: 0.0,
+        ry: 0.0,
+    };
+    // The keyboard's metadata may come first
+    for row in rows.iter().filter_map(Value::as_array) {
+        for item in row {
+            match item {
+                Value::Object(props) => {
+                    let prop = |name: &str| props.get(name).and_then(Value::as_f64);
+                    if let Some(r) = prop("r") {
+                        at.r = r;
+                    }
+                    // A new rotation origin restarts the rows there
+                    if let Some(rx) = prop("rx") {
+                        at.rx = rx;
+                        (at.x, at.y) = (at.rx, at.ry);
+                    }
+                    if let Some(ry) = prop("ry") {
+                        at.ry = ry;
+                        (at.x, at.y) = (at.rx, at.ry);
+                    }
+                    at.x += prop("x").unwrap_or(0.0);
+                    at.y += prop("y").unwrap_or(0.0);
+                    at.w = prop("w").unwrap_or(at.w);
+                    at.h = prop("h").unwrap_or(at.h);
+                }
+                Value::String(legends) => {
+                    let encoder = legends.split('\n').nth(9) == Some("e");
+                    if !encoder {
+                        let (row, col) = matrix_position(legends)?;
+                        keys.push(Key {
+                            row,
+                            col,
+                            x: at.x,
+                            y: at.y,
+                            w: at.w,
+                            h: at.h,
+                            r: at.r,
+                            rx: at.rx,
+                            ry: at.ry,
+                        });
+                    }
+                    at.x += at.w;
+                    (at.w, at.h) = (1.0, 1.0);
+                }
+                _ => return Err(format!("unexpected {item} in a KLE row")),
+            }
+        }
+        at.y += 1.0;
+        at.x = at.rx;
+    }
+    Ok(keys)
+}
+
+/// The `row,col` in the first legend
+fn matrix_position(legends: &str) -> Result<(usize, usize), String> {
+    let first = legends.split('\n').next().unwrap_or_default();
+    first
+        .split_once(',')
+        .and_then(|(row, col)| Some((row.trim().parse().ok()?, col.trim().parse().ok()?)))
+        .ok_or_else(|| format!("key {first:?} has no row,col legend"))
+}
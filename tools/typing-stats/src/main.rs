@@ -0,0 +1,116 @@
+//! Read the keyboard's typing statistics over Vial and draw a heatmap
+//!
+//! ```text
+//! typing-stats [--mock] summary
+//! typing-stats [--mock] heatmap <kle.json> <out.svg> [<layer>]
+//! typing-stats [--mock] reset
+//! ```
+//!
+//! The heatmap colors the keys of a KLE layout, e.g. `jzf-cornix-v1-kle.json`,
+//! by how often they were pressed on `layer`, or on all layers without one.
+//! With `--mock`, made-up counts stand in for the keyboard.
+
+mod device;
+mod heatmap;
+mod kle;
+mod protocol;
+#[cfg(test)]
+mod tests;
+mod transfer;
+
+use std::process::ExitCode;
+
+use device::{HidDevice, MockDevice};
+use protocol::*;
+use transfer::{Device, command, read_counts, read_summary};
+
+fn usage() -> ExitCode {
+    eprintln!(
+        "usage: typing-stats [--mock] summary | heatmap <kle.json> <out.svg> [<layer>] | reset"
+    );
+    ExitCode::FAILURE
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let (mock, args) = match args {
+        [flag, rest @ ..] if flag == "--mock" => (true, rest),
+        _ => (false, args),
+    };
+    let known = match args {
+        [cmd] => cmd == "summary" || cmd == "reset",
+        [cmd, _, _] | [cmd, _, _, _] => cmd == "heatmap",
+        _ => false,
+    };
+    if !known {
+        return Err(String::new());
+    }
+    let mut mock_device;
+    let mut hid_device;
+    let device: &mut dyn Device = if mock {
+        mock_device = MockDevice::new();
+        &mut mock_device
+    } else {
+        hid_device = HidDevice::open()?;
+        &mut hid_device
+    };
+
+    match args {
+        [cmd] if cmd == "summary" => {
+            let summary = read_summary(device)?;
+            println!(
+                "{} presses, {} WPM now, {} WPM peak since boot",
+                summary.total, summary.wpm, summary.peak_wpm
+            );
+            let counts = read_counts(device, &summary)?;
+            for (layer, presses) in counts.layers.iter().enumerate() {
+                let total: u64 = presses.iter().map(|&n| u64::from(n)).sum();
+                if total > 0 {
+                    println!("layer {layer}: {total} presses");
+                }
+            }
+        }
+        [cmd] if cmd == "reset" => {
+            command(device, CUSTOM_SET_VALUE, VALUE_RESET, &[])?
+                .ok_or("the firmware does not count key presses")?;
+            println!("reset the typing statistics");
+        }
+        [cmd, layout, out, layer @ ..] if cmd == "heatmap" => {
+            let layer = match layer {
+                [layer] => Some(
+                    layer
+                        .parse::<usize>()
+                        .map_err(|_| format!("{layer} is not a layer"))?,
+                ),
+                _ => None,
+            };
+            let json = std::fs::read_to_string(layout).map_err(|e| format!("{layout}: {e}"))?;
+            let keys = kle::parse(&json).map_err(|e| format!("{layout}: {e}"))?;
+            let summary = read_summary(device)?;
+            if layer.is_some_and(|l| l >= summary.layers as usize) {
+                return Err(format!("the keyboard has {} layers", summary.layers));
+            }
+            let counts = read_counts(device, &summary)?;
+            let title = match layer {
+                Some(layer) => format!("Layer {layer}"),
+                None => "All layers".to_string(),
+            };
+            let svg = heatmap::render(&keys, &counts, layer, &title);
+            std::fs::write(out, svg).map_err(|e| format!("{out}: {e}"))?;
+            println!("saved the heatmap of {} keys to {out}", keys.len());
+        }
+        _ => return Err(String::new()),
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) if e.is_empty() => usage(),
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! The typing statistics channel of the firmware, see `src/typing_stats.rs` and
+//! `src/vial_custom.rs`
+
+/// Size of a VIA report
+pub const REPORT_LEN: usize = 32;
+
+/// Offset of the first data byte in a custom command report
+pub const DATA_OFFSET: usize = 3;
+
+/// `id_custom_set_value`
+pub const CUSTOM_SET_VALUE: u8 = 0x07;
+/// `id_custom_get_value`
+pub const CUSTOM_GET_VALUE: u8 = 0x08;
+/// `id_unhandled`, byte 0 of the answer when the firmware rejected a command
+pub const UNHANDLED: u8 = 0xFF;
+
+/// Custom channel of the typing statistics commands
+pub const TYPING_STATS_CHANNEL: u8 = 0x14;
+
+pub const VALUE_SUMMARY: u8 = 0x01;
+pub const VALUE_ROW: u8 = 0x02;
+pub const VALUE_RESET: u8 = 0x03;
+
+/// Answer to [`VALUE_SUMMARY`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Summary {
+    pub layers: u8,
+    pub rows: u8,
+    pub cols: u8,
+    pub wpm: u16,
+    /// Highest WPM since the keyboard booted
+    pub peak_wpm: u16,
+    pub total: u32,
+}
+
+impl Summary {
+    pub const LEN: usize = 11;
+
+    pub fn parse(data: &[u8]) -> Self {
+        Self {
+            layers: data[0],
+            rows: data[1],
+            cols: data[2],
+            wpm: u16::from_le_bytes([data[3], data[4]]),
+            peak_wpm: u16::from_le_bytes([data[5], data[6]]),
+            total: u32::from_le_bytes(data[7..11].try_into().unwrap()),
+        }
+    }
+
+    pub fn to_bytes(self) -> [u8; Self::LEN] {
+        let mut buf = [0; Self::LEN];
+        buf[0] = self.layers;
+        buf[1] = self.rows;
+        buf[2] = self.cols;
+        buf[3..5].copy_from_slice(&self.wpm.to_le_bytes());
+        buf[5..7].copy_from_slice(&self.peak_wpm.to_le_bytes());
+        buf[7..11].copy_from_slice(&self.total.to_le_bytes());
+        buf
+    }
+}
+
+/// Press counts of every layer, row and column
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Counts {
+    pub rows: usize,
+    pub cols: usize,
+    /// `layers[layer][row * cols + col]`
+    pub layers: Vec<Vec<u32>>,
+}
+
+impl Counts {
+    pub fn new(layers: usize, rows: usize, cols: usize) -> Self {
+        Self {
+            rows,
+            cols,
+            layers: vec![vec![0; rows * cols]; layers],
+        }
+    }
+
+    /// Presses of `row`, `col` on `layer`, or summed over all layers
+    pub fn get(&self, layer: Option<usize>, row: usize, col: usize) -> Option<u32> {
+        if row >= self.rows || col >= self.cols {
+            return None;
+        }
+        let i = row * self.cols + col;
+        match layer {
+            Some(layer) => self.layers.get(layer).map(|counts| counts[i]),
+            None => Some(self.layers.iter().map(|counts| counts[i]).sum()),
+        }
+    }
+}
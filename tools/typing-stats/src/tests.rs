@@ -0,0 +1,129 @@
+//! Reading the statistics from the firmware is tested in `host-tests`
+
+use crate::heatmap::render;
+use crate::kle::{Key, parse};
+use crate::protocol::*;
+
+/// The Cornix layout the README draws heatmaps of
+const CORNIX: &str = include_str!("../../../jzf-cornix-v1-kle.json");
+
+fn key(row: usize, col: usize, x: f64, y: f64) -> Key {
+    Key {
+        row,
+        col,
+        x,
+        y,
+        w: 1.0,
+        h: 1.0,
+        r: 0.0,
+        rx: 0.0,
+        ry: 0.0,
+    }
+}
+
+#[test]
+fn keys_follow_each_other_along_the_rows() {
+    let keys =
+        parse(r#"[{"name": "test"}, ["0,0", {"x": 0.5, "w": 2}, "0,1", "0,2"], ["1,0"]]"#).unwrap();
+    let wide = Key {
+        w: 2.0,
+        ..key(0, 1, 1.5, 0.0)
+    };
+    assert_eq!(
+        keys,
+        [
+            key(0, 0, 0.0, 0.0),
+            wide,
+            key(0, 2, 3.5, 0.0),
+            key(1, 0, 0.0, 1.0)
+        ]
+    );
+}
+
+#[test]
+fn a_rotation_origin_restarts_the_rows() {
+    let keys = parse(r#"[[{"r": 15, "rx": 4, "ry": 2, "y": -0.5}, "3,1"], ["3,2"]]"#).unwrap();
+    let rotated = |row, col, y| Key {
+        r: 15.0,
+        rx: 4.0,
+        ry: 2.0,
+        ..key(row, col, 4.0, y)
+    };
+    assert_eq!(keys, [rotated(3, 1, 1.5), rotated(3, 2, 2.5)]);
+}
+
+#[test]
+fn encoder_directions_are_skipped() {
+    let keys = parse(r#"[["0,0\n\n\n\n\n\n\n\n\ne", "0,1"]]"#).unwrap();
+    assert_eq!(keys, [key(0, 1, 1.0, 0.0)]);
+}
+
+#[test]
+fn keys_need_a_matrix_position() {
+    assert!(parse(r#"[["Esc"]]"#).unwrap_err().contains("Esc"));
+    assert!(parse(r#"{"name": "not rows"}"#).is_err());
+    assert!(parse(r#"[[1]]"#).is_err());
+}
+
+#[test]
+fn the_cornix_layout_parses() {
+    let keys = parse(CORNIX).unwrap();
+    assert_eq!(keys.len(), 50);
+    assert!(keys.iter().all(|k| k.row < 8 && k.col < 7));
+    // The seventh column is outside the Corne's matrix
+    assert_eq!(keys.iter().filter(|k| k.col == 6).count(), 2);
+}
+
+#[test]
+fn summary_round_trips() {
+    let summary = Summary {
+        layers: 8,
+        rows: 8,
+        cols: 6,
+        wpm: 42,
+        peak_wpm: 97,
+        total: 123_456,
+    };
+    assert_eq!(Summary::parse(&summary.to_bytes()), summary);
+}
+
+#[test]
+fn counts_of_a_layer_or_all_layers() {
+    let mut counts = Counts::new(2, 2, 3);
+    // Row 1, col 2
+    counts.layers[0][5] = 5;
+    counts.layers[1][5] = 7;
+    assert_eq!(counts.get(Some(0), 1, 2), Some(5));
+    assert_eq!(counts.get(Some(1), 1, 2), Some(7));
+    assert_eq!(counts.get(None, 1, 2), Some(12));
+    assert_eq!(counts.get(Some(2), 1, 2), None);
+    assert_eq!(counts.get(None, 2, 0), None);
+    assert_eq!(counts.get(None, 0, 3), None);
+}
+
+#[test]
+fn the_heatmap_colors_keys_by_their_presses() {
+    let keys = [
+        key(0, 0, 0.0, 0.0),
+        key(0, 1, 1.0, 0.0),
+        key(0, 6, 2.0, 0.0),
+    ];
+    let mut counts = Counts::new(1, 1, 2);
+    counts.layers[0] = vec![30, 10];
+    let svg = render(&keys, &counts, None, "All layers");
+    assert!(svg.contains("All layers, 40 presses"), "{svg}");
+    // The most pressed key is red, the one outside the matrix grey
+    assert!(svg.contains(r##"fill="#ff0000""##), "{svg}");
+    assert!(svg.contains(r##"fill="#c8c8c8""##), "{svg}");
+    assert!(svg.contains(">75.0%<"), "{svg}");
+    assert!(svg.contains(">25.0%<"), "{svg}");
+    assert_eq!(svg.matches("<rect").count(), 3);
+}
+
+#[test]
+fn a_heatmap_without_presses_is_white() {
+    let keys = [key(0, 0, 0.0, 0.0)];
+    let svg = render(&keys, &Counts::new(1, 1, 1), Some(0), "Layer 0");
+    assert!(svg.contains("Layer 0, 0 presses"), "{svg}");
+    assert!(svg.contains(r##"fill="#ffffff""##), "{svg}");
+}
@@ -0,0 +1,59 @@
+//! Reading the statistics over the typing statistics channel of a [`Device`]
+//!
+//! Kept apart from the HID and command line code so the host tests of the
+//! firmware (`host-tests`) run it against its typing statistics channel.
+
+use super::protocol::*;
+
+/// Something that answers VIA reports
+pub trait Device {
+    /// Send `report` and replace it with the answer
+    fn exchange(&mut self, report: &mut [u8; REPORT_LEN]) -> Result<(), String>;
+}
+
+/// Send a custom command on the typing statistics channel, returns the data of the answer
+pub fn command(
+    device: &mut dyn Device,
+    command: u8,
+    value_id: u8,
+    data: &[u8],
+) -> Result<Option<[u8; REPORT_LEN - DATA_OFFSET]>, String> {
+    let mut report = [0; REPORT_LEN];
+    report[0] = command;
+    report[1] = TYPING_STATS_CHANNEL;
+    report[2] = value_id;
+    report[DATA_OFFSET..DATA_OFFSET + data.len()].copy_from_slice(data);
+    device.exchange(&mut report)?;
+    if report[0] == UNHANDLED {
+        return Ok(None);
+    }
+    Ok(Some(report[DATA_OFFSET..].try_into().unwrap()))
+}
+
+/// Read the WPM and the size of the counts
+pub fn read_summary(device: &mut dyn Device) -> Result<Summary, String> {
+    let data = command(device, CUSTOM_GET_VALUE, VALUE_SUMMARY, &[])?
+        .ok_or("the firmware does not count key presses")?;
+    Ok(Summary::parse(&data))
+}
+
+/// Read the counts of every layer and row
+pub fn read_counts(device: &mut dyn Device, summary: &Summary) -> Result<Counts, String> {
+    let (rows, cols) = (summary.rows as usize, summary.cols as usize);
+    let mut counts = Counts::new(summary.layers as usize, rows, cols);
+    for (layer, presses) in counts.layers.iter_mut().enumerate() {
+        for (row, presses) in presses.chunks_exact_mut(cols).enumerate() {
+            let data = command(
+                device,
+                CUSTOM_GET_VALUE,
+                VALUE_ROW,
+                &[layer as u8, row as u8],
+            )?
+            .ok_or_else(|| format!("failed to read row {row} of layer {layer}"))?;
+            for (count, bytes) in presses.iter_mut().zip(data[2..].as_chunks::<4>().0) {
+                *count = u32::from_le_bytes(*bytes);
+            }
+        }
+    }
+    Ok(counts)
+}